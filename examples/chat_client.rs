@@ -15,6 +15,7 @@ use mini::aio::stdio::{
     InputNotify,
     Stdin,
 };
+use mini::bytes::Bytes;
 
 struct Connection {
 }
@@ -39,8 +40,8 @@ impl TcpConnectionNotify for Connection {
         eprintln!("Error: {}", error);
     }
 
-    fn received(&mut self, _connection: &mut TcpConnection, data: Vec<u8>) {
-        match String::from_utf8(data) {
+    fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
+        match String::from_utf8(data.into_vec()) {
             Ok(text) => print!("-> {}", text),
             Err(error) => println!("Error: did not receive valid UTF-8: {}", error),
         }
@@ -61,7 +62,7 @@ impl StdinHandler {
 
 impl InputNotify for StdinHandler {
     fn received(&mut self, data: Vec<u8>) {
-        self.connection.send(Write(data));
+        self.connection.send(Write(data.into()));
     }
 }
 
@@ -14,12 +14,13 @@ use mini::aio::net::{
     TcpListenNotify,
 };
 use mini::aio::net::TcpListener;
+use mini::bytes::Bytes;
 
 use self::Msg::*;
 
 enum Msg {
     Accepted(TcpConnection),
-    Received(Vec<u8>),
+    Received(Bytes),
     Closed(TcpConnection),
 }
 
@@ -49,7 +50,7 @@ impl Handler for ChatHandler {
                         eprintln!("Error send message: {}", error);
                     }
                 }
-                if data == b"/quit\n" {
+                if data == b"/quit\n"[..] {
                     self.event_loop.stop();
                 }
             },
@@ -109,7 +110,7 @@ impl TcpConnectionNotify for Server {
         self.stream.send(Accepted(connection.clone()));
     }
 
-    fn received(&mut self, _connection: &mut TcpConnection, data: Vec<u8>) {
+    fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
         self.stream.send(Received(data));
     }
 
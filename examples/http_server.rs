@@ -20,6 +20,7 @@ use mini::aio::net::{
     TcpListenNotify,
 };
 use mini::aio::net::TcpListener;
+use mini::bytes::Bytes;
 
 struct Listener {
 }
@@ -50,8 +51,8 @@ impl TcpConnectionNotify for Server {
     fn accepted(&mut self, _connection: &mut TcpConnection) {
     }
 
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
-        let request = String::from_utf8(data).unwrap_or_else(|_| String::new());
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
+        let request = String::from_utf8(data.to_vec()).unwrap_or_else(|_| String::new());
         let mut lines = request.lines();
         let first_line = lines.next().unwrap_or("GET");
         let mut parts = first_line.split_whitespace();
@@ -17,6 +17,7 @@ use mini::aio::net::{
     TcpListenNotify,
 };
 use mini::aio::net::TcpListener;
+use mini::bytes::Bytes;
 
 //use cpuprofiler::PROFILER;
 
@@ -57,11 +58,11 @@ impl Server {
 }
 
 impl TcpConnectionNotify for Server {
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
         self.request_count += 1;
         let mut answer = vec![];
         let mut index = 0;
-        for byte in data {
+        for &byte in data.iter() {
             if byte == b'\n' {
                 answer.push(b'\n');
                 let _ = connection.write(answer); // TODO: handle errors.
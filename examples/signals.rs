@@ -0,0 +1,70 @@
+/*
+ * Reloads its configuration on SIGHUP and shuts down gracefully on SIGTERM or SIGINT.
+ * Try it with: kill -HUP <pid>; kill -TERM <pid>
+ */
+
+extern crate mini;
+
+use std::process;
+
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::signal::{self, Signal, SignalSet};
+
+use self::Msg::*;
+
+enum Msg {
+    Reload,
+    Shutdown,
+}
+
+struct Server {
+    event_loop: Loop,
+    reload_count: u32,
+}
+
+impl Server {
+    fn new(event_loop: &Loop) -> Self {
+        Self {
+            event_loop: event_loop.clone(),
+            reload_count: 0,
+        }
+    }
+}
+
+impl Handler for Server {
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Self::Msg) {
+        match msg {
+            Reload => {
+                self.reload_count += 1;
+                println!("Reloading configuration ({} reloads so far).", self.reload_count);
+            },
+            Shutdown => {
+                println!("Shutting down.");
+                self.event_loop.stop();
+            },
+        }
+    }
+}
+
+fn main() {
+    let mut event_loop = Loop::new().expect("event loop");
+    signal::ignore(Signal::Pipe).expect("ignore SIGPIPE");
+
+    let stream = event_loop.spawn(Server::new(&event_loop));
+    let signals: SignalSet = vec![Signal::Hangup, Signal::Interrupt, Signal::Terminate].into_iter().collect();
+    event_loop.add_signals(&signals, &stream, |signal| {
+        match signal {
+            Signal::Hangup => Reload,
+            _ => Shutdown,
+        }
+    }).expect("add signals");
+
+    println!("Running with pid {}.", process::id());
+    event_loop.run().expect("run");
+}
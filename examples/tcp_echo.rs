@@ -9,6 +9,7 @@ use mini::aio::net::{
     TcpListenNotify,
 };
 use mini::aio::net::TcpListener;
+use mini::bytes::Bytes;
 
 struct Listener {
 }
@@ -41,7 +42,7 @@ impl TcpConnectionNotify for Server {
         println!("Connection accepted.");
     }
 
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
         println!("Data of size {} received, looping it back.", data.len());
         let _ = connection.write(b"server says: ".to_vec());
         let _ = connection.write(data); // TODO: handle errors.
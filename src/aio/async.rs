@@ -1,43 +1,79 @@
-/*
- * TODO: reuse buffers.
- */
-
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::io::{
     Error,
     ErrorKind,
 };
+use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
 use std::ptr;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::u64;
 
+use aio::callback::{InlineFnMut, InlineFnOnce};
+use aio::inotify::{FileEvent, Inotify};
+use aio::net::close;
 use aio::slab::Slab;
+use aio::timer::TimerFd;
+use arena::TypedArena;
+use signal::SignalSet;
+use threadpool::{self, ThreadPool};
+
+/// Maximum number of events handled per wakeup, unless set with `EventLoop::new_with_capacity`.
+const DEFAULT_CAPACITY: usize = 100;
 
-const MAX_EVENTS: usize = 100; // TODO: tweak this value.
+/// Maximum number of threads running the functions given to `EventLoop::spawn_blocking`.
+const BLOCKING_THREADS: usize = 4;
 
 #[repr(u32)]
 pub enum Mode {
     Read = ffi::EPOLLIN | ffi::EPOLLEXCLUSIVE,
     ReadWrite = ffi::EPOLLIN | ffi::EPOLLOUT | ffi::EPOLLEXCLUSIVE,
     Write = ffi::EPOLLOUT | ffi::EPOLLEXCLUSIVE,
+    /// Readable, or priority data available, like TCP urgent data or a pseudo-terminal in packet
+    /// mode. Not exclusive, since the kernel refuses `EPOLLEXCLUSIVE` with `EPOLLPRI`.
+    ReadPriority = ffi::EPOLLIN | ffi::EPOLLPRI,
+    ReadWritePriority = ffi::EPOLLIN | ffi::EPOLLOUT | ffi::EPOLLPRI,
 }
 
-trait FnBox {
-    fn call_box(self: Box<Self>, event: ffi::epoll_event);
+/// The callbacks are stored inline in their slot when they fit, so that registering an fd with
+/// a small closure does not allocate.
+enum Callback {
+    Empty,
+    Normal(InlineFnMut<ffi::epoll_event, Action>),
+    Oneshot(InlineFnOnce<ffi::epoll_event>),
 }
 
-impl<T> FnBox for T where T: FnOnce(ffi::epoll_event) {
-    fn call_box(self: Box<Self>, event: ffi::epoll_event) {
-        (*self)(event);
-    }
+/// A callback with the fd it was registered for.
+struct Slot {
+    callback: Callback,
+    fd: RawFd,
+    /// Distinguishes the registrations reusing an entry, so that a stale `Event` cannot cancel a
+    /// newer registration.
+    generation: u64,
 }
 
-enum Callback {
-    Empty,
-    Normal(Box<FnMut(ffi::epoll_event) -> Action>),
-    Oneshot(Box<FnBox>),
+/// A registration change queued with one of the `EventLoop::defer_*` methods.
+enum Deferred {
+    /// Fd, events and callback entry.
+    Add(RawFd, u32, usize),
+    Modify(RawFd, Mode),
+    Remove(RawFd),
+}
+
+impl Deferred {
+    fn fd(&self) -> RawFd {
+        match *self {
+            Deferred::Add(fd, _, _) | Deferred::Modify(fd, _) | Deferred::Remove(fd) => fd,
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -49,40 +85,256 @@ pub enum Action {
 pub struct Event {
     callback_entry: usize,
     event_loop: EventLoop,
+    fd: RawFd,
+    generation: u64,
 }
 
 impl Event {
-    fn new(callback_entry: usize, event_loop: &EventLoop) -> Self {
+    fn new(callback_entry: usize, event_loop: &EventLoop, fd: RawFd) -> Self {
         Self {
             callback_entry,
             event_loop: event_loop.clone(),
+            fd,
+            generation: event_loop.generation(callback_entry),
         }
     }
 
-    pub fn set_callback<F>(self, callback: F)
+    /// Removes the fd from the event loop and frees its callback. Returns a `NotFound` error if
+    /// this registration was already removed, even if the fd was registered again since.
+    pub fn cancel(&self) -> io::Result<()> {
+        self.event_loop.cancel(self.fd, self.callback_entry, self.generation)
+    }
+
+    pub fn set_callback<F>(&self, callback: F)
     where F: FnMut(ffi::epoll_event) -> Action + 'static,
     {
-        self.event_loop.callbacks.borrow_mut()[self.callback_entry] = Callback::Normal(Box::new(callback));
+        if let Some(slot) = self.event_loop.callbacks.borrow_mut().get_mut(self.callback_entry) {
+            slot.callback = Callback::Normal(InlineFnMut::new(callback));
+        }
+    }
+
+    /// Changes the events the fd is watched for, see `EventLoop::modify_raw_fd`.
+    pub fn modify(&self, mode: Mode) -> io::Result<()> {
+        self.event_loop.modify_raw_fd(self.fd, mode)
     }
 }
 
 pub struct EventOnce {
     callback_entry: usize,
     event_loop: EventLoop,
+    fd: RawFd,
+    generation: u64,
 }
 
 impl EventOnce {
-    fn new(callback_entry: usize, event_loop: EventLoop) -> Self {
+    fn new(callback_entry: usize, event_loop: &EventLoop, fd: RawFd) -> Self {
         Self {
             callback_entry,
             event_loop: event_loop.clone(),
+            fd,
+            generation: event_loop.generation(callback_entry),
         }
     }
 
+    /// Removes the fd from the event loop without setting a callback, see `Event::cancel`.
+    pub fn cancel(self) -> io::Result<()> {
+        self.event_loop.cancel(self.fd, self.callback_entry, self.generation)
+    }
+
     pub fn set_callback<F>(self, callback: F)
     where F: FnOnce(ffi::epoll_event) + 'static,
     {
-        self.event_loop.callbacks.borrow_mut()[self.callback_entry] = Callback::Oneshot(Box::new(callback));
+        if let Some(slot) = self.event_loop.callbacks.borrow_mut().get_mut(self.callback_entry) {
+            slot.callback = Callback::Oneshot(InlineFnOnce::new(callback));
+        }
+    }
+}
+
+/// Job id and result of a function given to `EventLoop::spawn_blocking`, `None` if it panicked.
+type BlockingResult = (u64, Option<Box<dyn Any + Send>>);
+
+type Completion = Box<dyn FnOnce(Box<dyn Any + Send>)>;
+
+/// A callback queued with `EventLoop::defer`.
+type Task = InlineFnOnce<()>;
+
+/// The pool of `EventLoop::spawn_blocking`, started on first use.
+struct Blocking {
+    /// Completion callbacks by job id.
+    completions: Rc<RefCell<HashMap<u64, Completion>>>,
+    next_id: u64,
+    /// Wakes up the loop to run the completions of the finished jobs.
+    notifier: Notifier,
+    /// Cloned to queue a job without keeping the loop borrowed while the queue is full.
+    pool: Rc<ThreadPool>,
+    results: Arc<Mutex<Vec<BlockingResult>>>,
+}
+
+/// A handle to a timer registered with `EventLoop::add_timeout` or `EventLoop::add_interval`.
+#[derive(Clone)]
+pub struct TimerHandle {
+    event_loop: EventLoop,
+    timer: Rc<RefCell<Option<TimerFd>>>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer: its callback will not be called anymore. Does nothing if the timer
+    /// already expired or was canceled.
+    pub fn cancel(&self) {
+        if let Some(timer) = self.timer.borrow_mut().take() {
+            let _ = self.event_loop.remove_fd(&timer);
+        }
+    }
+
+    /// Returns true if the callback may still be called.
+    pub fn is_active(&self) -> bool {
+        self.timer.borrow().is_some()
+    }
+}
+
+/// A handle to a path watched with `EventLoop::watch_path`.
+#[derive(Clone)]
+pub struct PathWatch {
+    event_loop: EventLoop,
+    inotify: Rc<RefCell<Option<Inotify>>>,
+}
+
+impl PathWatch {
+    /// Stops watching the path: the callback will not be called anymore.
+    pub fn cancel(&self) {
+        if let Some(inotify) = self.inotify.borrow_mut().take() {
+            let _ = self.event_loop.remove_fd(&inotify);
+        }
+    }
+
+    /// Returns true if the callback may still be called.
+    pub fn is_active(&self) -> bool {
+        self.inotify.borrow().is_some()
+    }
+}
+
+/// A handle waking up an `EventLoop` from any thread to run the callback registered with
+/// `EventLoop::add_notifier`.
+#[derive(Clone)]
+pub struct Notifier {
+    event_fd: Arc<NotifierFd>,
+}
+
+impl Notifier {
+    /// Schedules a call of the callback on the event loop thread. Notifications sent before the
+    /// callback runs are coalesced into a single call.
+    pub fn notify(&self) {
+        unsafe {
+            ffi::eventfd_write(self.event_fd.0, 1);
+        }
+    }
+}
+
+impl AsRawFd for Notifier {
+    /// Returns the eventfd, which can be given to `EventLoop::remove_fd` to unregister the callback.
+    fn as_raw_fd(&self) -> RawFd {
+        self.event_fd.0
+    }
+}
+
+/// An eventfd, closed once the notifiers and the event loop callback are dropped.
+struct NotifierFd(RawFd);
+
+impl Drop for NotifierFd {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+/// Counters of an `EventLoop`, returned by `EventLoop::metrics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metrics {
+    /// Number of fds currently registered.
+    pub registered_fds: usize,
+    /// Number of callbacks currently allocated, including those of removed fds which are freed at
+    /// the end of the current iteration.
+    pub callbacks: usize,
+    /// Number of calls to `epoll_wait`.
+    pub iterations: u64,
+    /// Number of `epoll_wait` calls which returned events, as opposed to timing out.
+    pub wakeups: u64,
+    /// Number of events dispatched to the callbacks.
+    pub events: u64,
+    /// Largest number of events returned by a single `epoll_wait` call.
+    pub max_events_per_wakeup: usize,
+    /// Time spent in the callbacks, counting a callback that iterates the loop once per level.
+    pub callback_time: Duration,
+}
+
+/// An error happening while running an `EventLoop`.
+#[derive(Debug)]
+pub enum LoopError {
+    /// `epoll_wait` failed.
+    Epoll(io::Error),
+    /// An event happened on an fd without callback, e.g. an `Event` whose callback was not set.
+    MissingCallback(RawFd),
+    /// An operation queued with one of the `EventLoop::defer_*` methods failed.
+    Deferred(RawFd, io::Error),
+    /// A timer could not be armed by a component running on the loop, e.g. a
+    /// `ShutdownController` polling its components.
+    Timer(RawFd, io::Error),
+}
+
+impl Display for LoopError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            LoopError::Epoll(ref error) => write!(formatter, "epoll_wait failed: {}", error),
+            LoopError::MissingCallback(fd) => write!(formatter, "no callback for fd {}", fd),
+            LoopError::Deferred(fd, ref error) => write!(formatter, "deferred operation on fd {} failed: {}", fd, error),
+            LoopError::Timer(fd, ref error) => write!(formatter, "cannot arm timer fd {}: {}", fd, error),
+        }
+    }
+}
+
+impl From<LoopError> for io::Error {
+    fn from(error: LoopError) -> Self {
+        match error {
+            LoopError::Epoll(error) | LoopError::Deferred(_, error) | LoopError::Timer(_, error) => error,
+            LoopError::MissingCallback(_) => io::Error::other(error.to_string()),
+        }
+    }
+}
+
+/// What an `EventLoop` does on errors, set with `EventLoop::set_error_policy`.
+#[derive(Clone, Default)]
+pub enum ErrorPolicy {
+    /// Return the epoll errors from the run methods and panic on the errors happening in callbacks,
+    /// like an event without callback.
+    #[default]
+    Propagate,
+    /// Print the errors on stderr and continue.
+    Log,
+    /// Call the hook with the errors and continue. The hook can call `EventLoop::stop`.
+    Hook(Rc<dyn Fn(&LoopError)>),
+}
+
+/// Instrumentation hooks of an `EventLoop`, installed with `EventLoop::set_observer`, e.g. to trace
+/// latencies or profile the callbacks. The methods are called on the loop thread and can use
+/// interior mutability to record what they observe.
+pub trait EventLoopObserver {
+    /// Called when an fd is added to the loop, with its epoll events.
+    fn fd_registered(&self, _fd: RawFd, _events: u32) {
+    }
+
+    /// Called when an fd is removed from the loop.
+    fn fd_deregistered(&self, _fd: RawFd) {
+    }
+
+    /// Called after the callback of an fd ran, with the events it was called for.
+    fn event_dispatched(&self, _fd: RawFd, _events: u32, _duration: Duration) {
+    }
+
+    /// Called before waiting for events for `timeout` at most, forever if `None`.
+    fn wait_entered(&self, _timeout: Option<Duration>) {
+    }
+
+    /// Called after waiting, with the number of events received.
+    fn wait_exited(&self, _events: usize) {
     }
 }
 
@@ -94,25 +346,73 @@ pub enum EpollResult {
 
 thread_local! {
     static EVENT_FD: RawFd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK) };
+    /// The loop being iterated on this thread.
+    static CURRENT: RefCell<Option<EventLoop>> = const { RefCell::new(None) };
 }
 
+/// A single-threaded event loop: clones share the same state through `Rc`, which keeps it from
+/// being `Send` or `Sync`. Use `aio::shared::SharedEventLoop` to iterate from several threads.
 #[derive(Clone)]
 pub struct EventLoop {
-    callbacks: Rc<RefCell<Slab<Callback>>>,
+    callbacks: Rc<RefCell<Slab<Slot>>>,
+    /// Number of nested calls dispatching events.
+    dispatching: Rc<Cell<usize>>,
+    /// Registration changes to apply once the events are dispatched.
+    deferred: Rc<RefCell<Vec<Deferred>>>,
+    /// Callbacks queued with `defer`, and the arena they are moved to when run, swapped so that
+    /// queuing a callback does not allocate once the loop warmed up.
+    next_tick: Rc<RefCell<TypedArena<Task>>>,
+    spare_tick: Rc<RefCell<TypedArena<Task>>>,
     fd: RawFd,
-    stopped: bool,
+    /// Cumulative counters, without the current counts.
+    metrics: Rc<Cell<Metrics>>,
+    error_policy: Rc<RefCell<ErrorPolicy>>,
+    observer: Rc<RefCell<Option<Rc<dyn EventLoopObserver>>>>,
+    blocking: Rc<RefCell<Option<Blocking>>>,
+    /// Events and callback entry of the registered fds.
+    registrations: Rc<RefCell<HashMap<RawFd, (u32, usize)>>>,
+    /// Callback entries to free once the events are dispatched, since pending events may still
+    /// refer to them.
+    released: Rc<RefCell<Vec<usize>>>,
+    stopped: Rc<Cell<bool>>,
+    /// Buffer receiving the events of a wakeup.
+    event_list: Rc<RefCell<Vec<ffi::epoll_event>>>,
+    capacity: usize,
+    next_generation: Rc<Cell<u64>>,
 }
 
 impl EventLoop {
     pub fn new() -> io::Result<Self> {
+        Self::new_with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates an event loop handling up to `capacity` events per wakeup. A larger capacity means
+    /// fewer `epoll_wait` calls when many fds are ready at once.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new_with_capacity(capacity: usize) -> io::Result<Self> {
+        assert!(capacity > 0, "the event capacity should not be zero");
         let fd = unsafe { ffi::epoll_create1(0) };
         if fd == -1 {
             return Err(Error::last_os_error());
         }
         let event_loop = Self {
             callbacks: Rc::new(RefCell::new(Slab::new())),
+            dispatching: Rc::new(Cell::new(0)),
+            deferred: Rc::new(RefCell::new(vec![])),
+            next_tick: Rc::new(RefCell::new(TypedArena::new())),
+            spare_tick: Rc::new(RefCell::new(TypedArena::new())),
+            error_policy: Rc::new(RefCell::new(ErrorPolicy::default())),
+            observer: Rc::new(RefCell::new(None)),
+            blocking: Rc::new(RefCell::new(None)),
             fd,
-            stopped: false,
+            metrics: Rc::new(Cell::new(Metrics::default())),
+            registrations: Rc::new(RefCell::new(HashMap::new())),
+            released: Rc::new(RefCell::new(vec![])),
+            stopped: Rc::new(Cell::new(false)),
+            event_list: Rc::new(RefCell::new(event_list(capacity))),
+            capacity,
+            next_generation: Rc::new(Cell::new(0)),
         };
 
         let event_fd = EVENT_FD.with(|&event_fd| event_fd);
@@ -134,87 +434,543 @@ impl EventLoop {
         Ok(())
     }
 
+    pub fn add_fd<A: AsRawFd, F>(&self, as_fd: &A, mode: Mode, callback: F) -> io::Result<()>
+    where F: FnMut(ffi::epoll_event) -> Action + 'static,
+    {
+        self.add_raw_fd(as_fd.as_raw_fd(), mode, callback)
+    }
+
     pub fn add_raw_fd<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
     where F: FnMut(ffi::epoll_event) -> Action + 'static,
     {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Normal(Box::new(callback)));
+        let callback_entry = self.insert(fd, Callback::Normal(InlineFnMut::new(callback)));
+        self.register(fd, mode as u32, callback_entry)
+    }
+
+    pub fn add_fd_oneshot<A: AsRawFd, F>(&self, as_fd: &A, mode: Mode, callback: F) -> io::Result<()>
+    where F: FnOnce(ffi::epoll_event) + 'static,
+    {
+        self.add_raw_fd_oneshot(as_fd.as_raw_fd(), mode, callback)
+    }
+
+    pub fn add_raw_fd_oneshot<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
+    where F: FnOnce(ffi::epoll_event) + 'static,
+    {
+        let callback_entry = self.insert(fd, Callback::Oneshot(InlineFnOnce::new(callback)));
+        self.register(fd, mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT, callback_entry)
+    }
+
+    pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
+        self.remove_raw_fd(as_fd.as_raw_fd())
+    }
+
+    /// Stops watching the fd and frees its callback. The callback is freed even on error, since
+    /// the kernel already forgot about an fd that was closed.
+    pub fn remove_raw_fd(&self, fd: RawFd) -> io::Result<()> {
+        let result = self.deregister(fd);
+        let registration = self.registrations.borrow_mut().remove(&fd);
+        if let Some((_, callback_entry)) = registration {
+            self.observe(|observer| observer.fd_deregistered(fd));
+            self.release(callback_entry);
+        }
+        result
+    }
+
+    fn deregister(&self, fd: RawFd) -> io::Result<()> {
+        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Delete, fd, ptr::null_mut()) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn modify_fd<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<()> {
+        self.modify_raw_fd(as_fd.as_raw_fd(), mode)
+    }
+
+    /// Changes the events a registered fd is watched for, e.g. to `Mode::ReadWrite` while data is
+    /// waiting to be written, keeping its callback. A oneshot fd stays oneshot.
+    ///
+    /// Since the kernel cannot modify an fd registered with `EPOLLEXCLUSIVE`, which all the modes
+    /// but the priority ones include, such an fd is deregistered and registered again, as is an fd
+    /// switching from a priority mode to an exclusive one.
+    pub fn modify_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<()> {
+        let (events, callback_entry) =
+            match self.registrations.borrow().get(&fd) {
+                Some(&registration) => registration,
+                None => return Err(Error::new(ErrorKind::NotFound, "fd not registered in the event loop")),
+            };
+        let new_events =
+            if events & ffi::EPOLLONESHOT != 0 {
+                mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT
+            }
+            else {
+                mode as u32
+            };
+        if (events | new_events) & ffi::EPOLLEXCLUSIVE != 0 {
+            self.deregister(fd)?;
+            let result = self.register(fd, new_events, callback_entry);
+            if result.is_err() {
+                self.registrations.borrow_mut().remove(&fd);
+                self.observe(|observer| observer.fd_deregistered(fd));
+            }
+            return result;
+        }
         let mut event = ffi::epoll_event {
-            events: mode as u32,
+            events: new_events,
             data: ffi::epoll_data_t {
                 u64: callback_entry as u64,
             },
         };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
+        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Modify, fd, &mut event) } == -1 {
             return Err(Error::last_os_error());
         }
+        self.registrations.borrow_mut().insert(fd, (new_events, callback_entry));
         Ok(())
     }
 
-    pub fn add_raw_fd_oneshot<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
+    pub fn rearm_fd<A: AsRawFd, F>(&self, as_fd: &A, mode: Mode, callback: F) -> io::Result<()>
+    where F: FnOnce(ffi::epoll_event) + 'static,
+    {
+        self.rearm_raw_fd(as_fd.as_raw_fd(), mode, callback)
+    }
+
+    /// Watches a oneshot fd, registered with `add_raw_fd_oneshot`, for one more event, calling
+    /// `callback` for it. Unlike removing and adding the fd again, it keeps its registration, e.g.
+    /// to accept one connection at a time. Can be called from the previous callback. Replaces the
+    /// callback if the fd is still armed.
+    pub fn rearm_raw_fd<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
     where F: FnOnce(ffi::epoll_event) + 'static,
     {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Oneshot(Box::new(callback)));
+        let (events, callback_entry) =
+            match self.registrations.borrow().get(&fd) {
+                Some(&registration) => registration,
+                None => return Err(Error::new(ErrorKind::NotFound, "fd not registered in the event loop")),
+            };
+        if events & ffi::EPOLLONESHOT == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "fd not registered as oneshot"));
+        }
+        let new_events = mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT;
         let mut event = ffi::epoll_event {
-            events: mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT,
+            events: new_events,
             data: ffi::epoll_data_t {
                 u64: callback_entry as u64,
             },
         };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
+        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Modify, fd, &mut event) } == -1 {
             return Err(Error::last_os_error());
         }
+        self.registrations.borrow_mut().insert(fd, (new_events, callback_entry));
+        let previous = self.callbacks.borrow_mut().get_mut(callback_entry)
+            .map(|slot| mem::replace(&mut slot.callback, Callback::Oneshot(InlineFnOnce::new(callback))));
+        // Dropped after the borrow ends since dropping it could use the event loop.
+        drop(previous);
         Ok(())
     }
 
-    pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
-        self.remove_raw_fd(as_fd.as_raw_fd())
+    fn insert(&self, fd: RawFd, callback: Callback) -> usize {
+        let generation = self.next_generation.get();
+        self.next_generation.set(generation + 1);
+        self.callbacks.borrow_mut().insert(Slot {
+            callback,
+            fd,
+            generation,
+        })
     }
 
-    pub fn remove_raw_fd(&self, fd: RawFd) -> io::Result<()> {
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Delete, fd, ptr::null_mut()) } == -1 {
-            return Err(Error::last_os_error());
+    fn generation(&self, callback_entry: usize) -> u64 {
+        self.callbacks.borrow().get(callback_entry).map_or(u64::MAX, |slot| slot.generation)
+    }
+
+    /// Removes `fd` if it is still registered with the callback of `callback_entry` and
+    /// `generation`.
+    fn cancel(&self, fd: RawFd, callback_entry: usize, generation: u64) -> io::Result<()> {
+        let registered = self.registrations.borrow().get(&fd).map(|&(_, entry)| entry) == Some(callback_entry) &&
+            self.generation(callback_entry) == generation;
+        if !registered {
+            return Err(Error::new(ErrorKind::NotFound, "registration already removed from the event loop"));
         }
-        Ok(())
+        self.remove_raw_fd(fd)
     }
 
-    pub fn try_add_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<Event> {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Empty);
+    /// Adds the fd to epoll, freeing the callback on error.
+    fn register(&self, fd: RawFd, events: u32, callback_entry: usize) -> io::Result<()> {
         let mut event = ffi::epoll_event {
-            events: mode as u32,
+            events,
             data: ffi::epoll_data_t {
                 u64: callback_entry as u64,
             },
         };
         if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
-            return Err(Error::last_os_error());
+            let error = Error::last_os_error();
+            self.release(callback_entry);
+            return Err(error);
+        }
+        let previous = self.registrations.borrow_mut().insert(fd, (events, callback_entry));
+        match previous {
+            // Added again by modify_raw_fd().
+            Some((_, previous_entry)) if previous_entry == callback_entry => (),
+            Some((_, previous_entry)) => {
+                // The fd was closed without being removed and its number was reused.
+                self.release(previous_entry);
+                self.observe(|observer| observer.fd_registered(fd, events));
+            },
+            None => self.observe(|observer| observer.fd_registered(fd, events)),
+        }
+        Ok(())
+    }
+
+    /// Frees the callback entry, or waits until the events are dispatched to do so.
+    fn release(&self, callback_entry: usize) {
+        if self.dispatching.get() > 0 {
+            self.released.borrow_mut().push(callback_entry);
+            return;
         }
-        Ok(Event::new(callback_entry, self))
+        let slot = {
+            let mut callbacks = self.callbacks.borrow_mut();
+            if callbacks.contains(callback_entry) {
+                Some(callbacks.remove(callback_entry))
+            }
+            else {
+                None
+            }
+        };
+        // Drop the callback after the borrow ends since dropping it could use the event loop.
+        drop(slot);
+    }
+
+    fn free_released(&self) {
+        let mut released = mem::take(&mut *self.released.borrow_mut());
+        // An entry can be released twice, e.g. by removing its fd and returning Action::Stop.
+        released.sort_unstable();
+        released.dedup();
+        for &callback_entry in &released {
+            self.release(callback_entry);
+        }
+        // Keep the buffer for the next releases.
+        released.clear();
+        *self.released.borrow_mut() = released;
+    }
+
+    pub fn try_add_fd<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<Event> {
+        self.try_add_raw_fd(as_fd.as_raw_fd(), mode)
+    }
+
+    pub fn try_add_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<Event> {
+        let callback_entry = self.insert(fd, Callback::Empty);
+        self.register(fd, mode as u32, callback_entry)?;
+        Ok(Event::new(callback_entry, self, fd))
+    }
+
+    pub fn try_add_fd_oneshot<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<EventOnce> {
+        self.try_add_raw_fd_oneshot(as_fd.as_raw_fd(), mode)
     }
 
     pub fn try_add_raw_fd_oneshot(&self, fd: RawFd, mode: Mode) -> io::Result<EventOnce> {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Empty);
-        let mut event = ffi::epoll_event {
-            events: mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT,
-            data: ffi::epoll_data_t {
-                u64: callback_entry as u64,
-            },
+        let callback_entry = self.insert(fd, Callback::Empty);
+        self.register(fd, mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT, callback_entry)?;
+        Ok(EventOnce::new(callback_entry, self, fd))
+    }
+
+    /// Calls the callback once after `delay`, unless canceled through the returned handle.
+    pub fn add_timeout<F>(&self, delay: Duration, callback: F) -> io::Result<TimerHandle>
+    where F: FnOnce() + 'static,
+    {
+        let timer = TimerFd::new()?;
+        timer.set(delay)?;
+        let mut callback = Some(callback);
+        self.add_timer(timer, move || {
+            if let Some(callback) = callback.take() {
+                callback();
+            }
+            false
+        })
+    }
+
+    /// Calls the callback every `interval`, until canceled through the returned handle. Missed
+    /// expirations, when the loop is busy, are coalesced into a single call.
+    pub fn add_interval<F>(&self, interval: Duration, mut callback: F) -> io::Result<TimerHandle>
+    where F: FnMut() + 'static,
+    {
+        let timer = TimerFd::new()?;
+        timer.set_interval(interval)?;
+        self.add_timer(timer, move || {
+            callback();
+            true
+        })
+    }
+
+    /// Registers the timer, whose callback returns false once the timer is done.
+    fn add_timer<F>(&self, timer: TimerFd, mut callback: F) -> io::Result<TimerHandle>
+    where F: FnMut() -> bool + 'static,
+    {
+        let fd = timer.as_raw_fd();
+        let handle = TimerHandle {
+            event_loop: self.clone(),
+            timer: Rc::new(RefCell::new(Some(timer))),
         };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
+        // Only the timer is captured: a handle would keep the loop alive through its own callbacks.
+        let state = handle.timer.clone();
+        // Closed with the callback, once the loop stopped watching it.
+        let mut done = None;
+        self.add_raw_fd(fd, Mode::Read, move |_event| {
+            let expired = state.borrow().as_ref().map_or(0, |timer| timer.acknowledge());
+            if expired > 0 && !callback() {
+                done = state.borrow_mut().take();
+            }
+            // Also stopped when canceled through a handle.
+            if done.is_some() || state.borrow().is_none() {
+                Action::Stop
+            }
+            else {
+                Action::Continue
+            }
+        })?;
+        Ok(handle)
+    }
+
+    /// Calls the callback with the events of `mask`, e.g. `inotify::CREATE | inotify::MODIFY`,
+    /// happening to `path` or, for a directory, to its files. Stops once canceled through the
+    /// returned handle or after the watch is removed by the kernel, e.g. when `path` is deleted.
+    pub fn watch_path<P, F>(&self, path: P, mask: u32, mut callback: F) -> io::Result<PathWatch>
+    where P: AsRef<Path>,
+          F: FnMut(FileEvent) + 'static,
+    {
+        let inotify = Inotify::new()?;
+        inotify.add_watch(path, mask)?;
+        let fd = inotify.as_raw_fd();
+        let handle = PathWatch {
+            event_loop: self.clone(),
+            inotify: Rc::new(RefCell::new(Some(inotify))),
+        };
+        // Only the inotify instance is captured, like for the timers.
+        let state = handle.inotify.clone();
+        // Closed with the callback, once the loop stopped watching it.
+        let mut removed = None;
+        let mut events = vec![];
+        self.add_raw_fd(fd, Mode::Read, move |_event| {
+            // Read the events before calling the callback, which can cancel the watch.
+            if let Some(ref inotify) = *state.borrow() {
+                let _ = inotify.read(|event| events.push(event));
+            }
+            let mut watch_removed = false;
+            for event in events.drain(..) {
+                watch_removed |= event.is_watch_removed();
+                if state.borrow().is_some() {
+                    callback(event);
+                }
+            }
+            if watch_removed {
+                removed = state.borrow_mut().take();
+            }
+            // Also stopped when canceled through a handle.
+            if removed.is_some() || state.borrow().is_none() {
+                Action::Stop
+            }
+            else {
+                Action::Continue
+            }
+        })?;
+        Ok(handle)
+    }
+
+    /// Registers a callback called on this thread whenever the returned `Notifier`, which can be
+    /// sent to other threads, is notified.
+    pub fn add_notifier<F>(&self, mut callback: F) -> io::Result<Notifier>
+    where F: FnMut() + 'static,
+    {
+        let fd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK | ffi::EFD_CLOEXEC) };
+        if fd == -1 {
             return Err(Error::last_os_error());
         }
-        Ok(EventOnce::new(callback_entry, self.clone()))
+        let event_fd = Arc::new(NotifierFd(fd));
+        let loop_event_fd = event_fd.clone();
+        self.add_raw_fd(fd, Mode::Read, move |_event| {
+            let mut value = 0u64;
+            if unsafe { ffi::eventfd_read(loop_event_fd.0, &mut value) } == 0 {
+                callback();
+            }
+            Action::Continue
+        })?;
+        Ok(Notifier {
+            event_fd,
+        })
     }
 
-    pub fn iterate(&self, event_list: &mut [ffi::epoll_event]) -> EpollResult {
+    /// Runs `function` on a thread of an internal pool, so that blocking calls like name
+    /// resolution or disk I/O don't stall the loop, then calls `on_complete` with its result on
+    /// the loop thread. If `function` panics, `on_complete` is dropped without being called.
+    ///
+    /// Blocks while the queue of the pool is full, and fails if the pool cannot spawn a thread
+    /// for `function`. Dropping the loop waits for the running functions.
+    pub fn spawn_blocking<F, T, C>(&self, function: F, on_complete: C) -> io::Result<()>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static,
+          C: FnOnce(T) + 'static,
+    {
+        let (id, completions, notifier, pool, results) = {
+            let mut blocking = self.blocking.borrow_mut();
+            if blocking.is_none() {
+                *blocking = Some(self.start_blocking()?);
+            }
+            let blocking = blocking.as_mut().expect("blocking pool");
+            let id = blocking.next_id;
+            blocking.next_id += 1;
+            let completions = blocking.completions.clone();
+            (id, completions, blocking.notifier.clone(), blocking.pool.clone(), blocking.results.clone())
+        };
+        completions.borrow_mut().insert(id, Box::new(move |result: Box<dyn Any + Send>| {
+            if let Ok(result) = result.downcast::<T>() {
+                on_complete(*result);
+            }
+        }));
+        // Not borrowed anymore: `execute` blocks while the queue is full.
+        let queued = pool.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(function)).ok()
+                .map(|result| Box::new(result) as Box<dyn Any + Send>);
+            results.lock().unwrap_or_else(|error| error.into_inner()).push((id, result));
+            notifier.notify();
+        });
+        if queued.is_err() {
+            completions.borrow_mut().remove(&id);
+        }
+        queued
+    }
+
+    fn start_blocking(&self) -> io::Result<Blocking> {
+        let pool = threadpool::Builder::new()
+            .min_threads(0)
+            .max_threads(BLOCKING_THREADS)
+            .name("mini-blocking")
+            .build()?;
+        let completions = Rc::new(RefCell::new(HashMap::<u64, Completion>::new()));
+        let results = Arc::new(Mutex::new(vec![]));
+        let loop_completions = completions.clone();
+        let loop_results = results.clone();
+        let notifier = self.add_notifier(move || {
+            let finished: Vec<BlockingResult> = mem::take(&mut *loop_results.lock().unwrap_or_else(|error| error.into_inner()));
+            for (id, result) in finished {
+                // Removed before the call since the completion can spawn other functions.
+                let completion = loop_completions.borrow_mut().remove(&id);
+                if let (Some(completion), Some(result)) = (completion, result) {
+                    completion(result);
+                }
+            }
+        })?;
+        Ok(Blocking {
+            completions,
+            next_id: 0,
+            notifier,
+            pool: Rc::new(pool),
+            results,
+        })
+    }
+
+    pub fn iterate(&self) -> EpollResult {
+        self.iterate_timeout(None)
+    }
+
+    /// Waits for events like `iterate()`, but for `timeout` at most, rounded up to the millisecond,
+    /// and returns `EpollResult::Ok` without calling any callback if none happened. A zero timeout
+    /// only polls.
+    pub fn iterate_timeout(&self, timeout: Option<Duration>) -> EpollResult {
+        self.iterate_with(timeout, None)
+    }
+
+    /// Waits for events like `iterate_timeout()`, with the signal mask of the thread replaced by
+    /// `mask` during the wait (`epoll_pwait`). This lets a thread blocking signals receive them only
+    /// while waiting, without the race of unblocking them just before waiting.
+    pub fn iterate_with_signal_mask(&self, timeout: Option<Duration>, mask: &SignalSet) -> EpollResult {
+        self.iterate_with(timeout, Some(mask))
+    }
+
+    /// Returns the loop being iterated on this thread, so that callbacks can register fds and
+    /// timers without keeping a clone of the loop. Returns `None` outside of the callbacks.
+    pub fn current() -> Option<EventLoop> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Waits for events once, like `iterate()`, and returns the number of callbacks called, for
+    /// embedding the loop in the main loop of a GUI or a game which does its own work between the
+    /// calls. Errors are handled by the error policy.
+    pub fn run_once(&self) -> io::Result<usize> {
+        self.run_once_timeout(None)
+    }
+
+    /// Like `run_once()`, but waits for `timeout` at most: a zero timeout only polls.
+    pub fn run_once_timeout(&self, timeout: Option<Duration>) -> io::Result<usize> {
+        let (result, dispatched) = self.iterate_counting(timeout, None);
+        if let EpollResult::Error(error) = result {
+            self.report_error(LoopError::Epoll(error))?;
+        }
+        Ok(dispatched)
+    }
+
+    fn iterate_with(&self, timeout: Option<Duration>, mask: Option<&SignalSet>) -> EpollResult {
+        self.iterate_counting(timeout, mask).0
+    }
+
+    /// Iterates and returns the number of callbacks called, not counting those called by nested
+    /// iterations.
+    fn iterate_counting(&self, timeout: Option<Duration>, mask: Option<&SignalSet>) -> (EpollResult, usize) {
+        if self.dispatching.get() == 0 {
+            // Run the callbacks and apply the changes queued outside of the callbacks before waiting.
+            self.run_next_tick();
+            if let Err(error) = self.apply_deferred() {
+                return (EpollResult::Error(error), 0);
+            }
+        }
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let mut event_list = mem::take(&mut *self.event_list.borrow_mut());
+        if event_list.is_empty() {
+            // The buffer is in use by a callback calling this method.
+            event_list = self::event_list(self.capacity);
+        }
+        self.dispatching.set(self.dispatching.get() + 1);
+        let mut dispatched = 0;
+        let mut result = self.wait_and_dispatch(&mut event_list, timeout, mask, &mut dispatched);
+        self.dispatching.set(self.dispatching.get() - 1);
+        *self.event_list.borrow_mut() = event_list;
+        if self.dispatching.get() == 0 {
+            self.run_next_tick();
+            if let Err(error) = self.apply_deferred() {
+                if let EpollResult::Ok = result {
+                    result = EpollResult::Error(error);
+                }
+            }
+            self.free_released();
+        }
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        (result, dispatched)
+    }
+
+    fn wait_and_dispatch(&self, event_list: &mut [ffi::epoll_event], timeout: Option<Duration>, mask: Option<&SignalSet>,
+        dispatched: &mut usize) -> EpollResult
+    {
         let epoll_fd = self.fd;
+        self.observe(|observer| observer.wait_entered(timeout));
+        let timeout = timeout.map_or(-1, |timeout| {
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            millis.min(i32::MAX as u128) as i32
+        });
 
-        let ready = unsafe { ffi::epoll_wait(epoll_fd, event_list.as_mut_ptr(), event_list.len() as i32, -1) };
-        if ready == -1 {
-            let last_error = Error::last_os_error();
+        let ready =
+            match mask {
+                Some(mask) => unsafe {
+                    ffi::epoll_pwait(epoll_fd, event_list.as_mut_ptr(), event_list.len() as i32, timeout, mask.as_ptr())
+                },
+                None => unsafe { ffi::epoll_wait(epoll_fd, event_list.as_mut_ptr(), event_list.len() as i32, timeout) },
+            };
+        // Before the observer can change errno.
+        let last_error = if ready == -1 { Some(Error::last_os_error()) } else { None };
+        self.observe(|observer| observer.wait_exited(ready.max(0) as usize));
+        self.update_metrics(|metrics| {
+            metrics.iterations += 1;
+            if ready > 0 {
+                metrics.wakeups += 1;
+                metrics.max_events_per_wakeup = metrics.max_events_per_wakeup.max(ready as usize);
+            }
+        });
+        if let Some(last_error) = last_error {
             if last_error.kind() == ErrorKind::Interrupted {
                 return EpollResult::Interrupted;
             }
@@ -235,13 +991,30 @@ impl EventLoop {
                 }
             }
             let entry = unsafe { event.data.u64 as usize };
+            if self.released.borrow().contains(&entry) {
+                // The fd was removed by a callback called for a previous event.
+                continue;
+            }
             // NOTE: Remove the callback because callbacks can be added in the update() method.
-            let callback = std::mem::replace(&mut self.callbacks.borrow_mut()[entry], Callback::Empty);
+            let (callback, fd) = {
+                let slot = &mut self.callbacks.borrow_mut()[entry];
+                (std::mem::replace(&mut slot.callback, Callback::Empty), slot.fd)
+            };
+            let start = Instant::now();
             let callback =
                 match callback {
-                    Callback::Empty => panic!("callback should not be empty"),
+                    Callback::Empty => {
+                        if let Err(error) = self.report_error(LoopError::MissingCallback(fd)) {
+                            panic!("{}", error);
+                        }
+                        // Nothing handles the events of this fd.
+                        self.stop_callback(fd, entry);
+                        None
+                    },
                     Callback::Normal(mut callback) => {
-                        if callback(event) == Action::Stop {
+                        *dispatched += 1;
+                        if callback.call(event) == Action::Stop {
+                            self.stop_callback(fd, entry);
                             None
                         }
                         else {
@@ -249,36 +1022,242 @@ impl EventLoop {
                         }
                     },
                     Callback::Oneshot(callback) => {
-                        let callback: Box<_> = callback;
-                        callback.call_box(event);
+                        *dispatched += 1;
+                        callback.call(event);
                         None
                     },
                 };
+            let elapsed = start.elapsed();
+            self.update_metrics(|metrics| {
+                metrics.events += 1;
+                metrics.callback_time += elapsed;
+            });
+            self.observe(|observer| observer.event_dispatched(fd, event.events, elapsed));
             if let Some(callback) = callback {
-                self.callbacks.borrow_mut()[entry] = callback;
+                self.callbacks.borrow_mut()[entry].callback = callback;
             }
         }
 
         EpollResult::Ok
     }
 
+    /// Queues `callback` to run once the events of the current iteration are dispatched, before
+    /// waiting for the next ones, e.g. to close a connection from its own read callback. Callbacks
+    /// queued by these callbacks run before the following wait.
+    pub fn defer<F: FnOnce() + 'static>(&self, callback: F) {
+        self.next_tick.borrow().alloc(InlineFnOnce::new(move |()| callback()));
+    }
+
+    fn run_next_tick(&self) {
+        let spare = mem::take(&mut *self.spare_tick.borrow_mut());
+        let mut callbacks = mem::replace(&mut *self.next_tick.borrow_mut(), spare);
+        for callback in callbacks.drain() {
+            callback.call(());
+        }
+        *self.spare_tick.borrow_mut() = callbacks;
+    }
+
+    /// Queues the registration of the fd, applied with the other queued changes after the events
+    /// of the current iteration are dispatched, or before waiting for the next ones if called
+    /// outside of a callback. Errors are reported as `LoopError::Deferred` to the error policy.
+    pub fn defer_add_fd<A: AsRawFd, F>(&self, as_fd: &A, mode: Mode, callback: F)
+    where F: FnMut(ffi::epoll_event) -> Action + 'static,
+    {
+        self.defer_add_raw_fd(as_fd.as_raw_fd(), mode, callback)
+    }
+
+    pub fn defer_add_raw_fd<F>(&self, fd: RawFd, mode: Mode, callback: F)
+    where F: FnMut(ffi::epoll_event) -> Action + 'static,
+    {
+        let callback_entry = self.insert(fd, Callback::Normal(InlineFnMut::new(callback)));
+        self.deferred.borrow_mut().push(Deferred::Add(fd, mode as u32, callback_entry));
+    }
+
+    /// Queues a change of the events the fd is watched for, see `defer_add_fd`. Replaces the
+    /// previous change queued for the fd if it was also a modification.
+    pub fn defer_modify_fd<A: AsRawFd>(&self, as_fd: &A, mode: Mode) {
+        self.defer_modify_raw_fd(as_fd.as_raw_fd(), mode)
+    }
+
+    pub fn defer_modify_raw_fd(&self, fd: RawFd, mode: Mode) {
+        let mut deferred = self.deferred.borrow_mut();
+        if let Some(&mut Deferred::Modify(_, ref mut queued_mode)) =
+            deferred.iter_mut().rev().find(|operation| operation.fd() == fd)
+        {
+            *queued_mode = mode;
+            return;
+        }
+        deferred.push(Deferred::Modify(fd, mode));
+    }
+
+    /// Queues the removal of the fd, see `defer_add_fd`. Cancels the registration of the fd
+    /// instead if it is still queued.
+    pub fn defer_remove_fd<A: AsRawFd>(&self, as_fd: &A) {
+        self.defer_remove_raw_fd(as_fd.as_raw_fd())
+    }
+
+    pub fn defer_remove_raw_fd(&self, fd: RawFd) {
+        let queued_add = {
+            let mut deferred = self.deferred.borrow_mut();
+            match deferred.iter().rposition(|operation| operation.fd() == fd) {
+                Some(index) if matches!(deferred[index], Deferred::Add(..)) => Some(deferred.remove(index)),
+                _ => {
+                    deferred.push(Deferred::Remove(fd));
+                    None
+                },
+            }
+        };
+        if let Some(Deferred::Add(_, _, callback_entry)) = queued_add {
+            self.release(callback_entry);
+        }
+    }
+
+    /// Applies the queued registration changes, returning the first error to propagate.
+    fn apply_deferred(&self) -> io::Result<()> {
+        let mut deferred = mem::take(&mut *self.deferred.borrow_mut());
+        let mut result = Ok(());
+        for operation in deferred.drain(..) {
+            let fd = operation.fd();
+            let operation_result =
+                match operation {
+                    Deferred::Add(fd, events, callback_entry) => self.register(fd, events, callback_entry),
+                    Deferred::Modify(fd, mode) => self.modify_raw_fd(fd, mode),
+                    Deferred::Remove(fd) => self.remove_raw_fd(fd),
+                };
+            if let Err(error) = operation_result {
+                let reported = self.report_error(LoopError::Deferred(fd, error));
+                if result.is_ok() {
+                    result = reported;
+                }
+            }
+        }
+        // Keep the buffer unless changes were queued while applying these ones.
+        let mut queued = self.deferred.borrow_mut();
+        if queued.is_empty() {
+            *queued = deferred;
+        }
+        result
+    }
+
+    /// Sets what to do on errors. With a policy other than `ErrorPolicy::Propagate`, the fd of an
+    /// event without callback is removed and the run methods continue after an epoll error.
+    pub fn set_error_policy(&self, policy: ErrorPolicy) {
+        *self.error_policy.borrow_mut() = policy;
+    }
+
+    /// Applies the error policy to `error`, returning it if it should be propagated.
+    pub fn report_error(&self, error: LoopError) -> io::Result<()> {
+        let policy = self.error_policy.borrow().clone();
+        match policy {
+            ErrorPolicy::Propagate => return Err(error.into()),
+            ErrorPolicy::Log => eprintln!("Event loop error: {}.", error),
+            ErrorPolicy::Hook(hook) => hook(&error),
+        }
+        Ok(())
+    }
+
+    /// Installs the instrumentation hooks, replacing the previous ones, or removes them with
+    /// `None`.
+    pub fn set_observer(&self, observer: Option<Rc<dyn EventLoopObserver>>) {
+        *self.observer.borrow_mut() = observer;
+    }
+
+    fn observe<F: FnOnce(&dyn EventLoopObserver)>(&self, notify: F) {
+        // Cloned so that the observer can replace itself.
+        let observer = self.observer.borrow().clone();
+        if let Some(observer) = observer {
+            notify(&*observer);
+        }
+    }
+
+    /// Returns the counters of the loop, e.g. to export them periodically.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            registered_fds: self.registrations.borrow().len(),
+            callbacks: self.callbacks.borrow().len(),
+            ..self.metrics.get()
+        }
+    }
+
+    /// Resets the cumulative counters of the metrics to zero.
+    pub fn reset_metrics(&self) {
+        self.metrics.set(Metrics::default());
+    }
+
+    fn update_metrics<F: FnOnce(&mut Metrics)>(&self, update: F) {
+        let mut metrics = self.metrics.get();
+        update(&mut metrics);
+        self.metrics.set(metrics);
+    }
+
+    /// Removes the fd of a callback that returned `Action::Stop`, unless it was already removed.
+    fn stop_callback(&self, fd: RawFd, callback_entry: usize) {
+        let registered = self.registrations.borrow().get(&fd).map(|&(_, entry)| entry) == Some(callback_entry);
+        if registered {
+            let _ = self.remove_raw_fd(fd);
+        }
+        else {
+            self.release(callback_entry);
+        }
+    }
+
+    /// Runs the loop until `stop()` is called.
     pub fn run(&self) -> io::Result<()> {
-        let mut event_list = event_list();
+        self.run_until(|| false)
+    }
 
-        while !self.stopped {
-            match self.iterate(&mut event_list) {
+    /// Runs the loop until `stop()` is called or `predicate` returns true, which is checked before
+    /// waiting for events, so after each batch of callbacks.
+    pub fn run_until<F>(&self, mut predicate: F) -> io::Result<()>
+    where F: FnMut() -> bool,
+    {
+        while !self.stopped.get() && !predicate() {
+            match self.iterate() {
                 // Restart if interrupted by signal.
                 EpollResult::Interrupted => continue,
-                EpollResult::Error(error) => return Err(error),
+                EpollResult::Error(error) => self.report_error(LoopError::Epoll(error))?,
                 EpollResult::Ok => (),
             }
         }
+        // The loop can be run again.
+        self.stopped.set(false);
 
         Ok(())
     }
 
-    pub fn stop(&mut self) {
-        self.stopped = true;
+    /// Runs the loop until `stop()` is called, calling `idle` after each batch of callbacks and,
+    /// if `timeout` is given, whenever no event happened for `timeout`, e.g. to expire caches or
+    /// flush logs.
+    pub fn run_with_idle<F>(&self, timeout: Option<Duration>, mut idle: F) -> io::Result<()>
+    where F: FnMut(),
+    {
+        while !self.stopped.get() {
+            match self.iterate_timeout(timeout) {
+                // Restart if interrupted by signal.
+                EpollResult::Interrupted => continue,
+                EpollResult::Error(error) => self.report_error(LoopError::Epoll(error))?,
+                EpollResult::Ok => idle(),
+            }
+        }
+        // The loop can be run again.
+        self.stopped.set(false);
+
+        Ok(())
+    }
+
+    /// Runs the loop until `stop()` is called or for `duration` at most.
+    pub fn run_for(&self, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + duration;
+        // Wakes up the loop at the deadline.
+        let timer = self.add_timeout(duration, || ())?;
+        let result = self.run_until(|| Instant::now() >= deadline);
+        timer.cancel();
+        result
+    }
+
+    /// Makes `run()` return after the current callback, from a callback or a `Notifier` callback.
+    pub fn stop(&self) {
+        self.stopped.set(true);
         EventLoop::wakeup();
     }
 
@@ -292,14 +1271,14 @@ impl EventLoop {
     }
 }
 
-pub fn event_list() -> [ffi::epoll_event; MAX_EVENTS] {
-    [
+fn event_list(capacity: usize) -> Vec<ffi::epoll_event> {
+    vec![
         ffi::epoll_event {
             events: 0,
             data: ffi::epoll_data_t {
                 u32: 0,
             }
-        }; MAX_EVENTS
+        }; capacity
     ]
 }
 
@@ -314,10 +1293,12 @@ pub mod ffi {
     }
 
     pub const EPOLLIN: u32 = 0x001;
+    pub const EPOLLPRI: u32 = 0x002;
     pub const EPOLLOUT: u32 = 0x004;
     pub const EPOLLERR: u32 = 0x008;
     pub const EPOLLONESHOT: u32 = 1 << 30;
     pub const EPOLLHUP: u32 = 0x010;
+    pub const EFD_CLOEXEC: i32 = 0o2000000;
     pub const EFD_NONBLOCK: i32 = 0o4000;
     pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
 
@@ -344,9 +1325,677 @@ pub mod ffi {
         pub fn epoll_create1(flags: i32) -> i32;
         pub fn epoll_ctl(epfd: i32, op: EpollOperation, fd: i32, event: *mut epoll_event) -> i32;
         pub fn epoll_wait(epdf: i32, events: *mut epoll_event, maxevents: i32, timeout: i32) -> i32;
+        pub fn epoll_pwait(epdf: i32, events: *mut epoll_event, maxevents: i32, timeout: i32, sigmask: *const c_void)
+            -> i32;
 
         pub fn eventfd(initval: u32, flags: i32) -> i32;
         pub fn eventfd_read(fd: i32, value: *mut eventfd_t) -> i32;
         pub fn eventfd_write(fd: i32, value: eventfd_t) -> i32;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::env::temp_dir;
+    use std::fs;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use aio::inotify::{self, FileEventKind};
+    use aio::net;
+    use signal::SignalSet;
+    use super::{Action, EpollResult, ErrorPolicy, EventLoop, EventLoopObserver, LoopError, Metrics, Mode, ffi};
+
+    fn iterate(event_loop: &EventLoop) {
+        match event_loop.iterate() {
+            EpollResult::Ok | EpollResult::Interrupted => (),
+            EpollResult::Error(error) => panic!("iterate: {}", error),
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let fired = Rc::new(Cell::new(false));
+        let start = Instant::now();
+        let flag = fired.clone();
+        let handle = event_loop.add_timeout(Duration::from_millis(20), move || flag.set(true)).expect("timeout");
+        let canceled = event_loop.add_timeout(Duration::from_millis(1), || panic!("canceled timeout fired"))
+            .expect("timeout");
+        canceled.cancel();
+        assert!(!canceled.is_active());
+        assert!(handle.is_active());
+        while !fired.get() {
+            iterate(&event_loop);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(!handle.is_active());
+    }
+
+    #[test]
+    fn interval() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let handle = event_loop.add_interval(Duration::from_millis(5), move || counter.set(counter.get() + 1))
+            .expect("interval");
+        while count.get() < 3 {
+            iterate(&event_loop);
+        }
+        handle.cancel();
+        assert!(!handle.is_active());
+
+        // The loop no longer waits on the canceled interval.
+        let fired = Rc::new(Cell::new(false));
+        let flag = fired.clone();
+        event_loop.add_timeout(Duration::from_millis(20), move || flag.set(true)).expect("timeout");
+        while !fired.get() {
+            iterate(&event_loop);
+        }
+        assert_eq!(count.get(), 3);
+    }
+
+    #[test]
+    fn dropped_interval() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let handle = event_loop.add_interval(Duration::from_millis(5), || ()).expect("interval");
+        let callbacks = Rc::downgrade(&event_loop.callbacks);
+        // Never canceled: the callback must not keep the loop alive.
+        drop(handle);
+        drop(event_loop);
+        assert!(callbacks.upgrade().is_none());
+    }
+
+    #[test]
+    fn notifier() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let notifier = event_loop.add_notifier(move || counter.set(counter.get() + 1)).expect("notifier");
+        let thread_notifier = notifier.clone();
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            thread_notifier.notify();
+        });
+        while count.get() == 0 {
+            iterate(&event_loop);
+        }
+        thread.join().expect("join");
+        assert_eq!(count.get(), 1);
+
+        notifier.notify();
+        notifier.notify();
+        drop(notifier);
+        iterate(&event_loop);
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn priority() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address")).expect("connect");
+        let (server, _) = listener.accept().expect("accept");
+        let urgent = Rc::new(Cell::new(None));
+        let urgent_byte = urgent.clone();
+        let fd = server.as_raw_fd();
+        event_loop.add_fd(&server, Mode::ReadPriority, move |event| {
+            if event.events & ffi::EPOLLPRI != 0 {
+                let mut byte = 0u8;
+                if unsafe { net::ffi::recv(fd, &mut byte as *mut u8 as *mut _, 1, net::ffi::MSG_OOB) } == 1 {
+                    urgent_byte.set(Some(byte));
+                }
+            }
+            Action::Continue
+        }).expect("add");
+
+        // Going through an exclusive mode, which cannot be set with EPOLL_CTL_MOD.
+        event_loop.modify_fd(&server, Mode::Read).expect("modify");
+        event_loop.modify_fd(&server, Mode::ReadPriority).expect("modify");
+
+        client.write_all(b"data").expect("write");
+        let byte = b'!';
+        let sent = unsafe { net::ffi::send(client.as_raw_fd(), &byte as *const u8 as *const _, 1, net::ffi::MSG_OOB) };
+        assert_eq!(sent, 1);
+        while urgent.get().is_none() {
+            iterate(&event_loop);
+        }
+        assert_eq!(urgent.get(), Some(b'!'));
+        event_loop.remove_fd(&server).expect("remove");
+    }
+
+    #[test]
+    fn modify() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let notifier = event_loop.add_notifier(|| ()).expect("notifier");
+        let (reader, writer) = UnixStream::pair().expect("socket pair");
+        let writable = Rc::new(Cell::new(0));
+        let counter = writable.clone();
+        let event = event_loop.try_add_fd(&writer, Mode::Read).expect("add");
+        event.set_callback(move |event| {
+            if event.events & ffi::EPOLLOUT != 0 {
+                counter.set(counter.get() + 1);
+            }
+            Action::Continue
+        });
+        notifier.notify();
+        iterate(&event_loop);
+        assert_eq!(writable.get(), 0);
+
+        event.modify(Mode::ReadWrite).expect("modify");
+        iterate(&event_loop);
+        assert_eq!(writable.get(), 1);
+
+        event_loop.modify_fd(&writer, Mode::Read).expect("modify");
+        notifier.notify();
+        iterate(&event_loop);
+        assert_eq!(writable.get(), 1);
+
+        event_loop.remove_fd(&writer).expect("remove");
+        assert_eq!(event_loop.modify_fd(&writer, Mode::Read).err().map(|error| error.kind()), Some(ErrorKind::NotFound));
+        drop(reader);
+    }
+
+    #[test]
+    fn run_until_and_stop() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let handle = event_loop.add_interval(Duration::from_millis(1), move || counter.set(counter.get() + 1))
+            .expect("interval");
+        event_loop.run_until(|| count.get() >= 3).expect("run");
+        assert_eq!(count.get(), 3);
+
+        let loop_handle = event_loop.clone();
+        let notifier = event_loop.add_notifier(move || loop_handle.stop()).expect("notifier");
+        notifier.notify();
+        event_loop.run().expect("run");
+        handle.cancel();
+
+        let start = Instant::now();
+        event_loop.run_for(Duration::from_millis(20)).expect("run");
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn iterate_timeout() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let start = Instant::now();
+        match event_loop.iterate_timeout(Some(Duration::from_millis(10))) {
+            EpollResult::Ok => (),
+            _ => panic!("iterate_timeout should time out"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        match event_loop.iterate_timeout(Some(Duration::from_secs(0))) {
+            EpollResult::Ok => (),
+            _ => panic!("iterate_timeout should poll"),
+        }
+
+        let fired = Rc::new(Cell::new(false));
+        let flag = fired.clone();
+        event_loop.add_timeout(Duration::from_millis(1), move || flag.set(true)).expect("timeout");
+        while !fired.get() {
+            if let EpollResult::Error(error) = event_loop.iterate_timeout(Some(Duration::from_secs(5))) {
+                panic!("iterate: {}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn capacity() {
+        let event_loop = EventLoop::new_with_capacity(1).expect("event loop");
+        let count = Rc::new(Cell::new(0));
+        let mut notifiers = vec![];
+        for _ in 0..3 {
+            let counter = count.clone();
+            let notifier = event_loop.add_notifier(move || counter.set(counter.get() + 1)).expect("notifier");
+            notifier.notify();
+            notifiers.push(notifier);
+        }
+        // One event per wakeup.
+        for expected in 1..4 {
+            event_loop.iterate();
+            assert_eq!(count.get(), expected);
+        }
+    }
+
+    #[test]
+    fn cancel_event() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (reader, _writer) = UnixStream::pair().expect("socket pair");
+        let captured = Rc::new(());
+
+        let value = captured.clone();
+        let event = event_loop.try_add_fd(&reader, Mode::Read).expect("add");
+        event.set_callback(move |_event| { let _ = &value; Action::Continue });
+        event.cancel().expect("cancel");
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert_eq!(event_loop.callbacks.borrow().len(), 0);
+        assert!(event_loop.registrations.borrow().is_empty());
+        assert_eq!(event.cancel().map_err(|error| error.kind()), Err(ErrorKind::NotFound));
+
+        // A stale handle does not cancel a newer registration reusing the same fd and entry.
+        event_loop.add_fd(&reader, Mode::Read, |_event| Action::Continue).expect("add");
+        assert_eq!(event.cancel().map_err(|error| error.kind()), Err(ErrorKind::NotFound));
+        assert_eq!(event_loop.registrations.borrow().len(), 1);
+        event_loop.remove_fd(&reader).expect("remove");
+
+        let event = event_loop.try_add_fd_oneshot(&reader, Mode::Read).expect("add");
+        event.cancel().expect("cancel");
+        assert_eq!(event_loop.callbacks.borrow().len(), 0);
+        assert!(event_loop.registrations.borrow().is_empty());
+    }
+
+    #[test]
+    fn free_callbacks() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (reader, writer) = UnixStream::pair().expect("socket pair");
+        let captured = Rc::new(());
+
+        // Registration failure.
+        let value = captured.clone();
+        assert!(event_loop.add_raw_fd(-1, Mode::Read, move |_event| { let _ = &value; Action::Continue }).is_err());
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert_eq!(event_loop.callbacks.borrow().len(), 0);
+
+        // Deregistration.
+        let value = captured.clone();
+        event_loop.add_fd(&reader, Mode::Read, move |_event| { let _ = &value; Action::Continue }).expect("add");
+        assert_eq!(Rc::strong_count(&captured), 2);
+        event_loop.remove_fd(&reader).expect("remove");
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert_eq!(event_loop.callbacks.borrow().len(), 0);
+
+        // Action::Stop, which also removes the fd.
+        let value = captured.clone();
+        event_loop.add_fd(&writer, Mode::Write, move |_event| { let _ = &value; Action::Stop }).expect("add");
+        iterate(&event_loop);
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert_eq!(event_loop.callbacks.borrow().len(), 0);
+        assert!(event_loop.registrations.borrow().is_empty());
+
+        // Expired timeout.
+        event_loop.add_timeout(Duration::from_millis(1), || ()).expect("timeout");
+        while !event_loop.callbacks.borrow().is_empty() {
+            iterate(&event_loop);
+        }
+    }
+
+    #[test]
+    fn remove_pending() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (first, second) = UnixStream::pair().expect("socket pair");
+        let count = Rc::new(Cell::new(0));
+        // Both fds are writable: the first callback called removes the other fd, whose pending event
+        // is then ignored.
+        for &(fd, other) in &[(first.as_raw_fd(), second.as_raw_fd()), (second.as_raw_fd(), first.as_raw_fd())] {
+            let loop_handle = event_loop.clone();
+            let counter = count.clone();
+            event_loop.add_raw_fd(fd, Mode::Write, move |_event| {
+                counter.set(counter.get() + 1);
+                let _ = loop_handle.remove_raw_fd(other);
+                Action::Continue
+            }).expect("add");
+        }
+        iterate(&event_loop);
+        assert_eq!(count.get(), 1);
+        assert_eq!(event_loop.callbacks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn watch_path() {
+        let directory = temp_dir().join(format!("mini-aio-watch-{}", process::id()));
+        fs::create_dir_all(&directory).expect("create directory");
+        let event_loop = EventLoop::new().expect("event loop");
+        let events = Rc::new(RefCell::new(vec![]));
+        let received = events.clone();
+        let handle = event_loop.watch_path(&directory, inotify::CREATE | inotify::CLOSE_WRITE | inotify::MOVE |
+            inotify::DELETE | inotify::DELETE_SELF, move |event| received.borrow_mut().push(event))
+            .expect("watch");
+
+        fs::write(directory.join("config"), "value").expect("write");
+        fs::rename(directory.join("config"), directory.join("config.old")).expect("rename");
+        fs::remove_file(directory.join("config.old")).expect("remove");
+        while events.borrow().len() < 5 {
+            iterate(&event_loop);
+        }
+        let kinds: Vec<_> = events.borrow().iter()
+            .map(|event| (event.kind, event.name.clone().expect("name").to_string_lossy().into_owned()))
+            .collect();
+        assert_eq!(kinds, vec![
+            (FileEventKind::Created, "config".to_string()),
+            (FileEventKind::Modified, "config".to_string()),
+            (FileEventKind::MovedFrom, "config".to_string()),
+            (FileEventKind::MovedTo, "config.old".to_string()),
+            (FileEventKind::Deleted, "config.old".to_string()),
+        ]);
+        assert_eq!(events.borrow()[2].cookie, events.borrow()[3].cookie);
+
+        // Deleting the watched directory removes the watch.
+        fs::remove_dir(&directory).expect("remove directory");
+        while handle.is_active() {
+            iterate(&event_loop);
+        }
+        assert!(events.borrow().iter().any(|event| event.kind == FileEventKind::Deleted && event.name.is_none()));
+        assert!(event_loop.callbacks.borrow().is_empty());
+    }
+
+    #[test]
+    fn dropped_watch() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let handle = event_loop.watch_path(temp_dir(), inotify::CREATE, |_event| ()).expect("watch");
+        let callbacks = Rc::downgrade(&event_loop.callbacks);
+        // Never canceled: the callback must not keep the loop alive.
+        drop(handle);
+        drop(event_loop);
+        assert!(callbacks.upgrade().is_none());
+    }
+
+    #[test]
+    fn metrics() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let notifier = event_loop.add_notifier(|| thread::sleep(Duration::from_millis(2))).expect("notifier");
+        let timer = event_loop.add_interval(Duration::from_secs(60), || ()).expect("interval");
+        let metrics = event_loop.metrics();
+        assert_eq!(metrics.registered_fds, 2);
+        assert_eq!(metrics.callbacks, 2);
+        assert_eq!(metrics.events, 0);
+
+        notifier.notify();
+        iterate(&event_loop);
+        event_loop.iterate_timeout(Some(Duration::from_secs(0)));
+        let metrics = event_loop.metrics();
+        assert_eq!(metrics.iterations, 2);
+        assert_eq!(metrics.wakeups, 1);
+        assert_eq!(metrics.events, 1);
+        assert_eq!(metrics.max_events_per_wakeup, 1);
+        assert!(metrics.callback_time >= Duration::from_millis(2));
+
+        timer.cancel();
+        event_loop.reset_metrics();
+        assert_eq!(event_loop.metrics(), Metrics {
+            registered_fds: 1,
+            callbacks: 1,
+            ..Metrics::default()
+        });
+    }
+
+    #[test]
+    fn run_with_idle() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        let notifier = event_loop.add_notifier(move || counter.set(counter.get() + 1)).expect("notifier");
+        notifier.notify();
+        let mut idle_calls = vec![];
+        let loop_handle = event_loop.clone();
+        event_loop.run_with_idle(Some(Duration::from_millis(1)), || {
+            idle_calls.push(count.get());
+            if idle_calls.len() == 3 {
+                loop_handle.stop();
+            }
+        }).expect("run");
+        // Called after the notifier callback, then on timeouts.
+        assert_eq!(idle_calls, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn run_once() {
+        let event_loop = EventLoop::new().expect("event loop");
+        assert_eq!(event_loop.run_once_timeout(Some(Duration::from_millis(0))).expect("run once"), 0);
+
+        let (_reader, writer) = UnixStream::pair().expect("socket pair");
+        let (_reader2, writer2) = UnixStream::pair().expect("socket pair");
+        event_loop.add_fd(&writer, Mode::Write, |_event| Action::Continue).expect("add");
+        event_loop.add_fd(&writer2, Mode::Write, |_event| Action::Stop).expect("add");
+        assert_eq!(event_loop.run_once().expect("run once"), 2);
+        assert_eq!(event_loop.run_once().expect("run once"), 1);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl EventLoopObserver for Recorder {
+        fn fd_registered(&self, fd: RawFd, _events: u32) {
+            self.calls.borrow_mut().push(format!("registered {}", fd));
+        }
+
+        fn fd_deregistered(&self, fd: RawFd) {
+            self.calls.borrow_mut().push(format!("deregistered {}", fd));
+        }
+
+        fn event_dispatched(&self, fd: RawFd, events: u32, _duration: Duration) {
+            self.calls.borrow_mut().push(format!("dispatched {} {}", fd, events & ffi::EPOLLOUT != 0));
+        }
+
+        fn wait_entered(&self, timeout: Option<Duration>) {
+            self.calls.borrow_mut().push(format!("wait {:?}", timeout));
+        }
+
+        fn wait_exited(&self, events: usize) {
+            self.calls.borrow_mut().push(format!("woke {}", events));
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let recorder = Rc::new(Recorder::default());
+        event_loop.set_observer(Some(recorder.clone()));
+        let (_reader, writer) = UnixStream::pair().expect("socket pair");
+        let fd = writer.as_raw_fd();
+        event_loop.add_fd(&writer, Mode::Write, |_event| Action::Stop).expect("add");
+        // Modifying does not count as a new registration.
+        event_loop.modify_fd(&writer, Mode::Write).expect("modify");
+        iterate(&event_loop);
+        event_loop.set_observer(None);
+        assert_eq!(*recorder.calls.borrow(), vec![
+            format!("registered {}", fd),
+            "wait None".to_string(),
+            "woke 1".to_string(),
+            format!("deregistered {}", fd),
+            format!("dispatched {} true", fd),
+        ]);
+    }
+
+    #[test]
+    fn spawn_blocking() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let thread_name = Rc::new(RefCell::new(None));
+        let result = thread_name.clone();
+        event_loop.spawn_blocking(|| {
+            thread::sleep(Duration::from_millis(10));
+            thread::current().name().map(str::to_string)
+        }, move |name| *result.borrow_mut() = Some(name)).expect("spawn");
+        let captured = Rc::new(());
+        let value = captured.clone();
+        event_loop.spawn_blocking(|| panic!("blocking panic"), move |()| { let _ = &value; }).expect("spawn");
+        while thread_name.borrow().is_none() || Rc::strong_count(&captured) > 1 {
+            iterate(&event_loop);
+        }
+        let name = thread_name.borrow_mut().take().expect("name").expect("thread name");
+        assert!(name.starts_with("mini-blocking-"));
+    }
+
+    #[test]
+    fn rearm() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+        reader.set_nonblocking(true).expect("nonblocking");
+        let received = Rc::new(RefCell::new(vec![]));
+
+        // Reads one byte per event, rearming the fd from the callback.
+        fn read_one(event_loop: EventLoop, reader: Rc<UnixStream>, received: Rc<RefCell<Vec<u8>>>) -> impl FnOnce(ffi::epoll_event) {
+            move |_event| {
+                let mut byte = [0];
+                if let Ok(1) = (&*reader).read(&mut byte) {
+                    received.borrow_mut().push(byte[0]);
+                }
+                let callback = read_one(event_loop.clone(), reader.clone(), received);
+                event_loop.rearm_fd(&*reader, Mode::Read, callback).expect("rearm");
+            }
+        }
+
+        let reader = Rc::new(reader);
+        let callback = read_one(event_loop.clone(), reader.clone(), received.clone());
+        event_loop.add_fd_oneshot(&*reader, Mode::Read, callback).expect("add");
+        writer.write_all(b"abc").expect("write");
+        while received.borrow().len() < 3 {
+            iterate(&event_loop);
+        }
+        assert_eq!(*received.borrow(), b"abc".to_vec());
+        assert_eq!(event_loop.metrics().callbacks, 1);
+
+        let (_other_reader, other_writer) = UnixStream::pair().expect("socket pair");
+        event_loop.add_fd(&other_writer, Mode::Write, |_event| Action::Continue).expect("add");
+        let error = event_loop.rearm_fd(&other_writer, Mode::Write, |_event| ()).expect_err("rearm");
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn defer() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (_reader, writer) = UnixStream::pair().expect("socket pair");
+        let calls = Rc::new(RefCell::new(vec![]));
+        let callback_calls = calls.clone();
+        let callback_loop = event_loop.clone();
+        let writer_fd = writer.as_raw_fd();
+        event_loop.add_fd(&writer, Mode::Write, move |_event| {
+            callback_calls.borrow_mut().push("event");
+            let deferred_calls = callback_calls.clone();
+            let deferred_loop = callback_loop.clone();
+            // Removing the fd from its own callback.
+            callback_loop.defer(move || {
+                deferred_calls.borrow_mut().push("deferred");
+                deferred_loop.remove_raw_fd(writer_fd).expect("remove");
+                let nested_calls = deferred_calls.clone();
+                deferred_loop.defer(move || nested_calls.borrow_mut().push("nested"));
+            });
+            Action::Continue
+        }).expect("add");
+        iterate(&event_loop);
+        assert_eq!(*calls.borrow(), vec!["event", "deferred"]);
+        assert!(event_loop.registrations.borrow().is_empty());
+        // Run before waiting.
+        event_loop.iterate_timeout(Some(Duration::from_millis(0)));
+        assert_eq!(*calls.borrow(), vec!["event", "deferred", "nested"]);
+    }
+
+    #[test]
+    fn error_policy() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let errors = Rc::new(RefCell::new(vec![]));
+        let reported = errors.clone();
+        event_loop.set_error_policy(ErrorPolicy::Hook(Rc::new(move |error| reported.borrow_mut().push(error.to_string()))));
+        let (_reader, writer) = UnixStream::pair().expect("socket pair");
+        // The callback is never set.
+        let _event = event_loop.try_add_fd(&writer, Mode::Write).expect("add");
+        iterate(&event_loop);
+        assert_eq!(*errors.borrow(), vec![format!("no callback for fd {}", writer.as_raw_fd())]);
+        assert_eq!(event_loop.metrics().registered_fds, 0);
+
+        assert!(event_loop.report_error(LoopError::MissingCallback(-1)).is_ok());
+        event_loop.set_error_policy(ErrorPolicy::Propagate);
+        assert!(event_loop.report_error(LoopError::MissingCallback(-1)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "no callback for fd")]
+    fn missing_callback() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (_reader, writer) = UnixStream::pair().expect("socket pair");
+        let _event = event_loop.try_add_fd(&writer, Mode::Write).expect("add");
+        iterate(&event_loop);
+    }
+
+    #[test]
+    fn iterate_with_signal_mask() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let mask = SignalSet::current_mask().expect("mask");
+        let fired = Rc::new(Cell::new(false));
+        let flag = fired.clone();
+        event_loop.add_timeout(Duration::from_millis(1), move || flag.set(true)).expect("timeout");
+        while !fired.get() {
+            if let EpollResult::Error(error) = event_loop.iterate_with_signal_mask(Some(Duration::from_secs(5)), &mask) {
+                panic!("iterate: {}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn current() {
+        assert!(EventLoop::current().is_none());
+        let event_loop = EventLoop::new().expect("event loop");
+        let fired = Rc::new(Cell::new(false));
+        let flag = fired.clone();
+        event_loop.add_timeout(Duration::from_millis(1), move || {
+            // Registers a timer without a clone of the loop.
+            let flag = flag.clone();
+            EventLoop::current().expect("current loop")
+                .add_timeout(Duration::from_millis(1), move || flag.set(true))
+                .expect("timeout");
+        }).expect("timeout");
+        while !fired.get() {
+            iterate(&event_loop);
+        }
+        assert!(EventLoop::current().is_none());
+    }
+
+    #[test]
+    fn deferred_registration() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let (first, second) = UnixStream::pair().expect("socket pair");
+        let (third, fourth) = UnixStream::pair().expect("socket pair");
+        let count = Rc::new(Cell::new(0));
+        let loop_handle = event_loop.clone();
+        let first_fd = first.as_raw_fd();
+        let second_fd = second.as_raw_fd();
+        let counter = count.clone();
+        event_loop.add_fd(&first, Mode::Write, move |_event| {
+            let counter = counter.clone();
+            loop_handle.defer_add_raw_fd(second_fd, Mode::Write, move |_event| {
+                counter.set(counter.get() + 1);
+                Action::Continue
+            });
+            loop_handle.defer_remove_raw_fd(first_fd);
+            // Nothing is applied until the events are dispatched.
+            assert_eq!(loop_handle.metrics().registered_fds, 1);
+            Action::Continue
+        }).expect("add");
+        iterate(&event_loop);
+        assert_eq!(event_loop.metrics().registered_fds, 1);
+        assert_eq!(event_loop.metrics().callbacks, 1);
+        iterate(&event_loop);
+        assert_eq!(count.get(), 1);
+
+        // Consecutive modifications are coalesced and a removal cancels a queued registration.
+        event_loop.defer_add_fd(&third, Mode::Read, |_event| Action::Continue);
+        event_loop.defer_modify_fd(&third, Mode::Write);
+        event_loop.defer_modify_fd(&third, Mode::ReadWrite);
+        event_loop.defer_remove_fd(&third);
+        assert_eq!(event_loop.deferred.borrow().len(), 3);
+        event_loop.defer_add_fd(&third, Mode::Read, |_event| Action::Continue);
+        event_loop.defer_remove_fd(&third);
+        assert_eq!(event_loop.deferred.borrow().len(), 3);
+        assert_eq!(event_loop.metrics().callbacks, 2);
+
+        let errors = Rc::new(RefCell::new(vec![]));
+        let reported = errors.clone();
+        event_loop.set_error_policy(ErrorPolicy::Hook(Rc::new(move |error| reported.borrow_mut().push(error.to_string()))));
+        event_loop.defer_modify_fd(&fourth, Mode::Read);
+        event_loop.iterate_timeout(Some(Duration::from_secs(0)));
+        assert_eq!(errors.borrow().len(), 1);
+        assert!(errors.borrow()[0].starts_with(&format!("deferred operation on fd {} failed", fourth.as_raw_fd())));
+        assert_eq!(event_loop.metrics().registered_fds, 1);
+        assert_eq!(event_loop.metrics().callbacks, 1);
+    }
+}
@@ -0,0 +1,216 @@
+//! Storage of the event loop callbacks without boxing them: a closure fitting in a few words, like
+//! one capturing a `Stream` or a couple of `Rc`s, is stored inline in the slot of its fd, so that
+//! registering an fd does not allocate. Larger closures are boxed.
+
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+
+/// Number of words a closure can take to be stored inline.
+const INLINE_WORDS: usize = 4;
+
+type Words = [usize; INLINE_WORDS];
+
+/// Returns true if a `T` can be stored inline.
+fn fits<T>() -> bool {
+    mem::size_of::<T>() <= mem::size_of::<Words>() && mem::align_of::<T>() <= mem::align_of::<Words>()
+}
+
+/// A value whose type is only known by the functions given along with it.
+struct Storage {
+    data: MaybeUninit<Words>,
+    drop: unsafe fn(*mut u8),
+    /// Neither `Send` nor `Sync`, like the closures stored.
+    _marker: PhantomData<*mut ()>,
+}
+
+impl Storage {
+    fn new<T>(value: T) -> Self {
+        assert!(fits::<T>(), "value too large to be stored inline");
+        let mut data = MaybeUninit::<Words>::uninit();
+        unsafe {
+            ptr::write(data.as_mut_ptr() as *mut T, value);
+        }
+        Self {
+            data,
+            drop: drop_value::<T>,
+            _marker: PhantomData,
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_mut_ptr() as *mut u8
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        unsafe {
+            (self.drop)(self.as_mut_ptr());
+        }
+    }
+}
+
+unsafe fn drop_value<T>(data: *mut u8) {
+    ptr::drop_in_place(data as *mut T);
+}
+
+/// Drop function of a value moved out.
+unsafe fn moved(_data: *mut u8) {
+}
+
+unsafe fn call_mut<F, A, R>(data: *mut u8, argument: A) -> R
+where F: FnMut(A) -> R,
+{
+    (*(data as *mut F))(argument)
+}
+
+unsafe fn call_once<F, A>(data: *mut u8, argument: A)
+where F: FnOnce(A),
+{
+    ptr::read(data as *mut F)(argument)
+}
+
+/// An `FnMut(A) -> R` closure, stored inline if it fits.
+pub struct InlineFnMut<A, R> {
+    call: unsafe fn(*mut u8, A) -> R,
+    storage: Storage,
+}
+
+impl<A, R> InlineFnMut<A, R> {
+    pub fn new<F>(function: F) -> Self
+    where F: FnMut(A) -> R + 'static,
+    {
+        if fits::<F>() {
+            Self::store(function)
+        }
+        else {
+            Self::store(Box::new(function))
+        }
+    }
+
+    fn store<F>(function: F) -> Self
+    where F: FnMut(A) -> R,
+    {
+        Self {
+            call: call_mut::<F, A, R>,
+            storage: Storage::new(function),
+        }
+    }
+
+    pub fn call(&mut self, argument: A) -> R {
+        unsafe { (self.call)(self.storage.as_mut_ptr(), argument) }
+    }
+}
+
+/// An `FnOnce(A)` closure, stored inline if it fits.
+pub struct InlineFnOnce<A> {
+    call: unsafe fn(*mut u8, A),
+    storage: Storage,
+}
+
+impl<A> InlineFnOnce<A> {
+    pub fn new<F>(function: F) -> Self
+    where F: FnOnce(A) + 'static,
+    {
+        if fits::<F>() {
+            Self::store(function)
+        }
+        else {
+            Self::store(Box::new(function))
+        }
+    }
+
+    fn store<F>(function: F) -> Self
+    where F: FnOnce(A),
+    {
+        Self {
+            call: call_once::<F, A>,
+            storage: Storage::new(function),
+        }
+    }
+
+    pub fn call(mut self, argument: A) {
+        // The closure is moved out by the call, even if it panics.
+        self.storage.drop = moved;
+        unsafe { (self.call)(self.storage.as_mut_ptr(), argument) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use super::{InlineFnMut, InlineFnOnce, fits};
+
+    /// Counts its drops.
+    struct Guard(Rc<Cell<usize>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn inline_and_boxed() {
+        let drops = Rc::new(Cell::new(0));
+        let guard = Guard(drops.clone());
+        let mut total = 0;
+        let small = move |value: usize| {
+            let _ = &guard;
+            total += value;
+            total
+        };
+        assert!(fits::<Guard>());
+        let guard = Guard(drops.clone());
+        let large_state = [1usize; 8];
+        let large = move |value: usize| {
+            let _ = &guard;
+            large_state.iter().sum::<usize>() + value
+        };
+        assert!(!fits::<[usize; 8]>());
+
+        let mut small = InlineFnMut::new(small);
+        assert_eq!(small.call(2), 2);
+        assert_eq!(small.call(3), 5);
+        let mut large = InlineFnMut::new(large);
+        assert_eq!(large.call(1), 9);
+        // Moving the storage moves the closure.
+        let mut moved = vec![small];
+        assert_eq!(moved[0].call(1), 6);
+        drop(moved);
+        drop(large);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn once() {
+        let drops = Rc::new(Cell::new(0));
+        let called = Rc::new(Cell::new(0));
+        let guard = Guard(drops.clone());
+        let counter = called.clone();
+        InlineFnOnce::new(move |value: usize| {
+            let _guard = guard;
+            counter.set(value);
+        }).call(7);
+        assert_eq!((called.get(), drops.get()), (7, 1));
+
+        // Dropped without being called.
+        let guard = Guard(drops.clone());
+        drop(InlineFnOnce::new(move |_: ()| drop(guard)));
+        assert_eq!(drops.get(), 2);
+
+        // Dropped once by the call even if it panics.
+        let guard = Guard(drops.clone());
+        let large_state = [0u8; 64];
+        let function = InlineFnOnce::new(move |_: ()| {
+            let _guard = guard;
+            panic!("callback panic {}", large_state.len());
+        });
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| function.call(()))).is_err());
+        assert_eq!(drops.get(), 3);
+    }
+}
@@ -0,0 +1,198 @@
+//! File operations run on a thread pool, with their result delivered to a `Stream`.
+//!
+//! Regular files are always ready for epoll, so reading them from an event-loop thread blocks the
+//! loop on disk I/O. `FileSystem` runs each operation on a `ThreadPool` and sends the result back
+//! through a oneshot channel registered on the `Loop`.
+
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use aio::handler::{Loop, Stream};
+use bytes::Bytes;
+use oneshot;
+use threadpool::ThreadPool;
+
+const DEFAULT_THREADS: usize = 4;
+
+/// Runs file operations off the event-loop thread.
+#[derive(Clone)]
+pub struct FileSystem {
+    event_loop: Loop,
+    pool: Arc<ThreadPool>,
+}
+
+impl FileSystem {
+    /// Creates a file system with its own pool of 4 threads.
+    pub fn new(event_loop: &Loop) -> io::Result<Self> {
+        Ok(Self::with_pool(event_loop, Arc::new(ThreadPool::new(DEFAULT_THREADS)?)))
+    }
+
+    /// Creates a file system running the operations on `pool`, which can be shared with other
+    /// loops.
+    pub fn with_pool(event_loop: &Loop, pool: Arc<ThreadPool>) -> Self {
+        Self {
+            event_loop: event_loop.clone(),
+            pool,
+        }
+    }
+
+    /// Appends data to a file, creating it if needed.
+    pub fn append<P, B, CALLBACK, MSG>(&self, path: P, data: B, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          B: Into<Bytes>,
+          CALLBACK: FnOnce(io::Result<()>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        let data = data.into();
+        self.execute(move || {
+            let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+            file.write_all(&data)
+        }, stream, callback)
+    }
+
+    /// Queries the metadata of a file.
+    pub fn metadata<P, CALLBACK, MSG>(&self, path: P, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          CALLBACK: FnOnce(io::Result<Metadata>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        self.execute(move || fs::metadata(path), stream, callback)
+    }
+
+    /// Reads the whole content of a file.
+    pub fn read<P, CALLBACK, MSG>(&self, path: P, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          CALLBACK: FnOnce(io::Result<Bytes>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        self.execute(move || fs::read(path).map(Bytes::from), stream, callback)
+    }
+
+    /// Reads the whole content of a file as UTF-8.
+    pub fn read_to_string<P, CALLBACK, MSG>(&self, path: P, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          CALLBACK: FnOnce(io::Result<String>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        self.execute(move || fs::read_to_string(path), stream, callback)
+    }
+
+    /// Removes a file.
+    pub fn remove_file<P, CALLBACK, MSG>(&self, path: P, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          CALLBACK: FnOnce(io::Result<()>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        self.execute(move || fs::remove_file(path), stream, callback)
+    }
+
+    /// Writes data to a file, replacing its content.
+    pub fn write<P, B, CALLBACK, MSG>(&self, path: P, data: B, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where P: Into<PathBuf>,
+          B: Into<Bytes>,
+          CALLBACK: FnOnce(io::Result<()>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let path = path.into();
+        let data = data.into();
+        self.execute(move || fs::write(path, &data), stream, callback)
+    }
+
+    /// Runs any blocking operation on the pool and sends its result to the stream, converted by the
+    /// callback. The callback receives an error if the operation panicked.
+    pub fn execute<JOB, T, CALLBACK, MSG>(&self, job: JOB, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where JOB: FnOnce() -> io::Result<T> + Send + 'static,
+          T: Send + 'static,
+          CALLBACK: FnOnce(io::Result<T>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        // Queued first so that the callback is not called when the pool cannot take the job.
+        self.pool.execute(move || {
+            let _ = sender.send(job());
+        })?;
+        self.event_loop.add_oneshot(receiver, stream, move |result| {
+            callback(result.unwrap_or_else(|_| Err(io::Error::new(ErrorKind::Other, "file operation panicked"))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::io;
+    use std::path::Path;
+    use std::process;
+
+    use aio::handler::{Handler, Loop, Stream};
+    use bytes::Bytes;
+    use super::FileSystem;
+
+    use self::Msg::*;
+
+    enum Msg {
+        Appended(io::Result<()>),
+        Read(io::Result<Bytes>),
+        Removed(io::Result<()>),
+        Written(io::Result<()>),
+    }
+
+    struct Writer {
+        event_loop: Loop,
+        fs: FileSystem,
+        path: String,
+        steps: Vec<&'static str>,
+    }
+
+    impl Handler for Writer {
+        type Msg = Msg;
+
+        fn update(&mut self, stream: &Stream<Msg>, msg: Msg) {
+            match msg {
+                Written(result) => {
+                    result.expect("write");
+                    self.steps.push("written");
+                    self.fs.append(self.path.clone(), " world", stream, Appended).expect("append");
+                },
+                Appended(result) => {
+                    result.expect("append");
+                    self.steps.push("appended");
+                    self.fs.read(self.path.clone(), stream, Read).expect("read");
+                },
+                Read(result) => {
+                    assert_eq!(result.expect("read"), b"hello world"[..]);
+                    self.steps.push("read");
+                    self.fs.remove_file(self.path.clone(), stream, Removed).expect("remove");
+                },
+                Removed(result) => {
+                    result.expect("remove");
+                    assert!(!Path::new(&self.path).exists());
+                    assert_eq!(self.steps, ["written", "appended", "read"]);
+                    self.event_loop.stop();
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let fs = FileSystem::new(&event_loop).expect("file system");
+        let path = temp_dir().join(format!("mini-aio-fs-{}", process::id())).to_string_lossy().into_owned();
+        let stream = event_loop.spawn(Writer {
+            event_loop: event_loop.clone(),
+            fs: fs.clone(),
+            path: path.clone(),
+            steps: vec![],
+        });
+        fs.write(path, "hello", &stream, Written).expect("write");
+        event_loop.run().expect("run");
+    }
+}
@@ -6,18 +6,30 @@ use std::os::unix::io::{
     AsRawFd,
     RawFd,
 };
+use std::path::Path;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use aio::async::{
     self,
     Action,
     EpollResult,
     EventLoop,
+    LoopError,
     Mode,
-    event_list,
+    PathWatch,
 };
 use aio::async::ffi::epoll_event;
+use aio::inotify::FileEvent;
 use aio::slab::Slab;
+use channel::{Receiver, RecvError, TryRecvError};
+use oneshot;
+use signal::{Signal, SignalFd, SignalSet};
+
+thread_local! {
+    /// The loop being iterated on this thread.
+    static CURRENT: RefCell<Option<Loop>> = const { RefCell::new(None) };
+}
 
 pub struct Stream<MSG> {
     elements: Rc<RefCell<VecDeque<MSG>>>,
@@ -89,6 +101,8 @@ impl<HANDLER: Handler<Msg=MSG>, MSG> Callable for Component<HANDLER, MSG> {
 struct Inner {
     handlers: Slab<Box<Callable>>,
     registered_entries: Rc<RefCell<Vec<usize>>>,
+    /// Buffer swapped with `registered_entries` on each iteration to avoid allocating a new one.
+    spare_entries: Vec<usize>,
     stopped: bool,
 }
 
@@ -100,11 +114,22 @@ pub struct Loop {
 
 impl Loop {
     pub fn new() -> io::Result<Self> {
+        Self::with_event_loop(EventLoop::new()?)
+    }
+
+    /// Creates a loop handling up to `capacity` events per wakeup, see
+    /// `EventLoop::new_with_capacity`.
+    pub fn new_with_capacity(capacity: usize) -> io::Result<Self> {
+        Self::with_event_loop(EventLoop::new_with_capacity(capacity)?)
+    }
+
+    fn with_event_loop(event_loop: EventLoop) -> io::Result<Self> {
         Ok(Self {
-            event_loop: EventLoop::new()?,
+            event_loop,
             inner: Rc::new(RefCell::new(Inner {
                 handlers: Slab::new(),
                 registered_entries: Rc::new(RefCell::new(vec![])),
+                spare_entries: vec![],
                 stopped: false,
             })),
         })
@@ -128,6 +153,103 @@ impl Loop {
         })
     }
 
+    /// Sends the messages received on the channel to the stream, converted by the callback.
+    pub fn add_receiver<CALLBACK, MSG, T>(&self, receiver: Receiver<T>, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where CALLBACK: Fn(T) -> MSG + 'static,
+          MSG: 'static,
+          T: 'static,
+    {
+        let fd = receiver.event_fd()?;
+        let stream = stream.clone();
+        let event_loop = self.event_loop.clone();
+        self.event_loop.add_raw_fd(fd, Mode::Read, move |_event| {
+            loop {
+                match receiver.try_recv() {
+                    Ok(value) => stream.send(callback(value)),
+                    Err(TryRecvError::Empty) => return Action::Continue,
+                    Err(TryRecvError::Disconnected) => {
+                        // Remove the fd before the receiver closes it when this callback is dropped.
+                        let _ = event_loop.remove_raw_fd(fd);
+                        return Action::Stop;
+                    },
+                }
+            }
+        })
+    }
+
+    /// Sends the value received on the oneshot channel to the stream, converted by the callback. The
+    /// callback receives an error if the sender was dropped without sending a value.
+    pub fn add_oneshot<CALLBACK, MSG, T>(&self, receiver: oneshot::Receiver<T>, stream: &Stream<MSG>, callback: CALLBACK)
+        -> io::Result<()>
+    where CALLBACK: FnOnce(Result<T, RecvError>) -> MSG + 'static,
+          MSG: 'static,
+          T: 'static,
+    {
+        let fd = receiver.event_fd()?;
+        let stream = stream.clone();
+        let event_loop = self.event_loop.clone();
+        self.event_loop.add_raw_fd_oneshot(fd, Mode::Read, move |_event| {
+            let result =
+                match receiver.try_recv() {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(RecvError),
+                };
+            // Remove the fd before the receiver closes it when this callback is dropped.
+            let _ = event_loop.remove_raw_fd(fd);
+            stream.send(callback(result));
+        })
+    }
+
+    /// Runs `function` on a thread of the pool of the event loop and sends its result to the
+    /// stream, converted by the callback, see `EventLoop::spawn_blocking`.
+    pub fn spawn_blocking<F, T, CALLBACK, MSG>(&self, function: F, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static,
+          CALLBACK: FnOnce(T) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        self.event_loop.spawn_blocking(function, move |result| stream.send(callback(result)))
+    }
+
+    /// Blocks the signals on the current thread and sends them to the stream as they arrive,
+    /// converted by the callback. Call it before spawning threads so that they inherit the mask.
+    pub fn add_signals<CALLBACK, MSG>(&self, signals: &SignalSet, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<()>
+    where CALLBACK: Fn(Signal) -> MSG + 'static,
+          MSG: 'static,
+    {
+        signals.block()?;
+        let signal_fd = SignalFd::new(signals)?;
+        let fd = signal_fd.as_raw_fd();
+        let stream = stream.clone();
+        let event_loop = self.event_loop.clone();
+        self.event_loop.add_raw_fd(fd, Mode::Read, move |_event| {
+            loop {
+                match signal_fd.read() {
+                    Ok(Some(signal)) => stream.send(callback(signal)),
+                    Ok(None) => return Action::Continue,
+                    Err(_) => {
+                        // Remove the fd before it is closed when this callback is dropped.
+                        let _ = event_loop.remove_raw_fd(fd);
+                        return Action::Stop;
+                    },
+                }
+            }
+        })
+    }
+
+    /// Sends the events happening to `path` to the stream, converted by the callback, see
+    /// `EventLoop::watch_path`.
+    pub fn watch_path<P, CALLBACK, MSG>(&self, path: P, mask: u32, stream: &Stream<MSG>, callback: CALLBACK)
+        -> io::Result<PathWatch>
+    where P: AsRef<Path>,
+          CALLBACK: Fn(FileEvent) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        self.event_loop.watch_path(path, mask, move |event| stream.send(callback(event)))
+    }
+
     pub fn event_loop(&self) -> &EventLoop {
         &self.event_loop
     }
@@ -149,9 +271,70 @@ impl Loop {
         stream
     }
 
-    pub fn iterate(&mut self, event_list: &mut [epoll_event]) -> EpollResult {
-        let registered_entries = mem::replace(&mut *self.inner.borrow().registered_entries.borrow_mut(), vec![]);
-        for entry in registered_entries {
+    /// Returns true if no handler has messages waiting to be processed.
+    pub fn is_idle(&self) -> bool {
+        self.inner.borrow().registered_entries.borrow().is_empty()
+    }
+
+    pub fn iterate(&mut self) -> EpollResult {
+        self.iterate_timeout(None)
+    }
+
+    /// Processes the waiting messages, then waits for events for `timeout` at most, see
+    /// `EventLoop::iterate_timeout`.
+    pub fn iterate_timeout(&mut self, timeout: Option<Duration>) -> EpollResult {
+        self.with_current(|event_loop| {
+            event_loop.process_messages();
+            event_loop.event_loop.iterate_timeout(timeout)
+        })
+    }
+
+    /// Processes the waiting messages, then waits for events with the signal mask replaced by
+    /// `mask`, see `EventLoop::iterate_with_signal_mask`.
+    pub fn iterate_with_signal_mask(&mut self, timeout: Option<Duration>, mask: &SignalSet) -> EpollResult {
+        self.with_current(|event_loop| {
+            event_loop.process_messages();
+            event_loop.event_loop.iterate_with_signal_mask(timeout, mask)
+        })
+    }
+
+    /// Processes the waiting messages, then waits for events once and returns the number of
+    /// callbacks called, see `EventLoop::run_once`.
+    pub fn run_once(&mut self) -> io::Result<usize> {
+        self.run_once_timeout(None)
+    }
+
+    pub fn run_once_timeout(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        self.with_current(|event_loop| {
+            event_loop.process_messages();
+            event_loop.event_loop.run_once_timeout(timeout)
+        })
+    }
+
+    /// Returns the loop being iterated on this thread, so that handlers and notify trait
+    /// implementations can register fds and spawn handlers without keeping a clone of the loop.
+    /// Returns `None` outside of the iterations.
+    pub fn current() -> Option<Loop> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    fn with_current<T, F>(&mut self, iterate: F) -> T
+    where F: FnOnce(&mut Self) -> T,
+    {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = iterate(self);
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+
+    fn process_messages(&mut self) {
+        let mut registered_entries = {
+            let mut inner = self.inner.borrow_mut();
+            let spare_entries = mem::take(&mut inner.spare_entries);
+            let registered_entries = mem::replace(&mut *inner.registered_entries.borrow_mut(), spare_entries);
+            registered_entries
+        };
+        for &entry in &registered_entries {
             if self.inner.borrow().handlers.contains(entry) {
                 // NOTE: Remove the handler because handlers can be added in the update() method.
                 let mut handler = std::mem::replace(&mut self.inner.borrow_mut().handlers[entry], Box::new(NotCallable));
@@ -159,7 +342,8 @@ impl Loop {
                 self.inner.borrow_mut().handlers[entry] = handler;
             }
         }
-        self.event_loop.iterate(event_list)
+        registered_entries.clear();
+        self.inner.borrow_mut().spare_entries = registered_entries;
     }
 
     pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
@@ -171,20 +355,57 @@ impl Loop {
     }
 
     pub fn run(&mut self) -> io::Result<()> {
-        let mut event_list = event_list();
+        self.run_until(|| false)
+    }
 
-        while !self.inner.borrow().stopped {
-            match self.iterate(&mut event_list) {
+    /// Runs the loop until `stop()` is called or `predicate` returns true, which is checked before
+    /// waiting for events.
+    pub fn run_until<F>(&mut self, mut predicate: F) -> io::Result<()>
+    where F: FnMut() -> bool,
+    {
+        while !self.inner.borrow().stopped && !predicate() {
+            match self.iterate() {
                 // Restart if interrupted by signal.
                 EpollResult::Interrupted => continue,
-                EpollResult::Error(error) => return Err(error),
+                EpollResult::Error(error) => self.event_loop.report_error(LoopError::Epoll(error))?,
                 EpollResult::Ok => (),
             }
         }
+        // The loop can be run again.
+        self.inner.borrow_mut().stopped = false;
+
+        Ok(())
+    }
+
+    /// Runs the loop until `stop()` is called, calling `idle` after each iteration, see
+    /// `EventLoop::run_with_idle`.
+    pub fn run_with_idle<F>(&mut self, timeout: Option<Duration>, mut idle: F) -> io::Result<()>
+    where F: FnMut(),
+    {
+        while !self.inner.borrow().stopped {
+            match self.iterate_timeout(timeout) {
+                // Restart if interrupted by signal.
+                EpollResult::Interrupted => continue,
+                EpollResult::Error(error) => self.event_loop.report_error(LoopError::Epoll(error))?,
+                EpollResult::Ok => idle(),
+            }
+        }
+        // The loop can be run again.
+        self.inner.borrow_mut().stopped = false;
 
         Ok(())
     }
 
+    /// Runs the loop until `stop()` is called or for `duration` at most.
+    pub fn run_for(&mut self, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + duration;
+        // Wakes up the loop at the deadline.
+        let timer = self.event_loop.add_timeout(duration, || ())?;
+        let result = self.run_until(|| Instant::now() >= deadline);
+        timer.cancel();
+        result
+    }
+
     pub fn stop(&mut self) {
         self.inner.borrow_mut().stopped = true;
         EventLoop::wakeup();
@@ -198,6 +419,10 @@ impl Loop {
         Ok(Event::new(self.event_loop.try_add_raw_fd(fd, mode)?))
     }
 
+    pub fn try_add_fd_oneshot<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<EventOnce> {
+        self.try_add_raw_fd_oneshot(as_fd.as_raw_fd(), mode)
+    }
+
     pub fn try_add_raw_fd_oneshot(&self, fd: RawFd, mode: Mode) -> io::Result<EventOnce> {
         Ok(EventOnce::new(self.event_loop.try_add_raw_fd_oneshot(fd, mode)?))
     }
@@ -214,7 +439,7 @@ impl Event {
         }
     }
 
-    pub fn set_callback<CALLBACK, MSG>(self, stream: &Stream<MSG>, callback: CALLBACK)
+    pub fn set_callback<CALLBACK, MSG>(&self, stream: &Stream<MSG>, callback: CALLBACK)
     where CALLBACK: Fn(epoll_event) -> MSG + 'static,
           MSG: 'static,
     {
@@ -224,6 +449,16 @@ impl Event {
             Action::Continue
         });
     }
+
+    /// Changes the events the fd is watched for, see `EventLoop::modify_raw_fd`.
+    pub fn modify(&self, mode: Mode) -> io::Result<()> {
+        self.event.modify(mode)
+    }
+
+    /// Removes the fd from the event loop, see `async::Event::cancel`.
+    pub fn cancel(&self) -> io::Result<()> {
+        self.event.cancel()
+    }
 }
 
 pub struct EventOnce {
@@ -244,4 +479,9 @@ impl EventOnce {
         let stream = stream.clone();
         self.event.set_callback(move |event| stream.send(callback(event)));
     }
+
+    /// Removes the fd from the event loop without setting a callback.
+    pub fn cancel(self) -> io::Result<()> {
+        self.event.cancel()
+    }
 }
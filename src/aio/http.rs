@@ -17,6 +17,8 @@ use aio::net::{
     TcpConnectionNotify,
 };
 use aio::uhttp_uri::HttpUri;
+use bytes::Bytes;
+use compress::{self, Format};
 
 use self::Msg::*;
 
@@ -65,6 +67,25 @@ fn parse_headers(buffer: &VecDeque<u8>) -> Option<usize> {
     None
 }
 
+fn parse_content_encoding(buffer: &VecDeque<u8>) -> Option<Format> {
+    let headers: Vec<u8> = buffer.iter().cloned().collect();
+    let headers = String::from_utf8_lossy(&headers);
+    headers.split("\r\n")
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?;
+            if name.trim().eq_ignore_ascii_case("Content-Encoding") {
+                parts.next()
+            }
+            else {
+                None
+            }
+        })
+        .next()
+        .and_then(Format::from_content_encoding)
+}
+
 fn remove_until_boundary(buffer: &mut VecDeque<u8>) {
     let mut index = buffer.len() - 1;
     for i in 0..buffer.len() {
@@ -81,6 +102,7 @@ fn remove_until_boundary(buffer: &mut VecDeque<u8>) {
 #[derive(Clone)]
 struct Connection<HANDLER> {
     buffer: VecDeque<u8>,
+    content_encoding: Option<Format>,
     content_length: usize,
     handler: HANDLER,
     host: String,
@@ -92,6 +114,7 @@ impl<HANDLER> Connection<HANDLER> {
     fn new(host: &str, handler: HANDLER, path: &str, method: &'static str) -> Self {
         Self {
             buffer: VecDeque::new(),
+            content_encoding: None,
             content_length: 0,
             handler,
             host: host.to_string(),
@@ -109,7 +132,7 @@ where HANDLER: HttpHandler,
     }
 
     fn connected(&mut self, connection: &mut TcpConnection) {
-        if let Err(error) = connection.write(format!("{} {} HTTP/1.1\r\nHost: {}\r\n\r\n", self.method, self.path,
+        if let Err(error) = connection.write(format!("{} {} HTTP/1.1\r\nHost: {}\r\nAccept-Encoding: gzip, deflate\r\n\r\n", self.method, self.path,
             self.host).into_bytes())
         {
             self.handler.error(error);
@@ -120,11 +143,12 @@ where HANDLER: HttpHandler,
         self.handler.error(error);
     }
 
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
-        self.buffer.extend(data);
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
+        self.buffer.extend(data.iter());
         if self.content_length == 0 {
             match parse_headers(&self.buffer) {
                 Some(content_length) => {
+                    self.content_encoding = parse_content_encoding(&self.buffer);
                     remove_until_boundary(&mut self.buffer);
                     self.content_length = content_length;
                 },
@@ -132,8 +156,15 @@ where HANDLER: HttpHandler,
             }
         }
         if self.buffer.len() >= self.content_length {
-            let buffer = mem::replace(&mut self.buffer, VecDeque::new());
-            self.handler.response(buffer.into());
+            let buffer: Vec<u8> = mem::replace(&mut self.buffer, VecDeque::new()).into();
+            match self.content_encoding {
+                Some(format) =>
+                    match compress::decompress(format, &buffer) {
+                        Ok(body) => self.handler.response(body),
+                        Err(error) => self.handler.error(io::Error::new(io::ErrorKind::InvalidData, error)),
+                    },
+                None => self.handler.response(buffer),
+            }
             connection.dispose();
         }
     }
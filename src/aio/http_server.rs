@@ -5,7 +5,10 @@
 
 use std::fmt::{self, Display, Formatter};
 use std::io;
-use std::net;
+use std::net::{self, IpAddr, SocketAddr};
+use std::str;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use aio::handler::{Loop, Stream};
 use aio::net::{
@@ -15,20 +18,88 @@ use aio::net::{
     TcpListenNotify,
 };
 use aio::net::TcpListener;
+use arena::Arena;
+use bytes::Bytes;
+use compress::{self, Format, Level};
+use ratelimit::KeyedLimiter;
+use time::format::format_http_date;
+
+/// Rate limits per client IP address, for the connections and the requests to some routes.
+#[derive(Clone, Default)]
+pub struct Limits {
+    connections: Option<Arc<KeyedLimiter<IpAddr>>>,
+    routes: Vec<(String, Arc<KeyedLimiter<IpAddr>>)>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows bursts of `capacity` connections per client, refilled at `rate` per second.
+    pub fn connections(mut self, capacity: u32, rate: f64) -> Self {
+        self.connections = Some(Arc::new(KeyedLimiter::new(capacity, rate)));
+        self
+    }
+
+    /// Allows bursts of `capacity` requests per client to the paths starting with `prefix`,
+    /// refilled at `rate` per second. When several prefixes match, the longest one applies.
+    pub fn route(mut self, prefix: &str, capacity: u32, rate: f64) -> Self {
+        self.routes.push((prefix.to_string(), Arc::new(KeyedLimiter::new(capacity, rate))));
+        self
+    }
+
+    fn allow_connection(&self, addr: &SocketAddr) -> bool {
+        self.connections.as_ref()
+            .map_or(true, |limiter| limiter.try_acquire(addr.ip()))
+    }
+
+    fn allow_request(&self, path: &str, addr: Option<SocketAddr>) -> bool {
+        let limiter = self.routes.iter()
+            .filter(|&&(ref prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|&&(ref prefix, _)| prefix.len());
+        match (limiter, addr) {
+            (Some(&(_, ref limiter)), Some(addr)) => limiter.try_acquire(addr.ip()),
+            _ => true,
+        }
+    }
+}
+
+/// Responses smaller than this are not worth compressing.
+const MIN_COMPRESSED_LEN: usize = 1024;
+
+/// Returns true if the value of an Accept-Encoding header allows gzip.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|encoding| {
+        let mut parameters = encoding.split(';');
+        let name = parameters.next().unwrap_or("").trim();
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q=")?.parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0);
+        (name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") || name == "*") && quality > 0.0
+    })
+}
 
 struct Listener<HANDLER> {
     handler: HANDLER,
+    limits: Limits,
 }
 
 impl<HANDLER> Listener<HANDLER> {
-    fn new(handler: HANDLER) -> Self {
+    fn new(handler: HANDLER, limits: Limits) -> Self {
         Self {
             handler,
+            limits,
         }
     }
 }
 
 impl<HANDLER: HttpHandler + 'static> TcpListenNotify for Listener<HANDLER> {
+    fn accept(&mut self, addr: &SocketAddr) -> bool {
+        self.limits.allow_connection(addr)
+    }
+
     fn listening(&mut self, listener: &net::TcpListener) {
         match listener.local_addr() {
             Ok(address) =>
@@ -43,18 +114,47 @@ impl<HANDLER: HttpHandler + 'static> TcpListenNotify for Listener<HANDLER> {
     }
 
     fn connected(&mut self, _listener: &net::TcpListener) -> Box<TcpConnectionNotify> {
-        Box::new(Server::new(self.handler.clone()))
+        Box::new(Server::new(self.handler.clone(), self.limits.clone()))
+    }
+}
+
+/// Parses the request line and the headers of `text`, the headers being allocated in `arena`.
+fn parse_request<'a>(text: &'a str, arena: &'a Arena) -> Request<'a> {
+    let mut lines = text.lines();
+    let first_line = lines.next().unwrap_or("GET");
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or("GET");
+    let url = parts.next().unwrap_or("/");
+    let header_lines = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            Some((parts.next()?.trim(), parts.next()?.trim()))
+        });
+    let mut headers = header_lines.clone();
+    let headers = arena.alloc_slice_fill_with(header_lines.count(), |_| headers.next().expect("header"));
+    let mut url_parts = url.split('?');
+    Request {
+        method: Method::from_str(method),
+        path: url_parts.next().unwrap_or("/"),
+        query_string: url_parts.next().unwrap_or(""),
+        headers,
     }
 }
 
 struct Server<HANDLER> {
+    /// Holds the parsed headers of the current request, reset for each request.
+    arena: Arena,
     handler: HANDLER,
+    limits: Limits,
 }
 
 impl<HANDLER: HttpHandler> Server<HANDLER> {
-    fn new(handler: HANDLER) -> Self {
+    fn new(handler: HANDLER, limits: Limits) -> Self {
         Self {
+            arena: Arena::new(),
             handler,
+            limits,
         }
     }
 }
@@ -63,23 +163,31 @@ impl<HANDLER: HttpHandler> TcpConnectionNotify for Server<HANDLER> {
     fn accepted(&mut self, _connection: &mut TcpConnection) {
     }
 
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
-        let request = String::from_utf8(data).unwrap_or_else(|_| String::new());
-        let mut lines = request.lines();
-        let first_line = lines.next().unwrap_or("GET");
-        let mut parts = first_line.split_whitespace();
-        let method = parts.next().unwrap_or("GET");
-        let url = parts.next().unwrap_or("/");
-        let mut url_parts = url.split('?');
-        let request = Request {
-            method: Method::from_str(method),
-            path: url_parts.next().unwrap_or("/").to_string(),
-            query_string: url_parts.next().unwrap_or("").to_string(),
-        };
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
+        self.arena.reset();
+        let request = parse_request(str::from_utf8(&data).unwrap_or(""), &self.arena);
+        let gzip = request.headers.iter()
+            .filter(|&&(name, _)| name.eq_ignore_ascii_case("Accept-Encoding"))
+            .any(|&(_, value)| accepts_gzip(value));
+        if !self.limits.allow_request(request.path, connection.peer_addr().ok()) {
+            let response = format!("HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nDate: {}\r\n\r\n",
+                format_http_date(SystemTime::now()));
+            let _ = connection.write(response); // TODO: handle errors.
+            return;
+        }
         let content = self.handler.request(&request);
-        let len = content.len();
-        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/html\r\n\r\n{}", len, content);
-        let _ = connection.write(response.into_bytes()); // TODO: handle errors.
+        let (body, encoding) =
+            if gzip && content.len() >= MIN_COMPRESSED_LEN {
+                (compress::compress(Format::Gzip, Level::Default, content.as_bytes()),
+                    "Content-Encoding: gzip\r\nVary: Accept-Encoding\r\n")
+            }
+            else {
+                (content.into_bytes(), "")
+            };
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n{}Content-Type: text/html\r\nDate: {}\r\n\r\n",
+            body.len(), encoding, format_http_date(SystemTime::now())).into_bytes();
+        response.extend_from_slice(&body);
+        let _ = connection.write(response); // TODO: handle errors.
     }
 
     fn closed(&mut self, _connection: &mut TcpConnection) {
@@ -112,10 +220,22 @@ impl Display for Method {
     }
 }
 
-pub struct Request {
+/// A request borrowing the received data, valid during the call to `HttpHandler::request`.
+pub struct Request<'a> {
     pub method: Method,
-    pub path: String,
-    pub query_string: String,
+    pub path: &'a str,
+    pub query_string: &'a str,
+    /// Names and values of the headers, trimmed.
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+impl<'a> Request<'a> {
+    /// Returns the value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.iter()
+            .find(|&&(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    }
 }
 
 pub trait HttpHandler: Clone {
@@ -125,6 +245,50 @@ pub trait HttpHandler: Clone {
 pub fn serve<HANDLER>(event_loop: &mut Loop, addr: &str, handler: HANDLER) -> io::Result<Stream<ListenerMsg>>
 where HANDLER: HttpHandler + 'static,
 {
-    TcpListener::ip4(event_loop, addr, Listener::new(handler))
+    serve_with_limits(event_loop, addr, handler, Limits::new())
+}
+
+/// Like `serve`, but refuses the connections and answers 429 Too Many Requests to the requests
+/// exceeding the limits.
+pub fn serve_with_limits<HANDLER>(event_loop: &mut Loop, addr: &str, handler: HANDLER, limits: Limits)
+    -> io::Result<Stream<ListenerMsg>>
+where HANDLER: HttpHandler + 'static,
+{
+    TcpListener::ip4(event_loop, addr, Listener::new(handler, limits))
         .map(|(stream, _addr)| stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use arena::Arena;
+    use super::{Method, accepts_gzip, parse_request};
+
+    #[test]
+    fn request() {
+        let mut arena = Arena::new();
+        {
+            let request = parse_request("POST /items?id=3 HTTP/1.1\r\nHost: localhost\r\nX-Empty:\r\n\r\nbody: no",
+                &arena);
+            assert!(request.method == Method::Post);
+            assert_eq!((request.path, request.query_string), ("/items", "id=3"));
+            assert_eq!(request.headers, &[("Host", "localhost"), ("X-Empty", "")]);
+            assert_eq!(request.header("host"), Some("localhost"));
+            assert_eq!(request.header("Body"), None);
+        }
+        arena.reset();
+        let request = parse_request("", &arena);
+        assert!(request.method == Method::Get);
+        assert_eq!((request.path, request.query_string), ("/", ""));
+        assert!(request.headers.is_empty());
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert!(accepts_gzip("gzip, deflate, br"));
+        assert!(accepts_gzip("deflate;q=1.0, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip(""));
+    }
+}
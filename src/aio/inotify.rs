@@ -0,0 +1,217 @@
+//! File system events from inotify, whose fd becomes readable when a watched path changes and can
+//! thus be registered on the event loop.
+
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use aio::net::close;
+
+/// A file was created in a watched directory.
+pub const CREATE: u32 = ffi::IN_CREATE;
+/// A file was deleted from a watched directory.
+pub const DELETE: u32 = ffi::IN_DELETE;
+/// The watched path itself was deleted.
+pub const DELETE_SELF: u32 = ffi::IN_DELETE_SELF;
+/// A file was modified.
+pub const MODIFY: u32 = ffi::IN_MODIFY;
+/// A file opened for writing was closed: convenient to reload a file once fully written.
+pub const CLOSE_WRITE: u32 = ffi::IN_CLOSE_WRITE;
+/// A file was moved out of or into a watched directory.
+pub const MOVE: u32 = ffi::IN_MOVED_FROM | ffi::IN_MOVED_TO;
+/// The watched path itself was moved.
+pub const MOVE_SELF: u32 = ffi::IN_MOVE_SELF;
+/// The metadata of a file changed.
+pub const ATTRIB: u32 = ffi::IN_ATTRIB;
+/// All the above.
+pub const ALL: u32 = CREATE | DELETE | DELETE_SELF | MODIFY | CLOSE_WRITE | MOVE | MOVE_SELF | ATTRIB;
+
+const BUFFER_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileEventKind {
+    Created,
+    Deleted,
+    /// Written to, or closed after being written to.
+    Modified,
+    /// Moved out of the watched directory, or the watched path itself was moved.
+    MovedFrom,
+    /// Moved into the watched directory.
+    MovedTo,
+    /// Metadata changes, the removal of the watch (`ffi::IN_IGNORED`) or an event queue overflow.
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileEvent {
+    pub kind: FileEventKind,
+    /// The inotify mask of the event.
+    pub mask: u32,
+    /// Identical for the `MovedFrom` and `MovedTo` events of a rename.
+    pub cookie: u32,
+    /// The file in the watched directory, or `None` for the watched path itself.
+    pub name: Option<PathBuf>,
+    pub watch: Watch,
+}
+
+impl FileEvent {
+    pub fn is_dir(&self) -> bool {
+        self.mask & ffi::IN_ISDIR != 0
+    }
+
+    /// Returns true if the watch was removed, e.g. after the watched path was deleted: no events
+    /// will follow for it.
+    pub fn is_watch_removed(&self) -> bool {
+        self.mask & ffi::IN_IGNORED != 0
+    }
+}
+
+fn kind(mask: u32) -> FileEventKind {
+    if mask & ffi::IN_CREATE != 0 {
+        FileEventKind::Created
+    }
+    else if mask & (ffi::IN_DELETE | ffi::IN_DELETE_SELF) != 0 {
+        FileEventKind::Deleted
+    }
+    else if mask & (ffi::IN_MODIFY | ffi::IN_CLOSE_WRITE) != 0 {
+        FileEventKind::Modified
+    }
+    else if mask & (ffi::IN_MOVED_FROM | ffi::IN_MOVE_SELF) != 0 {
+        FileEventKind::MovedFrom
+    }
+    else if mask & ffi::IN_MOVED_TO != 0 {
+        FileEventKind::MovedTo
+    }
+    else {
+        FileEventKind::Other
+    }
+}
+
+/// A watch descriptor, identifying a watched path of an `Inotify`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Watch(i32);
+
+/// A nonblocking inotify instance.
+pub struct Inotify {
+    fd: RawFd,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { ffi::inotify_init1(ffi::IN_NONBLOCK | ffi::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd,
+        })
+    }
+
+    /// Watches `path` for the events of `mask`, e.g. `CREATE | MODIFY`. Watching a path again
+    /// replaces its mask.
+    pub fn add_watch<P: AsRef<Path>>(&self, path: P, mask: u32) -> io::Result<Watch> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))?;
+        let watch = unsafe { ffi::inotify_add_watch(self.fd, path.as_ptr(), mask) };
+        if watch == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watch(watch))
+    }
+
+    pub fn remove_watch(&self, watch: Watch) -> io::Result<()> {
+        if unsafe { ffi::inotify_rm_watch(self.fd, watch.0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` for each waiting event, until none is left.
+    pub fn read<F>(&self, mut callback: F) -> io::Result<()>
+    where F: FnMut(FileEvent),
+    {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        loop {
+            let size = unsafe { ffi::read(self.fd, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+            if size == -1 {
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                }
+            }
+            let size = size as usize;
+            let mut offset = 0;
+            while offset + mem::size_of::<ffi::inotify_event>() <= size {
+                let event = unsafe { ptr::read_unaligned(buffer[offset..].as_ptr() as *const ffi::inotify_event) };
+                let name_start = offset + mem::size_of::<ffi::inotify_event>();
+                let name_end = (name_start + event.len as usize).min(size);
+                // The name is padded with nul bytes.
+                let name = buffer[name_start..name_end].split(|&byte| byte == 0).next().unwrap_or(&[]);
+                callback(FileEvent {
+                    kind: kind(event.mask),
+                    mask: event.mask,
+                    cookie: event.cookie,
+                    name: if name.is_empty() { None } else { Some(PathBuf::from(OsStr::from_bytes(name))) },
+                    watch: Watch(event.wd),
+                });
+                offset = name_end;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+pub mod ffi {
+    #![allow(non_camel_case_types)]
+
+    use std::os::raw::{c_char, c_void};
+
+    pub const IN_ACCESS: u32 = 0x00000001;
+    pub const IN_MODIFY: u32 = 0x00000002;
+    pub const IN_ATTRIB: u32 = 0x00000004;
+    pub const IN_CLOSE_WRITE: u32 = 0x00000008;
+    pub const IN_MOVED_FROM: u32 = 0x00000040;
+    pub const IN_MOVED_TO: u32 = 0x00000080;
+    pub const IN_CREATE: u32 = 0x00000100;
+    pub const IN_DELETE: u32 = 0x00000200;
+    pub const IN_DELETE_SELF: u32 = 0x00000400;
+    pub const IN_MOVE_SELF: u32 = 0x00000800;
+    pub const IN_Q_OVERFLOW: u32 = 0x00004000;
+    pub const IN_IGNORED: u32 = 0x00008000;
+    pub const IN_ISDIR: u32 = 0x40000000;
+
+    pub const IN_CLOEXEC: i32 = 0o2000000;
+    pub const IN_NONBLOCK: i32 = 0o4000;
+
+    #[repr(C)]
+    pub struct inotify_event {
+        pub wd: i32,
+        pub mask: u32,
+        pub cookie: u32,
+        pub len: u32,
+    }
+
+    extern "C" {
+        pub fn inotify_add_watch(fd: i32, pathname: *const c_char, mask: u32) -> i32;
+        pub fn inotify_init1(flags: i32) -> i32;
+        pub fn inotify_rm_watch(fd: i32, wd: i32) -> i32;
+        pub fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
+    }
+}
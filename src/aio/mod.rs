@@ -1,8 +1,16 @@
 pub mod async;
+mod callback;
+pub mod fs;
 pub mod handler;
 pub mod http;
 pub mod http_server;
+pub mod inotify;
 pub mod net;
+pub mod process;
+pub mod reactor;
+pub mod runtime;
+pub mod shared;
 mod slab;
 pub mod stdio;
+pub mod timer;
 mod uhttp_uri;
@@ -27,9 +27,18 @@ use aio::handler::{
     Handler,
     Stream,
 };
+use bytes::{BufferPool, Bytes};
 
 use self::ListenerMsg::*;
 
+const READ_BUFFER_SIZE: usize = 4096;
+/// Maximum number of idle read buffers kept per thread.
+const MAX_IDLE_READ_BUFFERS: usize = 64;
+
+thread_local! {
+    static READ_BUFFERS: BufferPool = BufferPool::new(READ_BUFFER_SIZE, MAX_IDLE_READ_BUFFERS);
+}
+
 #[repr(u32)]
 enum StatusMode {
     Error = async::ffi::EPOLLERR,
@@ -54,8 +63,10 @@ pub fn set_nonblocking<A: AsRawFd>(socket: &A) -> io::Result<()> {
 }
 
 pub mod tcp {
+    use std::io;
+    use std::iter::Flatten;
     use std::mem;
-    use std::net::TcpStream;
+    use std::net::{IpAddr, TcpStream};
     use std::os::unix::io::FromRawFd;
     use std::marker::PhantomData;
 
@@ -66,6 +77,7 @@ pub mod tcp {
         Loop,
         Stream,
     };
+    use dns::Resolver;
     use self::ffi::ErrNo;
     use self::Msg::*;
     use super::{
@@ -84,9 +96,13 @@ pub mod tcp {
         socket,
     };
 
+    /// The addresses to try, from one or several calls to getaddrinfo().
+    pub type Addresses = Flatten<::std::vec::IntoIter<AddrInfoIter>>;
+
     pub enum Msg<NOTIFY> {
-        TryingConnectionToHost(NOTIFY, AddrInfoIter, u32),
-        WriteEvent(epoll_event, TcpConnection, NOTIFY, AddrInfoIter, u32),
+        Resolved(NOTIFY, io::Result<Vec<IpAddr>>, String),
+        TryingConnectionToHost(NOTIFY, Addresses, u32),
+        WriteEvent(epoll_event, TcpConnection, NOTIFY, Addresses, u32),
     }
 
     struct Connector<NOTIFY> {
@@ -112,6 +128,26 @@ pub mod tcp {
 
         fn update(&mut self, stream: &Stream<Msg<NOTIFY>>, msg: Msg<NOTIFY>) {
             match msg {
+                Resolved(mut connection_notify, result, port) => {
+                    match result {
+                        Ok(addresses) => {
+                            let address_infos: Vec<_> = addresses.iter()
+                                .filter_map(|address| {
+                                    let mut hints: ffi::addrinfo = unsafe { mem::zeroed() };
+                                    hints.ai_socktype = ffi::SOCK_STREAM as i32;
+                                    // The address is already resolved: this does not block.
+                                    hints.ai_flags = ffi::AI_NUMERICHOST;
+                                    getaddrinfo(Some(&address.to_string()), Some(&port), Some(hints)).ok()
+                                })
+                                .collect();
+                            stream.send(TryingConnectionToHost(connection_notify, address_infos.into_iter().flatten(), 0));
+                        },
+                        Err(error) => {
+                            connection_notify.error(error);
+                            connection_notify.connect_failed();
+                        },
+                    }
+                },
                 TryingConnectionToHost(mut connection_notify, mut address_infos, count) => {
                     match address_infos.next() {
                         Some(address_info) => {
@@ -235,7 +271,7 @@ pub mod tcp {
                 let connection_stream = event_loop.spawn(Connection::new());
                 let connector = Connector::new(&connection_stream, event_loop);
                 let stream = event_loop.spawn(connector);
-                stream.send(TryingConnectionToHost(connection_notify, address_infos, 0));
+                stream.send(TryingConnectionToHost(connection_notify, vec![address_infos].into_iter().flatten(), 0));
                 Some(connection_stream)
             },
             Err(error) => {
@@ -244,6 +280,20 @@ pub mod tcp {
             },
         }
     }
+
+    /// Like `connect_to_host()`, but resolves the host with the DNS resolver instead of the blocking
+    /// getaddrinfo(). Failures to resolve the host are reported to `connection_notify`.
+    pub fn connect_to_host_with_resolver<NOTIFY>(host: &str, port: &str, resolver: &Resolver, event_loop: &mut Loop,
+        connection_notify: NOTIFY) -> Stream<ConnectionMsg>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
+        let connection_stream = event_loop.spawn(Connection::new());
+        let connector = Connector::new(&connection_stream, event_loop);
+        let stream = event_loop.spawn(connector);
+        let port = port.to_string();
+        resolver.lookup_ip(host, &stream, move |result| Resolved(connection_notify, result, port));
+        connection_stream
+    }
 }
 
 #[derive(Debug)]
@@ -349,50 +399,20 @@ pub fn getsockopt(socket: RawFd, level: i32, name: i32) -> io::Result<i32> {
     Ok(option_value)
 }
 
-struct Buffer {
-    buffer: Vec<u8>,
-    index: usize,
-}
-
-impl Buffer {
-    fn new(buffer: Vec<u8>, index: usize) -> Self {
-        Self {
-            buffer,
-            index,
-        }
-    }
-
-    fn advance(&mut self, count: usize) {
-        self.index += count;
-    }
-
-    fn exhausted(&self) -> bool {
-        self.index >= self.len()
-    }
-
-    fn len(&self) -> usize {
-        self.buffer.len()
-    }
-
-    fn slice(&self) -> &[u8] {
-        &self.buffer[self.index..]
-    }
-}
-
 pub enum ConnectionMsg {
     Connected(Stream<ConnectionComponentMsg>),
-    Write(Vec<u8>),
+    Write(Bytes),
 }
 
 pub enum ConnectionComponentMsg {
     ReadWrite(epoll_event),
     Send,
-    Write(Vec<u8>),
+    Write(Bytes),
 }
 
 struct _TcpConnection {
     // TODO: should the VecDeque be bounded?
-    buffers: VecDeque<Buffer>, // The system should probably reuse the buffer and keep adding to it even if the trait does not consume its data. That should be better than a Vec inside a VecDeque.
+    buffers: VecDeque<Bytes>,
     disposed: bool,
     handle: Option<Stream<ConnectionComponentMsg>>,
     muted: bool,
@@ -404,11 +424,11 @@ impl _TcpConnection {
         let mut remove_buffer = false;
         if let Some(ref mut first_buffer) = self.buffers.front_mut() {
             if let Some(ref mut stream) = self.stream {
-                match stream.write(first_buffer.slice()) {
+                match stream.write(first_buffer) {
                     Ok(written) => {
                         connection_notify.sent();
                         first_buffer.advance(written);
-                        if first_buffer.exhausted() {
+                        if first_buffer.is_empty() {
                             remove_buffer = true;
                         }
                     },
@@ -461,6 +481,11 @@ impl TcpConnection {
         self.connection.borrow().disposed
     }
 
+    /// Returns true once the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.connection.borrow().stream.is_none()
+    }
+
     pub fn ip4<NOTIFY>(event_loop: &mut Loop, host: &str, port: u16, connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
     where NOTIFY: TcpConnectionNotify + 'static,
     {
@@ -475,6 +500,32 @@ impl TcpConnection {
         self.connection.borrow().muted
     }
 
+    pub fn peer_addr(&self) -> io::Result<net::SocketAddr> {
+        match self.connection.borrow().stream {
+            Some(ref stream) => stream.peer_addr(),
+            None => Err(io::Error::new(ErrorKind::NotConnected, "connection closed")),
+        }
+    }
+
+    /// Returns the number of bytes written but not yet sent because the socket was full.
+    pub fn pending_writes(&self) -> usize {
+        self.connection.borrow().buffers.iter().map(Bytes::len).sum()
+    }
+
+    /// Reads the urgent byte sent with `MSG_OOB`, unless the socket has `SO_OOBINLINE` set.
+    fn read_urgent(&self) -> io::Result<u8> {
+        let fd =
+            match self.as_raw_fd() {
+                Some(fd) => fd,
+                None => return Err(io::Error::new(ErrorKind::NotConnected, "connection closed")),
+            };
+        let mut byte = 0u8;
+        if unsafe { ffi::recv(fd, &mut byte as *mut u8 as *mut _, 1, ffi::MSG_OOB) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(byte)
+    }
+
     fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
         if let Some(ref mut stream) = self.connection.borrow_mut().stream {
             stream.read(buffer)
@@ -497,20 +548,19 @@ impl TcpConnection {
         self.connection.borrow_mut().muted = false;
     }
 
-    pub fn write(&self, buffer: Vec<u8>) -> io::Result<()> {
-        let buffer_size = buffer.len();
-        let mut index = 0;
+    pub fn write<B: Into<Bytes>>(&self, buffer: B) -> io::Result<()> {
+        let mut buffer = buffer.into();
         let mut connection = self.connection.borrow_mut();
-        while index < buffer.len() {
+        while !buffer.is_empty() {
             // TODO: yield to avoid starvation?
             let stream =
                 match connection.stream {
                     Some(ref mut stream) => stream,
                     None => break,
                 };
-            match stream.write(&buffer[index..]) {
+            match stream.write(&buffer) {
                 Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
-                    connection.buffers.push_back(Buffer::new(buffer, index));
+                    connection.buffers.push_back(buffer);
                     return Ok(());
                 },
                 Err(error) => return Err(error),
@@ -518,10 +568,7 @@ impl TcpConnection {
                     if let Some(ref handle) = connection.handle {
                         handle.send(ConnectionComponentMsg::Send);
                     }
-                    index += written;
-                    if index >= buffer_size {
-                        return Ok(());
-                    }
+                    buffer.advance(written);
                 },
             }
         }
@@ -549,13 +596,68 @@ impl ConnectionComponent {
     }
 }
 
+impl ConnectionComponent {
+    /// Reads the received data, until none is left when `drain` is true, e.g. before closing the
+    /// connection on hangup. Errors are then left to the hangup handling.
+    fn read(&mut self, drain: bool) {
+        loop {
+            let mut buffer = READ_BUFFERS.with(|pool| pool.get());
+            buffer.resize(READ_BUFFER_SIZE, 0);
+            match self.connection.read(&mut buffer) {
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
+                    error.kind() == ErrorKind::Interrupted => return,
+                Ok(bytes_read) if bytes_read > 0 => {
+                    buffer.truncate(bytes_read);
+                    self.connection_notify.received(&mut self.connection, buffer.freeze());
+                    if !drain || self.connection.as_raw_fd().is_none() || self.connection.muted() {
+                        return;
+                    }
+                },
+                Ok(_) => {
+                    if let Some(fd) = self.connection.as_raw_fd() {
+                        let _ = self.event_loop.remove_raw_fd(fd);
+                    }
+                    self.connection_notify.closed(&mut self.connection);
+                    self.connection.close();
+                    // TODO: remove the handler as well.
+                    return;
+                },
+                Err(_) if drain => return,
+                Err(_) => {
+                    if let Some(fd) = self.connection.as_raw_fd() {
+                        let _ = self.event_loop.remove_raw_fd(fd);
+                    }
+                    // TODO: remove the handler as well.
+                    return;
+                },
+            }
+        }
+    }
+}
+
 impl Handler for ConnectionComponent {
     type Msg = ConnectionComponentMsg;
 
     fn update(&mut self, _stream: &Stream<Self::Msg>, msg: Self::Msg) {
         match msg {
             ConnectionComponentMsg::ReadWrite(event) => {
-                if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
+                let hangup = (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0;
+                // Read the urgent byte first, since the kernel discards it if the normal data is
+                // read past it.
+                if event.events & async::ffi::EPOLLPRI != 0 {
+                    match self.connection.read_urgent() {
+                        Ok(byte) => self.connection_notify.urgent(&mut self.connection, byte),
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
+                            error.kind() == ErrorKind::Interrupted ||
+                            error.kind() == ErrorKind::InvalidInput => (),
+                        Err(error) => self.connection_notify.error(error),
+                    }
+                }
+                // On hangup, the data received before it is still delivered.
+                if (event.events & Mode::Read as u32 != 0 || hangup) && !self.connection.muted() {
+                    self.read(hangup);
+                }
+                if hangup {
                     // TODO: do we want to signal these errors to the trait?
                     // TODO: are we sure we want to remove the fd from epoll when there's an error?
                     if let Some(fd) = self.connection.as_raw_fd() {
@@ -568,33 +670,6 @@ impl Handler for ConnectionComponent {
                         // TODO: stop handler.
                     }
                 }
-                if event.events & Mode::Read as u32 != 0 && !self.connection.muted() {
-                    let mut buffer = vec![0; 4096];
-                    match self.connection.read(&mut buffer) {
-                        Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
-                            error.kind() == ErrorKind::Interrupted => (),
-                        Ok(bytes_read) => {
-                            if bytes_read > 0 {
-                                buffer.truncate(bytes_read);
-                                self.connection_notify.received(&mut self.connection, buffer);
-                            }
-                            else {
-                                if let Some(fd) = self.connection.as_raw_fd() {
-                                    let _ = self.event_loop.remove_raw_fd(fd);
-                                }
-                                self.connection_notify.closed(&mut self.connection);
-                                self.connection.close();
-                                // TODO: remove the handler as well.
-                            }
-                        },
-                        Err(_) => {
-                            if let Some(fd) = self.connection.as_raw_fd() {
-                                let _ = self.event_loop.remove_raw_fd(fd);
-                            }
-                            // TODO: remove the handler as well.
-                        },
-                    }
-                }
                 if event.events & Mode::Write as u32 != 0 {
                     self.connection.send(&mut self.event_loop, &mut *self.connection_notify);
                 }
@@ -620,6 +695,12 @@ impl Handler for ConnectionComponent {
 }
 
 pub trait TcpListenNotify {
+    /// Returns false to close the connection from `addr` right away, without calling `connected`,
+    /// e.g. to throttle clients with a `ratelimit::KeyedLimiter`.
+    fn accept(&mut self, _addr: &net::SocketAddr) -> bool {
+        true
+    }
+
     fn listening(&mut self, _listener: &net::TcpListener) {
     }
 
@@ -663,7 +744,11 @@ pub trait TcpConnectionNotify {
         0
     }
 
-    fn received(&mut self, _connection: &mut TcpConnection, _data: Vec<u8>) {
+    fn received(&mut self, _connection: &mut TcpConnection, _data: Bytes) {
+    }
+
+    /// Called with the urgent byte sent by the peer with `MSG_OOB`.
+    fn urgent(&mut self, _connection: &mut TcpConnection, _byte: u8) {
     }
 
     fn closed(&mut self, _connection: &mut TcpConnection) {
@@ -692,7 +777,7 @@ fn manage_connection(event_loop: &mut Loop, mut connection: TcpConnection, mut c
             Some(fd) => fd,
             None => return,
         };
-    match event_loop.try_add_raw_fd(fd, Mode::ReadWrite) {
+    match event_loop.try_add_raw_fd(fd, Mode::ReadWritePriority) {
         Ok(event) => {
             let component = ConnectionComponent::new(connection.clone(), connection_notify, event_loop);
             let stream = event_loop.spawn(component);
@@ -762,7 +847,7 @@ where L: TcpListenNotify,
                     if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
                         // TODO: do we want to signal these errors to the trait?
                         // TODO: are we sure we want to remove the fd from epoll when there's an error?
-                        if let Err(error) = self.event_loop.remove_raw_fd(tcp_listener.as_raw_fd()) { // TODO: do a version of this method that takes a AsRawFd.
+                        if let Err(error) = self.event_loop.remove_fd(tcp_listener) {
                             // TODO: not sure if it makes sense to report this error to the user.
                             self.listen_notify.error(error);
                         }
@@ -772,6 +857,7 @@ where L: TcpListenNotify,
                     else if event.events & Mode::Read as u32 != 0 {
                         // TODO: accept many times?
                         match tcp_listener.accept() {
+                            Ok((_stream, ref addr)) if !self.listen_notify.accept(addr) => (),
                             Ok((stream, _addr)) => {
                                 match stream.set_nonblocking(true) {
                                     Ok(()) => {
@@ -804,8 +890,12 @@ pub mod ffi {
         InProgress = 115,
     }
 
+    pub const AI_NUMERICHOST: i32 = 4;
+
     pub const EAI_SYSTEM: i32 = -11;
 
+    pub const MSG_OOB: i32 = 1;
+
     pub const F_GETFL: i32 = 3;
     pub const F_SETFL: i32 = 4;
 
@@ -849,6 +939,8 @@ pub mod ffi {
 
         pub fn getsockopt(socket: i32, level: i32, option_name: i32, option_value: *mut c_void, option_len: *mut socklen_t)
             -> i32;
+        pub fn recv(socket: i32, buffer: *mut c_void, length: usize, flags: i32) -> isize;
+        pub fn send(socket: i32, buffer: *const c_void, length: usize, flags: i32) -> isize;
         pub fn socket(domain: i32, typ: i32, protocol: i32) -> i32;
     }
 }
@@ -0,0 +1,450 @@
+//! Child processes whose pipes are driven by the event loop.
+//!
+//! `spawn()` starts a command with piped stdin, stdout and stderr, all nonblocking, and reports the
+//! output and the exit status to a `ProcessNotify`. The exit is detected with a pidfd, which
+//! requires Linux 5.3 or later; the output still buffered in the pipes is delivered before
+//! `exited()` is called.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{
+    self,
+    ErrorKind,
+    Read,
+    Write,
+};
+use std::os::unix::io::{
+    AsRawFd,
+    RawFd,
+};
+use std::process::{
+    Child,
+    ChildStderr,
+    ChildStdin,
+    ChildStdout,
+    Command,
+    ExitStatus,
+    Stdio,
+};
+use std::rc::Rc;
+
+use aio::async::Mode;
+use aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use aio::net::{close, set_nonblocking};
+use bytes::{BufferPool, Bytes};
+use signal::Signal;
+
+use self::Msg::*;
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+enum Msg {
+    Exit,
+    Stderr,
+    Stdin,
+    Stdout,
+}
+
+#[derive(Clone, Copy)]
+enum Output {
+    Stderr,
+    Stdout,
+}
+
+struct _Process {
+    buffers: VecDeque<Bytes>,
+    child: Child,
+    close_stdin: bool,
+    event_loop: Loop,
+    handle: Option<Stream<Msg>>,
+    stdin: Option<ChildStdin>,
+    stdin_registered: bool,
+}
+
+impl _Process {
+    fn close_stdin(&mut self) {
+        if let Some(stdin) = self.stdin.take() {
+            if self.stdin_registered {
+                let _ = self.event_loop.remove_fd(&stdin);
+                self.stdin_registered = false;
+            }
+        }
+        self.buffers.clear();
+    }
+
+    /// Writes the buffered data until the pipe is full.
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some(mut buffer) = self.buffers.pop_front() {
+            let result =
+                match self.stdin {
+                    Some(ref mut stdin) => stdin.write(&buffer),
+                    None => return Ok(()),
+                };
+            match result {
+                Ok(written) => {
+                    buffer.advance(written);
+                    if !buffer.is_empty() {
+                        self.buffers.push_front(buffer);
+                    }
+                },
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => self.buffers.push_front(buffer),
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                    self.buffers.push_front(buffer);
+                    return self.register_stdin();
+                },
+                Err(error) => {
+                    self.close_stdin();
+                    return Err(error);
+                },
+            }
+        }
+        if self.close_stdin {
+            self.close_stdin();
+        }
+        Ok(())
+    }
+
+    /// Waits for the stdin pipe to become writable.
+    fn register_stdin(&mut self) -> io::Result<()> {
+        if self.stdin_registered {
+            return Ok(());
+        }
+        if let (Some(ref stdin), Some(ref handle)) = (self.stdin.as_ref(), self.handle.as_ref()) {
+            let event = self.event_loop.try_add_fd_oneshot(*stdin, Mode::Write)?;
+            event.set_callback(handle, |_event| Stdin);
+            self.stdin_registered = true;
+        }
+        Ok(())
+    }
+}
+
+/// A handle to a child process spawned with `spawn()`.
+#[derive(Clone)]
+pub struct Process {
+    process: Rc<RefCell<_Process>>,
+}
+
+impl Process {
+    /// Closes the stdin of the child once the data written so far is sent, so that it sees the end
+    /// of its input.
+    pub fn close_stdin(&self) {
+        let mut process = self.process.borrow_mut();
+        if process.buffers.is_empty() {
+            process.close_stdin();
+        }
+        else {
+            process.close_stdin = true;
+        }
+    }
+
+    /// Kills the child with SIGKILL.
+    pub fn kill(&self) -> io::Result<()> {
+        self.process.borrow_mut().child.kill()
+    }
+
+    /// Returns the process id of the child.
+    pub fn pid(&self) -> u32 {
+        self.process.borrow().child.id()
+    }
+
+    /// Sends a signal to the child, for instance `Signal::Terminate` to ask it to exit.
+    pub fn signal(&self, signal: Signal) -> io::Result<()> {
+        if unsafe { ffi::kill(self.pid() as i32, signal.as_raw()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Writes data to the stdin of the child. The data that cannot be written right away is
+    /// buffered and sent when the pipe becomes writable.
+    pub fn write<B: Into<Bytes>>(&self, data: B) -> io::Result<()> {
+        let mut process = self.process.borrow_mut();
+        if process.stdin.is_none() || process.close_stdin {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "stdin is closed"));
+        }
+        process.buffers.push_back(data.into());
+        if process.stdin_registered {
+            // The pipe is full: the data will be sent once it is writable.
+            return Ok(());
+        }
+        process.flush()
+    }
+}
+
+struct ProcessComponent<NOTIFY> {
+    event_loop: Loop,
+    notify: NOTIFY,
+    pidfd: Option<RawFd>,
+    pool: BufferPool,
+    process: Process,
+    stderr: Option<ChildStderr>,
+    stdout: Option<ChildStdout>,
+}
+
+impl<NOTIFY> ProcessComponent<NOTIFY>
+where NOTIFY: ProcessNotify,
+{
+    fn exit(&mut self) {
+        let result = self.process.process.borrow_mut().child.try_wait();
+        match result {
+            Ok(Some(status)) => {
+                if let Some(pidfd) = self.pidfd.take() {
+                    let _ = self.event_loop.remove_raw_fd(pidfd);
+                    let _ = close(pidfd);
+                }
+                self.read(Output::Stdout, true);
+                self.read(Output::Stderr, true);
+                self.process.process.borrow_mut().close_stdin();
+                self.notify.exited(&mut self.process, status);
+            },
+            Ok(None) => (),
+            Err(error) => self.notify.error(error),
+        }
+    }
+
+    /// Reads from an output pipe, once or until it is empty when `drain` is true.
+    fn read(&mut self, output: Output, drain: bool) {
+        loop {
+            let result = {
+                let pool = &self.pool;
+                match output {
+                    Output::Stderr => self.stderr.as_mut().map(|stderr| read_chunk(stderr, pool)),
+                    Output::Stdout => self.stdout.as_mut().map(|stdout| read_chunk(stdout, pool)),
+                }
+            };
+            match result {
+                None => return,
+                Some(Ok(data)) => {
+                    if data.is_empty() {
+                        self.close_output(output);
+                        return;
+                    }
+                    match output {
+                        Output::Stderr => self.notify.stderr(&mut self.process, data),
+                        Output::Stdout => self.notify.stdout(&mut self.process, data),
+                    }
+                    if !drain {
+                        return;
+                    }
+                },
+                Some(Err(ref error)) if error.kind() == ErrorKind::Interrupted => (),
+                Some(Err(ref error)) if error.kind() == ErrorKind::WouldBlock => return,
+                Some(Err(error)) => {
+                    self.notify.error(error);
+                    self.close_output(output);
+                    return;
+                },
+            }
+        }
+    }
+
+    fn close_output(&mut self, output: Output) {
+        // The pipe is closed when dropped.
+        match output {
+            Output::Stderr => if let Some(stderr) = self.stderr.take() {
+                let _ = self.event_loop.remove_fd(&stderr);
+            },
+            Output::Stdout => if let Some(stdout) = self.stdout.take() {
+                let _ = self.event_loop.remove_fd(&stdout);
+            },
+        }
+    }
+}
+
+impl<NOTIFY> Handler for ProcessComponent<NOTIFY>
+where NOTIFY: ProcessNotify,
+{
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Exit => self.exit(),
+            Stderr => self.read(Output::Stderr, false),
+            Stdin => {
+                let result = {
+                    let mut process = self.process.process.borrow_mut();
+                    // The oneshot registration must be removed before registering the fd again.
+                    if let Some(ref stdin) = process.stdin {
+                        let _ = self.event_loop.remove_fd(stdin);
+                    }
+                    process.stdin_registered = false;
+                    process.flush()
+                };
+                if let Err(error) = result {
+                    self.notify.error(error);
+                }
+            },
+            Stdout => self.read(Output::Stdout, false),
+        }
+    }
+}
+
+/// Receives the events of a child process.
+pub trait ProcessNotify {
+    /// Called with data written by the child on its stdout.
+    fn stdout(&mut self, _process: &mut Process, _data: Bytes) {
+    }
+
+    /// Called with data written by the child on its stderr.
+    fn stderr(&mut self, _process: &mut Process, _data: Bytes) {
+    }
+
+    /// Called when the child exited, after the remaining output was delivered.
+    fn exited(&mut self, _process: &mut Process, _status: ExitStatus) {
+    }
+
+    /// Called when reading from or writing to the child fails.
+    fn error(&mut self, _error: io::Error) {
+    }
+}
+
+/// Spawns the command with piped stdin, stdout and stderr, reporting its events to `notify`.
+pub fn spawn<NOTIFY>(event_loop: &mut Loop, command: &mut Command, notify: NOTIFY) -> io::Result<Process>
+where NOTIFY: ProcessNotify + 'static,
+{
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take().expect("piped stdin");
+    let stdout = child.stdout.take().expect("piped stdout");
+    let stderr = child.stderr.take().expect("piped stderr");
+    let stdout_fd = stdout.as_raw_fd();
+    let stderr_fd = stderr.as_raw_fd();
+    let result = set_nonblocking(&stdin)
+        .and_then(|()| set_nonblocking(&stdout))
+        .and_then(|()| set_nonblocking(&stderr))
+        .and_then(|()| pidfd_open(child.id()));
+    let pidfd =
+        match result {
+            Ok(pidfd) => pidfd,
+            Err(error) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(error);
+            },
+        };
+
+    let process = Process {
+        process: Rc::new(RefCell::new(_Process {
+            buffers: VecDeque::new(),
+            child,
+            close_stdin: false,
+            event_loop: event_loop.clone(),
+            handle: None,
+            stdin: Some(stdin),
+            stdin_registered: false,
+        })),
+    };
+    let stream = event_loop.spawn(ProcessComponent {
+        event_loop: event_loop.clone(),
+        notify,
+        pidfd: Some(pidfd),
+        pool: BufferPool::new(READ_BUFFER_SIZE, 2),
+        process: process.clone(),
+        stderr: Some(stderr),
+        stdout: Some(stdout),
+    });
+    process.process.borrow_mut().handle = Some(stream.clone());
+    let result = event_loop.add_raw_fd(stdout_fd, Mode::Read, &stream, |_event| Stdout)
+        .and_then(|()| event_loop.add_raw_fd(stderr_fd, Mode::Read, &stream, |_event| Stderr))
+        .and_then(|()| event_loop.add_raw_fd(pidfd, Mode::Read, &stream, |_event| Exit));
+    if let Err(error) = result {
+        let _ = process.kill();
+        let _ = process.process.borrow_mut().child.wait();
+        return Err(error);
+    }
+    Ok(process)
+}
+
+fn read_chunk<R: Read>(pipe: &mut R, pool: &BufferPool) -> io::Result<Bytes> {
+    let mut buffer = pool.get();
+    buffer.resize(READ_BUFFER_SIZE, 0);
+    let size = pipe.read(&mut buffer)?;
+    buffer.truncate(size);
+    Ok(buffer.freeze())
+}
+
+fn pidfd_open(pid: u32) -> io::Result<RawFd> {
+    let fd = unsafe { ffi::syscall(ffi::SYS_PIDFD_OPEN, pid as i64, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd as RawFd)
+}
+
+mod ffi {
+    /// Same number on all architectures since it was added after the syscall table unification.
+    pub const SYS_PIDFD_OPEN: i64 = 434;
+
+    extern "C" {
+        pub fn kill(pid: i32, sig: i32) -> i32;
+        pub fn syscall(number: i64, ...) -> i64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::process::{Command, ExitStatus};
+    use std::rc::Rc;
+
+    use aio::handler::Loop;
+    use bytes::Bytes;
+    use super::{Process, ProcessNotify, spawn};
+
+    #[derive(Default)]
+    struct Output {
+        status: Option<ExitStatus>,
+        stderr: Vec<u8>,
+        stdout: Vec<u8>,
+    }
+
+    struct Collector {
+        event_loop: Loop,
+        output: Rc<RefCell<Output>>,
+    }
+
+    impl ProcessNotify for Collector {
+        fn stdout(&mut self, _process: &mut Process, data: Bytes) {
+            self.output.borrow_mut().stdout.extend_from_slice(&data);
+        }
+
+        fn stderr(&mut self, _process: &mut Process, data: Bytes) {
+            self.output.borrow_mut().stderr.extend_from_slice(&data);
+        }
+
+        fn exited(&mut self, _process: &mut Process, status: ExitStatus) {
+            self.output.borrow_mut().status = Some(status);
+            self.event_loop.stop();
+        }
+    }
+
+    #[test]
+    fn pipes_and_status() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let output = Rc::new(RefCell::new(Output::default()));
+        let collector = Collector {
+            event_loop: event_loop.clone(),
+            output: output.clone(),
+        };
+        let mut command = Command::new("sh");
+        command.args(&["-c", "echo start; echo oops >&2; wc -c; exit 3"]);
+        let process = spawn(&mut event_loop, &mut command, collector).expect("spawn");
+        // Larger than the pipe buffer to exercise the buffering of stdin.
+        process.write(vec![b'a'; 200_000]).expect("write");
+        process.close_stdin();
+        assert!(process.write("more").is_err());
+        event_loop.run().expect("run");
+
+        let output = output.borrow();
+        assert_eq!(output.status.and_then(|status| status.code()), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout).split_whitespace().collect::<Vec<_>>(), ["start", "200000"]);
+        assert_eq!(output.stderr, b"oops\n");
+    }
+}
@@ -0,0 +1,563 @@
+//! Futures running on an `EventLoop`, so that `async fn` code can be written against this crate.
+//!
+//! A `Reactor` wakes the tasks waiting for an fd to become ready and `Reactor::block_on` runs a
+//! future to completion on the loop. `TcpStream` and `TcpListener` adapt the standard sockets to
+//! `AsyncRead`, `AsyncWrite` and an accept future.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::io::{Read as IoRead, Write as IoWrite};
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use aio::async::{Action, EpollResult, EventLoop, Mode, Notifier, ffi};
+
+/// The readiness a task waits for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interest {
+    Read,
+    Write,
+}
+
+/// Tasks waiting for an fd.
+#[derive(Default)]
+struct Waiters {
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+    /// Events the fd is registered for in the event loop, 0 if it is not registered.
+    events: u32,
+}
+
+impl Waiters {
+    fn mode(&self) -> Option<Mode> {
+        match (self.reader.is_some(), self.writer.is_some()) {
+            (true, true) => Some(Mode::ReadWrite),
+            (true, false) => Some(Mode::Read),
+            (false, true) => Some(Mode::Write),
+            (false, false) => None,
+        }
+    }
+
+    fn wanted_events(&self) -> u32 {
+        self.mode().map_or(0, |mode| mode as u32)
+    }
+}
+
+struct Inner {
+    event_loop: EventLoop,
+    notifier: Notifier,
+    waiters: HashMap<RawFd, Waiters>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = self.event_loop.remove_fd(&self.notifier);
+    }
+}
+
+/// Wakes the tasks waiting for fds registered on an `EventLoop`.
+#[derive(Clone)]
+pub struct Reactor {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Reactor {
+    pub fn new(event_loop: &EventLoop) -> io::Result<Self> {
+        // Wakes up the loop when a task is woken from another thread.
+        let notifier = event_loop.add_notifier(|| ())?;
+        Ok(Self {
+            inner: Rc::new(RefCell::new(Inner {
+                event_loop: event_loop.clone(),
+                notifier,
+                waiters: HashMap::new(),
+            })),
+        })
+    }
+
+    pub fn event_loop(&self) -> EventLoop {
+        self.inner.borrow().event_loop.clone()
+    }
+
+    /// Wakes `waker` once `fd` is ready for `interest`, or on error. Only one task per interest
+    /// can wait for an fd: the last waker replaces the previous one.
+    pub fn wake_when_ready(&self, fd: RawFd, interest: Interest, waker: &Waker) -> io::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let (registered, mode) = {
+            let waiters = inner.waiters.entry(fd).or_default();
+            let waiter =
+                match interest {
+                    Interest::Read => &mut waiters.reader,
+                    Interest::Write => &mut waiters.writer,
+                };
+            match *waiter {
+                Some(ref current) if current.will_wake(waker) => (),
+                _ => *waiter = Some(waker.clone()),
+            }
+            let events = waiters.wanted_events();
+            if events == waiters.events {
+                return Ok(());
+            }
+            let registered = waiters.events != 0;
+            waiters.events = events;
+            (registered, waiters.mode().expect("waiting task"))
+        };
+        let result =
+            if registered {
+                inner.event_loop.modify_raw_fd(fd, mode)
+            }
+            else {
+                let weak_inner = Rc::downgrade(&self.inner);
+                inner.event_loop.add_raw_fd(fd, mode, move |event| ready(&weak_inner, fd, event))
+            };
+        if result.is_err() {
+            inner.waiters.remove(&fd);
+        }
+        result
+    }
+
+    /// Forgets the tasks waiting for `fd`. Must be called before closing it.
+    pub fn remove(&self, fd: RawFd) {
+        let waiters = self.inner.borrow_mut().waiters.remove(&fd);
+        if let Some(waiters) = waiters {
+            if waiters.events != 0 {
+                let _ = self.event_loop().remove_raw_fd(fd);
+            }
+        }
+    }
+
+    /// Runs the event loop until `future` completes and returns its output.
+    pub fn block_on<F: Future>(&self, future: F) -> io::Result<F::Output> {
+        let (event_loop, notifier) = {
+            let inner = self.inner.borrow();
+            (inner.event_loop.clone(), inner.notifier.clone())
+        };
+        let mut future = Box::pin(future);
+        let task = Arc::new(Task {
+            notifier,
+            woken: AtomicBool::new(true),
+        });
+        let waker = Waker::from(task.clone());
+        let mut context = Context::from_waker(&waker);
+        loop {
+            if task.woken.swap(false, Ordering::SeqCst) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return Ok(output);
+                }
+                continue;
+            }
+            if let EpollResult::Error(error) = event_loop.iterate() {
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// Wakes the tasks waiting for the event and stops watching the fd when no task is left.
+fn ready(weak_inner: &Weak<RefCell<Inner>>, fd: RawFd, event: ffi::epoll_event) -> Action {
+    let inner =
+        match weak_inner.upgrade() {
+            Some(inner) => inner,
+            None => return Action::Stop,
+        };
+    let (reader, writer, action) = {
+        let mut inner = inner.borrow_mut();
+        let inner = &mut *inner;
+        let waiters =
+            match inner.waiters.get_mut(&fd) {
+                Some(waiters) => waiters,
+                None => return Action::Stop,
+            };
+        let errors = ffi::EPOLLERR | ffi::EPOLLHUP;
+        let reader = if event.events & (ffi::EPOLLIN | errors) != 0 { waiters.reader.take() } else { None };
+        let writer = if event.events & (ffi::EPOLLOUT | errors) != 0 { waiters.writer.take() } else { None };
+        let action =
+            match waiters.mode() {
+                None => {
+                    inner.waiters.remove(&fd);
+                    Action::Stop
+                },
+                Some(mode) => {
+                    let events = waiters.wanted_events();
+                    if events != waiters.events {
+                        waiters.events = events;
+                        let _ = inner.event_loop.modify_raw_fd(fd, mode);
+                    }
+                    Action::Continue
+                },
+            };
+        (reader, writer, action)
+    };
+    if let Some(reader) = reader {
+        reader.wake();
+    }
+    if let Some(writer) = writer {
+        writer.wake();
+    }
+    action
+}
+
+/// The task run by `Reactor::block_on`.
+struct Task {
+    notifier: Notifier,
+    woken: AtomicBool,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.notifier.notify();
+    }
+}
+
+/// Calls `operation` until it does not block, waiting for `fd` to be ready for `interest` if it
+/// does.
+fn poll_io<T, F>(reactor: &Reactor, fd: RawFd, interest: Interest, context: &mut Context, mut operation: F)
+    -> Poll<io::Result<T>>
+where F: FnMut() -> io::Result<T>,
+{
+    loop {
+        match operation() {
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => (),
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                if let Err(error) = reactor.wake_when_ready(fd, interest, context.waker()) {
+                    return Poll::Ready(Err(error));
+                }
+                return Poll::Pending;
+            },
+            result => return Poll::Ready(result),
+        }
+    }
+}
+
+pub trait AsyncRead {
+    /// Reads into `buffer`, returning `Poll::Pending` and waking the task later if no data is
+    /// available.
+    fn poll_read(&mut self, context: &mut Context, buffer: &mut [u8]) -> Poll<io::Result<usize>>;
+
+    /// Returns a future reading into `buffer` and resolving to the number of bytes read, 0 at the
+    /// end of the stream.
+    fn read<'a>(&'a mut self, buffer: &'a mut [u8]) -> Read<'a, Self>
+    where Self: Sized,
+    {
+        Read {
+            buffer,
+            reader: self,
+        }
+    }
+}
+
+pub trait AsyncWrite {
+    /// Writes from `buffer`, returning `Poll::Pending` and waking the task later if the write
+    /// would block.
+    fn poll_write(&mut self, context: &mut Context, buffer: &[u8]) -> Poll<io::Result<usize>>;
+
+    /// Returns a future writing from `buffer` and resolving to the number of bytes written.
+    fn write<'a>(&'a mut self, buffer: &'a [u8]) -> Write<'a, Self>
+    where Self: Sized,
+    {
+        Write {
+            buffer,
+            writer: self,
+        }
+    }
+
+    /// Returns a future writing the whole `buffer`.
+    fn write_all<'a>(&'a mut self, buffer: &'a [u8]) -> WriteAll<'a, Self>
+    where Self: Sized,
+    {
+        WriteAll {
+            buffer,
+            writer: self,
+        }
+    }
+}
+
+/// Future returned by `AsyncRead::read`.
+pub struct Read<'a, R: 'a> {
+    buffer: &'a mut [u8],
+    reader: &'a mut R,
+}
+
+impl<'a, R: AsyncRead> Future for Read<'a, R> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.reader.poll_read(context, this.buffer)
+    }
+}
+
+/// Future returned by `AsyncWrite::write`.
+pub struct Write<'a, W: 'a> {
+    buffer: &'a [u8],
+    writer: &'a mut W,
+}
+
+impl<'a, W: AsyncWrite> Future for Write<'a, W> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.writer.poll_write(context, this.buffer)
+    }
+}
+
+/// Future returned by `AsyncWrite::write_all`.
+pub struct WriteAll<'a, W: 'a> {
+    buffer: &'a [u8],
+    writer: &'a mut W,
+}
+
+impl<'a, W: AsyncWrite> Future for WriteAll<'a, W> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while !this.buffer.is_empty() {
+            match this.writer.poll_write(context, this.buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Ready(Ok(0)) =>
+                    return Poll::Ready(Err(io::Error::new(ErrorKind::WriteZero, "failed to write whole buffer"))),
+                Poll::Ready(Ok(size)) => this.buffer = &this.buffer[size..],
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A nonblocking TCP stream waiting on a `Reactor`.
+pub struct TcpStream {
+    reactor: Reactor,
+    stream: net::TcpStream,
+}
+
+impl TcpStream {
+    /// Makes `stream`, e.g. connected with `std::net::TcpStream::connect`, nonblocking.
+    pub fn new(reactor: &Reactor, stream: net::TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            reactor: reactor.clone(),
+            stream,
+        })
+    }
+
+    pub fn get_ref(&self) -> &net::TcpStream {
+        &self.stream
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(&mut self, context: &mut Context, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        let stream = &mut self.stream;
+        poll_io(&self.reactor, stream.as_raw_fd(), Interest::Read, context, || stream.read(buffer))
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(&mut self, context: &mut Context, buffer: &[u8]) -> Poll<io::Result<usize>> {
+        let stream = &mut self.stream;
+        poll_io(&self.reactor, stream.as_raw_fd(), Interest::Write, context, || stream.write(buffer))
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.reactor.remove(self.stream.as_raw_fd());
+    }
+}
+
+/// A nonblocking TCP listener waiting on a `Reactor`.
+pub struct TcpListener {
+    listener: net::TcpListener,
+    reactor: Reactor,
+}
+
+impl TcpListener {
+    pub fn bind<A: ToSocketAddrs>(reactor: &Reactor, address: A) -> io::Result<Self> {
+        Self::new(reactor, net::TcpListener::bind(address)?)
+    }
+
+    /// Makes `listener` nonblocking.
+    pub fn new(reactor: &Reactor, listener: net::TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            reactor: reactor.clone(),
+        })
+    }
+
+    /// Returns a future resolving to the next connection.
+    pub fn accept<'a>(&'a self) -> Accept<'a> {
+        Accept {
+            listener: self,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn poll_accept(&self, context: &mut Context) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        let listener = &self.listener;
+        match poll_io(&self.reactor, listener.as_raw_fd(), Interest::Read, context, || listener.accept()) {
+            Poll::Ready(Ok((stream, address))) => Poll::Ready(TcpStream::new(&self.reactor, stream).map(|stream| (stream, address))),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        self.reactor.remove(self.listener.as_raw_fd());
+    }
+}
+
+/// Future returned by `TcpListener::accept`.
+pub struct Accept<'a> {
+    listener: &'a TcpListener,
+}
+
+impl<'a> Future for Accept<'a> {
+    type Output = io::Result<(TcpStream, SocketAddr)>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        self.listener.poll_accept(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+
+    use aio::async::EventLoop;
+    use super::{AsyncRead, AsyncWrite, Reactor, TcpListener};
+
+    #[test]
+    fn echo() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let reactor = Reactor::new(&event_loop).expect("reactor");
+        let listener = TcpListener::bind(&reactor, "127.0.0.1:0").expect("bind");
+        let address = listener.local_addr().expect("address");
+        let client = thread::spawn(move || {
+            let mut stream = net::TcpStream::connect(address).expect("connect");
+            // Let the server wait for the data.
+            thread::sleep(Duration::from_millis(10));
+            stream.write_all(b"hello").expect("write");
+            let mut reply = [0; 5];
+            stream.read_exact(&mut reply).expect("read");
+            reply
+        });
+
+        let received = reactor.block_on(async_echo(&listener)).expect("block_on").expect("echo");
+        assert_eq!(received, 5);
+        assert_eq!(&client.join().expect("join"), b"hello");
+    }
+
+    // Written without async/await, which are not available in the 2015 edition.
+    fn async_echo<'a>(listener: &'a TcpListener) -> Echo<'a> {
+        Echo {
+            listener,
+            stream: None,
+            buffer: [0; 5],
+            size: 0,
+            written: 0,
+        }
+    }
+
+    struct Echo<'a> {
+        listener: &'a TcpListener,
+        stream: Option<super::TcpStream>,
+        buffer: [u8; 5],
+        size: usize,
+        written: usize,
+    }
+
+    impl<'a> Future for Echo<'a> {
+        type Output = ::std::io::Result<usize>;
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+            let this = self.get_mut();
+            if this.stream.is_none() {
+                match this.listener.poll_accept(context) {
+                    Poll::Ready(Ok((stream, _))) => this.stream = Some(stream),
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let stream = this.stream.as_mut().expect("stream");
+            while this.size < this.buffer.len() {
+                let buffer = &mut this.buffer[this.size..];
+                match Pin::new(&mut stream.read(buffer)).poll(context) {
+                    Poll::Ready(Ok(0)) => break,
+                    Poll::Ready(Ok(size)) => this.size += size,
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            while this.written < this.size {
+                match Pin::new(&mut stream.write(&this.buffer[this.written..this.size])).poll(context) {
+                    Poll::Ready(Ok(size)) => this.written += size,
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            Poll::Ready(Ok(this.size))
+        }
+    }
+
+    #[test]
+    fn wake_from_thread() {
+        let event_loop = EventLoop::new().expect("event loop");
+        let reactor = Reactor::new(&event_loop).expect("reactor");
+        let mut spawned = false;
+        let future = ::std::future::poll_fn(move |context| {
+            if spawned {
+                return Poll::Ready(42);
+            }
+            spawned = true;
+            let waker = context.waker().clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                waker.wake();
+            });
+            Poll::Pending
+        });
+        assert_eq!(reactor.block_on(future).expect("block_on"), 42);
+    }
+}
@@ -0,0 +1,194 @@
+//! Worker threads each running their own `EventLoop` along with the jobs spawned on them, so that
+//! an application needs neither a thread blocked in `EventLoop::run()` nor a separate pool of
+//! threads for its other work.
+//!
+//! A worker alternates between running the jobs in its queue and iterating its event loop, which
+//! a spawned job wakes up. Jobs get the loop of their worker to register fds on it: their
+//! callbacks then run on the same thread. A panicking job does not take its worker down.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use aio::async::{EpollResult, EventLoop, LoopError, Notifier};
+
+type Job = Box<dyn FnOnce(&EventLoop) + Send + 'static>;
+
+struct Queue {
+    jobs: Mutex<VecDeque<Job>>,
+    /// Wakes up the event loop of the worker.
+    notifier: Notifier,
+}
+
+/// Worker threads running an event loop and jobs.
+pub struct Runtime {
+    handles: Vec<JoinHandle<io::Result<()>>>,
+    next_worker: AtomicUsize,
+    queues: Vec<Arc<Queue>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Runtime {
+    /// Starts `threads` workers.
+    pub fn new(threads: usize) -> io::Result<Self> {
+        assert!(threads > 0, "the runtime needs at least one thread");
+        let mut runtime = Self {
+            handles: vec![],
+            next_worker: AtomicUsize::new(0),
+            queues: vec![],
+            shutdown: Arc::new(AtomicBool::new(false)),
+        };
+        for index in 0..threads {
+            let (sender, receiver) = mpsc::channel();
+            let shutdown = runtime.shutdown.clone();
+            let handle = thread::Builder::new()
+                .name(format!("mini-runtime-{}", index))
+                .spawn(move || worker(sender, shutdown))?;
+            match receiver.recv() {
+                Ok(Ok(queue)) => runtime.queues.push(queue),
+                Ok(Err(error)) => return Err(error),
+                Err(_) => return Err(io::Error::other("runtime worker exited on startup")),
+            }
+            runtime.handles.push(handle);
+        }
+        Ok(runtime)
+    }
+
+    /// Queues a job on the next worker, in turn.
+    pub fn spawn<F>(&self, job: F)
+    where F: FnOnce(&EventLoop) + Send + 'static,
+    {
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        self.spawn_on(worker, job);
+    }
+
+    /// Queues a job on the worker of index `worker`, e.g. to run it on the same event loop as an
+    /// earlier job.
+    ///
+    /// Panics if `worker` is not less than `threads()`.
+    pub fn spawn_on<F>(&self, worker: usize, job: F)
+    where F: FnOnce(&EventLoop) + Send + 'static,
+    {
+        let queue = &self.queues[worker];
+        queue.jobs.lock().unwrap_or_else(|error| error.into_inner()).push_back(Box::new(job));
+        queue.notifier.notify();
+    }
+
+    /// Returns the number of workers.
+    pub fn threads(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Runs the jobs remaining in the queues, then stops and joins the workers. The fds still
+    /// registered on their event loops are not watched anymore.
+    pub fn shutdown(self) {
+        // Done in drop().
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for queue in &self.queues {
+            queue.notifier.notify();
+        }
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn worker(sender: mpsc::Sender<io::Result<Arc<Queue>>>, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+    let start = EventLoop::new().and_then(|event_loop| {
+        // The jobs are run after each iteration: the notifier only needs to wake up the loop.
+        let notifier = event_loop.add_notifier(|| ())?;
+        Ok((event_loop, notifier))
+    });
+    let (event_loop, notifier) =
+        match start {
+            Ok(start) => start,
+            Err(error) => {
+                let _ = sender.send(Err(io::Error::new(error.kind(), error.to_string())));
+                return Err(error);
+            },
+        };
+    let queue = Arc::new(Queue {
+        jobs: Mutex::new(VecDeque::new()),
+        notifier,
+    });
+    let _ = sender.send(Ok(queue.clone()));
+    drop(sender);
+
+    let mut jobs = VecDeque::new();
+    loop {
+        // Take the whole queue so that jobs spawning jobs on this worker don't starve the loop.
+        mem::swap(&mut jobs, &mut *queue.jobs.lock().unwrap_or_else(|error| error.into_inner()));
+        for job in jobs.drain(..) {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&event_loop)));
+        }
+        if shutdown.load(Ordering::SeqCst) && queue.jobs.lock().unwrap_or_else(|error| error.into_inner()).is_empty() {
+            return Ok(());
+        }
+        match event_loop.iterate() {
+            EpollResult::Ok | EpollResult::Interrupted => (),
+            EpollResult::Error(error) => event_loop.report_error(LoopError::Epoll(error))?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    use aio::async::{Action, Mode};
+    use super::Runtime;
+
+    #[test]
+    fn jobs_and_events() {
+        let runtime = Runtime::new(2).expect("runtime");
+        assert_eq!(runtime.threads(), 2);
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let counter = counter.clone();
+            runtime.spawn(move |_event_loop| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        runtime.spawn(|_event_loop| panic!("job panic"));
+
+        let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+        reader.set_nonblocking(true).expect("nonblocking");
+        let (sender, receiver) = mpsc::channel();
+        let job_sender = sender.clone();
+        runtime.spawn_on(1, move |event_loop| {
+            event_loop.add_raw_fd(reader.as_raw_fd(), Mode::Read, move |_event| {
+                let mut buffer = [0; 16];
+                if let Ok(size) = (&reader).read(&mut buffer) {
+                    sender.send((thread::current().name().map(str::to_string), buffer[..size].to_vec()))
+                        .expect("send");
+                }
+                Action::Continue
+            }).expect("add");
+            job_sender.send((None, vec![])).expect("send");
+        });
+        // Registered.
+        assert_eq!(receiver.recv().expect("recv"), (None, vec![]));
+        writer.write_all(b"data").expect("write");
+        assert_eq!(receiver.recv().expect("recv"), (Some("mini-runtime-1".to_string()), b"data".to_vec()));
+
+        runtime.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+}
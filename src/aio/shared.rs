@@ -0,0 +1,371 @@
+//! An event loop that can be iterated from several threads at once.
+//!
+//! `EventLoop` keeps its callbacks in `Rc<RefCell<_>>` and is thus neither `Send` nor `Sync`:
+//! each thread needs its own. `SharedEventLoop` shares one epoll instance between threads
+//! instead, each of them calling `iterate` or `run` to wait for events and run the callbacks of
+//! the ready fds.
+//!
+//! Every fd is registered with `EPOLLONESHOT` and rearmed once its callback returns, so that a
+//! callback is never called from two threads at once, even for a level-triggered fd that is
+//! still ready while its callback runs. The callbacks of different fds do run concurrently.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use aio::async::{Action, EpollResult, Mode};
+use aio::async::ffi::{self, epoll_event};
+use aio::net::close;
+
+/// Maximum number of events handled per wakeup of a thread.
+const CAPACITY: usize = 32;
+
+/// Token of the eventfd used to wake up the waiting threads.
+const WAKEUP_TOKEN: u64 = u64::MAX;
+
+type SharedCallback = Arc<Mutex<Box<dyn FnMut(epoll_event) -> Action + Send>>>;
+
+struct Registration {
+    callback: SharedCallback,
+    /// Events without `EPOLLONESHOT`.
+    events: u32,
+    fd: RawFd,
+}
+
+#[derive(Default)]
+struct Registrations {
+    by_token: HashMap<u64, Registration>,
+    /// Token of each registered fd.
+    tokens: HashMap<RawFd, u64>,
+}
+
+impl Registrations {
+    /// Returns the token and the registration of `fd`.
+    fn find_mut(&mut self, fd: RawFd) -> Option<(u64, &mut Registration)> {
+        let token = *self.tokens.get(&fd)?;
+        self.by_token.get_mut(&token).map(|registration| (token, registration))
+    }
+
+    fn remove(&mut self, token: u64) -> Option<Registration> {
+        let registration = self.by_token.remove(&token)?;
+        self.tokens.remove(&registration.fd);
+        Some(registration)
+    }
+}
+
+struct Inner {
+    event_fd: RawFd,
+    fd: RawFd,
+    /// Tokens are never reused, so that an event received by a thread for an fd removed meanwhile
+    /// by another thread cannot call the callback of a newer registration.
+    next_token: AtomicU64,
+    registrations: Mutex<Registrations>,
+    stopped: AtomicBool,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = close(self.event_fd);
+        let _ = close(self.fd);
+    }
+}
+
+/// An event loop that is `Send` and `Sync`: clones share the same epoll instance and callbacks.
+#[derive(Clone)]
+pub struct SharedEventLoop {
+    inner: Arc<Inner>,
+}
+
+impl SharedEventLoop {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { ffi::epoll_create1(0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let event_fd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK | ffi::EFD_CLOEXEC) };
+        if event_fd == -1 {
+            let error = io::Error::last_os_error();
+            let _ = close(fd);
+            return Err(error);
+        }
+        let event_loop = Self {
+            inner: Arc::new(Inner {
+                event_fd,
+                fd,
+                next_token: AtomicU64::new(0),
+                registrations: Mutex::new(Registrations::default()),
+                stopped: AtomicBool::new(false),
+            }),
+        };
+        // Level-triggered and never read, so that a stop wakes up every waiting thread.
+        event_loop.ctl(ffi::EpollOperation::Add, event_fd, ffi::EPOLLIN, WAKEUP_TOKEN)?;
+        Ok(event_loop)
+    }
+
+    pub fn add_fd<A, F>(&self, as_fd: &A, mode: Mode, callback: F) -> io::Result<()>
+    where A: AsRawFd,
+          F: FnMut(epoll_event) -> Action + Send + 'static,
+    {
+        self.add_raw_fd(as_fd.as_raw_fd(), mode, callback)
+    }
+
+    /// Watches `fd` for the events of `mode`, calling `callback` from one of the threads iterating
+    /// the loop when it is ready, until it returns `Action::Stop`.
+    pub fn add_raw_fd<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
+    where F: FnMut(epoll_event) -> Action + Send + 'static,
+    {
+        let events = events(mode);
+        let token = self.inner.next_token.fetch_add(1, Ordering::Relaxed);
+        let mut registrations = self.inner.registrations.lock().expect("lock registrations");
+        if registrations.tokens.contains_key(&fd) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "fd already registered in the event loop"));
+        }
+        self.ctl(ffi::EpollOperation::Add, fd, events | ffi::EPOLLONESHOT, token)?;
+        registrations.tokens.insert(fd, token);
+        registrations.by_token.insert(token, Registration {
+            callback: Arc::new(Mutex::new(Box::new(callback))),
+            events,
+            fd,
+        });
+        Ok(())
+    }
+
+    pub fn modify_fd<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<()> {
+        self.modify_raw_fd(as_fd.as_raw_fd(), mode)
+    }
+
+    /// Changes the events `fd` is watched for. If its callback is running, the new events apply
+    /// once it returns.
+    pub fn modify_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<()> {
+        let events = events(mode);
+        let mut registrations = self.inner.registrations.lock().expect("lock registrations");
+        let (token, registration) =
+            match registrations.find_mut(fd) {
+                Some(registration) => registration,
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "fd not registered in the event loop")),
+            };
+        registration.events = events;
+        // Only rearm an fd whose callback is not running: the callback holds its lock.
+        if let Ok(_) | Err(TryLockError::Poisoned(_)) = registration.callback.try_lock() {
+            self.ctl(ffi::EpollOperation::Modify, fd, events | ffi::EPOLLONESHOT, token)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
+        self.remove_raw_fd(as_fd.as_raw_fd())
+    }
+
+    /// Stops watching `fd`. Its callback may still be running on another thread, but won't be
+    /// called again.
+    pub fn remove_raw_fd(&self, fd: RawFd) -> io::Result<()> {
+        let mut registrations = self.inner.registrations.lock().expect("lock registrations");
+        let token =
+            match registrations.tokens.get(&fd) {
+                Some(&token) => token,
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "fd not registered in the event loop")),
+            };
+        let registration = registrations.remove(token);
+        let result = self.ctl(ffi::EpollOperation::Delete, fd, 0, 0);
+        // Drop the callback, which could remove other fds, without holding the lock.
+        drop(registrations);
+        drop(registration);
+        result
+    }
+
+    /// Waits for events for up to `timeout`, forever if `None`, and calls the callbacks of the
+    /// ready fds on the current thread.
+    pub fn iterate(&self, timeout: Option<Duration>) -> EpollResult {
+        let timeout = timeout.map_or(-1, |timeout| {
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            millis.min(i32::MAX as u128) as i32
+        });
+        let mut event_list = [epoll_event { events: 0, data: ffi::epoll_data_t { u64: 0 } }; CAPACITY];
+        let ready = unsafe { ffi::epoll_wait(self.inner.fd, event_list.as_mut_ptr(), CAPACITY as i32, timeout) };
+        if ready == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return EpollResult::Interrupted;
+            }
+            return EpollResult::Error(error);
+        }
+        for &event in event_list.iter().take(ready as usize) {
+            let token = unsafe { event.data.u64 };
+            if token != WAKEUP_TOKEN {
+                self.dispatch(token, event);
+            }
+        }
+        EpollResult::Ok
+    }
+
+    /// Iterates until `stop()` is called. Call it from as many threads as wanted.
+    pub fn run(&self) -> io::Result<()> {
+        while !self.inner.stopped.load(Ordering::SeqCst) {
+            match self.iterate(None) {
+                EpollResult::Interrupted | EpollResult::Ok => (),
+                EpollResult::Error(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes `run()` return in every thread. The loop cannot be run again afterwards.
+    pub fn stop(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        unsafe {
+            ffi::eventfd_write(self.inner.event_fd, 1);
+        }
+    }
+
+    fn dispatch(&self, token: u64, event: epoll_event) {
+        let callback = {
+            let registrations = self.inner.registrations.lock().expect("lock registrations");
+            match registrations.by_token.get(&token) {
+                Some(registration) => registration.callback.clone(),
+                // Removed by another thread.
+                None => return,
+            }
+        };
+        // A callback which panicked is rearmed before the panic reaches the thread iterating the
+        // loop, and still called for the next events of its fd: it poisoned its mutex.
+        let action = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut callback = callback.lock().unwrap_or_else(PoisonError::into_inner);
+            (*callback)(event)
+        }));
+        let mut registrations = self.inner.registrations.lock().expect("lock registrations");
+        let (fd, events) =
+            match registrations.by_token.get(&token) {
+                Some(registration) => (registration.fd, registration.events),
+                None => return,
+            };
+        if let Ok(Action::Stop) = action {
+            let registration = registrations.remove(token);
+            let _ = self.ctl(ffi::EpollOperation::Delete, fd, 0, 0);
+            drop(registrations);
+            drop(registration);
+        }
+        else {
+            // The fd could have been closed by the callback.
+            let _ = self.ctl(ffi::EpollOperation::Modify, fd, events | ffi::EPOLLONESHOT, token);
+            drop(registrations);
+        }
+        if let Err(panic) = action {
+            panic::resume_unwind(panic);
+        }
+    }
+
+    fn ctl(&self, operation: ffi::EpollOperation, fd: RawFd, events: u32, token: u64) -> io::Result<()> {
+        let mut event = epoll_event {
+            events,
+            data: ffi::epoll_data_t {
+                u64: token,
+            },
+        };
+        if unsafe { ffi::epoll_ctl(self.inner.fd, operation, fd, &mut event) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the events of `mode` without `EPOLLEXCLUSIVE`, which cannot be used with
+/// `EPOLLONESHOT`.
+fn events(mode: Mode) -> u32 {
+    mode as u32 & !ffi::EPOLLEXCLUSIVE
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use aio::async::{Action, Mode};
+    use super::SharedEventLoop;
+
+    #[test]
+    fn threads() {
+        const BYTES: usize = 100;
+
+        let event_loop = SharedEventLoop::new().expect("event loop");
+        let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+        reader.set_nonblocking(true).expect("nonblocking");
+        let count = Arc::new(AtomicUsize::new(0));
+        let running = Arc::new(AtomicBool::new(false));
+        let threads_seen = Arc::new(Mutex::new(vec![]));
+        {
+            let count = count.clone();
+            let threads_seen = threads_seen.clone();
+            let mut callback_reader = reader.try_clone().expect("clone");
+            event_loop.add_fd(&reader, Mode::Read, move |_event| {
+                assert!(!running.swap(true, Ordering::SeqCst), "callback called concurrently");
+                let id = thread::current().id();
+                let mut threads_seen = threads_seen.lock().expect("lock");
+                if !threads_seen.contains(&id) {
+                    threads_seen.push(id);
+                }
+                let mut buffer = [0; 1];
+                if let Ok(1) = callback_reader.read(&mut buffer) {
+                    count.fetch_add(1, Ordering::SeqCst);
+                }
+                // Give the other threads a chance to receive the event meanwhile.
+                thread::sleep(Duration::from_micros(100));
+                running.store(false, Ordering::SeqCst);
+                Action::Continue
+            }).expect("add");
+        }
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let event_loop = event_loop.clone();
+            thread::spawn(move || event_loop.run())
+        }).collect();
+        writer.write_all(&[1; BYTES]).expect("write");
+        while count.load(Ordering::SeqCst) < BYTES {
+            thread::sleep(Duration::from_millis(1));
+        }
+        event_loop.remove_fd(&reader).expect("remove");
+        event_loop.stop();
+        for thread in threads {
+            thread.join().expect("join").expect("run");
+        }
+        assert_eq!(count.load(Ordering::SeqCst), BYTES);
+        assert!(!threads_seen.lock().expect("lock").is_empty());
+    }
+
+    #[test]
+    fn panicking_callback() {
+        let event_loop = SharedEventLoop::new().expect("event loop");
+        let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+        reader.set_nonblocking(true).expect("nonblocking");
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let count = count.clone();
+            let mut callback_reader = reader.try_clone().expect("clone");
+            event_loop.add_fd(&reader, Mode::Read, move |_event| {
+                let mut buffer = [0; 1];
+                if let Ok(1) = callback_reader.read(&mut buffer) {
+                    if count.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("callback panic");
+                    }
+                }
+                Action::Continue
+            }).expect("add");
+        }
+
+        writer.write_all(&[1, 2]).expect("write");
+        let timeout = Some(Duration::from_secs(1));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| event_loop.iterate(timeout))).is_err());
+        // Rearmed, and called again despite its poisoned mutex.
+        event_loop.iterate(timeout);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        event_loop.remove_fd(&reader).expect("remove");
+    }
+}
@@ -22,6 +22,8 @@ enum Msg {
 }
 
 struct StdinHandler<NOTIFY> {
+    /// Reused for every read, only the bytes read are given to `input_notify`.
+    buffer: Vec<u8>,
     input_notify: NOTIFY,
     stdin: StdStdin,
 }
@@ -31,6 +33,7 @@ impl<NOTIFY> StdinHandler<NOTIFY> {
         let stdin = stdin();
         set_nonblocking(&stdin)?;
         Ok(Self {
+            buffer: vec![0; 4096],
             input_notify,
             stdin,
         })
@@ -46,14 +49,12 @@ where NOTIFY: InputNotify,
         match msg {
             Read(event) => {
                 if event.events & Mode::Read as u32 != 0 {
-                    let mut buffer = vec![0; 4096];
-                    match self.stdin.read(&mut buffer) {
+                    match self.stdin.read(&mut self.buffer) {
                         Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
                             error.kind() == ErrorKind::Interrupted => (),
                         Ok(bytes_read) => {
                             if bytes_read > 0 {
-                                buffer.truncate(bytes_read);
-                                self.input_notify.received(buffer);
+                                self.input_notify.received(self.buffer[..bytes_read].to_vec());
                             }
                         },
                         _ => (),
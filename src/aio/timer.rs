@@ -0,0 +1,117 @@
+//! Timers as file descriptors (timerfd), which become readable when they expire and can thus be
+//! registered on the event loop.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+
+use aio::net::close;
+
+/// A nonblocking timer on the monotonic clock.
+pub struct TimerFd {
+    fd: RawFd,
+}
+
+impl TimerFd {
+    /// Creates a disarmed timer.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { ffi::timerfd_create(ffi::CLOCK_MONOTONIC, ffi::TFD_NONBLOCK | ffi::TFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd,
+        })
+    }
+
+    /// Reads the number of expirations since the last call, which makes the fd not readable until
+    /// the next expiration. Returns 0 if the timer did not expire.
+    pub fn acknowledge(&self) -> u64 {
+        let mut expirations = 0u64;
+        let size = unsafe { ffi::read(self.fd, &mut expirations as *mut u64 as *mut _, mem::size_of::<u64>()) };
+        if size == mem::size_of::<u64>() as isize {
+            expirations
+        }
+        else {
+            0
+        }
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> io::Result<()> {
+        self.settime(Duration::from_secs(0), Duration::from_secs(0))
+    }
+
+    /// Arms the timer to expire once after `delay`. A zero delay expires immediately.
+    pub fn set(&self, delay: Duration) -> io::Result<()> {
+        // A zero value would disarm the timer.
+        let delay = delay.max(Duration::from_nanos(1));
+        self.settime(delay, Duration::from_secs(0))
+    }
+
+    /// Arms the timer to expire every `interval`, starting after `interval`.
+    pub fn set_interval(&self, interval: Duration) -> io::Result<()> {
+        let interval = interval.max(Duration::from_nanos(1));
+        self.settime(interval, interval)
+    }
+
+    fn settime(&self, value: Duration, interval: Duration) -> io::Result<()> {
+        let value = ffi::itimerspec {
+            it_interval: timespec(interval),
+            it_value: timespec(value),
+        };
+        if unsafe { ffi::timerfd_settime(self.fd, 0, &value, ptr::null_mut()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+fn timespec(duration: Duration) -> ffi::timespec {
+    ffi::timespec {
+        tv_sec: duration.as_secs() as i64,
+        tv_nsec: i64::from(duration.subsec_nanos()),
+    }
+}
+
+mod ffi {
+    #![allow(non_camel_case_types)]
+
+    use std::os::raw::c_void;
+
+    pub const CLOCK_MONOTONIC: i32 = 1;
+    pub const TFD_CLOEXEC: i32 = 0o2000000;
+    pub const TFD_NONBLOCK: i32 = 0o4000;
+
+    #[repr(C)]
+    pub struct timespec {
+        pub tv_sec: i64,
+        pub tv_nsec: i64,
+    }
+
+    #[repr(C)]
+    pub struct itimerspec {
+        pub it_interval: timespec,
+        pub it_value: timespec,
+    }
+
+    extern "C" {
+        pub fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
+        pub fn timerfd_create(clockid: i32, flags: i32) -> i32;
+        pub fn timerfd_settime(fd: i32, flags: i32, new_value: *const itimerspec, old_value: *mut itimerspec) -> i32;
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Bump arenas: values are allocated by bumping a pointer in large chunks and are all freed at
+//! once, when the arena is reset or dropped. This replaces many small allocations by a few large
+//! ones, e.g. for data living as long as a request.
+
+use std::cell::RefCell;
+use std::mem;
+use std::slice;
+use std::str;
+
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+const DEFAULT_TYPED_CHUNK_LEN: usize = 64;
+
+struct Chunks {
+    chunks: Vec<Box<[u8]>>,
+    /// Number of bytes used in the last chunk.
+    used: usize,
+}
+
+impl Chunks {
+    fn bump(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let chunk = self.chunks.last_mut()?;
+        let base = chunk.as_mut_ptr() as usize;
+        let start = (base + self.used + align - 1) & !(align - 1);
+        let end = start.checked_add(size)?;
+        if end > base + chunk.len() {
+            return None;
+        }
+        self.used = end - base;
+        Some(start as *mut u8)
+    }
+
+    fn grow(&mut self, min_size: usize) {
+        let size = self.chunks.last().map_or(DEFAULT_CHUNK_SIZE, |chunk| chunk.len() * 2).max(min_size);
+        self.chunks.push(vec![0; size].into_boxed_slice());
+        self.used = 0;
+    }
+}
+
+/// An arena for values of any `Copy` type. Since their destructors would not run, types needing
+/// one should use a `TypedArena` instead.
+pub struct Arena {
+    chunks: RefCell<Chunks>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self {
+            chunks: RefCell::new(Chunks {
+                chunks: vec![],
+                used: 0,
+            }),
+        }
+    }
+
+    /// Creates an arena whose first chunk has `capacity` bytes.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            chunks: RefCell::new(Chunks {
+                chunks: vec![vec![0; capacity].into_boxed_slice()],
+                used: 0,
+            }),
+        }
+    }
+
+    // Each call bumps past the memory it returns, so the references never alias.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_raw(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr.write(value);
+            &mut *ptr
+        }
+    }
+
+    // Each call bumps past the memory it returns, so the references never alias.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        let ptr = self.alloc_raw(mem::size_of_val(values), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            ptr.copy_from_nonoverlapping(values.as_ptr(), values.len());
+            slice::from_raw_parts_mut(ptr, values.len())
+        }
+    }
+
+    /// Allocates a slice of `len` values returned by `function`, called with their index.
+    pub fn alloc_slice_fill_with<T: Copy, F>(&self, len: usize, mut function: F) -> &[T]
+    where F: FnMut(usize) -> T,
+    {
+        let ptr = self.alloc_raw(mem::size_of::<T>() * len, mem::align_of::<T>()) as *mut T;
+        unsafe {
+            for index in 0..len {
+                ptr.add(index).write(function(index));
+            }
+            slice::from_raw_parts(ptr, len)
+        }
+    }
+
+    // The bytes come from `alloc_slice_copy`, so the references never alias.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, string: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(string.as_bytes());
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Returns the number of bytes reserved by the arena.
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+
+    /// Frees all the values, keeping the largest chunk for the next allocations.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        if let Some(last) = chunks.chunks.pop() {
+            chunks.chunks.clear();
+            chunks.chunks.push(last);
+        }
+        chunks.used = 0;
+    }
+
+    fn alloc_raw(&self, size: usize, align: usize) -> *mut u8 {
+        let mut chunks = self.chunks.borrow_mut();
+        if let Some(ptr) = chunks.bump(size, align) {
+            return ptr;
+        }
+        chunks.grow(size + align);
+        chunks.bump(size, align).expect("chunk should be large enough")
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An arena for values of type `T`, which are dropped when the arena is reset or dropped.
+pub struct TypedArena<T> {
+    chunks: RefCell<Vec<Vec<T>>>,
+}
+
+impl<T> TypedArena<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks: RefCell::new(vec![]),
+        }
+    }
+
+    // Each value gets its own element of a chunk that never reallocates, so the references never
+    // alias.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc(&self, value: T) -> &mut T {
+        let mut chunks = self.chunks.borrow_mut();
+        let full = chunks.last().is_none_or(|chunk| chunk.len() == chunk.capacity());
+        if full {
+            let capacity = chunks.last().map_or(DEFAULT_TYPED_CHUNK_LEN, |chunk| chunk.capacity() * 2);
+            chunks.push(Vec::with_capacity(capacity));
+        }
+        let chunk = chunks.last_mut().expect("chunk");
+        // The chunk has enough capacity, so pushing never moves the values already allocated.
+        chunk.push(value);
+        unsafe { &mut *chunk.as_mut_ptr().add(chunk.len() - 1) }
+    }
+
+    /// Moves the values out in allocation order, keeping the largest chunk for the next
+    /// allocations like `reset()`.
+    pub fn drain<'a>(&'a mut self) -> impl Iterator<Item=T> + 'a {
+        let chunks = self.chunks.get_mut();
+        let smaller: Vec<Vec<T>> = chunks.drain(..chunks.len().saturating_sub(1)).collect();
+        smaller.into_iter()
+            .flatten()
+            .chain(chunks.last_mut().into_iter().flat_map(|chunk| chunk.drain(..)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the values in allocation order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=&mut T> {
+        self.chunks.get_mut().iter_mut().flat_map(|chunk| chunk.iter_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.borrow().iter().map(Vec::len).sum()
+    }
+
+    /// Drops all the values, keeping the largest chunk for the next allocations.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        if let Some(mut last) = chunks.pop() {
+            chunks.clear();
+            last.clear();
+            chunks.push(last);
+        }
+    }
+}
+
+impl<T> Default for TypedArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem;
+
+    use super::{Arena, TypedArena};
+
+    #[test]
+    fn arena() {
+        let mut arena = Arena::with_capacity(16);
+        let byte = arena.alloc(1u8);
+        let number = arena.alloc(0x0102_0304_0506_0708u64);
+        assert_eq!(number as *mut u64 as usize % mem::align_of::<u64>(), 0);
+        let text = arena.alloc_str("a string longer than the first chunk");
+        *byte += 1;
+        assert_eq!(*byte, 2);
+        assert_eq!(*number, 0x0102_0304_0506_0708);
+        assert_eq!(text, "a string longer than the first chunk");
+        assert_eq!(arena.alloc_slice_copy(&[1u16, 2, 3]), &[1, 2, 3]);
+        assert_eq!(arena.alloc_slice_fill_with(4, |index| index as u32 * 2), &[0, 2, 4, 6]);
+        assert!(arena.capacity() > 16);
+
+        let capacity = arena.capacity();
+        arena.reset();
+        assert!(arena.capacity() < capacity);
+        let capacity = arena.capacity();
+        for i in 0..10 {
+            assert_eq!(*arena.alloc(i), i);
+        }
+        assert_eq!(arena.capacity(), capacity);
+    }
+
+    struct Counted<'a>(&'a Cell<usize>);
+
+    impl<'a> Drop for Counted<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn typed_arena() {
+        let drops = Cell::new(0);
+        let mut arena = TypedArena::new();
+        let values: Vec<_> = (0..200).map(|_| arena.alloc(Counted(&drops)) as *const _).collect();
+        assert_eq!(arena.len(), 200);
+        // The values did not move.
+        assert_eq!(values[0], arena.iter_mut().next().expect("value") as *const _);
+        assert_eq!(drops.get(), 0);
+        arena.reset();
+        assert_eq!(drops.get(), 200);
+        assert!(arena.is_empty());
+
+        arena.alloc(Counted(&drops));
+        drop(arena);
+        assert_eq!(drops.get(), 201);
+
+        let mut numbers = TypedArena::new();
+        for number in 0..100 {
+            numbers.alloc(number);
+        }
+        assert_eq!(numbers.drain().collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+        assert!(numbers.is_empty());
+        numbers.alloc(100);
+        assert_eq!(numbers.drain().collect::<Vec<_>>(), vec![100]);
+
+        let strings = TypedArena::new();
+        let first = strings.alloc(String::from("first"));
+        let second = strings.alloc(String::from("second"));
+        first.push('!');
+        assert_eq!((first.as_str(), second.as_str()), ("first!", "second"));
+    }
+}
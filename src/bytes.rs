@@ -0,0 +1,401 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Reference-counted byte buffers and a pool of reusable allocations.
+//!
+//! `Bytes` is a cheaply cloneable and sliceable view into an immutable buffer: cloning or slicing
+//! it only bumps a reference count. A buffer taken from a `BufferPool` goes back to the pool when
+//! the last `Bytes` referring to it is dropped, so that network reads do not allocate once the
+//! pool is warm.
+
+use std::borrow::Borrow;
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::sync::{Arc, Mutex, Weak};
+
+struct PoolState {
+    buffer_size: usize,
+    buffers: Vec<Vec<u8>>,
+    max_buffers: usize,
+}
+
+/// A pool of byte buffers of a fixed capacity.
+#[derive(Clone)]
+pub struct BufferPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl BufferPool {
+    /// Creates a pool of buffers of `buffer_size` bytes, keeping at most `max_buffers` idle buffers.
+    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PoolState {
+                buffer_size,
+                buffers: vec![],
+                max_buffers,
+            })),
+        }
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn available(&self) -> usize {
+        self.state.lock().expect("lock").buffers.len()
+    }
+
+    /// Returns the capacity of the buffers of this pool.
+    pub fn buffer_size(&self) -> usize {
+        self.state.lock().expect("lock").buffer_size
+    }
+
+    /// Takes an empty buffer from the pool, allocating a new one if none is idle.
+    pub fn get(&self) -> PooledBuffer {
+        let data = {
+            let mut state = self.state.lock().expect("lock");
+            let buffer_size = state.buffer_size;
+            state.buffers.pop().unwrap_or_else(|| Vec::with_capacity(buffer_size))
+        };
+        PooledBuffer {
+            shared: Shared {
+                data,
+                pool: Some(Arc::downgrade(&self.state)),
+            },
+        }
+    }
+}
+
+struct Shared {
+    data: Vec<u8>,
+    pool: Option<Weak<Mutex<PoolState>>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let state = match self.pool.take().and_then(|pool| pool.upgrade()) {
+            Some(state) => state,
+            None => return,
+        };
+        if let Ok(mut state) = state.lock() {
+            // Do not keep a buffer that was shrunk by the user.
+            if state.buffers.len() < state.max_buffers && self.data.capacity() >= state.buffer_size {
+                let mut data = mem::replace(&mut self.data, vec![]);
+                data.clear();
+                state.buffers.push(data);
+            }
+        };
+    }
+}
+
+/// A mutable buffer borrowed from a `BufferPool`, returned to the pool when dropped.
+pub struct PooledBuffer {
+    shared: Shared,
+}
+
+impl PooledBuffer {
+    /// Converts the buffer to an immutable `Bytes`. The buffer goes back to the pool when the last
+    /// clone of the `Bytes` is dropped.
+    pub fn freeze(self) -> Bytes {
+        let end = self.shared.data.len();
+        Bytes {
+            shared: Arc::new(self.shared),
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.shared.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.shared.data
+    }
+}
+
+/// An immutable, reference-counted slice of bytes.
+#[derive(Clone)]
+pub struct Bytes {
+    shared: Arc<Shared>,
+    start: usize,
+    end: usize,
+}
+
+impl Bytes {
+    /// Creates an empty `Bytes`.
+    pub fn new() -> Self {
+        Self::from(vec![])
+    }
+
+    /// Removes the first `count` bytes.
+    ///
+    /// Panics if `count` is greater than the length.
+    pub fn advance(&mut self, count: usize) {
+        assert!(count <= self.len(), "cannot advance past the end: {} > {}", count, self.len());
+        self.start += count;
+    }
+
+    /// Returns true if the slice is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the number of bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns a sub-slice sharing the same buffer.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Self {
+        let start =
+            match range.start_bound() {
+                Bound::Included(&start) => start,
+                Bound::Excluded(&start) => start + 1,
+                Bound::Unbounded => 0,
+            };
+        let end =
+            match range.end_bound() {
+                Bound::Included(&end) => end + 1,
+                Bound::Excluded(&end) => end,
+                Bound::Unbounded => self.len(),
+            };
+        assert!(start <= end, "range start {} is greater than range end {}", start, end);
+        assert!(end <= self.len(), "range end {} is out of bounds for length {}", end, self.len());
+        Self {
+            shared: self.shared.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+
+    /// Splits the bytes in two: `self` keeps `[at, len)` and `[0, at)` is returned.
+    ///
+    /// Panics if `at` is greater than the length.
+    pub fn split_to(&mut self, at: usize) -> Self {
+        let head = self.slice(..at);
+        self.start += at;
+        head
+    }
+
+    /// Splits the bytes in two: `self` keeps `[0, at)` and `[at, len)` is returned.
+    ///
+    /// Panics if `at` is greater than the length.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let tail = self.slice(at..);
+        self.end = self.start + at;
+        tail
+    }
+
+    /// Shortens the slice to `len` bytes. Does nothing if `len` is greater than the current length.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.end = self.start + len;
+        }
+    }
+
+    /// Converts to a `Vec`, without copying if this is the only reference to a whole unpooled
+    /// buffer.
+    pub fn into_vec(self) -> Vec<u8> {
+        if self.start == 0 && self.end == self.shared.data.len() && self.shared.pool.is_none() {
+            match Arc::try_unwrap(self.shared) {
+                Ok(mut shared) => return mem::replace(&mut shared.data, vec![]),
+                Err(shared) => return shared.data.clone(),
+            }
+        }
+        self.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Borrow<[u8]> for Bytes {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for Bytes {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "b\"")?;
+        for &byte in self.iter() {
+            for escaped in ::std::ascii::escape_default(byte) {
+                write!(formatter, "{}", escaped as char)?;
+            }
+        }
+        write!(formatter, "\"")
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.shared.data[self.start..self.end]
+    }
+}
+
+impl Eq for Bytes {
+}
+
+impl<'a> From<&'a [u8]> for Bytes {
+    fn from(bytes: &'a [u8]) -> Self {
+        Self::from(bytes.to_vec())
+    }
+}
+
+impl<'a> From<&'a str> for Bytes {
+    fn from(string: &'a str) -> Self {
+        Self::from(string.as_bytes())
+    }
+}
+
+impl From<String> for Bytes {
+    fn from(string: String) -> Self {
+        Self::from(string.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Self {
+        let end = data.len();
+        Self {
+            shared: Arc::new(Shared {
+                data,
+                pool: None,
+            }),
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl Hash for Bytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<[u8]> for Bytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        &**self == other
+    }
+}
+
+impl<'a> PartialEq<&'a [u8]> for Bytes {
+    fn eq(&self, other: &&'a [u8]) -> bool {
+        &**self == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Bytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == other[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{BufferPool, Bytes};
+
+    #[test]
+    fn slicing() {
+        let mut bytes = Bytes::from("hello world");
+        assert_eq!(bytes.len(), 11);
+        assert_eq!(bytes.slice(6..), b"world"[..]);
+        assert_eq!(bytes.slice(..=4), b"hello"[..]);
+        let hello = bytes.split_to(6);
+        assert_eq!(hello, b"hello "[..]);
+        assert_eq!(bytes, b"world"[..]);
+        let ld = bytes.split_off(3);
+        assert_eq!(bytes, b"wor"[..]);
+        assert_eq!(ld, b"ld"[..]);
+        bytes.advance(1);
+        bytes.truncate(1);
+        assert_eq!(bytes, b"o"[..]);
+        assert_eq!(format!("{:?}", Bytes::from(&b"a\n\""[..])), "b\"a\\n\\\"\"");
+    }
+
+    #[test]
+    fn into_vec() {
+        let vec = vec![1, 2, 3];
+        let pointer = vec.as_ptr();
+        let bytes = Bytes::from(vec);
+        let vec = bytes.into_vec();
+        assert_eq!(vec.as_ptr(), pointer);
+
+        let bytes = Bytes::from(vec);
+        let clone = bytes.clone();
+        assert_eq!(bytes.into_vec(), vec![1, 2, 3]);
+        assert_eq!(clone.slice(1..).into_vec(), vec![2, 3]);
+    }
+
+    #[test]
+    fn pool() {
+        let pool = BufferPool::new(16, 1);
+        let mut buffer = pool.get();
+        assert_eq!(buffer.capacity(), 16);
+        buffer.extend_from_slice(b"data");
+        let pointer = buffer.as_ptr();
+        let bytes = buffer.freeze();
+        let slice = bytes.slice(1..);
+        drop(bytes);
+        assert_eq!(pool.available(), 0);
+        let slice = thread::spawn(move || slice).join().expect("join");
+        assert_eq!(slice, b"ata"[..]);
+        drop(slice);
+        assert_eq!(pool.available(), 1);
+
+        // The buffer is reused and emptied.
+        let first = pool.get();
+        assert!(first.is_empty());
+        assert_eq!(first.as_ptr(), pointer);
+        let second = pool.get();
+        drop(first);
+        drop(second);
+        assert_eq!(pool.available(), 1);
+    }
+}
@@ -0,0 +1,389 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Least recently used cache with optional expiration.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::hash::Hash;
+use std::mem;
+use std::time::{Duration, Instant};
+
+const NIL: usize = usize::MAX;
+
+struct Node<K, V> {
+    expires: Option<Instant>,
+    key: K,
+    next: usize,
+    prev: usize,
+    value: V,
+}
+
+impl<K, V> Node<K, V> {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+}
+
+/// A map holding at most `capacity` entries, evicting the least recently used one when full.
+///
+/// Entries can also expire after a time to live, either the default one of the cache or one given
+/// on insertion. Expired entries are never returned and are removed when they are accessed, or by
+/// `purge_expired`.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    free: Vec<usize>,
+    /// Most recently used entry.
+    head: usize,
+    map: HashMap<K, usize>,
+    nodes: Vec<Option<Node<K, V>>>,
+    /// Least recently used entry.
+    tail: usize,
+    ttl: Option<Duration>,
+}
+
+impl<K: Clone + Eq + Hash, V> LruCache<K, V> {
+    /// Creates a cache without expiration. A capacity of 0 is treated as 1.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            free: vec![],
+            head: NIL,
+            map: HashMap::with_capacity(capacity),
+            nodes: Vec::with_capacity(capacity),
+            tail: NIL,
+            ttl: None,
+        }
+    }
+
+    /// Creates a cache whose entries expire `ttl` after their insertion.
+    pub fn with_ttl(capacity: usize, ttl: Duration) -> Self {
+        let mut cache = Self::new(capacity);
+        cache.ttl = Some(ttl);
+        cache
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.free.clear();
+        self.head = NIL;
+        self.map.clear();
+        self.nodes.clear();
+        self.tail = NIL;
+    }
+
+    /// Returns true if the cache contains an unexpired value for the key, without marking it as
+    /// used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        self.peek(key).is_some()
+    }
+
+    /// Returns the value of the key and marks it as the most recently used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        let index = self.touch(key)?;
+        self.nodes[index].as_ref().map(|node| &node.value)
+    }
+
+    /// Returns the value of the key and marks it as the most recently used.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        let index = self.touch(key)?;
+        self.nodes[index].as_mut().map(|node| &mut node.value)
+    }
+
+    /// Inserts a value expiring after the default time to live of the cache, if any. Returns the
+    /// previous value of the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let expires = self.ttl.map(|ttl| Instant::now() + ttl);
+        self.insert_node(key, value, expires)
+    }
+
+    /// Inserts a value expiring after `ttl`. Returns the previous value of the key.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.insert_node(key, value, Some(Instant::now() + ttl))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the unexpired entries, from the most to the least recently used.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            cache: self,
+            index: self.head,
+            now: Instant::now(),
+        }
+    }
+
+    /// Returns the number of entries, including the expired ones not removed yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns the value of the key without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        let now = Instant::now();
+        let index = *self.map.get(key)?;
+        self.nodes[index].as_ref()
+            .filter(|node| !node.expired(now))
+            .map(|node| &node.value)
+    }
+
+    /// Removes the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        if self.tail == NIL {
+            return None;
+        }
+        let tail = self.tail;
+        Some(self.remove_index(tail))
+    }
+
+    /// Removes the expired entries.
+    pub fn purge_expired(&mut self) {
+        let now = Instant::now();
+        let mut index = self.tail;
+        while index != NIL {
+            let (prev, expired) =
+                match self.nodes[index] {
+                    Some(ref node) => (node.prev, node.expired(now)),
+                    None => break,
+                };
+            if expired {
+                self.remove_index(index);
+            }
+            index = prev;
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        Some(self.remove_index(index).1)
+    }
+
+    fn insert_node(&mut self, key: K, value: V, expires: Option<Instant>) -> Option<V> {
+        if let Some(&index) = self.map.get(&key) {
+            self.unlink(index);
+            self.push_front(index);
+            let node = self.nodes[index].as_mut().expect("node");
+            node.expires = expires;
+            let old_value = mem::replace(&mut node.value, value);
+            return Some(old_value);
+        }
+        if self.map.len() >= self.capacity {
+            self.pop_lru();
+        }
+        let node = Node {
+            expires,
+            key: key.clone(),
+            next: NIL,
+            prev: NIL,
+            value,
+        };
+        let index =
+            match self.free.pop() {
+                Some(index) => {
+                    self.nodes[index] = Some(node);
+                    index
+                },
+                None => {
+                    self.nodes.push(Some(node));
+                    self.nodes.len() - 1
+                },
+            };
+        self.map.insert(key, index);
+        self.push_front(index);
+        None
+    }
+
+    fn push_front(&mut self, index: usize) {
+        let head = self.head;
+        if let Some(ref mut node) = self.nodes[index] {
+            node.prev = NIL;
+            node.next = head;
+        }
+        if head != NIL {
+            if let Some(ref mut node) = self.nodes[head] {
+                node.prev = index;
+            }
+        }
+        else {
+            self.tail = index;
+        }
+        self.head = index;
+    }
+
+    fn remove_index(&mut self, index: usize) -> (K, V) {
+        self.unlink(index);
+        let node = self.nodes[index].take().expect("node");
+        self.free.push(index);
+        self.map.remove(&node.key);
+        (node.key, node.value)
+    }
+
+    /// Returns the index of the unexpired entry of the key after moving it to the front, removing
+    /// it if it expired.
+    fn touch<Q>(&mut self, key: &Q) -> Option<usize>
+    where K: Borrow<Q>,
+          Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        if self.nodes[index].as_ref().map_or(false, |node| node.expired(Instant::now())) {
+            self.remove_index(index);
+            return None;
+        }
+        if index != self.head {
+            self.unlink(index);
+            self.push_front(index);
+        }
+        Some(index)
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) =
+            match self.nodes[index] {
+                Some(ref node) => (node.prev, node.next),
+                None => return,
+            };
+        if prev != NIL {
+            if let Some(ref mut node) = self.nodes[prev] {
+                node.next = next;
+            }
+        }
+        else {
+            self.head = next;
+        }
+        if next != NIL {
+            if let Some(ref mut node) = self.nodes[next] {
+                node.prev = prev;
+            }
+        }
+        else {
+            self.tail = prev;
+        }
+    }
+}
+
+impl<K: Clone + Debug + Eq + Hash, V: Debug> Debug for LruCache<K, V> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator returned by `LruCache::iter`.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    cache: &'a LruCache<K, V>,
+    index: usize,
+    now: Instant,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index != NIL {
+            let node = self.cache.nodes[self.index].as_ref()?;
+            self.index = node.next;
+            if !node.expired(self.now) {
+                return Some((&node.key, &node.value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::LruCache;
+
+    #[test]
+    fn lru() {
+        let mut cache = LruCache::new(3);
+        assert_eq!(cache.insert("a", 1), None);
+        cache.insert("b", 2);
+        cache.insert("c", 3);
+        assert_eq!(cache.get("a"), Some(&1));
+        cache.insert("d", 4);
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&"d", &4), (&"a", &1), (&"c", &3)]);
+
+        assert_eq!(cache.peek("c"), Some(&3));
+        cache.insert("e", 5);
+        assert!(!cache.contains_key("c"));
+
+        assert_eq!(cache.insert("a", 10), Some(1));
+        *cache.get_mut("d").expect("d") += 1;
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&"d", &5), (&"a", &10), (&"e", &5)]);
+        assert_eq!(cache.pop_lru(), Some(("e", 5)));
+        assert_eq!(cache.remove("d"), Some(5));
+        assert_eq!(cache.remove("d"), None);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&"a", &10)]);
+        cache.insert("f", 6);
+        cache.insert("g", 7);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&"g", &7), (&"f", &6), (&"a", &10)]);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.pop_lru(), None);
+
+        let mut cache = LruCache::new(2);
+        cache.insert(String::from("key"), 1);
+        assert_eq!(cache.get("key"), Some(&1));
+    }
+
+    #[test]
+    fn ttl() {
+        let mut cache = LruCache::with_ttl(4, Duration::from_millis(20));
+        cache.insert(1, "one");
+        cache.insert_with_ttl(2, "two", Duration::from_secs(60));
+        cache.insert(3, "three");
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&3), None);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&2, &"two")]);
+        cache.purge_expired();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&2), Some(&"two"));
+    }
+}
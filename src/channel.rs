@@ -0,0 +1,477 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Bounded multi-producer, single-consumer channel.
+//!
+//! Unlike `std::sync::mpsc`, the receiving end can be registered in an `EventLoop`: its eventfd is
+//! readable whenever messages are waiting, so that other threads can feed data to handlers.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use aio::async::ffi;
+use aio::net::close;
+
+/// Error returned by `Sender::send` when the receiver was dropped. Contains the message.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct SendError<T>(pub T);
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str("SendError(..)")
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str("sending on a disconnected channel")
+    }
+}
+
+impl<T> Error for SendError<T> {
+}
+
+/// Error returned by `Sender::try_send`. Contains the message.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// The receiver was dropped.
+    Disconnected(T),
+}
+
+impl<T> Debug for TrySendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            TrySendError::Full(_) => formatter.write_str("Full(..)"),
+            TrySendError::Disconnected(_) => formatter.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> Display for TrySendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            TrySendError::Full(_) => formatter.write_str("sending on a full channel"),
+            TrySendError::Disconnected(_) => formatter.write_str("sending on a disconnected channel"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {
+}
+
+/// Error returned by `Receiver::recv` when all the senders were dropped and the channel is empty.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RecvError;
+
+impl Display for RecvError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str("receiving on an empty and disconnected channel")
+    }
+}
+
+impl Error for RecvError {
+}
+
+/// Error returned by `Receiver::try_recv` and `Receiver::recv_timeout`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TryRecvError {
+    /// No message is available yet.
+    Empty,
+    /// All the senders were dropped and the channel is empty.
+    Disconnected,
+}
+
+impl Display for TryRecvError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            TryRecvError::Empty => formatter.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => formatter.write_str("receiving on an empty and disconnected channel"),
+        }
+    }
+}
+
+impl Error for TryRecvError {
+}
+
+struct State<T> {
+    event_fd: Option<RawFd>,
+    messages: VecDeque<T>,
+    receiver_alive: bool,
+    senders: usize,
+}
+
+impl<T> State<T> {
+    fn signal(&self) {
+        if let Some(event_fd) = self.event_fd {
+            unsafe {
+                ffi::eventfd_write(event_fd, 1);
+            }
+        }
+    }
+}
+
+struct Shared<T> {
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    state: Mutex<State<T>>,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if let Some(event_fd) = self.lock().event_fd.take() {
+            let _ = close(event_fd);
+        }
+    }
+}
+
+/// Creates a channel which can hold up to `capacity` messages.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "the capacity should not be zero");
+    let shared = Arc::new(Shared {
+        capacity,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        state: Mutex::new(State {
+            event_fd: None,
+            messages: VecDeque::new(),
+            receiver_alive: true,
+            senders: 1,
+        }),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+/// The sending end of a channel. Clone it to send from several threads.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message, blocking while the channel is full.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        loop {
+            if !state.receiver_alive {
+                return Err(SendError(msg));
+            }
+            if state.messages.len() < self.shared.capacity {
+                break;
+            }
+            state = self.shared.not_full.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+        self.push(state, msg);
+        Ok(())
+    }
+
+    /// Sends a message, blocking for at most `timeout` while the channel is full.
+    pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), TrySendError<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if !state.receiver_alive {
+                return Err(TrySendError::Disconnected(msg));
+            }
+            if state.messages.len() < self.shared.capacity {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TrySendError::Full(msg));
+            }
+            state = self.shared.not_full.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|error| error.into_inner()).0;
+        }
+        self.push(state, msg);
+        Ok(())
+    }
+
+    /// Sends a message if the channel is not full.
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        let state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        if state.messages.len() >= self.shared.capacity {
+            return Err(TrySendError::Full(msg));
+        }
+        self.push(state, msg);
+        Ok(())
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, State<T>>, msg: T) {
+        state.messages.push_back(msg);
+        state.signal();
+        self.shared.not_empty.notify_one();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            // Wake up the receiver so that it notices the disconnection.
+            state.signal();
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+/// The receiving end of a channel.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives a message, blocking while the channel is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.not_empty.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+    }
+
+    /// Receives a message, blocking for at most `timeout` while the channel is empty.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TryRecvError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(TryRecvError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TryRecvError::Empty);
+            }
+            state = self.shared.not_empty.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|error| error.into_inner()).0;
+        }
+    }
+
+    /// Receives a message if one is available.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match self.pop(&mut state) {
+            Some(msg) => Ok(msg),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Returns an iterator over the messages currently available, without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter {
+            receiver: self,
+        }
+    }
+
+    /// Returns an eventfd which is readable while messages are available or when all the senders are
+    /// dropped, to register the receiver in an `EventLoop`.
+    ///
+    /// Call `try_recv` until it returns `TryRecvError::Empty` when the fd is readable: the eventfd is
+    /// only reset once the channel is drained.
+    pub fn event_fd(&self) -> io::Result<RawFd> {
+        let mut state = self.shared.lock();
+        if let Some(event_fd) = state.event_fd {
+            return Ok(event_fd);
+        }
+        let event_fd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK) };
+        if event_fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        state.event_fd = Some(event_fd);
+        if !state.messages.is_empty() || state.senders == 0 {
+            state.signal();
+        }
+        Ok(event_fd)
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        let msg = state.messages.pop_front()?;
+        if state.messages.is_empty() && state.senders > 0 {
+            if let Some(event_fd) = state.event_fd {
+                let mut value = 0;
+                unsafe {
+                    ffi::eventfd_read(event_fd, &mut value);
+                }
+            }
+        }
+        self.shared.not_full.notify_one();
+        Some(msg)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.messages.clear();
+        self.shared.not_full.notify_all();
+    }
+}
+
+impl<T> Iterator for Receiver<T> {
+    type Item = T;
+
+    /// Blocks until a message is received, or returns `None` once all the senders are dropped.
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+/// Iterator over the messages available in a channel, created by `Receiver::try_iter`.
+pub struct TryIter<'a, T: 'a> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use aio::handler::{Handler, Loop, Stream};
+    use super::{TryRecvError, TrySendError, bounded};
+
+    #[test]
+    fn send_recv() {
+        let (sender, receiver) = bounded(4);
+        let mut threads = vec![];
+        for i in 0..4 {
+            let sender = sender.clone();
+            threads.push(thread::spawn(move || {
+                for j in 0..100 {
+                    sender.send(i * 100 + j).expect("send");
+                }
+            }));
+        }
+        drop(sender);
+        let mut values: Vec<_> = receiver.collect();
+        values.sort();
+        assert_eq!(values, (0..400).collect::<Vec<_>>());
+        for thread in threads {
+            thread.join().expect("join");
+        }
+    }
+
+    #[test]
+    fn try_variants() {
+        let (sender, receiver) = bounded(1);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        assert!(sender.try_send(1).is_ok());
+        match sender.try_send(2) {
+            Err(TrySendError::Full(2)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match sender.send_timeout(3, Duration::from_millis(1)) {
+            Err(TrySendError::Full(3)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), Err(TryRecvError::Empty));
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (sender, receiver) = bounded(1);
+        drop(receiver);
+        match sender.try_send(1) {
+            Err(TrySendError::Disconnected(1)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    struct Collector {
+        event_loop: Loop,
+        values: Rc<RefCell<Vec<u32>>>,
+    }
+
+    impl Handler for Collector {
+        type Msg = u32;
+
+        fn update(&mut self, _stream: &Stream<u32>, value: u32) {
+            self.values.borrow_mut().push(value);
+            if value == 9 {
+                self.event_loop.stop();
+            }
+        }
+    }
+
+    #[test]
+    fn event_loop() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let values = Rc::new(RefCell::new(vec![]));
+        let stream = event_loop.spawn(Collector {
+            event_loop: event_loop.clone(),
+            values: values.clone(),
+        });
+        let (sender, receiver) = bounded(2);
+        event_loop.add_receiver(receiver, &stream, |value| value).expect("add receiver");
+        let thread = thread::spawn(move || {
+            for i in 0..10 {
+                sender.send(i).expect("send");
+            }
+        });
+        event_loop.run().expect("run");
+        thread.join().expect("join");
+        assert_eq!(*values.borrow(), (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! CRC32 and Adler-32 checksums.
+//!
+//! Each checksum can be computed over a whole slice with a function (`crc32()`, `crc32c()`,
+//! `adler32()`) or incrementally by feeding chunks to a checksum object with `update()`.
+
+use std::io::{self, Write};
+
+/// Reversed polynomial of CRC-32/IEEE, used by Ethernet, gzip and zip.
+const IEEE_POLYNOMIAL: u32 = 0xedb8_8320;
+/// Reversed polynomial of CRC-32C (Castagnoli), used by iSCSI, SCTP and ext4.
+const CASTAGNOLI_POLYNOMIAL: u32 = 0x82f6_3b78;
+
+static IEEE_TABLE: [u32; 256] = make_table(IEEE_POLYNOMIAL);
+static CASTAGNOLI_TABLE: [u32; 256] = make_table(CASTAGNOLI_POLYNOMIAL);
+
+const fn make_table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value =
+                if value & 1 != 0 {
+                    (value >> 1) ^ polynomial
+                }
+                else {
+                    value >> 1
+                };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+}
+
+/// Incremental CRC32 computation.
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+    table: &'static [u32; 256],
+}
+
+impl Crc32 {
+    /// Creates a CRC-32/IEEE checksum.
+    pub fn ieee() -> Self {
+        Self::with_table(&IEEE_TABLE)
+    }
+
+    /// Creates a CRC-32C (Castagnoli) checksum.
+    pub fn castagnoli() -> Self {
+        Self::with_table(&CASTAGNOLI_TABLE)
+    }
+
+    fn with_table(table: &'static [u32; 256]) -> Self {
+        Self {
+            state: !0,
+            table,
+        }
+    }
+
+    /// Returns the checksum of the data fed so far.
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+
+    /// Resets the checksum to its initial state.
+    pub fn reset(&mut self) {
+        self.state = !0;
+    }
+
+    /// Feeds data to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut state = self.state;
+        for &byte in data {
+            state = self.table[((state ^ u32::from(byte)) & 0xff) as usize] ^ (state >> 8);
+        }
+        self.state = state;
+    }
+}
+
+impl Write for Crc32 {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.update(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Computes the CRC-32/IEEE checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::ieee();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Computes the CRC-32C (Castagnoli) checksum of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = Crc32::castagnoli();
+    crc.update(data);
+    crc.finish()
+}
+
+const ADLER_MODULO: u32 = 65521;
+/// Largest number of bytes that can be summed before `b` may overflow a u32.
+const ADLER_MAX_CHUNK: usize = 5552;
+
+/// Incremental Adler-32 computation.
+#[derive(Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    /// Creates a new checksum.
+    pub fn new() -> Self {
+        Self {
+            a: 1,
+            b: 0,
+        }
+    }
+
+    /// Returns the checksum of the data fed so far.
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    /// Resets the checksum to its initial state.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feeds data to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(ADLER_MAX_CHUNK) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULO;
+            self.b %= ADLER_MODULO;
+        }
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for Adler32 {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.update(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Computes the Adler-32 checksum of `data`.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{Adler32, Crc32, adler32, crc32, crc32c};
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+
+        let mut crc = Crc32::castagnoli();
+        crc.update(b"1234");
+        write!(crc, "{}", 56789).expect("write");
+        assert_eq!(crc.finish(), 0xe306_9283);
+        crc.reset();
+        assert_eq!(crc.finish(), 0);
+    }
+
+    #[test]
+    fn adler() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+        // Long input exercising the deferred modulo.
+        let data: Vec<u8> = (0..100_000).map(|index| (index % 251) as u8).collect();
+        let mut naive_a = 1;
+        let mut naive_b = 0;
+        for &byte in &data {
+            naive_a = (naive_a + u32::from(byte)) % 65521;
+            naive_b = (naive_b + naive_a) % 65521;
+        }
+        let mut adler = Adler32::new();
+        for chunk in data.chunks(7000) {
+            adler.update(chunk);
+        }
+        assert_eq!(adler.finish(), (naive_b << 16) | naive_a);
+        assert_eq!(adler32(&data), adler.finish());
+    }
+}
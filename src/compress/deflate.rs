@@ -0,0 +1,637 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::{
+    CODE_LENGTH_ORDER,
+    DISTANCE_BASE,
+    DISTANCE_EXTRA_BITS,
+    END_OF_BLOCK,
+    LENGTH_BASE,
+    LENGTH_EXTRA_BITS,
+    Level,
+    WINDOW_SIZE,
+    fixed_lengths,
+};
+
+/// Number of input bytes compressed in a block.
+const BLOCK_SIZE: usize = 65536;
+const HASH_BITS: u32 = 15;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+const MAX_MATCH: usize = 258;
+const MAX_STORED_LEN: usize = 65535;
+const MIN_MATCH: usize = 3;
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match {
+        distance: u16,
+        len: u16,
+    },
+}
+
+/// Writes bits starting from the least significant bit of each byte.
+struct BitWriter {
+    bit_count: u32,
+    bits: u64,
+    bytes: Vec<u8>,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bit_count: 0,
+            bits: 0,
+            bytes: vec![],
+        }
+    }
+
+    /// Pads with zeros up to the next byte.
+    fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+            self.bits = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u8) {
+        self.bits |= u64::from(value) << self.bit_count;
+        self.bit_count += u32::from(count);
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which starts from its most significant bit.
+    fn write_code(&mut self, code: u16, len: u8) {
+        let reversed = u32::from(code).reverse_bits() >> (32 - u32::from(len));
+        self.write(reversed, len);
+    }
+
+    /// Moves the complete bytes to `output`.
+    fn take(&mut self, output: &mut Vec<u8>) {
+        output.append(&mut self.bytes);
+    }
+}
+
+struct Params {
+    lazy: bool,
+    max_chain: usize,
+    /// Length at which a match is good enough to stop searching.
+    nice_len: usize,
+}
+
+fn params(level: Level) -> Params {
+    match level {
+        Level::None | Level::Fast => Params { lazy: false, max_chain: 8, nice_len: 32 },
+        Level::Default => Params { lazy: true, max_chain: 128, nice_len: 128 },
+        Level::Best => Params { lazy: true, max_chain: 1024, nice_len: MAX_MATCH },
+    }
+}
+
+/// Streaming DEFLATE encoder. The input is compressed by blocks of 64K, so the output is delayed
+/// until that much input is available, unless flushed.
+pub struct Deflater {
+    /// Up to 32K of history followed by the input not compressed yet.
+    data: Vec<u8>,
+    level: Level,
+    /// Start of the input not compressed yet.
+    position: usize,
+    writer: BitWriter,
+}
+
+impl Deflater {
+    pub fn new(level: Level) -> Self {
+        Self {
+            data: vec![],
+            level,
+            position: 0,
+            writer: BitWriter::new(),
+        }
+    }
+
+    pub fn deflate(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        self.data.extend_from_slice(input);
+        while self.data.len() - self.position >= BLOCK_SIZE {
+            let end = self.position + BLOCK_SIZE;
+            self.compress_block(end, false);
+        }
+        self.writer.take(output);
+    }
+
+    /// Compresses the pending input and aligns the output on a byte with an empty stored block.
+    pub fn flush(&mut self, output: &mut Vec<u8>) {
+        if self.position < self.data.len() {
+            let end = self.data.len();
+            self.compress_block(end, false);
+        }
+        self.write_stored(0, 0, false);
+        self.writer.take(output);
+    }
+
+    pub fn finish(&mut self, output: &mut Vec<u8>) {
+        let end = self.data.len();
+        self.compress_block(end, true);
+        self.writer.align();
+        self.writer.take(output);
+    }
+
+    /// Compresses the input up to `end` in a block of the cheapest type.
+    fn compress_block(&mut self, end: usize, last: bool) {
+        let start = self.position;
+        let tokens =
+            if self.level == Level::None {
+                vec![]
+            }
+            else {
+                tokenize(&self.data, start, end, &params(self.level))
+            };
+
+        let stored_chunks = (end - start).div_ceil(MAX_STORED_LEN).max(1);
+        // Header, padding, length and its complement.
+        let stored_cost = stored_chunks * (3 + 7 + 32) + (end - start) * 8;
+        if self.level == Level::None {
+            self.write_stored(start, end, last);
+        }
+        else {
+            let mut literal_frequencies = [0u32; 286];
+            let mut distance_frequencies = [0u32; 30];
+            literal_frequencies[END_OF_BLOCK] = 1;
+            for &token in &tokens {
+                match token {
+                    Token::Literal(byte) => literal_frequencies[byte as usize] += 1,
+                    Token::Match { distance, len } => {
+                        literal_frequencies[END_OF_BLOCK + 1 + length_index(len as usize)] += 1;
+                        distance_frequencies[distance_index(distance as usize)] += 1;
+                    },
+                }
+            }
+            let literal_lengths = code_lengths(&literal_frequencies, MAX_CODE_LENGTH);
+            let mut distance_lengths = code_lengths(&distance_frequencies, MAX_CODE_LENGTH);
+            if distance_lengths.iter().all(|&length| length == 0) {
+                // At least one distance code must be sent.
+                distance_lengths[0] = 1;
+            }
+            let header = DynamicHeader::new(&literal_lengths, &distance_lengths);
+            let dynamic_cost = 3 + header.cost()
+                + data_cost(&literal_frequencies, &distance_frequencies, &literal_lengths, &distance_lengths);
+            let (fixed_literal_lengths, fixed_distance_lengths) = fixed_lengths();
+            let fixed_cost = 3 + data_cost(&literal_frequencies, &distance_frequencies, &fixed_literal_lengths,
+                &fixed_distance_lengths);
+
+            if stored_cost <= dynamic_cost.min(fixed_cost) {
+                self.write_stored(start, end, last);
+            }
+            else if fixed_cost <= dynamic_cost {
+                self.writer.write(u32::from(last) | 1 << 1, 3);
+                write_tokens(&mut self.writer, &tokens, &fixed_literal_lengths, &fixed_distance_lengths);
+            }
+            else {
+                self.writer.write(u32::from(last) | 2 << 1, 3);
+                header.write(&mut self.writer);
+                write_tokens(&mut self.writer, &tokens, &literal_lengths, &distance_lengths);
+            }
+        }
+
+        self.position = end;
+        if self.position > WINDOW_SIZE {
+            let excess = self.position - WINDOW_SIZE;
+            self.data.drain(..excess);
+            self.position = WINDOW_SIZE;
+        }
+    }
+
+    /// Writes the data from `start` to `end` in stored blocks.
+    fn write_stored(&mut self, start: usize, end: usize, last: bool) {
+        let mut chunk_start = start;
+        loop {
+            let chunk_end = end.min(chunk_start + MAX_STORED_LEN);
+            let last_chunk = chunk_end == end;
+            self.writer.write(u32::from(last && last_chunk), 3);
+            self.writer.align();
+            let len = (chunk_end - chunk_start) as u32;
+            self.writer.write(len, 16);
+            self.writer.write(!len & 0xffff, 16);
+            self.writer.bytes.extend_from_slice(&self.data[chunk_start..chunk_end]);
+            if last_chunk {
+                break;
+            }
+            chunk_start = chunk_end;
+        }
+    }
+}
+
+fn length_index(len: usize) -> usize {
+    LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap_or(0)
+}
+
+fn distance_index(distance: usize) -> usize {
+    DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Hash chains of the positions of the 3-byte sequences.
+struct Matcher<'a> {
+    data: &'a [u8],
+    end: usize,
+    head: Vec<u32>,
+    /// Previous position with the same hash, indexed from window_start.
+    previous: Vec<u32>,
+    window_start: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8], window_start: usize, end: usize) -> Self {
+        Self {
+            data,
+            end,
+            head: vec![NONE; 1 << HASH_BITS],
+            previous: vec![NONE; end - window_start],
+            window_start,
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH <= self.end {
+            let hash = hash(&self.data[position..]);
+            self.previous[position - self.window_start] = self.head[hash];
+            self.head[hash] = position as u32;
+        }
+    }
+
+    /// Returns the length and distance of the longest match at `position` among the inserted
+    /// positions, or a length of 0 if there is none.
+    fn longest_match(&self, position: usize, params: &Params) -> (usize, usize) {
+        let data = self.data;
+        let max_len = MAX_MATCH.min(self.end - position);
+        if max_len < MIN_MATCH {
+            return (0, 0);
+        }
+        let mut best = (0, 0);
+        let mut next = self.head[hash(&data[position..])];
+        let mut chain = params.max_chain;
+        while next != NONE && chain > 0 {
+            let candidate = next as usize;
+            let distance = position - candidate;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+            // Check the byte that would make the match longer first.
+            let probe = best.0.min(max_len - 1);
+            if data[candidate + probe] == data[position + probe] {
+                let len = data[candidate..candidate + max_len].iter()
+                    .zip(&data[position..position + max_len])
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, distance);
+                    if len >= params.nice_len || len == max_len {
+                        break;
+                    }
+                }
+            }
+            next = self.previous[candidate - self.window_start];
+            chain -= 1;
+        }
+        if best.0 < MIN_MATCH {
+            (0, 0)
+        }
+        else {
+            best
+        }
+    }
+}
+
+/// Finds the matches (LZ77) in `data[start..end]`, which can refer to the 32K preceding `start`.
+fn tokenize(data: &[u8], start: usize, end: usize, params: &Params) -> Vec<Token> {
+    let mut matcher = Matcher::new(data, start.saturating_sub(WINDOW_SIZE), end);
+    for position in matcher.window_start..start {
+        matcher.insert(position);
+    }
+
+    let mut tokens = vec![];
+    let mut position = start;
+    // With lazy matching, a match is emitted only if the match at the next position is not longer.
+    let mut pending: Option<(usize, usize)> = None;
+    while position < end {
+        let (len, distance) =
+            match pending {
+                Some((len, _)) if len >= params.nice_len => (0, 0),
+                _ => matcher.longest_match(position, params),
+            };
+        matcher.insert(position);
+        if let Some((pending_len, pending_distance)) = pending.take() {
+            if len <= pending_len {
+                tokens.push(Token::Match { distance: pending_distance as u16, len: pending_len as u16 });
+                // The match started at position - 1.
+                for next in position + 1..position - 1 + pending_len {
+                    matcher.insert(next);
+                }
+                position += pending_len - 1;
+                continue;
+            }
+            tokens.push(Token::Literal(data[position - 1]));
+        }
+        if len >= MIN_MATCH && params.lazy {
+            pending = Some((len, distance));
+        }
+        else if len >= MIN_MATCH {
+            tokens.push(Token::Match { distance: distance as u16, len: len as u16 });
+            for next in position + 1..position + len {
+                matcher.insert(next);
+            }
+            position += len;
+            continue;
+        }
+        else {
+            tokens.push(Token::Literal(data[position]));
+        }
+        position += 1;
+    }
+    if let Some((len, distance)) = pending {
+        tokens.push(Token::Match { distance: distance as u16, len: len as u16 });
+    }
+    tokens
+}
+
+/// Returns the lengths of a Huffman code for the frequencies, limited to `max_len` bits.
+fn code_lengths(frequencies: &[u32], max_len: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    let mut lengths = vec![0; frequencies.len()];
+    loop {
+        let symbols: Vec<usize> = (0..frequencies.len()).filter(|&symbol| frequencies[symbol] > 0).collect();
+        match symbols.len() {
+            0 => return lengths,
+            1 => {
+                lengths[symbols[0]] = 1;
+                return lengths;
+            },
+            _ => (),
+        }
+
+        // The leaves are the nodes 0 to symbols.len() - 1, followed by the internal nodes.
+        let mut parents = vec![0; symbols.len() * 2 - 1];
+        let mut heap: BinaryHeap<_> = symbols.iter().enumerate()
+            .map(|(node, &symbol)| Reverse((u64::from(frequencies[symbol]), node)))
+            .collect();
+        let mut next_node = symbols.len();
+        while heap.len() > 1 {
+            let Reverse((weight1, node1)) = heap.pop().expect("node");
+            let Reverse((weight2, node2)) = heap.pop().expect("node");
+            parents[node1] = next_node;
+            parents[node2] = next_node;
+            heap.push(Reverse((weight1 + weight2, next_node)));
+            next_node += 1;
+        }
+        let root = next_node - 1;
+        let mut depths = vec![0u8; parents.len()];
+        for node in (0..root).rev() {
+            depths[node] = depths[parents[node]] + 1;
+        }
+
+        if depths[..symbols.len()].iter().all(|&depth| depth <= max_len) {
+            for (node, &symbol) in symbols.iter().enumerate() {
+                lengths[symbol] = depths[node];
+            }
+            return lengths;
+        }
+        // Flatten the distribution until the code fits.
+        for frequency in &mut frequencies {
+            if *frequency > 0 {
+                *frequency = (*frequency / 2).max(1);
+            }
+        }
+    }
+}
+
+/// Returns the canonical codes for the lengths.
+fn codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next_code = [0u16; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0;
+    for length in 1..next_code.len() {
+        code = (code + counts[length - 1]) << 1;
+        next_code[length] = code;
+    }
+    lengths.iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Returns the number of bits of the tokens with these frequencies.
+fn data_cost(literal_frequencies: &[u32], distance_frequencies: &[u32], literal_lengths: &[u8],
+    distance_lengths: &[u8]) -> usize
+{
+    let mut cost = 0;
+    for (symbol, &frequency) in literal_frequencies.iter().enumerate() {
+        let mut bits = literal_lengths[symbol] as usize;
+        if symbol > END_OF_BLOCK {
+            bits += LENGTH_EXTRA_BITS[symbol - END_OF_BLOCK - 1] as usize;
+        }
+        cost += frequency as usize * bits;
+    }
+    for (symbol, &frequency) in distance_frequencies.iter().enumerate() {
+        cost += frequency as usize * (distance_lengths[symbol] + DISTANCE_EXTRA_BITS[symbol]) as usize;
+    }
+    cost
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], literal_lengths: &[u8], distance_lengths: &[u8]) {
+    let literal_codes = codes(literal_lengths);
+    let distance_codes = codes(distance_lengths);
+    for &token in tokens {
+        match token {
+            Token::Literal(byte) => writer.write_code(literal_codes[byte as usize], literal_lengths[byte as usize]),
+            Token::Match { distance, len } => {
+                let index = length_index(len as usize);
+                let symbol = END_OF_BLOCK + 1 + index;
+                writer.write_code(literal_codes[symbol], literal_lengths[symbol]);
+                writer.write(u32::from(len - LENGTH_BASE[index]), LENGTH_EXTRA_BITS[index]);
+                let index = distance_index(distance as usize);
+                writer.write_code(distance_codes[index], distance_lengths[index]);
+                writer.write(u32::from(distance - DISTANCE_BASE[index]), DISTANCE_EXTRA_BITS[index]);
+            },
+        }
+    }
+    writer.write_code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+/// The code lengths of a dynamic block, run-length encoded with the symbols 16 to 18.
+struct DynamicHeader {
+    code_length_lengths: Vec<u8>,
+    /// Number of code length code lengths sent.
+    code_length_count: usize,
+    distance_count: usize,
+    literal_count: usize,
+    /// Symbols and their extra bits.
+    symbols: Vec<(u8, u8)>,
+}
+
+impl DynamicHeader {
+    fn new(literal_lengths: &[u8], distance_lengths: &[u8]) -> Self {
+        let literal_count = (literal_lengths.iter().rposition(|&length| length != 0).unwrap_or(0) + 1).max(257);
+        let distance_count = (distance_lengths.iter().rposition(|&length| length != 0).unwrap_or(0) + 1).max(1);
+        let lengths: Vec<u8> = literal_lengths[..literal_count].iter()
+            .chain(&distance_lengths[..distance_count])
+            .cloned()
+            .collect();
+
+        let mut symbols = vec![];
+        let mut index = 0;
+        while index < lengths.len() {
+            let length = lengths[index];
+            let run = lengths[index..].iter().take_while(|&&other| other == length).count();
+            if length == 0 && run >= 11 {
+                let run = run.min(138);
+                symbols.push((18, (run - 11) as u8));
+                index += run;
+            }
+            else if length == 0 && run >= 3 {
+                symbols.push((17, (run - 3) as u8));
+                index += run;
+            }
+            else if length != 0 && run >= 4 {
+                // The first length is sent as is, then repeated.
+                symbols.push((length, 0));
+                let run = (run - 1).min(6);
+                symbols.push((16, (run - 3) as u8));
+                index += run + 1;
+            }
+            else {
+                symbols.push((length, 0));
+                index += 1;
+            }
+        }
+
+        let mut frequencies = [0u32; 19];
+        for &(symbol, _) in &symbols {
+            frequencies[symbol as usize] += 1;
+        }
+        let code_length_lengths = code_lengths(&frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+        let code_length_count = (CODE_LENGTH_ORDER.iter().rposition(|&symbol| code_length_lengths[symbol] != 0)
+            .unwrap_or(0) + 1).max(4);
+        Self {
+            code_length_lengths,
+            code_length_count,
+            distance_count,
+            literal_count,
+            symbols,
+        }
+    }
+
+    fn cost(&self) -> usize {
+        let symbols: usize = self.symbols.iter()
+            .map(|&(symbol, _)| self.code_length_lengths[symbol as usize] as usize + extra_bits(symbol) as usize)
+            .sum();
+        5 + 5 + 4 + 3 * self.code_length_count + symbols
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write((self.literal_count - 257) as u32, 5);
+        writer.write((self.distance_count - 1) as u32, 5);
+        writer.write((self.code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write(u32::from(self.code_length_lengths[symbol]), 3);
+        }
+        let codes = codes(&self.code_length_lengths);
+        for &(symbol, extra) in &self.symbols {
+            writer.write_code(codes[symbol as usize], self.code_length_lengths[symbol as usize]);
+            writer.write(u32::from(extra), extra_bits(symbol));
+        }
+    }
+}
+
+fn extra_bits(code_length_symbol: u8) -> u8 {
+    match code_length_symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compress::{Format, Level, decompress};
+    use rand::Rng;
+    use super::{Deflater, WINDOW_SIZE};
+
+    fn deflate(level: Level, input: &[u8]) -> Vec<u8> {
+        let mut deflater = Deflater::new(level);
+        let mut output = vec![];
+        deflater.deflate(input, &mut output);
+        deflater.finish(&mut output);
+        output
+    }
+
+    // The expected streams below are decoded by zlib.
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(deflate(Level::None, b"stored"), [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100]);
+        // An empty final stored block.
+        assert_eq!(deflate(Level::None, b""), [1, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn fixed_block() {
+        let output = deflate(Level::Default, b"hello hello hello hello, world!\n");
+        assert_eq!(output, [203, 72, 205, 201, 201, 87, 192, 32, 117, 20, 202, 243, 139, 114, 82, 20, 185, 0]);
+        assert_eq!(output[0] >> 1 & 3, 1);
+    }
+
+    #[test]
+    fn dynamic_block() {
+        let text = b"the event loop polls the socket, the timer wakes the loop, and the handler reads the stream of \
+            the connection\n";
+        let expected = [
+            45, 139, 193, 13, 192, 32, 12, 3, 255, 157, 34, 3, 176, 20, 2, 87, 32, 66, 130, 32, 106, 215, 47, 80, 126,
+            103, 159, 109, 9, 132, 7, 98, 196, 170, 141, 154, 50, 15, 90, 229, 208, 80, 96, 110, 179, 229, 138, 78, 175,
+            47, 248, 221, 154, 58, 242, 18, 119, 74, 19, 120, 250, 14, 31, 207, 215, 38, 87, 210, 123, 167, 160, 34, 8,
+            150, 85, 174, 15,
+        ];
+        for &level in &[Level::Fast, Level::Default, Level::Best] {
+            assert_eq!(deflate(level, text), expected.as_ref(), "{:?}", level);
+        }
+        assert_eq!(expected[0] >> 1 & 3, 2);
+    }
+
+    #[test]
+    fn distance_across_blocks() {
+        // Incompressible by itself, but repeated at the largest distance over several blocks.
+        let mut rng = Rng::seed_with(11);
+        let window: Vec<u8> = (0..WINDOW_SIZE).map(|_| rng.gen_int() as u8).collect();
+        let input: Vec<u8> = window.iter().cycle().take(WINDOW_SIZE * 5).cloned().collect();
+        for &level in &[Level::Fast, Level::Default, Level::Best] {
+            let mut deflater = Deflater::new(level);
+            let mut output = vec![];
+            for chunk in input.chunks(10_000) {
+                deflater.deflate(chunk, &mut output);
+            }
+            deflater.finish(&mut output);
+            // Only the first copy is stored.
+            assert!(output.len() < WINDOW_SIZE + 2000, "{:?} {}", level, output.len());
+            assert_eq!(decompress(Format::Raw, &output), Ok(input.clone()), "{:?}", level);
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use std::mem;
+
+use super::{
+    CODE_LENGTH_ORDER,
+    DISTANCE_BASE,
+    DISTANCE_EXTRA_BITS,
+    END_OF_BLOCK,
+    Error,
+    LENGTH_BASE,
+    LENGTH_EXTRA_BITS,
+    WINDOW_SIZE,
+    fixed_lengths,
+};
+
+const MAX_BITS: usize = 15;
+
+/// Why decoding stopped before the end of the stream.
+enum Stop {
+    /// More input is needed. The position in the input must be restored to the last checkpoint.
+    Incomplete,
+    Error(Error),
+}
+
+impl From<Error> for Stop {
+    fn from(error: Error) -> Self {
+        Stop::Error(error)
+    }
+}
+
+/// Reads the input bits, starting from the least significant bit of each byte.
+struct Bits<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> Self {
+        Self {
+            bytes,
+            position,
+        }
+    }
+
+    fn align(&mut self) {
+        self.position = (self.position + 7) & !7;
+    }
+
+    fn bit(&mut self) -> Result<u32, Stop> {
+        let byte = *self.bytes.get(self.position >> 3).ok_or(Stop::Incomplete)?;
+        let bit = (byte >> (self.position & 7)) & 1;
+        self.position += 1;
+        Ok(u32::from(bit))
+    }
+
+    fn read(&mut self, count: u32) -> Result<u32, Stop> {
+        let mut value = 0;
+        for index in 0..count {
+            value |= self.bit()? << index;
+        }
+        Ok(value)
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        // Check that the code is not over-subscribed. Incomplete codes are allowed: the missing
+        // codes are rejected when decoding.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left <<= 1;
+            left -= i32::from(count);
+            if left < 0 {
+                return Err(Error::InvalidCodeLengths);
+            }
+        }
+        let mut offsets = [0; MAX_BITS + 2];
+        for length in 1..MAX_BITS + 1 {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1]];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize]] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Self {
+            counts,
+            symbols,
+        })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<usize, Stop> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= bits.bit()? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(Stop::Error(Error::InvalidCode))
+    }
+}
+
+enum State {
+    BlockHeader,
+    Stored(usize),
+    Compressed(Huffman, Huffman),
+    Done,
+}
+
+/// Streaming DEFLATE decoder. The input is consumed one symbol at a time, so that the decoding can
+/// stop anywhere when the input runs out and resume when more arrives.
+pub struct Inflater {
+    /// Unconsumed input.
+    input: Vec<u8>,
+    last_block: bool,
+    /// Position in the input, in bits.
+    position: usize,
+    state: State,
+    /// The output, of which at least the last 32K are kept for the back references.
+    window: Vec<u8>,
+}
+
+impl Inflater {
+    pub fn new() -> Self {
+        Self {
+            input: vec![],
+            last_block: false,
+            position: 0,
+            state: State::BlockHeader,
+            window: vec![],
+        }
+    }
+
+    pub fn inflate(&mut self, data: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+        self.input.extend_from_slice(data);
+        let start = self.window.len();
+        let result = self.run();
+        output.extend_from_slice(&self.window[start..]);
+
+        let consumed = self.position >> 3;
+        self.input.drain(..consumed);
+        self.position -= consumed << 3;
+        if self.window.len() > 2 * WINDOW_SIZE {
+            let excess = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..excess);
+        }
+
+        match result {
+            Ok(()) | Err(Stop::Incomplete) => Ok(()),
+            Err(Stop::Error(error)) => Err(error),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Returns the input following the end of the stream.
+    pub fn take_remaining(&mut self) -> Vec<u8> {
+        let consumed = self.position >> 3;
+        self.position = 0;
+        let mut remaining = mem::take(&mut self.input);
+        remaining.drain(..consumed);
+        remaining
+    }
+
+    fn end_block(&mut self) {
+        self.state =
+            if self.last_block {
+                // The data following the stream starts at the next byte.
+                self.position = (self.position + 7) & !7;
+                State::Done
+            }
+            else {
+                State::BlockHeader
+            };
+    }
+
+    fn run(&mut self) -> Result<(), Stop> {
+        loop {
+            match self.state {
+                State::BlockHeader => {
+                    let mut bits = Bits::new(&self.input, self.position);
+                    let header = bits.read(3)?;
+                    let state =
+                        match header >> 1 {
+                            0 => {
+                                bits.align();
+                                let len = bits.read(16)?;
+                                let complement = bits.read(16)?;
+                                if len != !complement & 0xffff {
+                                    return Err(Stop::Error(Error::InvalidStoredLength));
+                                }
+                                State::Stored(len as usize)
+                            },
+                            1 => {
+                                let (literals, distances) = fixed_lengths();
+                                State::Compressed(Huffman::new(&literals)?, Huffman::new(&distances)?)
+                            },
+                            2 => {
+                                let (literals, distances) = dynamic_codes(&mut bits)?;
+                                State::Compressed(literals, distances)
+                            },
+                            _ => return Err(Stop::Error(Error::InvalidBlockType)),
+                        };
+                    // The whole header was read: commit.
+                    self.last_block = header & 1 != 0;
+                    self.position = bits.position;
+                    self.state = state;
+                },
+                State::Stored(remaining) => {
+                    let start = self.position >> 3;
+                    let count = remaining.min(self.input.len() - start);
+                    self.window.extend_from_slice(&self.input[start..start + count]);
+                    self.position += count << 3;
+                    if count == remaining {
+                        self.end_block();
+                    }
+                    else {
+                        self.state = State::Stored(remaining - count);
+                        return Err(Stop::Incomplete);
+                    }
+                },
+                State::Compressed(ref literals, ref distances) => {
+                    let mut bits = Bits::new(&self.input, self.position);
+                    decode_symbols(&mut bits, &mut self.position, &mut self.window, literals, distances)?;
+                    self.end_block();
+                },
+                State::Done => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Decodes the symbols until the end of the block, updating `checkpoint` after each complete one.
+fn decode_symbols(bits: &mut Bits, checkpoint: &mut usize, window: &mut Vec<u8>, literals: &Huffman,
+    distances: &Huffman) -> Result<(), Stop>
+{
+    loop {
+        let symbol = literals.decode(bits)?;
+        if symbol < END_OF_BLOCK {
+            window.push(symbol as u8);
+        }
+        else if symbol == END_OF_BLOCK {
+            *checkpoint = bits.position;
+            return Ok(());
+        }
+        else {
+            let index = symbol - END_OF_BLOCK - 1;
+            if index >= LENGTH_BASE.len() {
+                return Err(Stop::Error(Error::InvalidCode));
+            }
+            let len = LENGTH_BASE[index] as usize + bits.read(u32::from(LENGTH_EXTRA_BITS[index]))? as usize;
+            let index = distances.decode(bits)?;
+            if index >= DISTANCE_BASE.len() {
+                return Err(Stop::Error(Error::InvalidCode));
+            }
+            let distance = DISTANCE_BASE[index] as usize + bits.read(u32::from(DISTANCE_EXTRA_BITS[index]))? as usize;
+            if distance > window.len() {
+                return Err(Stop::Error(Error::InvalidDistance));
+            }
+            // The copy can overlap with itself.
+            let start = window.len() - distance;
+            for index in start..start + len {
+                let byte = window[index];
+                window.push(byte);
+            }
+        }
+        *checkpoint = bits.position;
+    }
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), Stop> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(Stop::Error(Error::InvalidCodeLengths));
+    }
+
+    let mut code_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(bits)?;
+        let (value, repeat) =
+            match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if index == 0 {
+                        return Err(Stop::Error(Error::InvalidCodeLengths));
+                    }
+                    (lengths[index - 1], 3 + bits.read(2)? as usize)
+                },
+                17 => (0, 3 + bits.read(3)? as usize),
+                _ => (0, 11 + bits.read(7)? as usize),
+            };
+        if index + repeat > lengths.len() {
+            return Err(Stop::Error(Error::InvalidCodeLengths));
+        }
+        for length in &mut lengths[index..index + repeat] {
+            *length = value;
+        }
+        index += repeat;
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(Stop::Error(Error::InvalidCodeLengths));
+    }
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+#[cfg(test)]
+mod tests {
+    use compress::Error;
+    use super::Inflater;
+
+    const TEXT: &[u8] = b"the event loop polls the socket, the timer wakes the loop, and the handler reads the stream \
+        of the connection\n";
+    /// `TEXT` in a dynamic block, produced by zlib.
+    const DYNAMIC: [u8; 78] = [
+        45, 139, 193, 13, 192, 32, 12, 3, 255, 157, 34, 3, 176, 20, 2, 87, 32, 66, 130, 32, 106, 215, 111, 74, 251, 59,
+        251, 108, 43, 32, 92, 16, 35, 86, 29, 52, 148, 121, 145, 121, 185, 52, 53, 88, 216, 108, 181, 99, 210, 29, 27,
+        62, 247, 78, 3, 69, 201, 59, 21, 7, 118, 63, 17, 243, 255, 53, 231, 78, 122, 238, 148, 84, 4, 201, 170, 202,
+        241, 0,
+    ];
+
+    /// Inflates `data` fed in chunks of `chunk_size`, returning the output and whether the stream
+    /// ended.
+    fn inflate(data: &[u8], chunk_size: usize) -> Result<(Vec<u8>, bool), Error> {
+        let mut inflater = Inflater::new();
+        let mut output = vec![];
+        for chunk in data.chunks(chunk_size) {
+            inflater.inflate(chunk, &mut output)?;
+        }
+        Ok((output, inflater.is_finished()))
+    }
+
+    /// Writes bits starting from the least significant bit of each byte, like `BitWriter`.
+    #[derive(Default)]
+    struct Bits {
+        bit_count: u32,
+        bytes: Vec<u8>,
+    }
+
+    impl Bits {
+        fn write(&mut self, value: u32, count: u32) {
+            for index in 0..count {
+                if self.bit_count.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= (((value >> index) & 1) as u8) << (self.bit_count % 8);
+                self.bit_count += 1;
+            }
+        }
+
+        fn align(&mut self) {
+            self.bit_count = (self.bit_count + 7) & !7;
+        }
+
+        fn write_code(&mut self, code: u32, len: u32) {
+            self.write(code.reverse_bits() >> (32 - len), len);
+        }
+    }
+
+    #[test]
+    fn stored_block() {
+        // Produced by zlib.
+        let stored = [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100];
+        assert_eq!(inflate(&stored, stored.len()), Ok((b"stored".to_vec(), true)));
+        assert_eq!(inflate(&stored, 1), Ok((b"stored".to_vec(), true)));
+    }
+
+    #[test]
+    fn fixed_block() {
+        // Produced by zlib.
+        let fixed = [203, 72, 205, 201, 201, 87, 200, 64, 39, 117, 20, 202, 243, 139, 114, 82, 20, 185, 0];
+        let expected = b"hello hello hello hello, world!\n".to_vec();
+        assert_eq!(inflate(&fixed, fixed.len()), Ok((expected.clone(), true)));
+        assert_eq!(inflate(&fixed, 1), Ok((expected, true)));
+    }
+
+    #[test]
+    fn dynamic_block() {
+        assert_eq!(DYNAMIC[0] >> 1 & 3, 2);
+        assert_eq!(inflate(&DYNAMIC, DYNAMIC.len()), Ok((TEXT.to_vec(), true)));
+        assert_eq!(inflate(&DYNAMIC, 1), Ok((TEXT.to_vec(), true)));
+    }
+
+    #[test]
+    fn distance_across_windows() {
+        const REPEATS: usize = 300;
+        // A stored block of 32K followed by a fixed block of matches at the largest distance.
+        let window: Vec<u8> = (0..32768u32).map(|index| (index * 7 + index / 251) as u8).collect();
+        let mut bits = Bits::default();
+        bits.write(0, 3);
+        bits.align();
+        bits.write(32768, 16);
+        bits.write(!32768 & 0xffff, 16);
+        for &byte in &window {
+            bits.write(u32::from(byte), 8);
+        }
+        bits.write(1 | 1 << 1, 3);
+        for _ in 0..REPEATS {
+            // Length 258, then distance code 29 with all its extra bits set: 32768.
+            bits.write_code(0b1100_0101, 8);
+            bits.write_code(29, 5);
+            bits.write(0x1fff, 13);
+        }
+        bits.write_code(0, 7);
+
+        let expected: Vec<u8> = (0..window.len() + REPEATS * 258).map(|index| window[index % window.len()]).collect();
+        // In small chunks, the start of the window is dropped while the matches refer to it.
+        for &chunk_size in &[bits.bytes.len(), 1000, 7] {
+            assert_eq!(inflate(&bits.bytes, chunk_size).as_ref(), Ok(&(expected.clone(), true)));
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for len in 0..DYNAMIC.len() - 1 {
+            let (output, finished) = inflate(&DYNAMIC[..len], 1).expect("inflate");
+            assert!(!finished, "{}", len);
+            assert!(TEXT.starts_with(&output), "{}", len);
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        // Block type 3.
+        assert_eq!(inflate(&[7], 1), Err(Error::InvalidBlockType));
+        // Stored length not matching its complement.
+        assert_eq!(inflate(&[1, 6, 0, 249, 254, 115], 1), Err(Error::InvalidStoredLength));
+        // Fixed block starting with a match.
+        let mut bits = Bits::default();
+        bits.write(1 | 1 << 1, 3);
+        bits.write_code(0b000_0001, 7);
+        bits.write_code(0, 5);
+        assert_eq!(inflate(&bits.bytes, 1), Err(Error::InvalidDistance));
+        // Dynamic block whose code length code is over-subscribed.
+        let mut bits = Bits::default();
+        bits.write(1 | 2 << 1, 3);
+        bits.write(0, 5);
+        bits.write(0, 5);
+        bits.write(0, 4);
+        for _ in 0..4 {
+            bits.write(1, 3);
+        }
+        assert_eq!(inflate(&bits.bytes, 1), Err(Error::InvalidCodeLengths));
+
+        // Flipping any bit gives an error or some output, but never a panic.
+        for bit in 0..DYNAMIC.len() * 8 {
+            let mut corrupted = DYNAMIC.to_vec();
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            let _ = inflate(&corrupted, corrupted.len());
+        }
+    }
+}
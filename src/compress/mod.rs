@@ -0,0 +1,564 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! DEFLATE compression (RFC 1951) with the zlib (RFC 1950) and gzip (RFC 1952) wrappers.
+//!
+//! `Encoder` and `Decoder` are streaming: feed them chunks as they come and they append their
+//! output to a buffer. `compress()` and `decompress()` handle a whole buffer at once.
+
+mod deflate;
+mod inflate;
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+use checksum::{Adler32, Crc32};
+use parse::{self, Cursor};
+use self::deflate::Deflater;
+use self::inflate::Inflater;
+
+/// Size of the history that distances can refer to.
+const WINDOW_SIZE: usize = 32768;
+
+/// Base lengths of the length codes 257 to 285.
+static LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+static LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+static DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+static DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// Order in which the lengths of the code length code are transmitted.
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const END_OF_BLOCK: usize = 256;
+
+/// Returns the code lengths of the fixed Huffman codes for the literals/lengths and the distances.
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literals = vec![8; 288];
+    for length in &mut literals[144..256] {
+        *length = 9;
+    }
+    for length in &mut literals[256..280] {
+        *length = 7;
+    }
+    (literals, vec![5; 30])
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const GZIP_FHCRC: u8 = 0x02;
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+/// Compression method of zlib and gzip.
+const METHOD_DEFLATE: u8 = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The zlib or gzip header is invalid or uses an unsupported feature.
+    InvalidHeader,
+    InvalidBlockType,
+    /// The length of a stored block does not match its complement.
+    InvalidStoredLength,
+    /// The code lengths do not describe a valid Huffman code.
+    InvalidCodeLengths,
+    /// The data contains a code that is not in the Huffman code.
+    InvalidCode,
+    /// A distance points before the start of the data.
+    InvalidDistance,
+    /// The checksum of the decompressed data does not match the trailer.
+    ChecksumMismatch,
+    /// The size of the decompressed data does not match the gzip trailer.
+    LengthMismatch,
+    /// The stream ended before its end.
+    Truncated,
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                Error::InvalidHeader => "invalid header",
+                Error::InvalidBlockType => "invalid block type",
+                Error::InvalidStoredLength => "invalid stored block length",
+                Error::InvalidCodeLengths => "invalid code lengths",
+                Error::InvalidCode => "invalid code",
+                Error::InvalidDistance => "distance too far back",
+                Error::ChecksumMismatch => "checksum mismatch",
+                Error::LengthMismatch => "length mismatch",
+                Error::Truncated => "truncated stream",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// How the DEFLATE data is wrapped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// No header nor checksum.
+    Raw,
+    /// 2-byte header and Adler-32 trailer. This is the "deflate" content encoding of HTTP.
+    Zlib,
+    /// 10-byte header and CRC32 trailer.
+    Gzip,
+}
+
+impl Format {
+    /// Returns the format of an HTTP content encoding.
+    pub fn from_content_encoding(encoding: &str) -> Option<Self> {
+        let encoding = encoding.trim();
+        if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+            Some(Format::Gzip)
+        }
+        else if encoding.eq_ignore_ascii_case("deflate") {
+            Some(Format::Zlib)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Returns the name of the format as an HTTP content encoding.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match *self {
+            Format::Raw => None,
+            Format::Zlib => Some("deflate"),
+            Format::Gzip => Some("gzip"),
+        }
+    }
+}
+
+/// Trade-off between speed and compression ratio.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Level {
+    /// Stored blocks only.
+    None,
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+enum Checksum {
+    Adler(Adler32),
+    Crc(Crc32),
+    None,
+}
+
+impl Checksum {
+    fn new(format: Format) -> Self {
+        match format {
+            Format::Raw => Checksum::None,
+            Format::Zlib => Checksum::Adler(Adler32::new()),
+            Format::Gzip => Checksum::Crc(Crc32::ieee()),
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        match *self {
+            Checksum::Adler(ref adler) => adler.finish(),
+            Checksum::Crc(ref crc) => crc.finish(),
+            Checksum::None => 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            Checksum::Adler(ref mut adler) => adler.update(data),
+            Checksum::Crc(ref mut crc) => crc.update(data),
+            Checksum::None => (),
+        }
+    }
+}
+
+/// Streaming compressor.
+pub struct Encoder {
+    checksum: Checksum,
+    deflater: Deflater,
+    format: Format,
+    header_written: bool,
+    level: Level,
+    size: u32,
+}
+
+impl Encoder {
+    pub fn new(format: Format, level: Level) -> Self {
+        Self {
+            checksum: Checksum::new(format),
+            deflater: Deflater::new(level),
+            format,
+            header_written: false,
+            level,
+            size: 0,
+        }
+    }
+
+    /// Compresses `data`, appending the compressed bytes available so far to `output`.
+    pub fn write(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        self.write_header(output);
+        self.checksum.update(data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        self.deflater.deflate(data, output);
+    }
+
+    /// Outputs all the data written so far, so that the receiver can decompress it without
+    /// waiting for the end of the stream. Flushing often degrades the compression.
+    pub fn flush(&mut self, output: &mut Vec<u8>) {
+        self.write_header(output);
+        self.deflater.flush(output);
+    }
+
+    /// Ends the stream.
+    pub fn finish(mut self, output: &mut Vec<u8>) {
+        self.write_header(output);
+        self.deflater.finish(output);
+        let checksum = self.checksum.finish();
+        match self.format {
+            Format::Raw => (),
+            Format::Zlib => output.extend_from_slice(&checksum.to_be_bytes()),
+            Format::Gzip => {
+                output.extend_from_slice(&checksum.to_le_bytes());
+                output.extend_from_slice(&self.size.to_le_bytes());
+            },
+        }
+    }
+
+    fn write_header(&mut self, output: &mut Vec<u8>) {
+        if self.header_written {
+            return;
+        }
+        self.header_written = true;
+        match self.format {
+            Format::Raw => (),
+            Format::Zlib => {
+                // 32K window.
+                let method = 0x70 | METHOD_DEFLATE;
+                let level =
+                    match self.level {
+                        Level::None => 0,
+                        Level::Fast => 1,
+                        Level::Default => 2,
+                        Level::Best => 3,
+                    };
+                let mut flags = level << 6;
+                flags |= 31 - ((u16::from(method) << 8 | u16::from(flags)) % 31) as u8;
+                output.extend_from_slice(&[method, flags]);
+            },
+            Format::Gzip => {
+                let extra_flags =
+                    match self.level {
+                        Level::Best => 2,
+                        Level::None | Level::Fast => 4,
+                        Level::Default => 0,
+                    };
+                // No flags, no modification time and unknown operating system.
+                output.extend_from_slice(&GZIP_MAGIC);
+                output.extend_from_slice(&[METHOD_DEFLATE, 0, 0, 0, 0, 0, extra_flags, 255]);
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Stage {
+    Header,
+    Body,
+    Trailer,
+    Done,
+}
+
+/// Streaming decompressor. Concatenated gzip members are decompressed as a single stream.
+pub struct Decoder {
+    /// Bytes of the header or trailer received so far.
+    buffer: Vec<u8>,
+    checksum: Checksum,
+    format: Format,
+    inflater: Inflater,
+    size: u32,
+    stage: Stage,
+}
+
+impl Decoder {
+    pub fn new(format: Format) -> Self {
+        Self {
+            buffer: vec![],
+            checksum: Checksum::new(format),
+            format,
+            inflater: Inflater::new(),
+            size: 0,
+            stage: if format == Format::Raw { Stage::Body } else { Stage::Header },
+        }
+    }
+
+    /// Returns true when the end of the stream was reached.
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Returns an error if the end of the stream was not reached.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.is_finished() {
+            Ok(())
+        }
+        else {
+            Err(Error::Truncated)
+        }
+    }
+
+    /// Decompresses `data`, appending the decompressed bytes available so far to `output`. The
+    /// data following the end of a zlib or raw stream is ignored.
+    pub fn write(&mut self, data: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+        self.buffer.extend_from_slice(data);
+        loop {
+            match self.stage {
+                Stage::Header => {
+                    let result =
+                        match self.format {
+                            Format::Gzip => gzip_header_len(&self.buffer),
+                            _ => zlib_header_len(&self.buffer),
+                        };
+                    match result {
+                        Ok(len) => {
+                            self.buffer.drain(..len);
+                            self.stage = Stage::Body;
+                        },
+                        Err(ref error) if error.is_incomplete() => return Ok(()),
+                        Err(_) => return Err(Error::InvalidHeader),
+                    }
+                },
+                Stage::Body => {
+                    let start = output.len();
+                    let result = self.inflater.inflate(&self.buffer, output);
+                    self.buffer.clear();
+                    self.checksum.update(&output[start..]);
+                    self.size = self.size.wrapping_add((output.len() - start) as u32);
+                    result?;
+                    if !self.inflater.is_finished() {
+                        return Ok(());
+                    }
+                    self.buffer = self.inflater.take_remaining();
+                    self.stage = Stage::Trailer;
+                },
+                Stage::Trailer => {
+                    let len =
+                        match self.format {
+                            Format::Raw => 0,
+                            Format::Zlib => 4,
+                            Format::Gzip => 8,
+                        };
+                    if self.buffer.len() < len {
+                        return Ok(());
+                    }
+                    let checksum = self.checksum.finish();
+                    match self.format {
+                        Format::Raw => (),
+                        Format::Zlib => {
+                            if self.buffer[..4] != checksum.to_be_bytes() {
+                                return Err(Error::ChecksumMismatch);
+                            }
+                        },
+                        Format::Gzip => {
+                            if self.buffer[..4] != checksum.to_le_bytes() {
+                                return Err(Error::ChecksumMismatch);
+                            }
+                            if self.buffer[4..8] != self.size.to_le_bytes() {
+                                return Err(Error::LengthMismatch);
+                            }
+                        },
+                    }
+                    self.buffer.drain(..len);
+                    self.stage = Stage::Done;
+                },
+                Stage::Done => {
+                    if self.format != Format::Gzip || self.buffer.is_empty() {
+                        self.buffer.clear();
+                        return Ok(());
+                    }
+                    // Another member follows.
+                    self.checksum = Checksum::new(self.format);
+                    self.inflater = Inflater::new();
+                    self.size = 0;
+                    self.stage = Stage::Header;
+                },
+            }
+        }
+    }
+}
+
+fn zlib_header_len(bytes: &[u8]) -> parse::Result<usize> {
+    let mut cursor = Cursor::new(bytes);
+    let method = cursor.u8()?;
+    let flags = cursor.u8()?;
+    let has_dictionary = flags & 0x20 != 0;
+    if method & 0x0f != METHOD_DEFLATE || method >> 4 > 7 || (u16::from(method) << 8 | u16::from(flags)) % 31 != 0
+        || has_dictionary
+    {
+        return Err(parse::Error::new(parse::ErrorKind::Invalid, 0));
+    }
+    Ok(cursor.position())
+}
+
+fn gzip_header_len(bytes: &[u8]) -> parse::Result<usize> {
+    let mut cursor = Cursor::new(bytes);
+    cursor.expect(&GZIP_MAGIC)?;
+    if cursor.u8()? != METHOD_DEFLATE {
+        return Err(cursor.error(parse::ErrorKind::Invalid));
+    }
+    let flags = cursor.u8()?;
+    // Modification time, extra flags and operating system.
+    cursor.skip(6)?;
+    if flags & GZIP_FEXTRA != 0 {
+        let len = cursor.u16_le()?;
+        cursor.skip(len as usize)?;
+    }
+    if flags & GZIP_FNAME != 0 {
+        cursor.take_until(b"\0")?;
+    }
+    if flags & GZIP_FCOMMENT != 0 {
+        cursor.take_until(b"\0")?;
+    }
+    if flags & GZIP_FHCRC != 0 {
+        cursor.skip(2)?;
+    }
+    Ok(cursor.position())
+}
+
+pub fn compress(format: Format, level: Level, data: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    let mut encoder = Encoder::new(format, level);
+    encoder.write(data, &mut output);
+    encoder.finish(&mut output);
+    output
+}
+
+pub fn decompress(format: Format, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = vec![];
+    let mut decoder = Decoder::new(format);
+    decoder.write(data, &mut output)?;
+    decoder.finish()?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use super::{Decoder, Encoder, Error, Format, Level, compress, decompress};
+
+    fn sample_text() -> Vec<u8> {
+        let mut rng = Rng::seed_with(7);
+        let words = ["event", "loop", "stream", "handler", "connection", "timer", "the", "a", "of", "socket"];
+        let mut text = vec![];
+        while text.len() < 200_000 {
+            text.extend_from_slice(words[rng.gen_int_interval(0, words.len() as u32) as usize].as_bytes());
+            text.push(if rng.gen_int_interval(0, 12) == 0 { b'\n' } else { b' ' });
+        }
+        text
+    }
+
+    #[test]
+    fn reference_streams() {
+        // Produced by zlib.
+        let zlib = [
+            120, 218, 203, 72, 205, 201, 201, 87, 200, 64, 39, 117, 20, 202, 243, 139, 114, 82, 20, 185, 0, 196, 5, 11,
+            80,
+        ];
+        let gzip = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 203, 72, 205, 201, 201, 87, 200, 64, 39, 117, 20, 202, 243, 139, 114, 82,
+            20, 185, 0, 146, 189, 12, 139, 32, 0, 0, 0,
+        ];
+        let expected = b"hello hello hello hello, world!\n".to_vec();
+        assert_eq!(decompress(Format::Zlib, &zlib), Ok(expected.clone()));
+        assert_eq!(decompress(Format::Gzip, &gzip), Ok(expected.clone()));
+        assert_eq!(decompress(Format::Raw, &zlib[2..zlib.len() - 4]), Ok(expected.clone()));
+
+        let mut corrupted = gzip.to_vec();
+        corrupted[30] ^= 1;
+        assert_eq!(decompress(Format::Gzip, &corrupted), Err(Error::ChecksumMismatch));
+        assert_eq!(decompress(Format::Gzip, &gzip[..gzip.len() - 1]), Err(Error::Truncated));
+        assert_eq!(decompress(Format::Gzip, &zlib), Err(Error::InvalidHeader));
+
+        // Concatenated members.
+        let mut members = gzip.to_vec();
+        members.extend_from_slice(&gzip);
+        let mut twice = expected.clone();
+        twice.extend_from_slice(&expected);
+        assert_eq!(decompress(Format::Gzip, &members), Ok(twice));
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = sample_text();
+        let mut rng = Rng::seed_with(3);
+        let random: Vec<u8> = (0..100_000).map(|_| rng.gen_int() as u8).collect();
+        let inputs = [vec![], b"a".to_vec(), vec![0; 300_000], random.clone(), text.clone()];
+        for input in &inputs {
+            for &level in &[Level::None, Level::Fast, Level::Default, Level::Best] {
+                for &format in &[Format::Raw, Format::Zlib, Format::Gzip] {
+                    let compressed = compress(format, level, input);
+                    assert_eq!(decompress(format, &compressed).as_ref(), Ok(input), "{:?} {:?}", level, format);
+                }
+            }
+        }
+        assert!(compress(Format::Gzip, Level::Default, &text).len() < text.len() / 3);
+        // Incompressible data is stored.
+        assert!(compress(Format::Raw, Level::Best, &random).len() < random.len() + 20);
+    }
+
+    #[test]
+    fn streaming() {
+        let text = sample_text();
+        let mut encoder = Encoder::new(Format::Gzip, Level::Default);
+        let mut compressed = vec![];
+        for chunk in text[..1000].chunks(100) {
+            encoder.write(chunk, &mut compressed);
+        }
+        // Nothing is output before a whole block is available, except the header.
+        assert_eq!(compressed.len(), 10);
+        encoder.flush(&mut compressed);
+        let mut decoder = Decoder::new(Format::Gzip);
+        let mut output = vec![];
+        decoder.write(&compressed, &mut output).expect("decompress");
+        assert_eq!(output, &text[..1000]);
+        assert!(!decoder.is_finished());
+
+        let flushed = compressed.len();
+        encoder.write(&text[1000..], &mut compressed);
+        encoder.finish(&mut compressed);
+        for byte in &compressed[flushed..] {
+            decoder.write(&[*byte], &mut output).expect("decompress");
+        }
+        assert!(decoder.is_finished());
+        assert_eq!(output, text);
+
+        assert_eq!(Format::from_content_encoding(" GZIP"), Some(Format::Gzip));
+        assert_eq!(Format::from_content_encoding("deflate"), Some(Format::Zlib));
+        assert_eq!(Format::from_content_encoding("br"), None);
+    }
+}
@@ -0,0 +1,454 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Running a server in the background as a daemon.
+//!
+//! `Daemon::start()` detaches the process from its terminal with the classic double fork: the
+//! process forks, the child becomes a session leader with `setsid()` and forks again so that it
+//! can never reacquire a controlling terminal. The grandchild then creates the pid file, changes
+//! its working directory, drops its privileges, redirects the standard streams and closes the
+//! other file descriptors. The original process waits for all this to succeed before exiting, so
+//! that errors like a daemon already running are still reported on the terminal.
+//!
+//! Call `start()` before creating the event loop or spawning any thread: only the calling thread
+//! survives a fork, and file descriptors like the epoll one would be closed.
+
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+
+use aio::net::close;
+
+/// A file holding the pid of the running daemon, locked for as long as it is alive so that a second
+/// instance fails to start. The file is removed when dropped.
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates (or takes over a stale) pid file with the pid of the current process. Fails with
+    /// `ErrorKind::AlreadyExists` if another process holds it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        // Made absolute, so that it is still found after a change of the working directory.
+        let path = env::current_dir()?.join(path);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        if unsafe { ffi::flock(file.as_raw_fd(), ffi::LOCK_EX | ffi::LOCK_NB) } == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == ErrorKind::WouldBlock {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                let message = format!("{} is locked by process {}", path.display(), pid.trim());
+                return Err(io::Error::new(ErrorKind::AlreadyExists, message));
+            }
+            return Err(error);
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", process::id())?;
+        file.sync_all()?;
+        Ok(Self {
+            file,
+            path,
+        })
+    }
+
+    /// Returns the pid written in the file, or `None` if there is no file.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Option<u32>> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                content.trim().parse()
+                    .map(Some)
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid pid file"))
+            },
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // The lock is released afterwards, when the file is closed.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Returns true if a process with this pid exists.
+pub fn is_running(pid: u32) -> bool {
+    // Signal 0 only checks whether the signal could be sent.
+    let result = unsafe { ffi::kill(pid as i32, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(ffi::EPERM)
+}
+
+/// Closes the file descriptors of the process, except the standard streams and `keep`.
+pub fn close_fds(keep: &[RawFd]) -> io::Result<()> {
+    let fds: Vec<RawFd> = fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        // The fd used to read the directory is in the list but already closed.
+        if fd > 2 && !keep.contains(&fd) {
+            let _ = close(fd);
+        }
+    }
+    Ok(())
+}
+
+/// Switches to the group and then to the user, given by name, e.g. after binding privileged ports
+/// as root. The supplementary groups are set to the ones of the user, or cleared if only the group
+/// is given.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let user = match user {
+        Some(name) => Some((name, lookup_user(name)?)),
+        None => None,
+    };
+    let gid =
+        match (group, user) {
+            (Some(name), _) => Some(lookup_group(name)?),
+            (None, Some((_, (_, gid)))) => Some(gid),
+            (None, None) => None,
+        };
+    if let Some(gid) = gid {
+        let result =
+            match user {
+                Some((name, _)) => {
+                    let name = c_string(name)?;
+                    unsafe { ffi::initgroups(name.as_ptr(), gid) }
+                },
+                None => unsafe { ffi::setgroups(0, ptr::null()) },
+            };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { ffi::setgid(gid) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if let Some((_, (uid, _))) = user {
+        if unsafe { ffi::setuid(uid) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn c_string(text: &str) -> io::Result<CString> {
+    CString::new(text).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "name contains a nul byte"))
+}
+
+/// Returns the uid and the primary gid of the user.
+fn lookup_user(name: &str) -> io::Result<(u32, u32)> {
+    let c_name = c_string(name)?;
+    let mut passwd: ffi::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0i8; 16384];
+    let mut result = ptr::null_mut();
+    let error = unsafe { ffi::getpwnam_r(c_name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("unknown user {}", name)));
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+fn lookup_group(name: &str) -> io::Result<u32> {
+    let c_name = c_string(name)?;
+    let mut group: ffi::group = unsafe { mem::zeroed() };
+    let mut buffer = vec![0i8; 16384];
+    let mut result = ptr::null_mut();
+    let error = unsafe { ffi::getgrnam_r(c_name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("unknown group {}", name)));
+    }
+    Ok(group.gr_gid)
+}
+
+fn fork() -> io::Result<i32> {
+    let pid = unsafe { ffi::fork() };
+    if pid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pid)
+}
+
+/// Replaces the fd `target` by `file`.
+fn redirect(file: File, target: RawFd) -> io::Result<()> {
+    let fd = file.into_raw_fd();
+    let result = unsafe { ffi::dup2(fd, target) };
+    let _ = close(fd);
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn open_log(path: &Option<PathBuf>) -> io::Result<File> {
+    match *path {
+        Some(ref path) => OpenOptions::new().append(true).create(true).open(path),
+        None => OpenOptions::new().write(true).open("/dev/null"),
+    }
+}
+
+/// Configuration of the daemonization.
+#[derive(Clone, Debug)]
+pub struct Daemon {
+    group: Option<String>,
+    keep_fds: Vec<RawFd>,
+    pid_file: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    stdout: Option<PathBuf>,
+    umask: u32,
+    user: Option<String>,
+    working_directory: PathBuf,
+}
+
+impl Daemon {
+    /// By default, the daemon runs in `/` with the umask 027, its standard streams go to
+    /// `/dev/null` and no pid file is created.
+    pub fn new() -> Self {
+        Self {
+            group: None,
+            keep_fds: vec![],
+            pid_file: None,
+            stderr: None,
+            stdout: None,
+            umask: 0o027,
+            user: None,
+            working_directory: PathBuf::from("/"),
+        }
+    }
+
+    /// Switches to this group, by name.
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// Keeps this file descriptor open in the daemon, e.g. a listening socket bound to a privileged
+    /// port before dropping the privileges.
+    pub fn keep_fd<A: AsRawFd>(mut self, fd: &A) -> Self {
+        self.keep_fds.push(fd.as_raw_fd());
+        self
+    }
+
+    /// Creates a pid file, created before dropping the privileges. See `PidFile`.
+    pub fn pid_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.pid_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Appends the standard error to this file.
+    pub fn stderr<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stderr = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Appends the standard output to this file.
+    pub fn stdout<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdout = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Switches to this user, by name, and to its primary group unless `group()` is called.
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn working_directory<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.working_directory = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Turns the process into a daemon. Returns in the daemon, with its pid file if one was asked
+    /// for: keep it alive until the daemon exits. The original process exits with status 0 once the
+    /// daemon is set up, or gets the error if the setup failed.
+    pub fn start(self) -> io::Result<Option<PidFile>> {
+        let (mut parent, mut child) = UnixStream::pair()?;
+        if fork()? != 0 {
+            drop(child);
+            let mut status = String::new();
+            parent.read_to_string(&mut status)?;
+            if status == "ok" {
+                let _ = io::stdout().flush();
+                unsafe { ffi::_exit(0) };
+            }
+            if status.is_empty() {
+                status = "the daemon exited during its setup".to_string();
+            }
+            return Err(io::Error::other(status));
+        }
+
+        drop(parent);
+        let result = unsafe { ffi::setsid() };
+        if result == -1 {
+            let _ = write!(child, "setsid failed: {}", io::Error::last_os_error());
+            unsafe { ffi::_exit(1) };
+        }
+        match fork() {
+            Ok(0) => (),
+            Ok(_) => unsafe { ffi::_exit(0) },
+            Err(error) => {
+                let _ = write!(child, "fork failed: {}", error);
+                unsafe { ffi::_exit(1) };
+            },
+        }
+
+        match self.setup(&child) {
+            Ok(pid_file) => {
+                let _ = child.write_all(b"ok");
+                Ok(pid_file)
+            },
+            Err(error) => {
+                let _ = write!(child, "{}", error);
+                unsafe { ffi::_exit(1) };
+            },
+        }
+    }
+
+    fn setup(&self, status: &UnixStream) -> io::Result<Option<PidFile>> {
+        // Everything given by path is opened relative to the original working directory.
+        let stdout = open_log(&self.stdout)?;
+        let stderr = open_log(&self.stderr)?;
+        let pid_file =
+            match self.pid_file {
+                Some(ref path) => Some(PidFile::create(path)?),
+                None => None,
+            };
+        env::set_current_dir(&self.working_directory)?;
+        unsafe { ffi::umask(self.umask) };
+        drop_privileges(self.user.as_deref(), self.group.as_deref())?;
+
+        redirect(OpenOptions::new().read(true).open("/dev/null")?, 0)?;
+        redirect(stdout, 1)?;
+        redirect(stderr, 2)?;
+        let mut keep = self.keep_fds.clone();
+        keep.push(status.as_raw_fd());
+        if let Some(ref pid_file) = pid_file {
+            keep.push(pid_file.file.as_raw_fd());
+        }
+        close_fds(&keep)?;
+        Ok(pid_file)
+    }
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+mod ffi {
+    #![allow(non_camel_case_types)]
+
+    pub const EPERM: i32 = 1;
+    pub const LOCK_EX: i32 = 2;
+    pub const LOCK_NB: i32 = 4;
+
+    #[repr(C)]
+    pub struct passwd {
+        pub pw_name: *mut i8,
+        pub pw_passwd: *mut i8,
+        pub pw_uid: u32,
+        pub pw_gid: u32,
+        pub pw_gecos: *mut i8,
+        pub pw_dir: *mut i8,
+        pub pw_shell: *mut i8,
+    }
+
+    #[repr(C)]
+    pub struct group {
+        pub gr_name: *mut i8,
+        pub gr_passwd: *mut i8,
+        pub gr_gid: u32,
+        pub gr_mem: *mut *mut i8,
+    }
+
+    extern "C" {
+        pub fn _exit(status: i32) -> !;
+        pub fn dup2(oldfd: i32, newfd: i32) -> i32;
+        pub fn flock(fd: i32, operation: i32) -> i32;
+        pub fn fork() -> i32;
+        pub fn getgrnam_r(name: *const i8, grp: *mut group, buf: *mut i8, buflen: usize, result: *mut *mut group) -> i32;
+        pub fn getpwnam_r(name: *const i8, pwd: *mut passwd, buf: *mut i8, buflen: usize, result: *mut *mut passwd) -> i32;
+        pub fn initgroups(user: *const i8, group: u32) -> i32;
+        pub fn kill(pid: i32, sig: i32) -> i32;
+        pub fn setgid(gid: u32) -> i32;
+        pub fn setgroups(size: usize, list: *const u32) -> i32;
+        pub fn setsid() -> i32;
+        pub fn setuid(uid: u32) -> i32;
+        pub fn umask(mask: u32) -> u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::ErrorKind;
+    use std::process;
+
+    use super::{PidFile, is_running, lookup_group, lookup_user};
+
+    #[test]
+    fn pid_file() {
+        let path = env::temp_dir().join(format!("mini-daemon-test-{}.pid", process::id()));
+        {
+            let pid_file = PidFile::create(&path).expect("pid file");
+            assert_eq!(pid_file.path(), path.as_path());
+            assert_eq!(PidFile::read(&path).expect("read"), Some(process::id()));
+            let error = PidFile::create(&path).err().expect("locked");
+            assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+            assert!(error.to_string().ends_with(&format!("locked by process {}", process::id())));
+        }
+        assert!(!path.exists());
+        assert_eq!(PidFile::read(&path).expect("read"), None);
+    }
+
+    #[test]
+    fn processes_and_users() {
+        assert!(is_running(process::id()));
+        assert!(!is_running(i32::MAX as u32));
+        assert_eq!(lookup_user("root").expect("root"), (0, 0));
+        assert_eq!(lookup_group("root").expect("root"), 0);
+        assert_eq!(lookup_user("no-such-user-mini").err().map(|error| error.kind()), Some(ErrorKind::NotFound));
+    }
+}
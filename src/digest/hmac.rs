@@ -0,0 +1,118 @@
+//! Keyed-hash message authentication codes (RFC 2104).
+//!
+//! Verify a received tag with `Hmac::verify()` rather than by comparing it with `==`: the
+//! comparison must not reveal how many leading bytes matched.
+
+use std::io::{self, Write};
+
+use super::{Digest, Sha256, constant_time_eq};
+
+const INNER_PAD: u8 = 0x36;
+const OUTER_PAD: u8 = 0x5c;
+
+/// Incremental HMAC computation over the hash function `D`.
+#[derive(Clone)]
+pub struct Hmac<D> {
+    inner: D,
+    outer: D,
+}
+
+pub type HmacSha256 = Hmac<Sha256>;
+
+impl<D: Digest> Hmac<D> {
+    /// Creates an HMAC with a key of any length.
+    pub fn new(key: &[u8]) -> Self {
+        let mut block_key =
+            if key.len() > D::BLOCK_SIZE {
+                D::digest(key).as_ref().to_vec()
+            }
+            else {
+                key.to_vec()
+            };
+        block_key.resize(D::BLOCK_SIZE, 0);
+
+        let mut inner = D::new();
+        let mut outer = D::new();
+        let pad: Vec<u8> = block_key.iter().map(|byte| byte ^ INNER_PAD).collect();
+        inner.update(&pad);
+        let pad: Vec<u8> = block_key.iter().map(|byte| byte ^ OUTER_PAD).collect();
+        outer.update(&pad);
+        Self {
+            inner,
+            outer,
+        }
+    }
+
+    /// Returns the tag of the data fed so far.
+    pub fn finish(&self) -> D::Output {
+        let mut outer = self.outer.clone();
+        outer.update(self.inner.finish().as_ref());
+        outer.finish()
+    }
+
+    /// Feeds data to the HMAC.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Returns true if `tag` is the tag of the data fed so far, in constant time.
+    pub fn verify(&self, tag: &[u8]) -> bool {
+        constant_time_eq(self.finish().as_ref(), tag)
+    }
+}
+
+impl<D: Digest> Write for Hmac<D> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.update(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the HMAC-SHA256 tag of `data`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut hmac = HmacSha256::new(key);
+    hmac.update(data);
+    hmac.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use digest::Sha1;
+    use encoding::hex;
+    use super::{Hmac, HmacSha256, hmac_sha256};
+
+    #[test]
+    fn vectors() {
+        // RFC 4231.
+        assert_eq!(hex::encode(&hmac_sha256(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+        assert_eq!(hex::encode(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(hex::encode(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+        assert_eq!(hex::encode(&hmac_sha256(b"", b"")),
+            "b613679a0814d9ec772f95d778c35fc5ff1697c493715653c6c712144292c5ad");
+        // RFC 2202.
+        let mut hmac = Hmac::<Sha1>::new(b"Jefe");
+        hmac.update(b"what do ya want ");
+        hmac.update(b"for nothing?");
+        assert_eq!(hex::encode(&hmac.finish()), "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+    }
+
+    #[test]
+    fn verify() {
+        let tag = hmac_sha256(b"secret", b"payload");
+        let mut hmac = HmacSha256::new(b"secret");
+        hmac.update(b"payload");
+        assert!(hmac.verify(&tag));
+        assert!(!hmac.verify(&tag[..31]));
+        let mut forged = tag;
+        forged[31] ^= 1;
+        assert!(!hmac.verify(&forged));
+        assert!(!HmacSha256::new(b"other").verify(&tag));
+    }
+}
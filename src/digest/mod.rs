@@ -0,0 +1,150 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Cryptographic hash functions.
+//!
+//! Like the checksums, a digest can be computed over a whole slice with a function (`sha1()`,
+//! `sha256()`) or incrementally by feeding chunks to a hasher with `update()`. SHA-1 is not
+//! collision resistant anymore: only use it where a protocol requires it, like the WebSocket
+//! handshake.
+
+pub mod hmac;
+mod sha1;
+mod sha256;
+
+pub use encoding::hex::constant_time_eq;
+pub use self::hmac::{Hmac, HmacSha256, hmac_sha256};
+pub use self::sha1::{Sha1, sha1};
+pub use self::sha256::{Sha256, sha256};
+
+const BLOCK_SIZE: usize = 64;
+
+/// A hash function usable generically.
+pub trait Digest: Clone {
+    /// Size of the blocks processed by the compression function, in bytes.
+    const BLOCK_SIZE: usize;
+    type Output: AsRef<[u8]>;
+
+    fn new() -> Self;
+
+    /// Returns the digest of the data fed so far.
+    fn finish(&self) -> Self::Output;
+
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of `data`.
+    fn digest(data: &[u8]) -> Self::Output {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+}
+
+/// Splits the input in 64-byte blocks and pads the last one with its length in bits, as done by
+/// SHA-1 and SHA-2.
+#[derive(Clone, Copy)]
+struct Blocks {
+    buffer: [u8; BLOCK_SIZE],
+    len: usize,
+    total_len: u64,
+}
+
+impl Blocks {
+    fn new() -> Self {
+        Self {
+            buffer: [0; BLOCK_SIZE],
+            len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Calls `compress` with the padded last blocks.
+    fn finish<F: FnMut(&[u8; BLOCK_SIZE])>(mut self, mut compress: F) {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.buffer[self.len] = 0x80;
+        for byte in &mut self.buffer[self.len + 1..] {
+            *byte = 0;
+        }
+        if self.len + 1 > BLOCK_SIZE - 8 {
+            compress(&self.buffer);
+            self.buffer = [0; BLOCK_SIZE];
+        }
+        self.buffer[BLOCK_SIZE - 8..].copy_from_slice(&bit_len.to_be_bytes());
+        compress(&self.buffer);
+    }
+
+    /// Calls `compress` with each complete block.
+    fn update<F: FnMut(&[u8; BLOCK_SIZE])>(&mut self, mut data: &[u8], mut compress: F) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        if self.len > 0 {
+            let count = data.len().min(BLOCK_SIZE - self.len);
+            self.buffer[self.len..self.len + count].copy_from_slice(&data[..count]);
+            self.len += count;
+            data = &data[count..];
+            if self.len < BLOCK_SIZE {
+                return;
+            }
+            compress(&self.buffer);
+            self.len = 0;
+        }
+        let mut chunks = data.chunks_exact(BLOCK_SIZE);
+        for chunk in &mut chunks {
+            let mut block = [0; BLOCK_SIZE];
+            block.copy_from_slice(chunk);
+            compress(&block);
+        }
+        let remainder = chunks.remainder();
+        self.buffer[..remainder.len()].copy_from_slice(remainder);
+        self.len = remainder.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{Digest, Sha1, Sha256, sha1, sha256};
+
+    #[test]
+    fn incremental() {
+        let data: Vec<u8> = (0..1000).map(|index| (index % 251) as u8).collect();
+        // Every split around the block boundaries.
+        for split in 0..130 {
+            let mut hasher = Sha256::new();
+            hasher.update(&data[..split]);
+            hasher.write_all(b"").expect("write");
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), sha256(&data));
+
+            let mut hasher = Sha1::new();
+            hasher.update(&data[..split]);
+            // The padding of the last block depends on the length.
+            assert_eq!(hasher.finish(), sha1(&data[..split]));
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), sha1(&data));
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(b"abc");
+        hasher.reset();
+        assert_eq!(hasher.finish(), sha256(b""));
+        assert_eq!(<Sha1 as Digest>::digest(b"abc"), sha1(b"abc"));
+    }
+}
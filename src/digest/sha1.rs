@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use super::{BLOCK_SIZE, Blocks, Digest};
+
+const INITIAL_STATE: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+
+/// Incremental SHA-1 computation.
+#[derive(Clone, Copy)]
+pub struct Sha1 {
+    blocks: Blocks,
+    state: [u32; 5],
+}
+
+impl Sha1 {
+    /// Creates a new hasher.
+    pub fn new() -> Self {
+        Self {
+            blocks: Blocks::new(),
+            state: INITIAL_STATE,
+        }
+    }
+
+    /// Returns the digest of the data fed so far.
+    pub fn finish(&self) -> [u8; 20] {
+        let mut state = self.state;
+        self.blocks.finish(|block| compress(&mut state, block));
+        let mut digest = [0; 20];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(&state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Resets the hasher to its initial state.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feeds data to the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |block| compress(state, block));
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Digest for Sha1 {
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+    type Output = [u8; 20];
+
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn finish(&self) -> Self::Output {
+        self.finish()
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.update(data)
+    }
+}
+
+impl Write for Sha1 {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.update(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn compress(state: &mut [u32; 5], block: &[u8; BLOCK_SIZE]) {
+    let mut words = [0u32; 80];
+    for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for index in 16..80 {
+        words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (index, &word) in words.iter().enumerate() {
+        let (f, k) =
+            match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (word, value) in state.iter_mut().zip(&[a, b, c, d, e]) {
+        *word = word.wrapping_add(*value);
+    }
+}
+
+/// Returns the SHA-1 digest of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding::hex;
+    use super::{Sha1, sha1};
+
+    /// The 896-bit message of FIPS 180, two blocks long.
+    const TWO_BLOCKS: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+        hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+    const TWO_BLOCKS_DIGEST: &str = "a49b2446a02c645bf419f995b67091253a04a259";
+
+    #[test]
+    fn vectors() {
+        // FIPS 180 examples.
+        assert_eq!(hex::encode(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex::encode(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex::encode(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex::encode(&sha1(TWO_BLOCKS)), TWO_BLOCKS_DIGEST);
+        let million = "34aa973cd4c4daa4f61eeb2bdbad27316534016f";
+        assert_eq!(hex::encode(&sha1(&vec![b'a'; 1_000_000])), million);
+        let mut hasher = Sha1::new();
+        for _ in 0..1000 {
+            hasher.update(&[b'a'; 1000]);
+        }
+        assert_eq!(hex::encode(&hasher.finish()), million);
+        // WebSocket handshake example from RFC 6455.
+        let key = sha1(b"dGhlIHNhbXBsZSBub25jZQ==258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+        assert_eq!(hex::encode(&key), "b37a4f2cc0624f1690f64606cf385945b2bec4ea");
+    }
+
+    #[test]
+    fn split_updates() {
+        // Around the 56 bytes left for the data in a block padded with the length.
+        for &split in &[55, 56, 64] {
+            let mut hasher = Sha1::new();
+            hasher.update(&TWO_BLOCKS[..split]);
+            hasher.update(&TWO_BLOCKS[split..]);
+            assert_eq!(hex::encode(&hasher.finish()), TWO_BLOCKS_DIGEST, "{}", split);
+
+            let mut hasher = Sha1::new();
+            for chunk in TWO_BLOCKS.chunks(split) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hex::encode(&hasher.finish()), TWO_BLOCKS_DIGEST, "{}", split);
+        }
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let mut hasher = Sha1::new();
+        hasher.update(&message[..55]);
+        hasher.update(&message[55..]);
+        hasher.update(b"");
+        assert_eq!(hex::encode(&hasher.finish()), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+}
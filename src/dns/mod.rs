@@ -0,0 +1,611 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! DNS messages in wire format (RFC 1035) and a stub resolver running on the event loop.
+//!
+//! The codec supports the A, AAAA, CNAME, SRV and TXT record types; the data of other types is kept
+//! as raw bytes. Names are decompressed when decoding but never compressed when encoding.
+
+mod resolver;
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use parse::{self, Cursor};
+
+pub use self::resolver::{Config, Resolver};
+
+/// The Internet class.
+pub const CLASS_IN: u16 = 1;
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+/// Maximum size of a message sent over UDP without EDNS.
+pub const MAX_UDP_SIZE: usize = 512;
+
+/// An encoding or decoding error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The message ends in the middle of a field.
+    UnexpectedEnd,
+    /// A name contains an empty label or a label longer than 63 bytes.
+    InvalidLabel,
+    /// A name is longer than 255 bytes.
+    NameTooLong,
+    /// A compression pointer does not point to a previous name.
+    InvalidPointer,
+    /// The data of a record does not match its declared length or type.
+    InvalidRecord,
+    /// A section has more than 65535 entries.
+    TooManyRecords,
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                Error::UnexpectedEnd => "unexpected end of message",
+                Error::InvalidLabel => "invalid label in name",
+                Error::NameTooLong => "name too long",
+                Error::InvalidPointer => "invalid compression pointer",
+                Error::InvalidRecord => "invalid record data",
+                Error::TooManyRecords => "too many records in section",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// The type of a record or of a query.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Srv,
+    Txt,
+    Other(u16),
+}
+
+impl RecordType {
+    /// Returns the type with the specified code.
+    pub fn from_u16(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            5 => RecordType::Cname,
+            16 => RecordType::Txt,
+            28 => RecordType::Aaaa,
+            33 => RecordType::Srv,
+            code => RecordType::Other(code),
+        }
+    }
+
+    /// Returns the code of the type.
+    pub fn to_u16(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Cname => 5,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+            RecordType::Other(code) => code,
+        }
+    }
+}
+
+/// The response code of a message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    /// The name does not exist (NXDOMAIN).
+    NameError,
+    NotImplemented,
+    Refused,
+    Other(u8),
+}
+
+impl ResponseCode {
+    fn from_u8(code: u8) -> Self {
+        match code {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormatError,
+            2 => ResponseCode::ServerFailure,
+            3 => ResponseCode::NameError,
+            4 => ResponseCode::NotImplemented,
+            5 => ResponseCode::Refused,
+            code => ResponseCode::Other(code),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormatError => 1,
+            ResponseCode::ServerFailure => 2,
+            ResponseCode::NameError => 3,
+            ResponseCode::NotImplemented => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::Other(code) => code & 0x0f,
+        }
+    }
+}
+
+/// The header of a message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub id: u16,
+    pub is_response: bool,
+    pub opcode: u8,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub response_code: ResponseCode,
+}
+
+impl Header {
+    fn flags(&self) -> u16 {
+        (self.is_response as u16) << 15 |
+            u16::from(self.opcode & 0x0f) << 11 |
+            (self.authoritative as u16) << 10 |
+            (self.truncated as u16) << 9 |
+            (self.recursion_desired as u16) << 8 |
+            (self.recursion_available as u16) << 7 |
+            u16::from(self.response_code.to_u8())
+    }
+
+    fn from_flags(id: u16, flags: u16) -> Self {
+        Self {
+            id,
+            is_response: flags & 0x8000 != 0,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            authoritative: flags & 0x0400 != 0,
+            truncated: flags & 0x0200 != 0,
+            recursion_desired: flags & 0x0100 != 0,
+            recursion_available: flags & 0x0080 != 0,
+            response_code: ResponseCode::from_u8((flags & 0x0f) as u8),
+        }
+    }
+}
+
+/// An entry of the question section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Question {
+    /// The queried name, without the trailing dot.
+    pub name: String,
+    pub record_type: RecordType,
+    pub class: u16,
+}
+
+/// The data of a record.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    /// The character strings of the record.
+    Txt(Vec<Vec<u8>>),
+    /// The raw data of a record type without dedicated support.
+    Other(Vec<u8>),
+}
+
+/// A resource record.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub record_type: RecordType,
+    pub class: u16,
+    /// Time to live in seconds.
+    pub ttl: u32,
+    pub data: RData,
+}
+
+/// A DNS message, either a query or a response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+impl Message {
+    /// Creates a recursive query for a name.
+    pub fn query(id: u16, name: &str, record_type: RecordType) -> Self {
+        Self {
+            header: Header {
+                id,
+                is_response: false,
+                opcode: 0,
+                authoritative: false,
+                truncated: false,
+                recursion_desired: true,
+                recursion_available: false,
+                response_code: ResponseCode::NoError,
+            },
+            questions: vec![Question {
+                name: name.trim_end_matches('.').to_string(),
+                record_type,
+                class: CLASS_IN,
+            }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    /// Decodes a message from its wire format.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder {
+            cursor: Cursor::new(bytes),
+        };
+        let id = decoder.u16()?;
+        let flags = decoder.u16()?;
+        let question_count = decoder.u16()?;
+        let answer_count = decoder.u16()?;
+        let authority_count = decoder.u16()?;
+        let additional_count = decoder.u16()?;
+        let mut questions = vec![];
+        for _ in 0..question_count {
+            questions.push(Question {
+                name: decoder.name()?,
+                record_type: RecordType::from_u16(decoder.u16()?),
+                class: decoder.u16()?,
+            });
+        }
+        Ok(Self {
+            header: Header::from_flags(id, flags),
+            questions,
+            answers: decoder.records(answer_count)?,
+            authorities: decoder.records(authority_count)?,
+            additionals: decoder.records(additional_count)?,
+        })
+    }
+
+    /// Encodes the message to its wire format.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(MAX_UDP_SIZE);
+        put_u16(&mut bytes, self.header.id);
+        put_u16(&mut bytes, self.header.flags());
+        for &count in &[self.questions.len(), self.answers.len(), self.authorities.len(), self.additionals.len()] {
+            if count > usize::from(u16::MAX) {
+                return Err(Error::TooManyRecords);
+            }
+            put_u16(&mut bytes, count as u16);
+        }
+        for question in &self.questions {
+            put_name(&mut bytes, &question.name)?;
+            put_u16(&mut bytes, question.record_type.to_u16());
+            put_u16(&mut bytes, question.class);
+        }
+        for record in self.answers.iter().chain(&self.authorities).chain(&self.additionals) {
+            put_record(&mut bytes, record)?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl From<parse::Error> for Error {
+    fn from(_error: parse::Error) -> Self {
+        // Messages are decoded whole: the cursor only fails because of missing bytes.
+        Error::UnexpectedEnd
+    }
+}
+
+struct Decoder<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        Ok(self.cursor.take(len)?)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.cursor.u8()?)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(self.cursor.u16_be()?)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(self.cursor.u32_be()?)
+    }
+
+    /// Decodes a possibly compressed name.
+    fn name(&mut self) -> Result<String, Error> {
+        let bytes = self.cursor.bytes();
+        let mut name = String::new();
+        let mut position = self.cursor.position();
+        // Where to continue after the name, set at the first pointer.
+        let mut end = None;
+        // Pointers must go backward, which prevents loops.
+        let mut limit = position;
+        let mut len = 0;
+        loop {
+            let label_len = *bytes.get(position).ok_or(Error::UnexpectedEnd)? as usize;
+            match label_len & 0xc0 {
+                0x00 => {
+                    position += 1;
+                    if label_len == 0 {
+                        break;
+                    }
+                    let label = bytes.get(position..position + label_len).ok_or(Error::UnexpectedEnd)?;
+                    len += label_len + 1;
+                    if len > MAX_NAME_LEN {
+                        return Err(Error::NameTooLong);
+                    }
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&String::from_utf8_lossy(label));
+                    position += label_len;
+                },
+                0xc0 => {
+                    let low = *bytes.get(position + 1).ok_or(Error::UnexpectedEnd)? as usize;
+                    let target = (label_len & 0x3f) << 8 | low;
+                    if target >= limit {
+                        return Err(Error::InvalidPointer);
+                    }
+                    if end.is_none() {
+                        end = Some(position + 2);
+                    }
+                    limit = target;
+                    position = target;
+                },
+                _ => return Err(Error::InvalidLabel),
+            }
+        }
+        self.cursor.set_position(end.unwrap_or(position));
+        Ok(name)
+    }
+
+    fn records(&mut self, count: u16) -> Result<Vec<Record>, Error> {
+        let mut records = vec![];
+        for _ in 0..count {
+            let name = self.name()?;
+            let record_type = RecordType::from_u16(self.u16()?);
+            let class = self.u16()?;
+            let ttl = self.u32()?;
+            let len = self.u16()? as usize;
+            let end = self.cursor.position() + len;
+            if end > self.cursor.bytes().len() {
+                return Err(Error::UnexpectedEnd);
+            }
+            let data =
+                match record_type {
+                    RecordType::A => {
+                        let bytes = self.take(len)?;
+                        if len != 4 {
+                            return Err(Error::InvalidRecord);
+                        }
+                        RData::A(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+                    },
+                    RecordType::Aaaa => {
+                        let bytes = self.take(len)?;
+                        if len != 16 {
+                            return Err(Error::InvalidRecord);
+                        }
+                        let mut address = [0; 16];
+                        address.copy_from_slice(bytes);
+                        RData::Aaaa(Ipv6Addr::from(address))
+                    },
+                    RecordType::Cname => RData::Cname(self.name()?),
+                    RecordType::Srv => RData::Srv {
+                        priority: self.u16()?,
+                        weight: self.u16()?,
+                        port: self.u16()?,
+                        target: self.name()?,
+                    },
+                    RecordType::Txt => {
+                        let mut strings = vec![];
+                        while self.cursor.position() < end {
+                            let string_len = self.u8()? as usize;
+                            strings.push(self.take(string_len)?.to_vec());
+                        }
+                        RData::Txt(strings)
+                    },
+                    RecordType::Other(_) => RData::Other(self.take(len)?.to_vec()),
+                };
+            if self.cursor.position() != end {
+                return Err(Error::InvalidRecord);
+            }
+            records.push(Record {
+                name,
+                record_type,
+                class,
+                ttl,
+                data,
+            });
+        }
+        Ok(records)
+    }
+}
+
+fn put_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.push((value >> 8) as u8);
+    bytes.push(value as u8);
+}
+
+fn put_name(bytes: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    let name = name.trim_end_matches('.');
+    let mut len = 1;
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err(Error::InvalidLabel);
+            }
+            len += label.len() + 1;
+            if len > MAX_NAME_LEN {
+                return Err(Error::NameTooLong);
+            }
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+    }
+    bytes.push(0);
+    Ok(())
+}
+
+fn put_record(bytes: &mut Vec<u8>, record: &Record) -> Result<(), Error> {
+    put_name(bytes, &record.name)?;
+    put_u16(bytes, record.record_type.to_u16());
+    put_u16(bytes, record.class);
+    put_u16(bytes, (record.ttl >> 16) as u16);
+    put_u16(bytes, record.ttl as u16);
+    let length_position = bytes.len();
+    put_u16(bytes, 0);
+    match record.data {
+        RData::A(address) => bytes.extend_from_slice(&address.octets()),
+        RData::Aaaa(address) => bytes.extend_from_slice(&address.octets()),
+        RData::Cname(ref name) => put_name(bytes, name)?,
+        RData::Srv { priority, weight, port, ref target } => {
+            put_u16(bytes, priority);
+            put_u16(bytes, weight);
+            put_u16(bytes, port);
+            put_name(bytes, target)?;
+        },
+        RData::Txt(ref strings) => {
+            for string in strings {
+                if string.len() > usize::from(u8::MAX) {
+                    return Err(Error::InvalidRecord);
+                }
+                bytes.push(string.len() as u8);
+                bytes.extend_from_slice(string);
+            }
+        },
+        RData::Other(ref data) => bytes.extend_from_slice(data),
+    }
+    let len = bytes.len() - length_position - 2;
+    if len > usize::from(u16::MAX) {
+        return Err(Error::InvalidRecord);
+    }
+    bytes[length_position] = (len >> 8) as u8;
+    bytes[length_position + 1] = len as u8;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::{CLASS_IN, Error, Message, RData, Record, RecordType, ResponseCode};
+
+    #[test]
+    fn query() {
+        let query = Message::query(0x1234, "example.com.", RecordType::A);
+        let bytes = query.encode().expect("encode");
+        assert_eq!(bytes, [
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            0x00, 0x01, 0x00, 0x01,
+        ]);
+        assert_eq!(Message::decode(&bytes), Ok(query));
+    }
+
+    #[test]
+    fn compressed_response() {
+        let mut bytes = vec![
+            0xab, 0xcd, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            0x00, 0x01, 0x00, 0x01,
+            // www.example.com CNAME example.com, both compressed.
+            0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0, 0x10,
+            // example.com A 93.184.216.34
+            0xc0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04, 93, 184, 216, 34,
+        ];
+        let message = Message::decode(&bytes).expect("decode");
+        assert!(message.header.is_response);
+        assert!(message.header.recursion_available);
+        assert_eq!(message.header.response_code, ResponseCode::NoError);
+        assert_eq!(message.questions[0].name, "www.example.com");
+        assert_eq!(message.answers, [
+            Record {
+                name: "www.example.com".to_string(),
+                record_type: RecordType::Cname,
+                class: CLASS_IN,
+                ttl: 3600,
+                data: RData::Cname("example.com".to_string()),
+            },
+            Record {
+                name: "example.com".to_string(),
+                record_type: RecordType::A,
+                class: CLASS_IN,
+                ttl: 60,
+                data: RData::A(Ipv4Addr::new(93, 184, 216, 34)),
+            },
+        ]);
+
+        // The data of the CNAME record points to itself.
+        bytes[45] = 0xc0;
+        bytes[46] = 0x2d;
+        assert_eq!(Message::decode(&bytes), Err(Error::InvalidPointer));
+        assert_eq!(Message::decode(&bytes[..40]), Err(Error::UnexpectedEnd));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut message = Message::query(7, "_http._tcp.example.com", RecordType::Srv);
+        message.header.is_response = true;
+        message.header.truncated = true;
+        message.header.response_code = ResponseCode::NameError;
+        let record = |record_type, data| Record {
+            name: "example.com".to_string(),
+            record_type,
+            class: CLASS_IN,
+            ttl: 300,
+            data,
+        };
+        message.answers.push(record(RecordType::Srv, RData::Srv {
+            priority: 10,
+            weight: 5,
+            port: 8080,
+            target: "server.example.com".to_string(),
+        }));
+        message.answers.push(record(RecordType::Txt, RData::Txt(vec![b"v=1".to_vec(), vec![]])));
+        message.authorities.push(record(RecordType::Aaaa, RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))));
+        message.additionals.push(record(RecordType::Other(99), RData::Other(vec![1, 2, 3])));
+        let bytes = message.encode().expect("encode");
+        assert_eq!(Message::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert_eq!(Message::query(1, "a..b", RecordType::A).encode(), Err(Error::InvalidLabel));
+        assert_eq!(Message::query(1, &"a".repeat(64), RecordType::A).encode(), Err(Error::InvalidLabel));
+        let long_name = vec!["a".repeat(63); 4].join(".");
+        assert_eq!(Message::query(1, &long_name, RecordType::A).encode(), Err(Error::NameTooLong));
+        assert!(Message::query(1, "", RecordType::A).encode().is_ok());
+    }
+}
@@ -0,0 +1,682 @@
+//! Stub resolver sending queries to the configured nameservers from the event loop.
+//!
+//! Queries are sent over UDP and retried on the next nameserver after a timeout. A truncated
+//! response is retried over TCP.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+use std::time::Duration;
+
+use aio::async::{Action, Mode};
+use aio::handler::{Loop, Stream};
+use aio::net::{TcpConnection, TcpConnectionNotify, tcp};
+use aio::timer::TimerFd;
+use bytes::Bytes;
+use cache::LruCache;
+use rand::Rng;
+use super::{Message, RData, RecordType, ResponseCode};
+
+const DNS_PORT: u16 = 53;
+/// Large enough for the responses of servers sending more than 512 bytes over UDP.
+const RECEIVE_BUFFER_SIZE: usize = 4096;
+
+type Completion = Box<dyn FnOnce(io::Result<Message>)>;
+
+/// Resolver configuration, usually read from `/etc/resolv.conf` and `/etc/hosts`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Number of times each nameserver is tried.
+    pub attempts: u32,
+    /// Number of names whose addresses are cached by `lookup_ip`, for the time to live of their
+    /// records. 0 disables the cache.
+    pub cache_size: usize,
+    /// Static name to address mappings, checked before sending queries.
+    pub hosts: Vec<(String, IpAddr)>,
+    /// Nameservers tried in order. Only the ones with the address family of the first one are used.
+    pub nameservers: Vec<SocketAddr>,
+    /// Time to wait for a response before trying the next nameserver.
+    pub timeout: Duration,
+}
+
+impl Config {
+    /// Creates a configuration querying a nameserver on localhost.
+    pub fn new() -> Self {
+        Self {
+            attempts: 2,
+            cache_size: 256,
+            hosts: vec![],
+            nameservers: vec![],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Reads the configuration of the system, using the defaults for the missing files.
+    pub fn system() -> Self {
+        let mut config = Self::new();
+        if let Ok(content) = fs::read_to_string("/etc/resolv.conf") {
+            config.parse_resolv_conf(&content);
+        }
+        if let Ok(content) = fs::read_to_string("/etc/hosts") {
+            config.parse_hosts(&content);
+        }
+        config
+    }
+
+    /// Reads the `nameserver` lines and the `timeout` and `attempts` options of a `resolv.conf`.
+    pub fn parse_resolv_conf(&mut self, content: &str) {
+        for line in content.lines() {
+            let mut words = line.split(|character: char| character.is_whitespace()).filter(|word| !word.is_empty());
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(Ok(address)) = words.next().map(str::parse::<IpAddr>) {
+                        self.nameservers.push(SocketAddr::new(address, DNS_PORT));
+                    }
+                },
+                Some("options") => {
+                    for option in words {
+                        let mut parts = option.splitn(2, ':');
+                        match (parts.next(), parts.next().and_then(|value| value.parse::<u32>().ok())) {
+                            (Some("timeout"), Some(seconds)) => self.timeout = Duration::from_secs(u64::from(seconds.max(1))),
+                            (Some("attempts"), Some(attempts)) => self.attempts = attempts.max(1),
+                            _ => (),
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Reads the entries of a `hosts` file.
+    pub fn parse_hosts(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            if let Some(Ok(address)) = words.next().map(str::parse::<IpAddr>) {
+                for name in words {
+                    self.hosts.push((name.to_lowercase(), address));
+                }
+            }
+        }
+    }
+
+    fn host_addresses(&self, name: &str) -> Vec<IpAddr> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        self.hosts.iter()
+            .filter(|&(host, _)| *host == name)
+            .map(|&(_, address)| address)
+            .collect()
+    }
+
+    fn servers(&self) -> Vec<SocketAddr> {
+        let default = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), DNS_PORT);
+        let first = self.nameservers.first().cloned().unwrap_or(default);
+        let mut servers: Vec<_> = self.nameservers.iter()
+            .cloned()
+            .filter(|server| server.is_ipv4() == first.is_ipv4())
+            .collect();
+        if servers.is_empty() {
+            servers.push(default);
+        }
+        servers
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A DNS stub resolver.
+#[derive(Clone)]
+pub struct Resolver {
+    cache: Rc<RefCell<LruCache<String, Vec<IpAddr>>>>,
+    config: Rc<Config>,
+    event_loop: Loop,
+    rng: Rc<RefCell<Rng>>,
+}
+
+impl Resolver {
+    /// Creates a resolver with the configuration of the system.
+    pub fn new(event_loop: &Loop) -> Self {
+        Self::with_config(event_loop, Config::system())
+    }
+
+    /// Creates a resolver with the specified configuration.
+    pub fn with_config(event_loop: &Loop, config: Config) -> Self {
+        Self {
+            cache: Rc::new(RefCell::new(LruCache::new(config.cache_size))),
+            config: Rc::new(config),
+            event_loop: event_loop.clone(),
+            rng: Rc::new(RefCell::new(Rng::new())),
+        }
+    }
+
+    /// Resolves a name to its IPv4 addresses, or to its IPv6 addresses if it has none, and sends
+    /// the result to the stream, converted by the callback. IP literals and the names of the hosts
+    /// file are resolved without a query, as are the names in the cache.
+    pub fn lookup_ip<CALLBACK, MSG>(&self, name: &str, stream: &Stream<MSG>, callback: CALLBACK)
+    where CALLBACK: FnOnce(io::Result<Vec<IpAddr>>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        if let Ok(address) = name.parse::<IpAddr>() {
+            stream.send(callback(Ok(vec![address])));
+            return;
+        }
+        let addresses = self.config.host_addresses(name);
+        if !addresses.is_empty() {
+            stream.send(callback(Ok(addresses)));
+            return;
+        }
+
+        let key = name.trim_end_matches('.').to_lowercase();
+        if let Some(addresses) = self.cache.borrow_mut().get(&key) {
+            stream.send(callback(Ok(addresses.clone())));
+            return;
+        }
+
+        let stream = stream.clone();
+        let resolver = self.clone();
+        let name = name.to_string();
+        self.start_query(&name.clone(), RecordType::A, Box::new(move |result| {
+            match addresses_of(result) {
+                Ok((ref addresses, _)) if addresses.is_empty() => {
+                    let aaaa_resolver = resolver.clone();
+                    resolver.start_query(&name, RecordType::Aaaa, Box::new(move |result| {
+                        stream.send(callback(aaaa_resolver.cache_addresses(key, addresses_of(result))));
+                    }));
+                },
+                result => stream.send(callback(resolver.cache_addresses(key, result))),
+            }
+        }));
+    }
+
+    /// Sends a query and sends the response to the stream, converted by the callback. The response
+    /// is delivered whatever its response code.
+    pub fn query<CALLBACK, MSG>(&self, name: &str, record_type: RecordType, stream: &Stream<MSG>, callback: CALLBACK)
+    where CALLBACK: FnOnce(io::Result<Message>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        self.start_query(name, record_type, Box::new(move |result| stream.send(callback(result))));
+    }
+
+    fn cache_addresses(&self, key: String, result: io::Result<(Vec<IpAddr>, u32)>) -> io::Result<Vec<IpAddr>> {
+        let (addresses, ttl) = result?;
+        if self.config.cache_size > 0 && !addresses.is_empty() && ttl > 0 {
+            self.cache.borrow_mut().insert_with_ttl(key, addresses.clone(), Duration::from_secs(u64::from(ttl)));
+        }
+        Ok(addresses)
+    }
+
+    /// Starts a query whose result, including the errors when setting it up, is given to the
+    /// completion.
+    fn start_query(&self, name: &str, record_type: RecordType, completion: Completion) {
+        let id = self.rng.borrow_mut().gen_int() as u16;
+        let message = Message::query(id, name, record_type);
+        let request =
+            match message.encode() {
+                Ok(request) => request,
+                Err(error) => return completion(Err(io::Error::new(ErrorKind::InvalidInput, error))),
+            };
+        let servers = self.config.servers();
+        let bind_address =
+            if servers[0].is_ipv4() {
+                "0.0.0.0:0"
+            }
+            else {
+                "[::]:0"
+            };
+        let result = UdpSocket::bind(bind_address)
+            .and_then(|socket| socket.set_nonblocking(true).map(|()| socket))
+            .and_then(|socket| TimerFd::new().map(|timer| (socket, timer)));
+        let (socket, timer) =
+            match result {
+                Ok(resources) => resources,
+                Err(error) => return completion(Err(error)),
+            };
+        let socket_fd = socket.as_raw_fd();
+        let timer_fd = timer.as_raw_fd();
+        let query = Rc::new(RefCell::new(Query {
+            attempt: 0,
+            completion: Some(completion),
+            event_loop: self.event_loop.clone(),
+            max_attempts: self.config.attempts.max(1) as usize * servers.len(),
+            question: message,
+            request,
+            servers,
+            socket: Some(socket),
+            tcp_connection: None,
+            timeout: self.config.timeout,
+            timer: Some(timer),
+        }));
+
+        let event_loop = self.event_loop.event_loop();
+        let udp_query = query.clone();
+        let timer_query = query.clone();
+        let result = event_loop.add_raw_fd(socket_fd, Mode::Read, move |_event| {
+                Query::receive_udp(&udp_query);
+                action(&udp_query)
+            })
+            .and_then(|()| event_loop.add_raw_fd(timer_fd, Mode::Read, move |_event| {
+                Query::timeout(&timer_query);
+                action(&timer_query)
+            }))
+            .and_then(|()| query.borrow_mut().send_udp());
+        if let Err(error) = result {
+            Query::complete(&query, Err(error));
+        }
+    }
+}
+
+/// Extracts the addresses from a response, with their smallest time to live.
+fn addresses_of(result: io::Result<Message>) -> io::Result<(Vec<IpAddr>, u32)> {
+    let message = result?;
+    match message.header.response_code {
+        ResponseCode::NoError => (),
+        ResponseCode::NameError => return Err(io::Error::new(ErrorKind::NotFound, "name not found")),
+        code => return Err(io::Error::other(format!("query failed: {:?}", code))),
+    }
+    let mut ttl = u32::MAX;
+    let addresses = message.answers.iter()
+        .filter_map(|record| {
+            let address =
+                match record.data {
+                    RData::A(address) => IpAddr::V4(address),
+                    RData::Aaaa(address) => IpAddr::V6(address),
+                    _ => return None,
+                };
+            ttl = ttl.min(record.ttl);
+            Some(address)
+        })
+        .collect();
+    Ok((addresses, ttl))
+}
+
+/// Stops the callbacks of a completed query.
+fn action(query: &Rc<RefCell<Query>>) -> Action {
+    if query.borrow().completion.is_none() {
+        Action::Stop
+    }
+    else {
+        Action::Continue
+    }
+}
+
+struct Query {
+    attempt: usize,
+    completion: Option<Completion>,
+    event_loop: Loop,
+    max_attempts: usize,
+    question: Message,
+    request: Vec<u8>,
+    servers: Vec<SocketAddr>,
+    socket: Option<UdpSocket>,
+    tcp_connection: Option<TcpConnection>,
+    timeout: Duration,
+    timer: Option<TimerFd>,
+}
+
+impl Query {
+    fn complete(query: &Rc<RefCell<Query>>, result: io::Result<Message>) {
+        let completion = {
+            let mut query = query.borrow_mut();
+            if let Some(socket) = query.socket.take() {
+                let _ = query.event_loop.remove_fd(&socket);
+            }
+            if let Some(timer) = query.timer.take() {
+                let _ = query.event_loop.remove_fd(&timer);
+            }
+            if let Some(connection) = query.tcp_connection.take() {
+                connection.dispose();
+            }
+            query.completion.take()
+        };
+        if let Some(completion) = completion {
+            completion(result);
+        }
+    }
+
+    /// Returns true if the message is the response to this query.
+    fn is_response(&self, message: &Message) -> bool {
+        message.header.is_response && message.header.id == self.question.header.id &&
+            message.questions.len() == 1 && self.question.questions.len() == 1 &&
+            message.questions[0].name.eq_ignore_ascii_case(&self.question.questions[0].name) &&
+            message.questions[0].record_type == self.question.questions[0].record_type
+    }
+
+    fn receive_udp(query: &Rc<RefCell<Query>>) {
+        let mut buffer = [0; RECEIVE_BUFFER_SIZE];
+        loop {
+            let result =
+                match query.borrow().socket {
+                    Some(ref socket) => socket.recv_from(&mut buffer),
+                    None => return,
+                };
+            let (size, source) =
+                match result {
+                    Ok(received) => received,
+                    Err(ref error) if error.kind() == ErrorKind::WouldBlock => return,
+                    Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                    // ICMP errors from previous attempts are reported here: wait for the timeout.
+                    Err(_) => return,
+                };
+            if !query.borrow().servers.contains(&source) {
+                continue;
+            }
+            let message =
+                match Message::decode(&buffer[..size]) {
+                    Ok(message) => message,
+                    Err(_) => continue,
+                };
+            if !query.borrow().is_response(&message) {
+                continue;
+            }
+            if message.header.truncated {
+                Query::retry_tcp(query, source);
+            }
+            else {
+                Query::complete(query, Ok(message));
+            }
+            return;
+        }
+    }
+
+    fn retry_tcp(query: &Rc<RefCell<Query>>, server: SocketAddr) {
+        let result = {
+            let mut query_ref = query.borrow_mut();
+            if let Some(socket) = query_ref.socket.take() {
+                let _ = query_ref.event_loop.remove_fd(&socket);
+            }
+            // The TCP query gets a single full timeout.
+            query_ref.attempt = query_ref.max_attempts;
+            let timeout = query_ref.timeout;
+            query_ref.timer.as_ref().map(|timer| timer.set(timeout))
+        };
+        if let Some(Err(error)) = result {
+            Query::complete(query, Err(error));
+            return;
+        }
+        let mut event_loop = query.borrow().event_loop.clone();
+        let notify = TcpQuery {
+            buffer: vec![],
+            query: query.clone(),
+        };
+        let result = tcp::connect_to_host(&server.ip().to_string(), &server.port().to_string(), &mut event_loop, notify);
+        if result.is_none() {
+            Query::complete(query, Err(io::Error::other("cannot connect to nameserver")));
+        }
+    }
+
+    fn send_udp(&mut self) -> io::Result<()> {
+        let server = self.servers[self.attempt % self.servers.len()];
+        self.attempt += 1;
+        if let Some(ref socket) = self.socket {
+            socket.send_to(&self.request, server)?;
+        }
+        if let Some(ref timer) = self.timer {
+            timer.set(self.timeout)?;
+        }
+        Ok(())
+    }
+
+    fn timeout(query: &Rc<RefCell<Query>>) {
+        let result = {
+            let mut query = query.borrow_mut();
+            if let Some(ref timer) = query.timer {
+                timer.acknowledge();
+            }
+            if query.attempt >= query.max_attempts {
+                Err(io::Error::new(ErrorKind::TimedOut, "DNS query timed out"))
+            }
+            else {
+                query.send_udp()
+            }
+        };
+        if let Err(error) = result {
+            Query::complete(query, Err(error));
+        }
+    }
+}
+
+/// Sends a query over TCP, with the two-byte length prefix.
+struct TcpQuery {
+    buffer: Vec<u8>,
+    query: Rc<RefCell<Query>>,
+}
+
+impl TcpConnectionNotify for TcpQuery {
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        let request = {
+            let query = self.query.borrow();
+            let mut request = Vec::with_capacity(query.request.len() + 2);
+            request.push((query.request.len() >> 8) as u8);
+            request.push(query.request.len() as u8);
+            request.extend_from_slice(&query.request);
+            request
+        };
+        self.query.borrow_mut().tcp_connection = Some(connection.clone());
+        if let Err(error) = connection.write(request) {
+            Query::complete(&self.query, Err(error));
+        }
+    }
+
+    fn connect_failed(&mut self) {
+        Query::complete(&self.query, Err(io::Error::new(ErrorKind::ConnectionRefused, "cannot connect to nameserver")));
+    }
+
+    fn error(&mut self, error: io::Error) {
+        Query::complete(&self.query, Err(error));
+    }
+
+    fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
+        self.buffer.extend_from_slice(&data);
+        if self.buffer.len() < 2 {
+            return;
+        }
+        let len = (self.buffer[0] as usize) << 8 | self.buffer[1] as usize;
+        if self.buffer.len() < len + 2 {
+            return;
+        }
+        let result =
+            match Message::decode(&self.buffer[2..len + 2]) {
+                Ok(ref message) if !self.query.borrow().is_response(message) =>
+                    Err(io::Error::new(ErrorKind::InvalidData, "unexpected DNS response")),
+                Ok(message) => Ok(message),
+                Err(error) => Err(io::Error::new(ErrorKind::InvalidData, error)),
+            };
+        Query::complete(&self.query, result);
+    }
+
+    fn closed(&mut self, _connection: &mut TcpConnection) {
+        Query::complete(&self.query, Err(io::Error::new(ErrorKind::UnexpectedEof, "nameserver closed the connection")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::net::{self, IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use aio::handler::{Handler, Loop, Stream};
+    use aio::net::{TcpConnection, TcpConnectionNotify};
+    use aio::net::tcp::connect_to_host_with_resolver;
+    use dns::{CLASS_IN, Message, RData, Record, RecordType, ResponseCode};
+    use super::{Config, Resolver};
+
+    struct Collector {
+        event_loop: Loop,
+        result: Rc<RefCell<Option<io::Result<Vec<IpAddr>>>>>,
+    }
+
+    impl Handler for Collector {
+        type Msg = io::Result<Vec<IpAddr>>;
+
+        fn update(&mut self, _stream: &Stream<Self::Msg>, result: Self::Msg) {
+            *self.result.borrow_mut() = Some(result);
+            self.event_loop.stop();
+        }
+    }
+
+    fn lookup(config: Config, name: &str) -> io::Result<Vec<IpAddr>> {
+        let mut event_loop = Loop::new().expect("event loop");
+        let result = Rc::new(RefCell::new(None));
+        let stream = event_loop.spawn(Collector {
+            event_loop: event_loop.clone(),
+            result: result.clone(),
+        });
+        let resolver = Resolver::with_config(&event_loop, config);
+        resolver.lookup_ip(name, &stream, |result| result);
+        event_loop.run().expect("run");
+        let result = result.borrow_mut().take().expect("result");
+        result
+    }
+
+    fn config(server: SocketAddr) -> Config {
+        let mut config = Config::new();
+        config.nameservers.push(server);
+        config.timeout = Duration::from_millis(100);
+        config.attempts = 1;
+        config
+    }
+
+    /// Answers the queries for "example.test" with 10.0.0.1 and the others with NXDOMAIN.
+    fn respond(request: &[u8], truncate: bool) -> Vec<u8> {
+        let mut message = Message::decode(request).expect("decode");
+        message.header.is_response = true;
+        message.header.truncated = truncate;
+        let question = message.questions[0].clone();
+        if question.name != "example.test" {
+            message.header.response_code = ResponseCode::NameError;
+        }
+        else if question.record_type == RecordType::A && !truncate {
+            message.answers.push(Record {
+                name: question.name,
+                record_type: RecordType::A,
+                class: CLASS_IN,
+                ttl: 60,
+                data: RData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            });
+        }
+        message.encode().expect("encode")
+    }
+
+    fn udp_server(truncate: bool) -> (SocketAddr, UdpSocket) {
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let address = socket.local_addr().expect("address");
+        let server = socket.try_clone().expect("clone");
+        thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((size, source)) = server.recv_from(&mut buffer) {
+                let _ = server.send_to(&respond(&buffer[..size], truncate), source);
+            }
+        });
+        (address, socket)
+    }
+
+    #[test]
+    fn udp() {
+        let (address, _server) = udp_server(false);
+        let addresses = lookup(config(address), "example.test").expect("lookup");
+        assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+        let error = lookup(config(address), "missing.test").expect_err("lookup");
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn cache() {
+        let (address, _server) = udp_server(false);
+        let mut event_loop = Loop::new().expect("event loop");
+        let result = Rc::new(RefCell::new(None));
+        let stream = event_loop.spawn(Collector {
+            event_loop: event_loop.clone(),
+            result: result.clone(),
+        });
+        let resolver = Resolver::with_config(&event_loop, config(address));
+        resolver.lookup_ip("example.test", &stream, |result| result);
+        event_loop.run().expect("run");
+        assert!(result.borrow().as_ref().expect("result").is_ok());
+        assert_eq!(resolver.cache.borrow_mut().get("example.test"), Some(&vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]));
+        assert!(resolver.cache.borrow().iter().all(|(name, _)| name == "example.test"));
+    }
+
+    #[test]
+    fn truncated_response_over_tcp() {
+        let (address, _server) = udp_server(true);
+        let listener = net::TcpListener::bind(address).expect("bind tcp");
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).expect("read length");
+            let mut request = vec![0; (len[0] as usize) << 8 | len[1] as usize];
+            stream.read_exact(&mut request).expect("read request");
+            let response = respond(&request, false);
+            stream.write_all(&[(response.len() >> 8) as u8, response.len() as u8]).expect("write length");
+            stream.write_all(&response).expect("write response");
+        });
+        let addresses = lookup(config(address), "example.test").expect("lookup");
+        assert_eq!(addresses, [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+    }
+
+    #[test]
+    fn timeout() {
+        // Nobody answers on this socket.
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let mut config = config(socket.local_addr().expect("address"));
+        config.attempts = 2;
+        let error = lookup(config, "example.test").expect_err("lookup");
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn without_query() {
+        let mut config = Config::new();
+        config.parse_hosts("# comment\n127.0.0.1 localhost\n10.1.2.3  db.local db # database\n");
+        config.parse_resolv_conf("nameserver 192.0.2.1\nnameserver ::1\noptions timeout:2 attempts:3 rotate\n");
+        assert_eq!(config.nameservers, ["192.0.2.1:53".parse().expect("address"), "[::1]:53".parse().expect("address")]);
+        assert_eq!(config.servers().len(), 1);
+        assert_eq!(config.timeout, Duration::from_secs(2));
+        assert_eq!(config.attempts, 3);
+        assert_eq!(lookup(config.clone(), "DB.local.").expect("lookup"), [IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))]);
+        assert_eq!(lookup(config, "::1").expect("lookup"), ["::1".parse::<IpAddr>().expect("address")]);
+    }
+
+    struct Connected {
+        event_loop: Loop,
+    }
+
+    impl TcpConnectionNotify for Connected {
+        fn connected(&mut self, _connection: &mut TcpConnection) {
+            self.event_loop.stop();
+        }
+
+        fn connect_failed(&mut self) {
+            panic!("connection failed");
+        }
+    }
+
+    #[test]
+    fn connect_with_resolver() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("address").port();
+        let mut config = Config::new();
+        config.parse_hosts("127.0.0.1 service.local\n");
+        let mut event_loop = Loop::new().expect("event loop");
+        let resolver = Resolver::with_config(&event_loop, config);
+        let notify = Connected {
+            event_loop: event_loop.clone(),
+        };
+        connect_to_host_with_resolver("service.local", &port.to_string(), &resolver, &mut event_loop, notify);
+        event_loop.run().expect("run");
+        listener.accept().expect("accept");
+    }
+}
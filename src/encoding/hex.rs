@@ -0,0 +1,112 @@
+//! Hexadecimal encoding and decoding.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+const LOWER_DIGITS: &[u8; 16] = b"0123456789abcdef";
+const UPPER_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Error returned when decoding an invalid hexadecimal string.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The character at the specified byte index is not a hexadecimal digit.
+    InvalidCharacter(usize),
+    /// The string contains an odd number of digits.
+    OddLength,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::InvalidCharacter(index) => write!(formatter, "invalid hexadecimal character at index {}", index),
+            DecodeError::OddLength => write!(formatter, "odd number of hexadecimal digits"),
+        }
+    }
+}
+
+impl Error for DecodeError {
+}
+
+/// Encodes the bytes as a lowercase hexadecimal string.
+pub fn encode(data: &[u8]) -> String {
+    encode_with(data, LOWER_DIGITS)
+}
+
+/// Encodes the bytes as an uppercase hexadecimal string.
+pub fn encode_upper(data: &[u8]) -> String {
+    encode_with(data, UPPER_DIGITS)
+}
+
+fn encode_with(data: &[u8], digits: &[u8; 16]) -> String {
+    let mut result = String::with_capacity(data.len() * 2);
+    for &byte in data {
+        result.push(digits[(byte >> 4) as usize] as char);
+        result.push(digits[(byte & 0xF) as usize] as char);
+    }
+    result
+}
+
+/// Decodes a hexadecimal string, accepting both lowercase and uppercase digits.
+pub fn decode(string: &str) -> Result<Vec<u8>, DecodeError> {
+    let bytes = string.as_bytes();
+    if bytes.len() % 2 != 0 {
+        return Err(DecodeError::OddLength);
+    }
+    let mut result = Vec::with_capacity(bytes.len() / 2);
+    for (index, pair) in bytes.chunks(2).enumerate() {
+        let high = digit_value(pair[0]).ok_or(DecodeError::InvalidCharacter(index * 2))?;
+        let low = digit_value(pair[1]).ok_or(DecodeError::InvalidCharacter(index * 2 + 1))?;
+        result.push(high << 4 | low);
+    }
+    Ok(result)
+}
+
+fn digit_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0' ..= b'9' => Some(digit - b'0'),
+        b'a' ..= b'f' => Some(digit - b'a' + 10),
+        b'A' ..= b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Compares two byte slices in a time that only depends on their length, not on their content.
+///
+/// Use this to compare secrets like digests or tokens, so that the position of the first
+/// difference cannot be inferred from timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut difference = 0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        difference |= x ^ y;
+    }
+    // Prevent the compiler from short-circuiting the loop above.
+    unsafe { ::std::ptr::read_volatile(&difference) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeError, constant_time_eq, decode, encode, encode_upper};
+
+    #[test]
+    fn encode_decode() {
+        let data = [0x00, 0x01, 0x7f, 0xab, 0xff];
+        assert_eq!(encode(&data), "00017fabff");
+        assert_eq!(encode_upper(&data), "00017FABFF");
+        assert_eq!(decode("00017fabff"), Ok(data.to_vec()));
+        assert_eq!(decode("00017FaBfF"), Ok(data.to_vec()));
+        assert_eq!(decode(""), Ok(vec![]));
+        assert_eq!(decode("abc"), Err(DecodeError::OddLength));
+        assert_eq!(decode("0g"), Err(DecodeError::InvalidCharacter(1)));
+    }
+
+    #[test]
+    fn compare() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Typed access to environment variables, for configuration following the twelve-factor style.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), mini::env::Error> {
+//! let port: u16 = mini::env::get_or("PORT", 8080)?;
+//! let database: String = mini::env::require("DATABASE_URL")?;
+//! let debug = mini::env::flag("DEBUG")?.unwrap_or(false);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::env::{self, VarError};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use getopts::Matches;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// A required variable is not set.
+    Missing(String),
+    /// The value of the variable is not valid UTF-8.
+    NotUnicode(String),
+    /// The value could not be parsed.
+    Invalid {
+        name: String,
+        value: String,
+        message: String,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Missing(ref name) => write!(formatter, "environment variable {} is not set", name),
+            Error::NotUnicode(ref name) => write!(formatter, "environment variable {} is not valid unicode", name),
+            Error::Invalid { ref name, ref value, ref message } =>
+                write!(formatter, "invalid value \"{}\" for {}: {}", value, name, message),
+        }
+    }
+}
+
+impl error::Error for Error {
+}
+
+fn var(name: &str) -> Result<Option<String>, Error> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(Error::NotUnicode(name.to_string())),
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: String) -> Result<T, Error>
+where T::Err: Display,
+{
+    value.trim().parse().map_err(|error: T::Err| Error::Invalid {
+        name: name.to_string(),
+        message: error.to_string(),
+        value,
+    })
+}
+
+/// Returns the parsed value of the variable, or `None` if it is not set.
+pub fn get<T: FromStr>(name: &str) -> Result<Option<T>, Error>
+where T::Err: Display,
+{
+    match var(name)? {
+        Some(value) => parse(name, value).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Returns the parsed value of the variable, or `default` if it is not set.
+pub fn get_or<T: FromStr>(name: &str, default: T) -> Result<T, Error>
+where T::Err: Display,
+{
+    get(name).map(|value| value.unwrap_or(default))
+}
+
+/// Returns the parsed value of the variable, or `Error::Missing` if it is not set.
+pub fn require<T: FromStr>(name: &str) -> Result<T, Error>
+where T::Err: Display,
+{
+    get(name)?.ok_or_else(|| Error::Missing(name.to_string()))
+}
+
+/// Returns the boolean value of the variable, which can be `1`, `true`, `yes` or `on`, or `0`,
+/// `false`, `no`, `off` or empty, ignoring case.
+pub fn flag(name: &str) -> Result<Option<bool>, Error> {
+    match var(name)? {
+        Some(value) => {
+            match value.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(Some(true)),
+                "" | "0" | "false" | "no" | "off" => Ok(Some(false)),
+                _ => Err(Error::Invalid {
+                    name: name.to_string(),
+                    message: "expected a boolean".to_string(),
+                    value,
+                }),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the comma-separated values of the variable, or an empty vector if it is not set.
+pub fn list<T: FromStr>(name: &str) -> Result<Vec<T>, Error>
+where T::Err: Display,
+{
+    match var(name)? {
+        Some(value) => {
+            value.split(',')
+                .filter(|item| !item.trim().is_empty())
+                .map(|item| parse(name, item.to_string()))
+                .collect()
+        },
+        None => Ok(vec![]),
+    }
+}
+
+/// Returns the variables whose name starts with `prefix`, keyed by the rest of their name in
+/// lowercase: with the prefix `APP_`, `APP_LOG_LEVEL` gives `log_level`. The variables that are not
+/// valid unicode are skipped.
+pub fn with_prefix(prefix: &str) -> HashMap<String, String> {
+    env::vars_os()
+        .filter_map(|(name, value)| {
+            let name = name.into_string().ok()?;
+            let key = name.strip_prefix(prefix)?;
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_ascii_lowercase(), value.into_string().ok()?))
+        })
+        .collect()
+}
+
+/// Returns the parsed value of the option `option` if it was given on the command line, and else
+/// the one of the variable `name`. The command line thus overrides the environment.
+///
+/// ```no_run
+/// # fn main() -> Result<(), mini::env::Error> {
+/// let mut options = mini::getopts::Options::new();
+/// options.optopt("p", "port", "port to listen on (env: PORT)", "PORT");
+/// let matches = options.parse(std::env::args().skip(1)).expect("arguments");
+/// let port = mini::env::opt_or_env(&matches, "port", "PORT")?.unwrap_or(8080u16);
+/// # Ok(())
+/// # }
+/// ```
+pub fn opt_or_env<T: FromStr>(matches: &Matches, option: &str, name: &str) -> Result<Option<T>, Error>
+where T::Err: Display,
+{
+    match matches.opt_str(option) {
+        Some(value) => {
+            let option = if option.len() == 1 { format!("-{}", option) } else { format!("--{}", option) };
+            parse(&option, value).map(Some)
+        },
+        None => get(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use getopts::Options;
+    use super::{Error, flag, get, get_or, list, opt_or_env, require, with_prefix};
+
+    // The tests run in parallel in the same process, so each one uses its own variables.
+
+    #[test]
+    fn typed() {
+        env::set_var("MINI_ENV_TEST_PORT", " 8080 ");
+        env::set_var("MINI_ENV_TEST_BAD_PORT", "http");
+        env::set_var("MINI_ENV_TEST_FLAG", "Yes");
+        env::set_var("MINI_ENV_TEST_HOSTS", "a.example, b.example,");
+
+        assert_eq!(get::<u16>("MINI_ENV_TEST_PORT"), Ok(Some(8080)));
+        assert_eq!(get::<u16>("MINI_ENV_TEST_UNSET"), Ok(None));
+        assert_eq!(get_or("MINI_ENV_TEST_UNSET", 80u16), Ok(80));
+        assert_eq!(require::<u16>("MINI_ENV_TEST_UNSET"), Err(Error::Missing("MINI_ENV_TEST_UNSET".to_string())));
+        let error = get::<u16>("MINI_ENV_TEST_BAD_PORT").unwrap_err();
+        assert_eq!(error.to_string(), "invalid value \"http\" for MINI_ENV_TEST_BAD_PORT: invalid digit found in string");
+        assert_eq!(flag("MINI_ENV_TEST_FLAG"), Ok(Some(true)));
+        assert!(flag("MINI_ENV_TEST_PORT").is_err());
+        assert_eq!(list::<String>("MINI_ENV_TEST_HOSTS"), Ok(vec!["a.example".to_string(), "b.example".to_string()]));
+    }
+
+    #[test]
+    fn prefix() {
+        env::set_var("MINI_ENV_PREFIX_LOG_LEVEL", "debug");
+        env::set_var("MINI_ENV_PREFIX_WORKERS", "4");
+        let vars = with_prefix("MINI_ENV_PREFIX_");
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["log_level"], "debug");
+        assert_eq!(vars["workers"], "4");
+    }
+
+    #[test]
+    fn getopts() {
+        env::set_var("MINI_ENV_OPT_PORT", "8080");
+        let mut options = Options::new();
+        options.optopt("p", "port", "port", "PORT");
+        let matches = options.parse(&["-p", "9090"]).expect("parse");
+        assert_eq!(opt_or_env(&matches, "port", "MINI_ENV_OPT_PORT"), Ok(Some(9090u16)));
+        let matches = options.parse(&[] as &[&str]).expect("parse");
+        assert_eq!(opt_or_env(&matches, "port", "MINI_ENV_OPT_PORT"), Ok(Some(8080u16)));
+        let matches = options.parse(&["--port", "x"]).expect("parse");
+        assert!(opt_or_env::<u16>(&matches, "port", "MINI_ENV_OPT_PORT").unwrap_err().to_string().starts_with("invalid value \"x\" for --port"));
+    }
+}
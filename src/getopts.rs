@@ -24,7 +24,7 @@
 //! single preceding dash; multiple-character options are expected to be
 //! proceeded by two dashes. Options that expect an argument accept their
 //! argument following either a space or an equals sign. Single-character
-//! options don't require the space.
+//! options require neither: `-ofile`, `-o=file` and `-o file` are equivalent.
 //!
 //! # Usage
 //!
@@ -103,21 +103,242 @@ use self::SplitWithinState::*;
 use self::Whitespace::*;
 use self::LengthLimit::*;
 
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt;
+use std::fs;
 use std::iter::{repeat, IntoIterator};
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::rc::Rc;
 use std::result;
+use std::str::FromStr;
+
+use inline_vec::InlineVec;
+use term::{self, Color, StdStream, Style};
+
+/// A rule between options, checked after parsing.
+#[derive(Clone)]
+enum Rule {
+    /// The first option is only valid with the second one.
+    Requires(String, String),
+    /// The first option is required unless the second one is present.
+    RequiredUnless(String, String),
+    /// The last option is required when the first one has the given value.
+    RequiredIf(String, String, String),
+}
 
 /// A description of the options that a program can handle.
 pub struct Options {
     grps: Vec<OptGroup>,
+    /// Cross-option rules, checked in order
+    rules: Vec<Rule>,
+    /// Whether unambiguous prefixes of long options are accepted
+    abbreviations: bool,
+    /// Characters introducing an option besides `-`
+    prefix_chars: Vec<char>,
+    /// Whether `-name` is looked up as a long option first
+    long_only: bool,
+    /// Whether `@file` arguments are replaced by the arguments in the file
+    response_files: bool,
+    /// Section assigned to the options defined from now on
+    section: Option<String>,
+    /// Layout of the usage help
+    usage_config: UsageConfig,
+    /// Styles of the usage help
+    usage_style: UsageStyle,
+    /// Translations of the fixed texts of the usage help
+    messages: Messages,
+    /// Named free arguments, in order
+    positionals: Vec<Positional>,
+    /// Validation callbacks, by index of the option
+    validators: Vec<(usize, Validator)>,
+    /// Version reported by `-V`, if `with_standard_flags` was called
+    version: Option<String>,
+    /// Example invocations and their descriptions, for usage help
+    examples: Vec<(String, String)>,
+    /// Free-form text ending the usage help
+    footer: Option<String>,
+}
+
+/// A validation callback, returning the error message of an invalid argument.
+type Validator = Rc<dyn Fn(&str) -> result::Result<(), String>>;
+
+/// A named positional argument.
+#[derive(Clone, PartialEq, Eq)]
+struct Positional {
+    /// Name, e.g. `SRC`, used in the usage help and to look up the values
+    name: String,
+    /// Description for usage help text
+    desc: String,
+    /// How many values it takes: `Req` for one, `Optional` for zero or one,
+    /// `Multi` for all the remaining ones
+    occur: Occur,
+}
+
+/// Layout of the option rows in the usage help.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsageConfig {
+    /// Number of spaces before each option.
+    pub indent: usize,
+    /// Column at which the descriptions start. Options longer than that get
+    /// their description on the next line.
+    pub desc_column: usize,
+    /// Maximum width of a description line before wrapping.
+    pub wrap_width: usize,
+}
+
+/// Deepest nesting of response files, to stop reference cycles.
+const MAX_RESPONSE_FILE_DEPTH: usize = 16;
+
+/// Narrowest description wrap width, so that a tiny terminal still gets a
+/// readable help.
+const MIN_WRAP_WIDTH: usize = 20;
+
+impl Default for UsageConfig {
+    /// The classic layout, fitting 80 columns.
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            desc_column: 24,
+            wrap_width: 54,
+        }
+    }
+}
+
+impl UsageConfig {
+    /// Creates a layout wrapping the descriptions to fit `width` columns.
+    pub fn for_width(width: usize) -> Self {
+        let mut config = Self::default();
+        // Keep a margin of 2 columns, like the default layout in 80 columns.
+        config.wrap_width = width.saturating_sub(config.desc_column + 2).max(MIN_WRAP_WIDTH);
+        config
+    }
+
+    /// Creates a layout fitting the width of the terminal, falling back to
+    /// the default layout when it is unknown.
+    pub fn detect() -> Self {
+        term::width().map_or_else(Self::default, Self::for_width)
+    }
+}
+
+/// Styles of the usage help, for terminals supporting ANSI escape sequences.
+///
+/// The default is plain text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UsageStyle {
+    /// Style of the option and positional argument names.
+    pub name: Style,
+    /// Style of the default values.
+    pub default: Style,
+    /// Style of the section headers.
+    pub header: Style,
+}
+
+impl UsageStyle {
+    /// Creates styles writing plain text.
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    /// Creates the standard styles: bold names, dimmed defaults and yellow
+    /// headers.
+    pub fn colored() -> Self {
+        Self {
+            name: Style::new().bold(),
+            default: Style::new().dimmed(),
+            header: Style::new().bold().fg(Color::Yellow),
+        }
+    }
+
+    /// Creates the standard styles if colors should be written to stdout, see
+    /// `term::colors_enabled`, and plain ones otherwise.
+    pub fn detect() -> Self {
+        if term::colors_enabled(StdStream::Stdout) {
+            Self::colored()
+        } else {
+            Self::plain()
+        }
+    }
+}
+
+/// A translation table for the fixed texts of the usage help and of the
+/// `Fail` messages, keyed by their English templates, such as `"Options:"` or
+/// `"Required option '{}' missing"`.
+///
+/// Each `{}` of a template is replaced by an argument, in order. A text
+/// without translation is kept in English.
+///
+/// # Example
+///
+/// ```
+/// use mini::getopts::{Messages, Options};
+///
+/// let mut messages = Messages::new();
+/// messages.translate("Options:", "Optionen:")
+///         .translate("Required option '{}' missing", "Pflichtoption '{}' fehlt");
+///
+/// let mut opts = Options::new();
+/// opts.messages(messages.clone()).reqopt("o", "output", "Ausgabedatei", "DATEI");
+/// assert!(opts.usage("Aufruf: prog").contains("Optionen:"));
+/// let fail = opts.parse(Vec::<String>::new()).err().unwrap();
+/// assert_eq!(fail.localized(&messages), "Pflichtoption 'output' fehlt");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Messages {
+    translations: HashMap<String, String>,
+}
+
+impl Messages {
+    /// Creates an empty table, keeping every text in English.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translates the English `template` to `translation`, which should have
+    /// as many `{}` placeholders.
+    pub fn translate(&mut self, template: &str, translation: &str) -> &mut Messages {
+        self.translations.insert(template.to_string(), translation.to_string());
+        self
+    }
+
+    /// Returns the translation of `template`, or `template` itself.
+    pub fn get<'a>(&'a self, template: &'a str) -> &'a str {
+        self.translations.get(template).map_or(template, |translation| translation.as_str())
+    }
+
+    /// Translates `template` and replaces its placeholders by `args`.
+    fn format(&self, template: &str, args: &[&str]) -> String {
+        let mut parts = self.get(template).split("{}");
+        let mut text = parts.next().unwrap_or("").to_string();
+        for (index, part) in parts.enumerate() {
+            text.push_str(args.get(index).cloned().unwrap_or(""));
+            text.push_str(part);
+        }
+        text
+    }
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             grps: Vec::new(),
+            rules: Vec::new(),
+            abbreviations: false,
+            prefix_chars: Vec::new(),
+            long_only: false,
+            response_files: false,
+            section: None,
+            usage_config: UsageConfig::default(),
+            usage_style: UsageStyle::default(),
+            messages: Messages::default(),
+            positionals: Vec::new(),
+            validators: Vec::new(),
+            version: None,
+            examples: Vec::new(),
+            footer: None,
         }
     }
 }
@@ -128,6 +349,68 @@ impl Options {
         Self::default()
     }
 
+    /// Sets the translations of the fixed texts of the usage help, such as
+    /// `"Options:"` or `"(required)"`. Use `Fail::localized` with the same
+    /// table for the errors.
+    ///
+    /// The descriptions of the standard flags and the texts of numeric ranges
+    /// are translated when they are defined, so call this first. The messages
+    /// of other validation callbacks are not translated.
+    pub fn messages(&mut self, messages: Messages) -> &mut Options {
+        self.messages = messages;
+        self
+    }
+
+    /// Sets whether long options may be abbreviated GNU-style, e.g. `--verb`
+    /// for `--verbose`, as long as no other long option shares the prefix.
+    ///
+    /// An ambiguous prefix fails with `AmbiguousOption`. Disabled by default.
+    pub fn abbreviations(&mut self, enabled: bool) -> &mut Options {
+        self.abbreviations = enabled;
+        self
+    }
+
+    /// Sets whether a single dash may introduce a long option, like GNU
+    /// `getopt_long_only`: `-verbose` and `-out=file` are matched against the
+    /// long options first, and only split into short options, `-v -e -r ...`,
+    /// if no long option matches. Disabled by default.
+    ///
+    /// With `abbreviations` enabled, an unambiguous prefix also matches.
+    pub fn long_only(&mut self, enabled: bool) -> &mut Options {
+        self.long_only = enabled;
+        self
+    }
+
+    /// Sets whether an `@file` argument is replaced by the arguments read from
+    /// `file`, like the response files of compilers and linkers. Disabled by
+    /// default.
+    ///
+    /// The file holds arguments separated by whitespace, which may be quoted
+    /// as in a shell. It may itself refer to other response files. A lone `@`
+    /// and the arguments after `--` are left as is. The argument indices
+    /// reported after parsing refer to the expanded arguments.
+    ///
+    /// An unreadable or malformed file fails with `ResponseFile`.
+    pub fn response_files(&mut self, enabled: bool) -> &mut Options {
+        self.response_files = enabled;
+        self
+    }
+
+    /// Sets additional characters introducing an option, e.g. `&['/', '+']`
+    /// to accept `/flag` Windows-style or `+opt` toggles besides `-opt`.
+    ///
+    /// An option introduced by one of these characters is a single option,
+    /// looked up by its long name, or by its short name if the name is a
+    /// single character, and takes its argument after an equals sign or in
+    /// the next argument: `/out=file`, `/out file`, `+x`. Use
+    /// `Matches::opt_prefix` to tell the prefixes apart. A lone prefix
+    /// character is a free argument, but any other argument starting with one,
+    /// such as an absolute path with `/`, is parsed as an option.
+    pub fn prefix_chars(&mut self, prefix_chars: &[char]) -> &mut Options {
+        self.prefix_chars = prefix_chars.iter().cloned().filter(|&c| c != '-').collect();
+        self
+    }
+
     /// Creates a generic option group, stating all parameters explicitly.
     pub fn opt(&mut self, short_name: &str, long_name: &str, desc: &str,
                        hint: &str, hasarg: HasArg, occur: Occur) -> &mut Options {
@@ -138,11 +421,71 @@ impl Options {
             hint: hint.to_string(),
             desc: desc.to_string(),
             hasarg,
-            occur
+            occur,
+            default: None,
+            choices: Vec::new(),
+            range: None,
+            section: self.section.clone(),
+            max_count: None,
+            deprecated: None,
+            rest: false,
         });
         self
     }
 
+    /// Sets the layout of the usage help, e.g. `UsageConfig::detect()` to
+    /// adapt it to the terminal.
+    pub fn usage_config(&mut self, config: UsageConfig) -> &mut Options {
+        self.usage_config = config;
+        self
+    }
+
+    /// Sets the styles of the usage help, e.g. `UsageStyle::detect()` to color
+    /// it on a terminal.
+    pub fn usage_style(&mut self, style: UsageStyle) -> &mut Options {
+        self.usage_style = style;
+        self
+    }
+
+    /// Starts a named section of the usage help, e.g. `"Output options"`.
+    ///
+    /// The options defined after this call are listed under `title`, after
+    /// the options that belong to no section. Sections appear in declaration
+    /// order.
+    pub fn section(&mut self, title: &str) -> &mut Options {
+        self.section = Some(title.to_string());
+        self
+    }
+
+    /// Adds an example invocation, e.g. `"prog -o out.txt in.txt"`, listed
+    /// with its description under "Examples:" after the options.
+    pub fn example(&mut self, command: &str, desc: &str) -> &mut Options {
+        self.examples.push((command.to_string(), desc.to_string()));
+        self
+    }
+
+    /// Sets the text ending the usage help, e.g. the environment variables
+    /// or exit codes of the program. It is rendered as is, after the
+    /// examples.
+    pub fn footer(&mut self, text: &str) -> &mut Options {
+        self.footer = Some(text.to_string());
+        self
+    }
+
+    /// Defines the `-h/--help` and `-V/--version` flags, handled after
+    /// parsing by `Matches::handled_standard_flags`.
+    ///
+    /// When either flag is given, missing required options and arguments
+    /// are not reported, so that `--help` always works.
+    pub fn with_standard_flags(&mut self, version: &str) -> &mut Options {
+        let help = self.messages.get("Print this help and exit").to_string();
+        let version_desc = self.messages.get("Print version information and exit").to_string();
+        self.optflag("h", "help", &help);
+        self.optflag("V", "version", &version_desc);
+        self.version = Some(version.to_string());
+        self
+    }
+
     /// Creates an option that is optional and does not take an argument.
     ///
     /// * `short_name` - e.g. `"h"` for a `-h` option, or `""` for none
@@ -150,16 +493,7 @@ impl Options {
     /// * `desc` - Description for usage help
     pub fn optflag(&mut self, short_name: &str, long_name: &str, desc: &str)
                            -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: "".to_string(),
-            desc: desc.to_string(),
-            hasarg: No,
-            occur: Optional
-        });
-        self
+        self.opt(short_name, long_name, desc, "", No, Optional)
     }
 
     /// Creates a long option that can occur more than once and does not
@@ -170,15 +504,22 @@ impl Options {
     /// * `desc` - Description for usage help
     pub fn optflagmulti(&mut self, short_name: &str, long_name: &str, desc: &str)
                                 -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: "".to_string(),
-            desc: desc.to_string(),
-            hasarg: No,
-            occur: Multi
-        });
+        self.opt(short_name, long_name, desc, "", No, Multi)
+    }
+
+    /// Creates a counting flag, e.g. `-v` for verbosity, which can be repeated
+    /// or stacked (`-vvv`). Read it with `Matches::opt_level`.
+    ///
+    /// * `short_name` - e.g. `"v"` for a `-v` option, or `""` for none
+    /// * `long_name` - e.g. `"verbose"` for a `--verbose` option, or `""` for none
+    /// * `desc` - Description for usage help
+    /// * `max` - Highest level returned by `opt_level`, or `None` for no cap
+    pub fn optcount(&mut self, short_name: &str, long_name: &str, desc: &str, max: Option<u32>)
+                            -> &mut Options {
+        self.opt(short_name, long_name, desc, "", No, Multi);
+        if let Some(grp) = self.grps.last_mut() {
+            grp.max_count = max;
+        }
         self
     }
 
@@ -191,16 +532,7 @@ impl Options {
     ///   e.g. `"FILE"` for a `-o FILE` option
     pub fn optflagopt(&mut self, short_name: &str, long_name: &str, desc: &str,
                               hint: &str) -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: hint.to_string(),
-            desc: desc.to_string(),
-            hasarg: Maybe,
-            occur: Optional
-        });
-        self
+        self.opt(short_name, long_name, desc, hint, Maybe, Optional)
     }
 
     /// Creates a long option that is optional, takes an argument, and may occur
@@ -213,15 +545,42 @@ impl Options {
     ///   e.g. `"FILE"` for a `-o FILE` option
     pub fn optmulti(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str)
                             -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: hint.to_string(),
-            desc: desc.to_string(),
-            hasarg: Yes,
-            occur: Multi
-        });
+        self.opt(short_name, long_name, desc, hint, Yes, Multi)
+    }
+
+    /// Creates an option that is optional, may occur multiple times, and takes
+    /// a `key=value` argument, like the `-D NAME=VALUE` defines of build tools.
+    /// Use `Matches::opt_map` to get the pairs.
+    ///
+    /// `parse` fails with `InvalidValue` for an argument without `=`.
+    ///
+    /// * `short_name` - e.g. `"D"` for a `-D` option, or `""` for none
+    /// * `long_name` - e.g. `"define"` for a `--define` option, or `""` for none
+    /// * `desc` - Description for usage help
+    /// * `hint` - Hint that is used in place of the argument in the usage help,
+    ///   e.g. `"NAME=VALUE"`
+    pub fn optmap(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str)
+                          -> &mut Options {
+        self.opt(short_name, long_name, desc, hint, Yes, Multi)
+            .validate(|value| if value.contains('=') { Ok(()) } else { Err("expected KEY=VALUE") })
+    }
+
+    /// Creates an option that is optional and takes its argument and all the
+    /// arguments after it as values, like `--exec CMD ARGS...`: parsing ends
+    /// there, so arguments starting with `-`, and even `--`, are values too.
+    /// Use `Matches::opt_strs` to get them.
+    ///
+    /// * `short_name` - e.g. `"e"` for a `-e` option, or `""` for none
+    /// * `long_name` - e.g. `"exec"` for a `--exec` option, or `""` for none
+    /// * `desc` - Description for usage help
+    /// * `hint` - Hint that is used in place of the arguments in the usage
+    ///   help, e.g. `"CMD ARGS..."`
+    pub fn optrest(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str)
+                           -> &mut Options {
+        self.opt(short_name, long_name, desc, hint, Yes, Optional);
+        if let Some(grp) = self.grps.last_mut() {
+            grp.rest = true;
+        }
         self
     }
 
@@ -234,15 +593,27 @@ impl Options {
     ///   e.g. `"FILE"` for a `-o FILE` option
     pub fn optopt(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str)
                           -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: hint.to_string(),
-            desc: desc.to_string(),
-            hasarg: Yes,
-            occur: Optional
-        });
+        self.opt(short_name, long_name, desc, hint, Yes, Optional)
+    }
+
+    /// Creates a long option that is optional, takes an argument, and falls
+    /// back to `default` when absent.
+    ///
+    /// `Matches::opt_str` returns the default if the option was not given, and
+    /// the usage help shows it after the description.
+    ///
+    /// * `short_name` - e.g. `"h"` for a `-h` option, or `""` for none
+    /// * `long_name` - e.g. `"help"` for a `--help` option, or `""` for none
+    /// * `desc` - Description for usage help
+    /// * `hint` - Hint that is used in place of the argument in the usage help,
+    ///   e.g. `"FILE"` for a `-o FILE` option
+    /// * `default` - Value used when the option is absent, e.g. `"a.out"`
+    pub fn optopt_default(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str,
+                                  default: &str) -> &mut Options {
+        self.opt(short_name, long_name, desc, hint, Yes, Optional);
+        if let Some(grp) = self.grps.last_mut() {
+            grp.default = Some(default.to_string());
+        }
         self
     }
 
@@ -255,18 +626,261 @@ impl Options {
     ///   e.g. `"FILE"` for a `-o FILE` option
     pub fn reqopt(&mut self, short_name: &str, long_name: &str, desc: &str, hint: &str)
                           -> &mut Options {
-        validate_names(short_name, long_name);
-        self.grps.push(OptGroup {
-            short_name: short_name.to_string(),
-            long_name: long_name.to_string(),
-            hint: hint.to_string(),
+        self.opt(short_name, long_name, desc, hint, Yes, Req)
+    }
+
+    /// Restricts the arguments accepted by the most recently defined option to
+    /// a closed set, e.g. `opts.optopt(...).choices(&["fast", "small"])`.
+    ///
+    /// `parse` fails with `InvalidChoice` for any other argument, and the usage
+    /// help lists the choices after the description.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no option was defined yet or if the last option does not take
+    /// an argument.
+    pub fn choices(&mut self, choices: &[&str]) -> &mut Options {
+        let grp = self.grps.last_mut().expect("choices() must follow an option definition");
+        assert!(grp.hasarg != No, "choices() applies to an option taking an argument");
+        grp.choices = choices.iter().map(|choice| choice.to_string()).collect();
+        self
+    }
+
+    /// Declares a named positional argument, taken from the free arguments in
+    /// declaration order.
+    ///
+    /// `occur` is the arity: `Req` for exactly one value, `Optional` for at
+    /// most one, and `Multi` for all the remaining free arguments. Once a
+    /// positional argument is declared, `parse` fails with `PositionalMissing`
+    /// or `UnexpectedPositional` if the count of free arguments does not fit.
+    /// The values are available by name with `Matches::positional`, and still
+    /// in `Matches::free`.
+    ///
+    /// * `name` - e.g. `"SRC"`, shown in the usage help
+    /// * `desc` - Description for usage help
+    ///
+    /// # Panics
+    ///
+    /// Panics if a required argument follows an optional one, or if any
+    /// argument follows a `Multi` one.
+    pub fn positional(&mut self, name: &str, desc: &str, occur: Occur) -> &mut Options {
+        if let Some(last) = self.positionals.last() {
+            assert!(last.occur != Multi, "no positional argument can follow a variadic one");
+            assert!(occur != Req || last.occur == Req,
+                    "a required positional argument cannot follow an optional one");
+        }
+        self.positionals.push(Positional {
+            name: name.to_string(),
             desc: desc.to_string(),
-            hasarg: Yes,
-            occur: Req
+            occur,
         });
         self
     }
 
+    /// Attaches a validation callback to the most recently defined option,
+    /// e.g. `.validate(|v| v.parse::<u16>())`.
+    ///
+    /// `parse` calls it with each argument given to the option, and fails with
+    /// `InvalidValue` carrying the error message if it returns an error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no option was defined yet.
+    pub fn validate<F, T, E>(&mut self, validator: F) -> &mut Options
+        where F: Fn(&str) -> result::Result<T, E> + 'static,
+              E: fmt::Display,
+    {
+        assert!(!self.grps.is_empty(), "validate() must follow an option definition");
+        let index = self.grps.len() - 1;
+        self.validators.push((index, Rc::new(move |value| {
+            validator(value).map(|_| ()).map_err(|error| error.to_string())
+        })));
+        self
+    }
+
+    /// Restricts the arguments of the most recently defined option to a
+    /// numeric range, e.g. `.range(1..=65535)` for a port.
+    ///
+    /// `parse` fails with `InvalidValue` for an argument that does not parse
+    /// as a `T` or is out of the range, and the usage help shows the range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no option was defined yet.
+    pub fn range<T, R>(&mut self, range: R) -> &mut Options
+        where T: FromStr + PartialOrd + fmt::Display + Clone + 'static,
+              T::Err: fmt::Display,
+              R: RangeBounds<T>,
+    {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let (template, bounds) =
+            match (&start, &end) {
+                (Bound::Included(start), Bound::Included(end)) => ("between {} and {}", vec![start.to_string(), end.to_string()]),
+                (Bound::Included(start), Bound::Excluded(end)) => {
+                    ("at least {} and less than {}", vec![start.to_string(), end.to_string()])
+                },
+                (Bound::Excluded(start), Bound::Included(end)) => {
+                    ("greater than {} and at most {}", vec![start.to_string(), end.to_string()])
+                },
+                (Bound::Excluded(start), Bound::Excluded(end)) => {
+                    ("greater than {} and less than {}", vec![start.to_string(), end.to_string()])
+                },
+                (Bound::Included(start), Bound::Unbounded) => ("at least {}", vec![start.to_string()]),
+                (Bound::Excluded(start), Bound::Unbounded) => ("greater than {}", vec![start.to_string()]),
+                (Bound::Unbounded, Bound::Included(end)) => ("at most {}", vec![end.to_string()]),
+                (Bound::Unbounded, Bound::Excluded(end)) => ("less than {}", vec![end.to_string()]),
+                (Bound::Unbounded, Bound::Unbounded) => ("any number", vec![]),
+            };
+        let bounds: Vec<&str> = bounds.iter().map(String::as_str).collect();
+        let description = self.messages.format(template, &bounds);
+        let error = self.messages.format("must be {}", &[&description]);
+        self.grps.last_mut().expect("range() must follow an option definition").range = Some(description);
+        self.validate(move |value| {
+            let number: T = value.parse().map_err(|error: T::Err| error.to_string())?;
+            if (start.as_ref(), end.as_ref()).contains(&number) {
+                Ok(())
+            } else {
+                Err(error.clone())
+            }
+        })
+    }
+
+    /// Marks the most recently defined option as deprecated, e.g.
+    /// `opts.optflag("", "old-flag", "...").deprecated("--new-flag")`.
+    ///
+    /// The option is still accepted, but `Matches::warnings` reports its use,
+    /// and the usage help flags it.
+    ///
+    /// * `replacement` - What to use instead, e.g. `"--new-flag"`, or `""` for
+    ///   none
+    ///
+    /// # Panics
+    ///
+    /// Panics if no option was defined yet.
+    pub fn deprecated(&mut self, replacement: &str) -> &mut Options {
+        let grp = self.grps.last_mut().expect("deprecated() must follow an option definition");
+        grp.deprecated = Some(replacement.to_string());
+        self
+    }
+
+    /// Declares that option `nm` can only be given together with option
+    /// `required`, e.g. `opts.requires("key-file", "cert-file")`.
+    ///
+    /// `parse` fails with `OptionRequires` when `nm` is present without
+    /// `required`, and with `UnrecognizedOption` if either name is not defined
+    /// by then.
+    pub fn requires(&mut self, nm: &str, required: &str) -> &mut Options {
+        self.rules.push(Rule::Requires(nm.to_string(), required.to_string()));
+        self
+    }
+
+    /// Declares that option `nm` is required unless option `other` is
+    /// present, e.g. `opts.required_unless("stdin", "input")`.
+    ///
+    /// `parse` fails with `RequiredUnless` when neither is present, and with
+    /// `UnrecognizedOption` if either name is not defined by then.
+    pub fn required_unless(&mut self, nm: &str, other: &str) -> &mut Options {
+        self.rules.push(Rule::RequiredUnless(nm.to_string(), other.to_string()));
+        self
+    }
+
+    /// Declares that option `required` is required when option `nm` is given
+    /// the argument `value`, e.g. `opts.required_if("format", "json", "schema")`.
+    ///
+    /// `parse` fails with `RequiredIf` when `required` is missing, and with
+    /// `UnrecognizedOption` if either name is not defined by then.
+    pub fn required_if(&mut self, nm: &str, value: &str, required: &str) -> &mut Options {
+        self.rules.push(Rule::RequiredIf(nm.to_string(), value.to_string(), required.to_string()));
+        self
+    }
+
+    /// Checks the definitions for conflicts: options or positional arguments
+    /// defined twice with the same name, rules naming undefined options, and
+    /// defaults outside of the declared choices.
+    ///
+    /// The parser silently uses the first of two options with the same name,
+    /// so calling this from a test catches a misconfigured command line early.
+    pub fn check_definitions(&self) -> result::Result<(), DefinitionError> {
+        let mut conflicts = Vec::new();
+        for (id, grp) in self.grps.iter().enumerate() {
+            conflicts.extend(name_conflicts(&self.grps[..id], grp));
+            if let Some(ref default) = grp.default {
+                if !grp.choices.is_empty() && !grp.choices.contains(default) {
+                    conflicts.push(format!("default '{}' of option '{}' is not one of its choices",
+                                           default, format_name(grp)));
+                }
+            }
+        }
+        for (index, positional) in self.positionals.iter().enumerate() {
+            if self.positionals[..index].iter().any(|other| other.name == positional.name) {
+                conflicts.push(format!("argument '{}' is defined more than once", positional.name));
+            }
+        }
+        for rule in &self.rules {
+            let names: Vec<&String> =
+                match *rule {
+                    Rule::Requires(ref nm, ref other) | Rule::RequiredUnless(ref nm, ref other) => vec![nm, other],
+                    Rule::RequiredIf(ref nm, _, ref required) => vec![nm, required],
+                };
+            for nm in names {
+                if find_grp(&self.grps, nm).is_none() {
+                    conflicts.push(format!("a rule names the undefined option '{}'", nm));
+                }
+            }
+        }
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(DefinitionError {
+                conflicts,
+            })
+        }
+    }
+
+    /// Adds the options, positional arguments and rules defined in `other`,
+    /// e.g. a group of logging flags shared by several programs, after those
+    /// defined so far.
+    ///
+    /// Settings such as `abbreviations` or the usage layout stay those of
+    /// `self`, and the merged options keep their own sections. Fails without
+    /// merging anything if a name is defined in both sets, or if the merged
+    /// positional arguments break the ordering rules of `positional`.
+    pub fn merge(&mut self, other: &Options) -> result::Result<&mut Options, DefinitionError> {
+        let mut conflicts = Vec::new();
+        for grp in &other.grps {
+            conflicts.extend(name_conflicts(&self.grps, grp));
+        }
+        for positional in &other.positionals {
+            if self.positionals.iter().any(|own| own.name == positional.name) {
+                conflicts.push(format!("argument '{}' is defined more than once", positional.name));
+            }
+        }
+        // The ordering checks of `positional`, for the arguments appended after those of `self`.
+        let previous = self.positionals.last().into_iter().chain(&other.positionals);
+        for (previous, positional) in previous.zip(&other.positionals) {
+            if previous.occur == Multi {
+                conflicts.push(format!("argument '{}' cannot follow the variadic argument '{}'",
+                                       positional.name, previous.name));
+            } else if positional.occur == Req && previous.occur != Req {
+                conflicts.push(format!("required argument '{}' cannot follow the optional argument '{}'",
+                                       positional.name, previous.name));
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(DefinitionError {
+                conflicts,
+            });
+        }
+        let offset = self.grps.len();
+        self.grps.extend(other.grps.iter().cloned());
+        self.positionals.extend(other.positionals.iter().cloned());
+        self.rules.extend(other.rules.iter().cloned());
+        self.validators.extend(other.validators.iter()
+                                   .map(|(index, validator)| (offset + index, validator.clone())));
+        Ok(self)
+    }
+
     /// Parses command line arguments according to the provided options.
     ///
     /// On success returns `Ok(Matches)`. Use methods such as `opt_present`
@@ -277,41 +891,105 @@ impl Options {
     /// to display information about it.
     pub fn parse<C: IntoIterator>(&self, args: C) -> Result
         where C::Item: AsRef<OsStr>
+    {
+        self.parse_detailed(args).map_err(Failure::into_fail)
+    }
+
+    /// Parses a command line held in a single string, e.g. typed in a REPL or
+    /// stored in a configuration file.
+    ///
+    /// The string is first split into arguments at unquoted whitespace, as a
+    /// shell would: `'...'` keeps its content as is, `"..."` too except for
+    /// the escapes `\"` and `\\`, and elsewhere a backslash escapes the next
+    /// character. Unbalanced quotes fail with `InvalidCommandLine`.
+    pub fn parse_str(&self, line: &str) -> Result {
+        let args = split_command_line(line).map_err(|reason| InvalidCommandLine(reason.to_string()))?;
+        self.parse(args)
+    }
+
+    /// Parses command line arguments according to the provided options, then
+    /// builds a `T` from the matches.
+    ///
+    /// Returns `Err(Fail)` if the arguments do not parse or if `T` rejects
+    /// them.
+    pub fn parse_into<T: FromMatches, C: IntoIterator>(&self, args: C) -> result::Result<T, Fail>
+        where C::Item: AsRef<OsStr>
+    {
+        T::from_matches(&self.parse(args)?)
+    }
+
+    /// Parses command line arguments like `parse`, but on failure returns
+    /// the `Fail` along with its context: the offending argument, its index
+    /// and a suggestion, for applications rendering their own diagnostics.
+    pub fn parse_detailed<C: IntoIterator>(&self, args: C) -> result::Result<Matches, Failure>
+        where C::Item: AsRef<OsStr>
     {
         let opts: Vec<Opt> = self.grps.iter().map(|x| x.long_to_short()).collect();
+        let lookup = index_names(&opts);
 
         let mut vals = (0 .. opts.len()).map(|_| Vec::new()).collect::<Vec<Vec<Optval>>>();
+        let mut positions = (0 .. opts.len()).map(|_| Vec::new()).collect::<Vec<Vec<usize>>>();
+        let mut prefixes = (0 .. opts.len()).map(|_| Vec::new()).collect::<Vec<Vec<char>>>();
+        let mut free_positions = Vec::new();
+        let mut separator = None;
+        let mut order = Vec::new();
         let mut free: Vec<String> = Vec::new();
-        let args = args.into_iter().map(|i| {
+        let tokens = args.into_iter().enumerate().map(|(index, i)| {
             i.as_ref().to_str().ok_or_else(|| {
-                Fail::UnrecognizedOption(format!("{:?}", i.as_ref()))
+                Failure::new(Fail::UnrecognizedOption(format!("{:?}", i.as_ref())))
+                    .at(index, &i.as_ref().to_string_lossy())
             }).map(|s| s.to_owned())
         }).collect::<::std::result::Result<Vec<_>,_>>()?;
-        let mut args = args.into_iter().peekable();
-        while let Some(cur) = args.next() {
-            if !is_arg(&cur) {
+        let tokens = if self.response_files {
+            expand_response_files(tokens, 0).map_err(Failure::new)?
+        } else {
+            tokens
+        };
+        let mut args = tokens.iter().cloned().enumerate().peekable();
+        while let Some((index, cur)) = args.next() {
+            if !is_arg(&cur, &self.prefix_chars) {
                 // If it's not an argument starting with `-`, it's a free argument.
+                order.push((None, free.len()));
                 free.push(cur);
+                free_positions.push(index);
             } else if cur == "--" {
                 // After `--`, the rest of the arguments are free arguments.
-                free.extend(args);
+                separator = Some((index, free.len()));
+                for (index, arg) in args {
+                    order.push((None, free.len()));
+                    free.push(arg);
+                    free_positions.push(index);
+                }
                 break;
             } else {
-                let mut names;
+                // Most arguments hold one or two options.
+                let mut names: InlineVec<Name, 2>;
                 let mut i_arg = None;
                 let mut was_long = true;
-                if cur.as_bytes()[1] == b'-' {
-                    // Parsing long argument.
-                    let tail = &cur[2..];
+                let prefix = cur.chars().next().unwrap();
+                // Only `--` introduces a long option: `/-x` is the option `-x`.
+                let double_dash = prefix == '-' && cur.as_bytes()[1] == b'-';
+                if prefix != '-' || double_dash || (self.long_only && self.is_long_name(&opts, &lookup, &cur[1..])) {
+                    // Parsing long argument, or a single option with another
+                    // prefix.
+                    let prefix_len = if double_dash { 2 } else { prefix.len_utf8() };
+                    let tail = &cur[prefix_len..];
                     let mut parts = tail.splitn(2, '=');
-                    names = vec![Name::from_str(parts.next().unwrap())];
+                    names = InlineVec::new();
+                    let name = parts.next().unwrap();
+                    let mut nm = Name::from_str(name);
+                    if self.abbreviations && find_opt(&lookup, &nm).is_none() {
+                        nm = expand_abbreviation(&opts, name)
+                            .map_err(|fail| Failure::new(fail).at_option(index, &cur))?;
+                    }
+                    names.push(nm);
                     if let Some(rest) = parts.next() {
                         i_arg = Some(rest.to_string());
                     }
                 } else {
                     // Parsing short argument.
                     was_long = false;
-                    names = Vec::new();
+                    names = InlineVec::new();
                     for (j, ch) in cur.char_indices().skip(1) {
                         let opt = Short(ch);
 
@@ -322,9 +1000,10 @@ impl Options {
                            interpreted correctly
                         */
 
-                        let opt_id = match find_opt(&opts, &opt) {
+                        let opt_id = match find_opt(&lookup, &opt) {
                           Some(id) => id,
-                          None => return Err(UnrecognizedOption(opt.to_string()))
+                          None => return Err(Failure::new(UnrecognizedOption(opt.to_string()))
+                                                 .at_option(index, &cur))
                         };
 
                         names.push(opt);
@@ -337,7 +1016,9 @@ impl Options {
                         if arg_follows {
                             let next = j + ch.len_utf8();
                             if next < cur.len() {
-                                i_arg = Some(cur[next..].to_string());
+                                // Accept `-o=value` like `-ovalue`.
+                                let rest = &cur[next..];
+                                i_arg = Some(rest.strip_prefix('=').unwrap_or(rest).to_string());
                                 break;
                             }
                         }
@@ -346,14 +1027,25 @@ impl Options {
                 let mut name_pos = 0;
                 for nm in &names {
                     name_pos += 1;
-                    let optid = match find_opt(&opts, &nm) {
+                    let optid = match find_opt(&lookup, &nm) {
                       Some(id) => id,
-                      None => return Err(UnrecognizedOption(nm.to_string()))
+                      None => {
+                          let mut failure = Failure::new(UnrecognizedOption(nm.to_string()))
+                              .at_option(index, &cur);
+                          if was_long {
+                              failure.context.suggestion = suggest(&opts, &nm.to_string());
+                          }
+                          return Err(failure);
+                      }
                     };
+                    order.push((Some(optid), positions[optid].len()));
+                    positions[optid].push(index);
+                    prefixes[optid].push(prefix);
                     match opts[optid].hasarg {
                       No => {
                         if name_pos == names.len() && i_arg.is_some() {
-                            return Err(UnexpectedArgument(nm.to_string()));
+                            return Err(Failure::new(UnexpectedArgument(nm.to_string()))
+                                           .at_option(index, &cur));
                         }
                         vals[optid].push(Given);
                       }
@@ -365,19 +1057,29 @@ impl Options {
                         // option at the end of the arguments.
                         if let Some(i_arg) = i_arg.take() {
                             vals[optid].push(Val(i_arg));
-                        } else if was_long || name_pos < names.len() || args.peek().map_or(true, |n| is_arg(&n)) {
+                        } else if was_long || name_pos < names.len() || args.peek().map_or(true, |n| is_arg(&n.1, &self.prefix_chars)) {
                             vals[optid].push(Given);
                         } else {
-                            vals[optid].push(Val(args.next().unwrap()));
+                            vals[optid].push(Val(args.next().unwrap().1));
                         }
                       }
                       Yes => {
                         if let Some(i_arg) = i_arg.take() {
                             vals[optid].push(Val(i_arg));
-                        } else if let Some(n) = args.next() {
+                        } else if let Some((_, n)) = args.next() {
                             vals[optid].push(Val(n));
                         } else {
-                            return Err(ArgumentMissing(nm.to_string()));
+                            return Err(Failure::new(ArgumentMissing(nm.to_string()))
+                                           .at_option(index, &cur));
+                        }
+                        // The remaining arguments are values too, ending the loop.
+                        if self.grps[optid].rest {
+                            for (index, arg) in args.by_ref() {
+                                order.push((Some(optid), positions[optid].len()));
+                                positions[optid].push(index);
+                                prefixes[optid].push(prefix);
+                                vals[optid].push(Val(arg));
+                            }
                         }
                       }
                     }
@@ -385,35 +1087,220 @@ impl Options {
             }
         }
         debug_assert_eq!(vals.len(), opts.len());
-        for (vals, opt) in vals.iter().zip(opts.iter()) {
-            if opt.occur == Req && vals.is_empty() {
-                return Err(OptionMissing(opt.name.to_string()));
+        // The occurrence of an option at argument `index`.
+        let occurrence = |fail: Fail, index: usize| Failure::new(fail).at_option(index, &tokens[index]);
+        let given = |nm: &str| find_opt(&lookup, &Name::from_str(nm)).is_some_and(|id| !vals[id].is_empty());
+        let help = self.version.is_some() && given("help");
+        let version = self.version.is_some() && given("version");
+        for (id, (vals, opt)) in vals.iter().zip(opts.iter()).enumerate() {
+            if opt.occur == Req && vals.is_empty() && !help && !version {
+                return Err(Failure::new(OptionMissing(opt.name.to_string())));
             }
-            if opt.occur != Multi && vals.len() > 1 {
-                return Err(OptionDuplicated(opt.name.to_string()));
+            if opt.occur != Multi && vals.len() > 1 && !self.grps[id].rest {
+                return Err(occurrence(OptionDuplicated(opt.name.to_string()), positions[id][1]));
             }
         }
+        for (id, (vals, (grp, opt))) in vals.iter().zip(self.grps.iter().zip(opts.iter())).enumerate() {
+            if grp.choices.is_empty() {
+                continue;
+            }
+            for (val, &index) in vals.iter().zip(positions[id].iter()) {
+                if let Val(ref value) = *val {
+                    if !grp.choices.contains(value) {
+                        let fail = InvalidChoice(opt.name.to_string(), value.clone(), grp.choices.clone());
+                        return Err(occurrence(fail, index));
+                    }
+                }
+            }
+        }
+        for &(id, ref validator) in &self.validators {
+            for (val, &index) in vals[id].iter().zip(positions[id].iter()) {
+                if let Val(ref value) = *val {
+                    if let Err(message) = validator(value) {
+                        let fail = InvalidValue {
+                            option: opts[id].name.to_string(),
+                            value: value.clone(),
+                            message,
+                        };
+                        return Err(occurrence(fail, index));
+                    }
+                }
+            }
+        }
+        // A rule naming an undefined option is a definition error rather than a panic.
+        let id = |nm: &str| {
+            find_opt(&lookup, &Name::from_str(nm)).ok_or_else(|| Failure::new(UnrecognizedOption(nm.to_string())))
+        };
+        for rule in &self.rules {
+            match *rule {
+                Rule::Requires(ref nm, ref required) => {
+                    let (nm_id, required_id) = (id(nm)?, id(required)?);
+                    if let Some(&index) = positions[nm_id].first() {
+                        if positions[required_id].is_empty() {
+                            return Err(occurrence(OptionRequires(nm.clone(), required.clone()), index));
+                        }
+                    }
+                },
+                Rule::RequiredUnless(ref nm, ref other) => {
+                    let (nm_id, other_id) = (id(nm)?, id(other)?);
+                    if positions[nm_id].is_empty() && positions[other_id].is_empty() && !help && !version {
+                        return Err(Failure::new(RequiredUnless(nm.clone(), other.clone())));
+                    }
+                },
+                Rule::RequiredIf(ref nm, ref value, ref required) => {
+                    let (nm_id, required_id) = (id(nm)?, id(required)?);
+                    let given = vals[nm_id].iter().zip(positions[nm_id].iter())
+                        .find(|&(val, _)| *val == Val(value.clone()));
+                    if let Some((_, &index)) = given {
+                        if positions[required_id].is_empty() {
+                            let fail = RequiredIf(required.clone(), nm.clone(), value.clone());
+                            return Err(occurrence(fail, index));
+                        }
+                    }
+                },
+            }
+        }
+        let positionals = match self.assign_positionals(&free, &free_positions) {
+            Err(_) if help || version => Vec::new(),
+            positionals => positionals?,
+        };
+        let warnings = self.grps.iter().zip(vals.iter())
+            .filter(|&(_, vals)| !vals.is_empty())
+            .filter_map(|(grp, _)| grp.deprecated.as_ref().map(|replacement| {
+                if replacement.is_empty() {
+                    format!("{} is deprecated", format_name(grp))
+                } else {
+                    format!("{} is deprecated, use {}", format_name(grp), replacement)
+                }
+            }))
+            .collect();
         Ok(Matches {
-            opts,
             vals,
-            free
+            grps: self.grps.clone(),
+            names: lookup,
+            positionals,
+            warnings,
+            positions,
+            prefixes,
+            free_positions,
+            separator,
+            order,
+            free,
+            help: if help { Some(self.usage(&self.short_usage(&program_name()))) } else { None },
+            version: if version { self.version.clone() } else { None },
         })
     }
 
+    /// Returns true if `tail`, an argument without its dash, names a long
+    /// option, possibly abbreviated, followed by an optional `=value`.
+    fn is_long_name(&self, opts: &[Opt], names: &HashMap<Name, usize>, tail: &str) -> bool {
+        let name = tail.split('=').next().unwrap();
+        if name.chars().count() < 2 {
+            return false;
+        }
+        let nm = Long(name.to_string());
+        if find_opt(names, &nm).is_some() {
+            return true;
+        }
+        self.abbreviations && expand_abbreviation(opts, name).is_ok_and(|nm| find_opt(names, &nm).is_some())
+    }
+
+    /// Distributes the free arguments among the declared positional arguments.
+    fn assign_positionals(&self, free: &[String], free_positions: &[usize])
+                          -> result::Result<Vec<(String, Vec<String>)>, Failure> {
+        if self.positionals.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut free = free.iter().zip(free_positions.iter());
+        let mut positionals = Vec::new();
+        for positional in &self.positionals {
+            let values: Vec<String> = match positional.occur {
+                Multi => free.by_ref().map(|(value, _)| value.clone()).collect(),
+                Req | Optional => free.next().map(|(value, _)| value.clone()).into_iter().collect(),
+            };
+            if positional.occur == Req && values.is_empty() {
+                return Err(Failure::new(PositionalMissing(positional.name.clone())));
+            }
+            positionals.push((positional.name.clone(), values));
+        }
+        match free.next() {
+            Some((extra, &index)) => Err(Failure::new(UnexpectedPositional(extra.clone())).at(index, extra)),
+            None => Ok(positionals),
+        }
+    }
+
     /// Derives a short one-line usage summary from a set of long options.
     pub fn short_usage(&self, program_name: &str) -> String {
-        let mut line = format!("Usage: {} ", program_name);
+        let mut line = self.messages.format("Usage: {}", &[program_name]);
+        line.push(' ');
         line.push_str(&self.grps.iter()
                            .map(format_option)
+                           .chain(self.positionals.iter().map(format_positional))
                            .collect::<Vec<String>>()
                            .join(" "));
         line
     }
 
     /// Derives a formatted message from a set of options.
+    ///
+    /// Positional arguments are listed first. Options assigned to a section
+    /// are listed under its title, after the other options. Examples and the
+    /// footer, if any, come last. The description
+    /// of an option is followed by what the parser enforces about it, e.g.
+    /// `(required)` or `[default: a.out]`.
     pub fn usage(&self, brief: &str) -> String {
-        self.usage_with_format(|opts|
-            format!("{}\n\nOptions:\n{}\n", brief, opts.collect::<Vec<String>>().join("\n")))
+        let rows: Vec<String> = self.usage_items().collect();
+        let mut titles: Vec<Option<&str>> = vec![None];
+        for grp in &self.grps {
+            let title = grp.section.as_deref();
+            if !titles.contains(&title) {
+                titles.push(title);
+            }
+        }
+
+        let mut usage = format!("{}\n", brief);
+        if !self.positionals.is_empty() {
+            let rows: Vec<String> = self.positionals.iter().map(|positional| {
+                let mut row = " ".repeat(self.usage_config.indent);
+                row.push_str(&self.usage_style.name.paint(&positional.name).to_string());
+                row.push(' ');
+                push_description(&mut row, &positional.desc, &self.usage_config);
+                row
+            }).collect();
+            usage.push_str(&format!("\n{}\n{}\n", self.usage_style.header.paint(self.messages.get("Arguments:")),
+                                    rows.join("\n")));
+        }
+        for title in titles {
+            let section_rows: Vec<&str> = self.grps.iter().zip(rows.iter())
+                .filter(|&(grp, _)| grp.section.as_deref() == title)
+                .map(|(_, row)| row.as_str())
+                .collect();
+            // Without any option at all, the empty list is still shown.
+            if section_rows.is_empty() && !self.grps.is_empty() {
+                continue;
+            }
+            let header = match title {
+                Some(title) => format!("{}:", title),
+                None => self.messages.get("Options:").to_string(),
+            };
+            usage.push_str(&format!("\n{}\n{}\n", self.usage_style.header.paint(header),
+                                    section_rows.join("\n")));
+        }
+        if !self.examples.is_empty() {
+            let rows: Vec<String> = self.examples.iter().map(|(command, desc)| {
+                let mut row = " ".repeat(self.usage_config.indent);
+                row.push_str(command);
+                row.push(' ');
+                push_description(&mut row, desc, &self.usage_config);
+                row
+            }).collect();
+            usage.push_str(&format!("\n{}\n{}\n", self.usage_style.header.paint(self.messages.get("Examples:")),
+                                    rows.join("\n")));
+        }
+        if let Some(ref footer) = self.footer {
+            usage.push_str(&format!("\n{}\n", footer.trim_end_matches('\n')));
+        }
+        usage
     }
 
     /// Derives a custom formatted message from a set of options. The formatted options provided to
@@ -424,8 +1311,9 @@ impl Options {
 
     /// Derives usage items from a set of options.
     fn usage_items<'a>(&'a self) -> Box<Iterator<Item=String> + 'a> {
-        let desc_sep = format!("\n{}", repeat(" ").take(24).collect::<String>());
-
+        let indent = self.usage_config.indent;
+        let style = self.usage_style;
+        let messages = &self.messages;
         let any_short = self.grps.iter().any(|optref| {
             !optref.short_name.is_empty()
         });
@@ -436,9 +1324,14 @@ impl Options {
                          hint,
                          desc,
                          hasarg,
+                         occur,
+                         default,
+                         choices,
+                         range,
+                         deprecated,
                          ..} = (*optref).clone();
 
-            let mut row = "    ".to_string();
+            let mut row = " ".repeat(indent);
 
             // short option
             match short_name.len() {
@@ -448,8 +1341,7 @@ impl Options {
                     }
                 }
                 1 => {
-                    row.push('-');
-                    row.push_str(&short_name);
+                    row.push_str(&style.name.paint(format!("-{}", short_name)).to_string());
                     if !long_name.is_empty() {
                         row.push_str(", ");
                     } else {
@@ -465,8 +1357,7 @@ impl Options {
             match long_name.len() {
                 0 => {}
                 _ => {
-                    row.push_str("--");
-                    row.push_str(&long_name);
+                    row.push_str(&style.name.paint(format!("--{}", long_name)).to_string());
                     row.push(' ');
                 }
             }
@@ -482,37 +1373,38 @@ impl Options {
                 }
             }
 
-            // FIXME: #5516 should be graphemes not codepoints
-            // here we just need to indent the start of the description
-            let rowlen = row.chars().count();
-            if rowlen < 24 {
-                for _ in 0 .. 24 - rowlen {
-                    row.push(' ');
-                }
-            } else {
-                row.push_str(&desc_sep)
+            let mut notes = Vec::new();
+            if occur == Req {
+                notes.push(messages.get("(required)").to_string());
             }
-
-            // Normalize desc to contain words separated by one space character
-            let mut desc_normalized_whitespace = String::new();
-            for word in desc.split(|c: char| c.is_whitespace())
-                            .filter(|s| !s.is_empty()) {
-                desc_normalized_whitespace.push_str(word);
-                desc_normalized_whitespace.push(' ');
+            match deprecated {
+                Some(ref replacement) if replacement.is_empty() => notes.push(messages.get("(deprecated)").to_string()),
+                Some(ref replacement) => notes.push(messages.format("(deprecated, use {})", &[replacement])),
+                None => {},
+            }
+            if !choices.is_empty() {
+                notes.push(messages.format("(one of: {})", &[&choices.join(", ")]));
+            }
+            if let Some(range) = range {
+                notes.push(format!("({})", range));
+            }
+            if let Some(ref default) = default {
+                notes.push(messages.format("[default: {}]", &[default]));
+            }
+            let mut desc = desc;
+            for note in notes {
+                desc.push(' ');
+                desc.push_str(&note);
+            }
+            push_description(&mut row, &desc, &self.usage_config);
+            // The default is the end of the description, possibly wrapped.
+            if default.is_some() && !style.default.is_plain() {
+                let marker = messages.get("[default: {}]").split("{}").next().unwrap_or("");
+                if let Some(start) = row.rfind(marker) {
+                    row.insert_str(start, &style.default.prefix());
+                    row.push_str(term::RESET);
+                }
             }
-
-            // FIXME: #5516 should be graphemes not codepoints
-            let mut desc_rows = Vec::new();
-            each_split_within(&desc_normalized_whitespace,
-                              54,
-                              |substr| {
-                desc_rows.push(substr.to_string());
-                true
-            });
-
-            // FIXME: #5516 should be graphemes not codepoints
-            // wrapped description
-            row.push_str(&desc_rows.join(&desc_sep));
 
             row
         });
@@ -521,6 +1413,42 @@ impl Options {
     }
 }
 
+/// Appends `desc` to a usage `row`, starting at the description column and
+/// wrapped to the configured width.
+fn push_description(row: &mut String, desc: &str, config: &UsageConfig) {
+    let desc_sep = format!("\n{}", repeat(" ").take(config.desc_column).collect::<String>());
+
+    // Measured in columns, so that wide characters and combining marks line up.
+    let rowlen = term::visible_width(row);
+    if rowlen < config.desc_column {
+        for _ in 0 .. config.desc_column - rowlen {
+            row.push(' ');
+        }
+    } else {
+        row.truncate(row.trim_end().len());
+        row.push_str(&desc_sep)
+    }
+
+    // Normalize desc to contain words separated by one space character
+    let mut desc_normalized_whitespace = String::new();
+    for word in desc.split(|c: char| c.is_whitespace())
+                    .filter(|s| !s.is_empty()) {
+        desc_normalized_whitespace.push_str(word);
+        desc_normalized_whitespace.push(' ');
+    }
+
+    let mut desc_rows = Vec::new();
+    each_split_within(&desc_normalized_whitespace,
+                      config.wrap_width,
+                      |substr| {
+        desc_rows.push(substr.to_string());
+        true
+    });
+
+    // wrapped description
+    row.push_str(&desc_rows.join(&desc_sep));
+}
+
 fn validate_names(short_name: &str, long_name: &str) {
     let len = short_name.len();
     assert!(len == 1 || len == 0,
@@ -533,7 +1461,7 @@ fn validate_names(short_name: &str, long_name: &str) {
 }
 
 /// Name of an option. Either a string or a single char.
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 enum Name {
     /// A string representing the long name of an option.
     /// For example: "help"
@@ -593,7 +1521,22 @@ struct OptGroup {
     /// Whether option has an argument
     hasarg: HasArg,
     /// How often it can occur
-    occur: Occur
+    occur: Occur,
+    /// Value used when the option is absent
+    default: Option<String>,
+    /// Accepted arguments, or empty for any
+    choices: Vec<String>,
+    /// Description of the accepted numeric range, e.g. `between 1 and 10`,
+    /// translated when defined
+    range: Option<String>,
+    /// Title of the usage help section, if any
+    section: Option<String>,
+    /// Cap of `Matches::opt_level`, if any
+    max_count: Option<u32>,
+    /// Replacement of a deprecated option, empty for none
+    deprecated: Option<String>,
+    /// Whether the option takes all the arguments after it
+    rest: bool,
 }
 
 /// Describes whether an option is given at all or has a value.
@@ -605,20 +1548,130 @@ enum Optval {
 
 /// The result of checking command line arguments. Contains a vector
 /// of matches and a vector of free strings.
+///
+/// The `opt_*` accessors accept either the short or the long name of an
+/// option: `opt_present("o")` and `opt_present("output")` are the same for an
+/// option defined with both. They panic if no option has the name: when it
+/// comes from user input, use the `try_opt_*` accessors instead, which fail
+/// with `UnrecognizedOption`, like the accessors returning a `Result`.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Matches {
-    /// Options that matched
-    opts: Vec<Opt>,
     /// Values of the Options that matched
     vals: Vec<Vec<Optval>>,
+    /// Definitions of the Options, for their declared properties
+    grps: Vec<OptGroup>,
+    /// Indices of the Options by name and alias, built once by `parse`
+    names: HashMap<Name, usize>,
+    /// Names and values of the declared positional arguments
+    positionals: Vec<(String, Vec<String>)>,
+    /// Warnings about the use of deprecated options
+    warnings: Vec<String>,
+    /// Argument indices of the occurrences of the Options
+    positions: Vec<Vec<usize>>,
+    /// Prefix characters of the occurrences of the Options
+    prefixes: Vec<Vec<char>>,
+    /// Argument indices of the free string fragments
+    free_positions: Vec<usize>,
+    /// Argument index of the `--` separator and count of the free string
+    /// fragments before it, if it was given
+    separator: Option<(usize, usize)>,
+    /// Option index, or `None` for a free string fragment, and index of the
+    /// occurrence, in command line order
+    order: Vec<(Option<usize>, usize)>,
     /// Free string fragments
     pub free: Vec<String>,
+    /// Usage help to print, if `--help` was given with standard flags
+    help: Option<String>,
+    /// Version to print, if `--version` was given with standard flags
+    version: Option<String>,
+}
+
+/// A type built from parsed command line arguments, see `Options::parse_into`.
+///
+/// # Example
+///
+/// ```
+/// use mini::getopts::{Fail, FromMatches, Matches, Options};
+///
+/// struct Config {
+///     port: u16,
+///     verbose: bool,
+///     files: Vec<String>,
+/// }
+///
+/// impl FromMatches for Config {
+///     fn from_matches(matches: &Matches) -> Result<Self, Fail> {
+///         Ok(Config {
+///             port: matches.opt_get_default("port", 8080)?,
+///             verbose: matches.opt_present("verbose"),
+///             files: matches.free.clone(),
+///         })
+///     }
+/// }
+///
+/// let mut opts = Options::new();
+/// opts.optopt("p", "port", "Port to listen on", "PORT");
+/// opts.optflag("v", "verbose", "Verbose output");
+/// let config: Config = opts.parse_into(vec!["-v", "index.html"]).unwrap();
+/// assert_eq!(config.port, 8080);
+/// assert!(config.verbose);
+/// assert_eq!(config.files, ["index.html"]);
+/// assert!(opts.parse_into::<Config, _>(vec!["-p", "http"]).is_err());
+/// ```
+pub trait FromMatches: Sized {
+    /// Extracts the value from the matches, typically with `Matches::opt_get`
+    /// and `Matches::opt_get_default` for typed fields.
+    fn from_matches(matches: &Matches) -> result::Result<Self, Fail>;
+}
+
+/// An item of the command line, as yielded by `Matches::iter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Item<'a> {
+    /// An option occurrence: the option name, long if it has one, and its
+    /// argument if any.
+    Opt(&'a str, Option<&'a str>),
+    /// A free string fragment.
+    Free(&'a str),
+}
+
+/// Iterator over the items of the command line in order, see `Matches::iter`.
+pub struct Iter<'a> {
+    matches: &'a Matches,
+    order: ::std::slice::Iter<'a, (Option<usize>, usize)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Item<'a>;
+
+    fn next(&mut self) -> Option<Item<'a>> {
+        let &(id, occurrence) = self.order.next()?;
+        let item =
+            match id {
+                Some(id) => {
+                    let grp = &self.matches.grps[id];
+                    let name = if grp.long_name.is_empty() { &grp.short_name } else { &grp.long_name };
+                    let value =
+                        match self.matches.vals[id][occurrence] {
+                            Val(ref value) => Some(value.as_str()),
+                            Given => None,
+                        };
+                    Item::Opt(name, value)
+                },
+                None => Item::Free(&self.matches.free[occurrence]),
+            };
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.order.size_hint()
+    }
 }
 
 /// The type returned when the command line does not conform to the
 /// expected format. Use the `Debug` implementation to output detailed
 /// information.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Fail {
     /// The option requires an argument but none was passed.
     ArgumentMissing(String),
@@ -630,6 +1683,45 @@ pub enum Fail {
     OptionDuplicated(String),
     /// There's an argument being passed to a non-argument option.
     UnexpectedArgument(String),
+    /// The argument to an option could not be parsed: the option name and the
+    /// offending value.
+    InvalidArgument(String, String),
+    /// An option is used without another option it requires: the option name
+    /// and the name of the required option.
+    OptionRequires(String, String),
+    /// Neither an option nor its alternative is present: the option name and
+    /// the name of the alternative.
+    RequiredUnless(String, String),
+    /// An option is missing while another one has the argument requiring it:
+    /// the missing option name, the other option name and its argument.
+    RequiredIf(String, String, String),
+    /// The argument to an option is not one of its declared choices: the option
+    /// name, the offending value, and the valid choices.
+    InvalidChoice(String, String, Vec<String>),
+    /// An abbreviated long option matches several options: the abbreviation
+    /// and the candidate option names.
+    AmbiguousOption(String, Vec<String>),
+    /// The argument to an option was rejected by its validation callback.
+    InvalidValue {
+        /// Name of the option.
+        option: String,
+        /// The rejected argument.
+        value: String,
+        /// Error message of the validation callback.
+        message: String,
+    },
+    /// A required positional argument is missing: its name.
+    PositionalMissing(String),
+    /// There are more free arguments than declared positional arguments: the
+    /// first extra one.
+    UnexpectedPositional(String),
+    /// The value of an option taking no argument was queried: the option name.
+    NoValue(String),
+    /// A response file could not be read or split into arguments: its path
+    /// and the reason.
+    ResponseFile(String, String),
+    /// A command line string could not be split into arguments: the reason.
+    InvalidCommandLine(String),
 }
 
 impl Error for Fail {
@@ -640,7 +1732,116 @@ impl Error for Fail {
             OptionMissing(_) => "missing option",
             OptionDuplicated(_) => "duplicated option",
             UnexpectedArgument(_) => "unexpected argument",
+            InvalidArgument(..) => "invalid argument",
+            OptionRequires(..) => "missing required option",
+            RequiredUnless(..) => "missing option",
+            RequiredIf(..) => "missing required option",
+            InvalidChoice(..) => "invalid choice",
+            AmbiguousOption(..) => "ambiguous option",
+            InvalidValue { .. } => "invalid value",
+            PositionalMissing(_) => "missing argument",
+            UnexpectedPositional(_) => "unexpected argument",
+            NoValue(_) => "option without value",
+            ResponseFile(..) => "invalid response file",
+            InvalidCommandLine(_) => "invalid command line",
+        }
+    }
+}
+
+/// Where and why a command line failed to parse, for applications rendering
+/// their own diagnostics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FailContext {
+    /// Index in the parsed arguments of the offending argument, if any.
+    pub index: Option<usize>,
+    /// The offending argument as given, e.g. `--colr=auto`.
+    pub token: Option<String>,
+    /// Whether the offending option was given by its long name, if it is an
+    /// option.
+    pub long: Option<bool>,
+    /// A defined long option close to an unrecognized one, e.g. `color`.
+    pub suggestion: Option<String>,
+}
+
+/// A `Fail` along with its context, returned by `Options::parse_detailed`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    fail: Fail,
+    context: Box<FailContext>,
+}
+
+impl Failure {
+    fn new(fail: Fail) -> Failure {
+        Failure {
+            fail,
+            context: Box::default(),
+        }
+    }
+
+    /// Returns what went wrong.
+    pub fn fail(&self) -> &Fail {
+        &self.fail
+    }
+
+    /// Returns where it went wrong.
+    pub fn context(&self) -> &FailContext {
+        &self.context
+    }
+
+    /// Returns what went wrong, dropping the context.
+    pub fn into_fail(self) -> Fail {
+        self.fail
+    }
+
+    /// Locates the failure at the argument `token`.
+    fn at(mut self, index: usize, token: &str) -> Failure {
+        self.context.index = Some(index);
+        self.context.token = Some(token.to_string());
+        self
+    }
+
+    /// Locates the failure at the option argument `token`.
+    fn at_option(self, index: usize, token: &str) -> Failure {
+        let mut failure = self.at(index, token);
+        let long =
+            if token.starts_with('-') {
+                token.starts_with("--")
+            } else {
+                // Another prefix character: long if the name is.
+                let name = token.chars().skip(1).take_while(|&c| c != '=');
+                name.count() > 1
+            };
+        failure.context.long = Some(long);
+        failure
+    }
+}
+
+impl Error for Failure {}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fail.fmt(f)?;
+        if let Some(ref suggestion) = self.context.suggestion {
+            write!(f, " (did you mean '--{}'?)", suggestion)?;
         }
+        Ok(())
+    }
+}
+
+/// Conflicting definitions in a set of options, returned by
+/// `Options::check_definitions`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefinitionError {
+    /// One message per conflict, e.g. `option '-o' is defined more than once`.
+    pub conflicts: Vec<String>,
+}
+
+impl Error for DefinitionError {}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Conflicting option definitions: {}", self.conflicts.join("; "))
     }
 }
 
@@ -709,46 +1910,151 @@ impl OptGroup {
 }
 
 impl Matches {
-    fn opt_vals(&self, nm: &str) -> Vec<Optval> {
-        match find_opt(&self.opts, &Name::from_str(nm)) {
-            Some(id) => self.vals[id].clone(),
-            None => panic!("No option '{}' defined", nm)
-        }
+    /// Returns the canonical name of an option, its long name if it has one,
+    /// from either of its names.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn canonical_name(&self, nm: &str) -> result::Result<&str, Fail> {
+        let grp = &self.grps[self.opt_id(nm)?];
+        Ok(if grp.long_name.is_empty() { &grp.short_name } else { &grp.long_name })
+    }
+
+    /// Finds the option named `nm`: every lookup by name goes through here.
+    fn opt_id(&self, nm: &str) -> result::Result<usize, Fail> {
+        find_opt(&self.names, &Name::from_str(nm)).ok_or_else(|| UnrecognizedOption(nm.to_string()))
+    }
+
+    fn opt_vals(&self, nm: &str) -> result::Result<&[Optval], Fail> {
+        self.opt_id(nm).map(|id| &self.vals[id][..])
     }
 
-    fn opt_val(&self, nm: &str) -> Option<Optval> {
-        self.opt_vals(nm).into_iter().next()
+    fn opt_val(&self, nm: &str) -> result::Result<Option<Optval>, Fail> {
+        self.opt_vals(nm).map(|vals| vals.first().cloned())
     }
+
     /// Returns true if an option was defined
     pub fn opt_defined(&self, nm: &str) -> bool {
-        find_opt(&self.opts, &Name::from_str(nm)).is_some()
+        self.opt_id(nm).is_ok()
     }
 
     /// Returns true if an option was matched.
     pub fn opt_present(&self, nm: &str) -> bool {
-        !self.opt_vals(nm).is_empty()
+        self.try_opt_present(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns true if an option was matched, like `opt_present`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_present(&self, nm: &str) -> result::Result<bool, Fail> {
+        self.opt_vals(nm).map(|vals| !vals.is_empty())
     }
 
     /// Returns the number of times an option was matched.
     pub fn opt_count(&self, nm: &str) -> usize {
-        self.opt_vals(nm).len()
+        self.try_opt_count(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns the number of times an option was matched, like `opt_count`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_count(&self, nm: &str) -> result::Result<usize, Fail> {
+        self.opt_vals(nm).map(|vals| vals.len())
+    }
+
+    /// Returns the indices in the parsed arguments at which an option
+    /// occurred, in order.
+    ///
+    /// With `free_positions`, this allows interleaving options with free
+    /// arguments, e.g. to apply each `-I` path to the inputs following it.
+    pub fn opt_positions(&self, nm: &str) -> Vec<usize> {
+        self.try_opt_positions(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns the indices at which an option occurred, like `opt_positions`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_positions(&self, nm: &str) -> result::Result<Vec<usize>, Fail> {
+        self.opt_id(nm).map(|id| self.positions[id].clone())
+    }
+
+    /// Returns the character introducing the last occurrence of an option:
+    /// `-`, or one of the characters set with `Options::prefix_chars`, e.g.
+    /// `+` for `+opt`. Returns `None` if the option was not matched.
+    pub fn opt_prefix(&self, nm: &str) -> Option<char> {
+        self.try_opt_prefix(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns the character introducing the last occurrence of an option,
+    /// like `opt_prefix`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_prefix(&self, nm: &str) -> result::Result<Option<char>, Fail> {
+        self.opt_id(nm).map(|id| self.prefixes[id].last().cloned())
+    }
+
+    /// Returns the indices in the parsed arguments of the free string
+    /// fragments, matching `free`.
+    pub fn free_positions(&self) -> &[usize] {
+        &self.free_positions
+    }
+
+    /// Returns the argument index of the `--` ending the options, if given.
+    pub fn separator_position(&self) -> Option<usize> {
+        self.separator.map(|(index, _)| index)
+    }
+
+    /// Returns the free string fragments given before `--`, or all of them
+    /// without `--`.
+    pub fn free_before_separator(&self) -> &[String] {
+        &self.free[..self.separator.map_or(self.free.len(), |(_, count)| count)]
+    }
+
+    /// Returns the free string fragments given after `--`, e.g. a command to
+    /// run, or none without `--`.
+    pub fn free_after_separator(&self) -> &[String] {
+        &self.free[self.separator.map_or(self.free.len(), |(_, count)| count)..]
+    }
+
+    /// Iterates over the matched options and the free string fragments in
+    /// command line order, e.g. to handle `-l` and `-L` relative to the inputs
+    /// like a linker.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            matches: self,
+            order: self.order.iter(),
+        }
+    }
+
+    /// Returns the warnings about the deprecated options that were matched,
+    /// e.g. `--old-flag is deprecated, use --new-flag`, for the application
+    /// to print.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Returns the level of a counting flag: the number of times it was
+    /// matched, limited to the cap declared with `Options::optcount`.
+    pub fn opt_level(&self, nm: &str) -> u32 {
+        let id = self.opt_id(nm).unwrap_or_else(|_| undefined(nm));
+        let count = self.vals[id].len().min(u32::MAX as usize) as u32;
+        match self.grps[id].max_count {
+            Some(max) => count.min(max),
+            None => count,
+        }
     }
 
     /// Returns true if any of several options were matched.
     pub fn opts_present(&self, names: &[String]) -> bool {
         names.iter().any(|nm| {
-            match find_opt(&self.opts, &Name::from_str(&nm)) {
-                Some(id) if !self.vals[id].is_empty() => true,
-                _ => false,
-            }
+            self.try_opt_present(nm).unwrap_or(false)
         })
     }
 
     /// Returns the string argument supplied to one of several matching options or `None`.
     pub fn opts_str(&self, names: &[String]) -> Option<String> {
         names.iter().filter_map(|nm| {
-            match self.opt_val(&nm) {
-                Some(Val(s)) => Some(s),
+            match self.opt_val(nm) {
+                Ok(Some(Val(s))) => Some(s),
                 _ => None,
             }
         }).next()
@@ -759,22 +2065,108 @@ impl Matches {
     ///
     /// Used when an option accepts multiple values.
     pub fn opt_strs(&self, nm: &str) -> Vec<String> {
-        self.opt_vals(nm).into_iter().filter_map(|v| {
-            match v {
-                Val(s) => Some(s),
+        self.try_opt_strs(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns the arguments provided to all matches of an option, like
+    /// `opt_strs`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_strs(&self, nm: &str) -> result::Result<Vec<String>, Fail> {
+        self.opt_vals(nm).map(|vals| vals.iter().filter_map(|v| {
+            match *v {
+                Val(ref s) => Some(s.clone()),
                 _ => None,
             }
+        }).collect())
+    }
+
+    /// Returns the `key=value` arguments provided to all matches of the given
+    /// option, split at the first `=`, in command line order.
+    ///
+    /// An argument without `=`, possible unless the option was defined with
+    /// `optmap`, is a key with an empty value.
+    pub fn opt_map(&self, nm: &str) -> Vec<(String, String)> {
+        self.opt_strs(nm).into_iter().map(|arg| {
+            let mut parts = arg.splitn(2, '=');
+            let key = parts.next().unwrap().to_string();
+            (key, parts.next().unwrap_or("").to_string())
         }).collect()
     }
 
-    /// Returns the string argument supplied to a matching option or `None`.
+    /// Returns the string argument supplied to a matching option, the default
+    /// declared with `optopt_default` if the option is absent, or `None`.
     pub fn opt_str(&self, nm: &str) -> Option<String> {
-        match self.opt_val(nm) {
-            Some(Val(s)) => Some(s),
-            _ => None,
+        self.try_opt_str(nm).unwrap_or_else(|_| undefined(nm))
+    }
+
+    /// Returns the string argument supplied to a matching option or its
+    /// default, like `opt_str`.
+    ///
+    /// Fails with `UnrecognizedOption` if no option has the name.
+    pub fn try_opt_str(&self, nm: &str) -> result::Result<Option<String>, Fail> {
+        let id = self.opt_id(nm)?;
+        Ok(match self.vals[id].first() {
+            Some(Val(s)) => Some(s.clone()),
+            Some(Given) => None,
+            None => self.grps[id].default.clone(),
+        })
+    }
+
+    /// Handles the flags defined by `Options::with_standard_flags`: prints
+    /// the usage help for `--help`, or the version for `--version`.
+    ///
+    /// Returns true if one was printed, in which case the program should
+    /// exit without doing anything else.
+    pub fn handled_standard_flags(&self) -> bool {
+        if let Some(ref help) = self.help {
+            print!("{}", help);
+        } else if let Some(ref version) = self.version {
+            println!("{} {}", program_name(), version);
+        } else {
+            return false;
         }
+        true
     }
 
+    /// Returns the string argument supplied to a matching option like
+    /// `opt_str`, but fails instead of hiding a misuse.
+    ///
+    /// Fails with `NoValue` if the option takes no argument, and with
+    /// `OptionDuplicated` if it was matched more than once, where `opt_str`
+    /// would silently return the first value.
+    pub fn opt_str_strict(&self, nm: &str) -> result::Result<Option<String>, Fail> {
+        let id = self.opt_id(nm)?;
+        if self.grps[id].hasarg == No {
+            return Err(NoValue(nm.to_string()));
+        }
+        match self.vals[id].as_slice() {
+            [] => Ok(self.grps[id].default.clone()),
+            [Val(ref s)] => Ok(Some(s.clone())),
+            [Given] => Ok(None),
+            _ => Err(OptionDuplicated(nm.to_string())),
+        }
+    }
+
+
+    fn positional_vals(&self, name: &str) -> &[String] {
+        match self.positionals.iter().find(|(positional, _)| positional == name) {
+            Some((_, values)) => values,
+            None => panic!("No positional argument '{}' defined", name),
+        }
+    }
+
+    /// Returns the value of a positional argument, or `None` if it is absent.
+    pub fn positional(&self, name: &str) -> Option<String> {
+        self.positional_vals(name).first().cloned()
+    }
+
+    /// Returns all the values of a positional argument.
+    ///
+    /// Used for a `Multi` positional argument.
+    pub fn positionals(&self, name: &str) -> Vec<String> {
+        self.positional_vals(name).to_vec()
+    }
 
     /// Returns the matching string, a default, or `None`.
     ///
@@ -782,58 +2174,323 @@ impl Matches {
     /// present but no argument was provided, and the argument if the option was
     /// present and an argument was provided.
     pub fn opt_default(&self, nm: &str, def: &str) -> Option<String> {
-        match self.opt_val(nm) {
+        match self.opt_val(nm).unwrap_or_else(|_| undefined(nm)) {
             Some(Val(s)) => Some(s),
             Some(_) => Some(def.to_string()),
             None => None,
         }
     }
 
+    /// Returns the argument supplied to a matching option parsed as a `T`, or
+    /// `None` if the option was not present.
+    ///
+    /// Fails with `InvalidArgument` if the argument does not parse, and with
+    /// `UnrecognizedOption` if no option has the name.
+    pub fn opt_get<T: FromStr>(&self, nm: &str) -> result::Result<Option<T>, Fail> {
+        match self.try_opt_str(nm)? {
+            Some(s) => s.parse().map(Some).map_err(|_| InvalidArgument(nm.to_string(), s)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the argument supplied to a matching option parsed as a `T`, or
+    /// `def` if the option was not present.
+    ///
+    /// Fails with `InvalidArgument` if the argument does not parse, and with
+    /// `UnrecognizedOption` if no option has the name.
+    pub fn opt_get_default<T: FromStr>(&self, nm: &str, def: T) -> result::Result<T, Fail> {
+        self.opt_get(nm).map(|value| value.unwrap_or(def))
+    }
+}
+
+/// Panics for an accessor given the name `nm` of no option.
+fn undefined(nm: &str) -> ! {
+    panic!("No option '{}' defined", nm)
+}
+
+fn is_arg(arg: &str, prefix_chars: &[char]) -> bool {
+    let mut chars = arg.chars();
+    match chars.next() {
+        Some(first) => (first == '-' || prefix_chars.contains(&first)) && chars.next().is_some(),
+        None => false,
+    }
+}
+
+/// Replaces the `@file` arguments before any `--` by the arguments in `file`,
+/// recursively.
+fn expand_response_files(args: Vec<String>, depth: usize) -> result::Result<Vec<String>, Fail> {
+    let mut expanded = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args);
+            break;
+        }
+        let path = match arg.strip_prefix('@') {
+            Some(path) if !path.is_empty() => path,
+            _ => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        let fail = |reason: String| ResponseFile(path.to_string(), reason);
+        if depth == MAX_RESPONSE_FILE_DEPTH {
+            return Err(fail("nested too deeply".to_string()));
+        }
+        let contents = fs::read_to_string(path).map_err(|e| fail(e.to_string()))?;
+        let file_args = split_command_line(&contents).map_err(|e| fail(e.to_string()))?;
+        let file_args = expand_response_files(file_args, depth + 1)?;
+        // An `--` in the file ends the options for the rest of the command line.
+        let ends_options = file_args.iter().any(|arg| arg == "--");
+        expanded.extend(file_args);
+        if ends_options {
+            expanded.extend(args);
+            break;
+        }
+    }
+    Ok(expanded)
+}
+
+/// Splits `line` into arguments at unquoted whitespace, following the shell:
+/// single quotes keep their content as is, double quotes keep it except for
+/// `\"` and `\\`, and a backslash elsewhere escapes the next character.
+fn split_command_line(line: &str) -> result::Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    // The argument being built, `None` between arguments.
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                args.extend(arg.take());
+            }
+            '\'' => {
+                let current = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            }
+            '"' => {
+                let current = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err("unterminated quote"),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                // A backslash before a line break joins the lines.
+                Some('\n') => {}
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash"),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// Finds the long option for which `prefix` is an unambiguous abbreviation.
+/// An unknown prefix is returned as is, to be reported as unrecognized.
+fn expand_abbreviation(opts: &[Opt], prefix: &str) -> result::Result<Name, Fail> {
+    let candidates: Vec<&String> = opts.iter().filter_map(|opt| {
+        match opt.name {
+            Long(ref name) if !prefix.is_empty() && name.starts_with(prefix) => Some(name),
+            _ => None,
+        }
+    }).collect();
+    match candidates.len() {
+        0 => Ok(Name::from_str(prefix)),
+        1 => Ok(Long(candidates[0].clone())),
+        _ => Err(AmbiguousOption(prefix.to_string(),
+                                 candidates.into_iter().cloned().collect())),
+    }
+}
+
+/// Finds the long option closest to an unrecognized name, within a few typos.
+fn suggest(opts: &[Opt], name: &str) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).clamp(1, 3);
+    opts.iter()
+        .filter_map(|opt| {
+            match opt.name {
+                Long(ref long) => Some((edit_distance(name, long), long)),
+                Short(_) => None,
+            }
+        })
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, long)| long.clone())
+}
+
+/// Returns the Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns an error message for each name of `grp` already used by an `earlier` option.
+fn name_conflicts(earlier: &[OptGroup], grp: &OptGroup) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    if !grp.short_name.is_empty() && earlier.iter().any(|other| other.short_name == grp.short_name) {
+        conflicts.push(format!("option '-{}' is defined more than once", grp.short_name));
+    }
+    if !grp.long_name.is_empty() && earlier.iter().any(|other| other.long_name == grp.long_name) {
+        conflicts.push(format!("option '--{}' is defined more than once", grp.long_name));
+    }
+    conflicts
 }
 
-fn is_arg(arg: &str) -> bool {
-    arg.as_bytes().get(0) == Some(&b'-') && arg.len() > 1
+/// The name the program was invoked with, without its directory.
+fn program_name() -> String {
+    env::args_os().next()
+        .as_ref()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
 }
 
-fn find_opt(opts: &[Opt], nm: &Name) -> Option<usize> {
-    // Search main options.
-    let pos = opts.iter().position(|opt| &opt.name == nm);
-    if pos.is_some() {
-        return pos
+/// Finds the option group having `nm` as its short or long name.
+fn find_grp(grps: &[OptGroup], nm: &str) -> Option<usize> {
+    if nm.is_empty() {
+        return None;
     }
+    grps.iter().position(|grp| grp.short_name == nm || grp.long_name == nm)
+}
 
-    // Search in aliases.
-    for candidate in opts.iter() {
-        if candidate.aliases.iter().any(|opt| &opt.name == nm) {
-            return opts.iter().position(|opt| opt.name == candidate.name);
+/// Maps the names and aliases of `opts` to their indices, so that each
+/// argument is looked up in constant time.
+///
+/// Names take precedence over aliases, then earlier options over later ones.
+fn index_names(opts: &[Opt]) -> HashMap<Name, usize> {
+    let mut names = HashMap::with_capacity(opts.len() * 2);
+    for (id, opt) in opts.iter().enumerate().rev() {
+        for alias in &opt.aliases {
+            names.insert(alias.name.clone(), id);
         }
     }
+    for (id, opt) in opts.iter().enumerate().rev() {
+        names.insert(opt.name.clone(), id);
+    }
+    names
+}
 
-    None
+fn find_opt(names: &HashMap<Name, usize>, nm: &Name) -> Option<usize> {
+    names.get(nm).cloned()
 }
 
-impl fmt::Display for Fail {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Fail {
+    /// Formats the message of this failure with the translations of
+    /// `messages`, keyed by the English templates of the `Display` output,
+    /// e.g. `"Required option '{}' missing"`.
+    ///
+    /// The reasons of `ResponseFile` and `InvalidCommandLine` are translated
+    /// too, but the message of `InvalidValue` is kept as is.
+    pub fn localized(&self, messages: &Messages) -> String {
         match *self {
             ArgumentMissing(ref nm) => {
-                write!(f, "Argument to option '{}' missing", *nm)
+                messages.format("Argument to option '{}' missing", &[nm])
             }
             UnrecognizedOption(ref nm) => {
-                write!(f, "Unrecognized option: '{}'", *nm)
+                messages.format("Unrecognized option: '{}'", &[nm])
             }
             OptionMissing(ref nm) => {
-                write!(f, "Required option '{}' missing", *nm)
+                messages.format("Required option '{}' missing", &[nm])
             }
             OptionDuplicated(ref nm) => {
-                write!(f, "Option '{}' given more than once", *nm)
+                messages.format("Option '{}' given more than once", &[nm])
             }
             UnexpectedArgument(ref nm) => {
-                write!(f, "Option '{}' does not take an argument", *nm)
+                messages.format("Option '{}' does not take an argument", &[nm])
+            }
+            InvalidArgument(ref nm, ref value) => {
+                messages.format("Invalid argument '{}' to option '{}'", &[value, nm])
+            }
+            OptionRequires(ref nm, ref required) => {
+                messages.format("Option '{}' requires option '{}'", &[nm, required])
+            }
+            RequiredUnless(ref nm, ref other) => {
+                messages.format("Required option '{}' missing (unless '{}' is given)", &[nm, other])
+            }
+            RequiredIf(ref nm, ref other, ref value) => {
+                messages.format("Option '{}' is required when option '{}' is '{}'", &[nm, other, value])
+            }
+            InvalidChoice(ref nm, ref value, ref choices) => {
+                messages.format("Invalid argument '{}' to option '{}' (expected one of: {})",
+                                &[value, nm, &choices.join(", ")])
+            }
+            AmbiguousOption(ref nm, ref candidates) => {
+                messages.format("Option '{}' is ambiguous (could be: {})", &[nm, &candidates.join(", ")])
+            }
+            InvalidValue { ref option, ref value, ref message } => {
+                messages.format("Invalid value '{}' for option '{}': {}", &[value, option, message])
+            }
+            PositionalMissing(ref name) => {
+                messages.format("Required argument '{}' missing", &[name])
+            }
+            UnexpectedPositional(ref value) => {
+                messages.format("Unexpected argument: '{}'", &[value])
+            }
+            NoValue(ref nm) => {
+                messages.format("Option '{}' does not take a value", &[nm])
+            }
+            ResponseFile(ref path, ref reason) => {
+                messages.format("Invalid response file '{}': {}", &[path, messages.get(reason)])
+            }
+            InvalidCommandLine(ref reason) => {
+                messages.format("Invalid command line: {}", &[messages.get(reason)])
             }
         }
     }
 }
 
+impl fmt::Display for Fail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.localized(&Messages::default()))
+    }
+}
+
+/// Formats the name of an option as given on the command line, preferring
+/// the long name, e.g. `--help`.
+fn format_name(grp: &OptGroup) -> String {
+    if grp.long_name.is_empty() {
+        format!("-{}", grp.short_name)
+    } else {
+        format!("--{}", grp.long_name)
+    }
+}
+
+fn format_positional(positional: &Positional) -> String {
+    match positional.occur {
+        Req => positional.name.clone(),
+        Optional => format!("[{}]", positional.name),
+        Multi => format!("[{}]..", positional.name),
+    }
+}
+
 fn format_option(opt: &OptGroup) -> String {
     let mut line = String::new();
 
@@ -892,7 +2549,7 @@ enum LengthLimit {
 
 
 /// Splits a string into substrings with possibly internal whitespace,
-/// each of them at most `lim` bytes long, if possible. The substrings
+/// each of them at most `lim` columns wide, if possible. The substrings
 /// have leading and trailing whitespace removed, and are only cut at
 /// whitespace boundaries.
 ///
@@ -912,20 +2569,38 @@ fn each_split_within<'a, F>(ss: &'a str, lim: usize, mut it: F)
     let mut cont = true;
 
     // if the limit is larger than the string, lower it to save cycles
+    // (a string is never wider than its length in bytes)
     if lim >= fake_i {
         lim = fake_i;
     }
 
+    // Columns before each char boundary, so that the limit applies to the
+    // display width. Whitespace counts as one column.
+    let char_columns = |c: char| if c.is_whitespace() { 1 } else { term::char_width(c) };
+    let mut columns_before = vec![0; ss.len() + 1];
+    let mut total = 0;
+    for (i, c) in ss.char_indices() {
+        columns_before[i] = total;
+        total += char_columns(c);
+    }
+    columns_before[ss.len()] = total;
+    // Width of the substring from `start` up to and including `c` at `i`,
+    // where `i` may point past the end for the trailing whitespace.
+    let width = |start: usize, i: usize, c: char| {
+        let before = if i < ss.len() { columns_before[i] } else { total + i - ss.len() };
+        before - columns_before[start] + char_columns(c)
+    };
+
     let mut machine = |cont: &mut bool, state: &mut SplitWithinState, (i, c): (usize, char)| {
         let whitespace = if c.is_whitespace() { Ws }       else { Cr };
-        let limit      = if (i - slice_start + 1) <= lim  { UnderLim } else { OverLim };
+        let limit      = if width(slice_start, i, c) <= lim  { UnderLim } else { OverLim };
 
         *state = match (*state, whitespace, limit) {
             (A, Ws, _)        => { A }
             (A, Cr, _)        => { slice_start = i; last_start = i; B }
 
             (B, Cr, UnderLim) => { B }
-            (B, Cr, OverLim)  if (i - last_start + 1) > lim => {
+            (B, Cr, OverLim)  if width(last_start, i, c) > lim => {
                 // A single word has gone over the limit.  In this
                 // case we just accept that the word will be too long.
                 B
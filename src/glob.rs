@@ -0,0 +1,375 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Shell-style wildcard matching.
+//!
+//! Supported syntax:
+//!
+//! * `?` matches any single character except `/`.
+//! * `*` matches any sequence of characters except `/`.
+//! * `**` matches any sequence of characters, including `/`. As a whole path component (`a/**/b`), it
+//!   also matches zero directories.
+//! * `[abc]`, `[a-z]` match one of the characters of the class; `[!a-z]` or `[^a-z]` negate it. To
+//!   include `]` in a class, put it first.
+//! * `\` escapes the next character.
+//!
+//! With `MatchOptions::require_literal_separator` set to false, `*` and `?` also match `/`.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The kind of error found in a pattern.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// A `[` is not closed.
+    UnclosedClass,
+    /// A class range has its start greater than its end, like `[z-a]`.
+    InvalidRange,
+    /// The pattern ends with a `\`.
+    TrailingEscape,
+}
+
+/// A pattern compilation error along with the character position where it occurred.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternError {
+    /// The kind of error.
+    pub kind: ErrorKind,
+    /// The position, in characters, of the error in the pattern.
+    pub position: usize,
+}
+
+impl Display for PatternError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::UnclosedClass => write!(formatter, "unclosed character class")?,
+            ErrorKind::InvalidRange => write!(formatter, "invalid character range")?,
+            ErrorKind::TrailingEscape => write!(formatter, "trailing escape character")?,
+        }
+        write!(formatter, " at position {}", self.position)
+    }
+}
+
+impl error::Error for PatternError {
+}
+
+/// Options controlling how a pattern matches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MatchOptions {
+    /// Whether letters must match with the same case.
+    pub case_sensitive: bool,
+    /// Whether `/` can only be matched by a literal `/` or `**`.
+    pub require_literal_separator: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            require_literal_separator: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    AnyChar,
+    AnySequence,
+    AnyRecursive,
+    /// `**/`: zero or more directories.
+    AnyDirectories,
+    Char(char),
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// A compiled glob pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    source: String,
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    /// Compiles a pattern.
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = vec![];
+        let mut index = 0;
+        while index < chars.len() {
+            match chars[index] {
+                '?' => tokens.push(Token::AnyChar),
+                '*' => {
+                    if chars.get(index + 1) == Some(&'*') {
+                        let component_start = index == 0 || chars[index - 1] == '/';
+                        index += 1;
+                        if component_start && chars.get(index + 1) == Some(&'/') {
+                            index += 1;
+                            tokens.push(Token::AnyDirectories);
+                        }
+                        else {
+                            tokens.push(Token::AnyRecursive);
+                        }
+                    }
+                    else if tokens.last() != Some(&Token::AnySequence) {
+                        tokens.push(Token::AnySequence);
+                    }
+                },
+                '[' => {
+                    let (token, end) = parse_class(&chars, index)?;
+                    tokens.push(token);
+                    index = end;
+                },
+                '\\' => {
+                    index += 1;
+                    match chars.get(index) {
+                        Some(&character) => tokens.push(Token::Char(character)),
+                        None => return Err(PatternError {
+                            kind: ErrorKind::TrailingEscape,
+                            position: index - 1,
+                        }),
+                    }
+                },
+                character => tokens.push(Token::Char(character)),
+            }
+            index += 1;
+        }
+        Ok(Self {
+            source: pattern.to_string(),
+            tokens,
+        })
+    }
+
+    /// Returns the source of the pattern.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns true if the whole `text` matches the pattern, with the default options.
+    pub fn matches(&self, text: &str) -> bool {
+        self.matches_with(text, MatchOptions::default())
+    }
+
+    /// Returns true if the whole path matches the pattern, with the default options. Paths that
+    /// are not valid UTF-8 are matched lossily.
+    pub fn matches_path<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.matches_with(&path.as_ref().to_string_lossy(), MatchOptions::default())
+    }
+
+    /// Returns true if the whole `text` matches the pattern.
+    pub fn matches_with(&self, text: &str, options: MatchOptions) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+        // matched[index] tells whether tokens[token..] matches chars[index..], computed from the last
+        // token to the first.
+        let mut next = vec![false; len + 1];
+        next[len] = true;
+        let mut matched = vec![false; len + 1];
+        for token in self.tokens.iter().rev() {
+            // Whether a `/` at or after the current index is followed by a match of the next tokens.
+            let mut slash_match = false;
+            for index in (0..=len).rev() {
+                let current = chars.get(index).cloned();
+                let is_separator = current == Some('/');
+                matched[index] =
+                    match *token {
+                        Token::AnyChar =>
+                            current.is_some() && !(is_separator && options.require_literal_separator) && next[index + 1],
+                        Token::AnySequence =>
+                            next[index] ||
+                                (current.is_some() && !(is_separator && options.require_literal_separator) &&
+                                 matched[index + 1]),
+                        Token::AnyRecursive => next[index] || (current.is_some() && matched[index + 1]),
+                        Token::AnyDirectories => {
+                            slash_match = slash_match || (is_separator && next[index + 1]);
+                            next[index] || slash_match
+                        },
+                        Token::Char(expected) =>
+                            current.map_or(false, |character| chars_eq(character, expected, options)) && next[index + 1],
+                        Token::Class { negated, ref ranges } =>
+                            current.map_or(false, |character| {
+                                !(is_separator && options.require_literal_separator) &&
+                                    class_matches(ranges, character, options) != negated
+                            }) && next[index + 1],
+                    };
+            }
+            ::std::mem::swap(&mut matched, &mut next);
+        }
+        next[0]
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.source)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::new(pattern)
+    }
+}
+
+/// Returns true if the whole `text` matches `pattern`, with the default options.
+///
+/// Use a `Pattern` to match a pattern several times.
+pub fn matches(pattern: &str, text: &str) -> Result<bool, PatternError> {
+    Ok(Pattern::new(pattern)?.matches(text))
+}
+
+fn chars_eq(character: char, expected: char, options: MatchOptions) -> bool {
+    if options.case_sensitive {
+        character == expected
+    }
+    else {
+        character.to_lowercase().eq(expected.to_lowercase())
+    }
+}
+
+fn class_matches(ranges: &[(char, char)], character: char, options: MatchOptions) -> bool {
+    let in_ranges = |character: char| ranges.iter().any(|&(start, end)| start <= character && character <= end);
+    if options.case_sensitive {
+        in_ranges(character)
+    }
+    else {
+        in_ranges(character) || character.to_lowercase().chain(character.to_uppercase()).any(in_ranges)
+    }
+}
+
+/// Parses the class starting at `chars[start]` (a `[`), returning the index of its `]`.
+fn parse_class(chars: &[char], start: usize) -> Result<(Token, usize), PatternError> {
+    let unclosed = PatternError {
+        kind: ErrorKind::UnclosedClass,
+        position: start,
+    };
+    let mut index = start + 1;
+    let negated = chars.get(index) == Some(&'!') || chars.get(index) == Some(&'^');
+    if negated {
+        index += 1;
+    }
+    let mut ranges = vec![];
+    let mut first = true;
+    loop {
+        let character =
+            match chars.get(index) {
+                Some(&']') if !first => break,
+                Some(&'\\') => {
+                    index += 1;
+                    *chars.get(index).ok_or_else(|| unclosed.clone())?
+                },
+                Some(&character) => character,
+                None => return Err(unclosed),
+            };
+        first = false;
+        if chars.get(index + 1) == Some(&'-') && chars.get(index + 2).map_or(false, |&end| end != ']') {
+            let end = chars[index + 2];
+            if end < character {
+                return Err(PatternError {
+                    kind: ErrorKind::InvalidRange,
+                    position: index,
+                });
+            }
+            ranges.push((character, end));
+            index += 3;
+        }
+        else {
+            ranges.push((character, character));
+            index += 1;
+        }
+    }
+    Ok((Token::Class { negated, ranges }, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorKind, MatchOptions, Pattern, PatternError, matches};
+
+    fn check(pattern: &str, text: &str) -> bool {
+        matches(pattern, text).expect("pattern")
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(check("", ""));
+        assert!(!check("", "a"));
+        assert!(check("*.rs", "lib.rs"));
+        assert!(check("*.rs", ".rs"));
+        assert!(!check("*.rs", "src/lib.rs"));
+        assert!(check("src/*.rs", "src/lib.rs"));
+        assert!(check("?at", "cat"));
+        assert!(!check("?at", "at"));
+        assert!(!check("a?b", "a/b"));
+        assert!(check("a*b*c", "aXbYbZc"));
+        assert!(!check("a*b*c", "aXbYbZ"));
+        assert!(check(r"\*", "*"));
+        assert!(!check(r"\*", "a"));
+        assert!(check("héllo*", "héllo wörld"));
+    }
+
+    #[test]
+    fn recursive() {
+        assert!(check("**", "a/b/c"));
+        assert!(check("**/*.rs", "lib.rs"));
+        assert!(check("**/*.rs", "src/aio/net.rs"));
+        assert!(check("src/**/net.rs", "src/net.rs"));
+        assert!(check("src/**/net.rs", "src/aio/net.rs"));
+        assert!(check("src/**/net.rs", "src/a/b/net.rs"));
+        assert!(!check("src/**/net.rs", "src/aionet.rs"));
+        assert!(check("/api/**", "/api/users/1"));
+        assert!(check("a**z", "a/b/z"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(check("[abc]", "b"));
+        assert!(!check("[abc]", "d"));
+        assert!(check("[a-z][0-9]", "x7"));
+        assert!(check("[!a-z]", "X"));
+        assert!(!check("[^a-z]", "x"));
+        assert!(check("[]]", "]"));
+        assert!(check("[a-]", "-"));
+        assert!(!check("[a-z]", "/"));
+        assert_eq!(Pattern::new("[abc"), Err(PatternError { kind: ErrorKind::UnclosedClass, position: 0 }));
+        assert_eq!(Pattern::new("a[z-a]"), Err(PatternError { kind: ErrorKind::InvalidRange, position: 2 }));
+        assert_eq!(Pattern::new("ab\\"), Err(PatternError { kind: ErrorKind::TrailingEscape, position: 2 }));
+    }
+
+    #[test]
+    fn options() {
+        let pattern: Pattern = "*.TXT".parse().expect("pattern");
+        assert!(!pattern.matches("notes.txt"));
+        assert!(!pattern.matches_path("dir/notes.TXT"));
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: false,
+        };
+        assert!(pattern.matches_with("notes.txt", options));
+        assert!(pattern.matches_with("dir/notes.txt", options));
+        assert!(Pattern::new("[A-C]").expect("pattern").matches_with("b", options));
+        assert_eq!(pattern.to_string(), "*.TXT");
+    }
+}
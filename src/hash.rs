@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Non-cryptographic hash functions implementing `std::hash::Hasher`.
+//!
+//! * FNV-1a is very fast on short keys, but offers no protection against collisions crafted by an
+//!   attacker: only use it for maps whose keys are not controlled by clients.
+//! * SipHash-1-3 is keyed: with a random key, clients cannot predict collisions, which makes it
+//!   suitable for maps indexed by request data.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::io::{self, Read};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hasher.
+#[derive(Clone, Copy, Debug)]
+pub struct FnvHasher {
+    hash: u64,
+}
+
+impl FnvHasher {
+    /// Creates a new hasher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Builds `FnvHasher`s for hash maps.
+pub type FnvBuildHasher = BuildHasherDefault<FnvHasher>;
+
+/// A `HashMap` using FNV-1a.
+pub type FnvHashMap<K, V> = HashMap<K, V, FnvBuildHasher>;
+
+/// A `HashSet` using FNV-1a.
+pub type FnvHashSet<T> = HashSet<T, FnvBuildHasher>;
+
+/// Hashes a byte slice with FNV-1a.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// SipHash state, generic over the number of compression and finalization rounds.
+#[derive(Clone, Copy, Debug)]
+struct SipState {
+    length: usize,
+    tail: u64,
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+}
+
+impl SipState {
+    fn new(key0: u64, key1: u64) -> Self {
+        Self {
+            length: 0,
+            tail: 0,
+            v0: key0 ^ 0x736f_6d65_7073_6575,
+            v1: key1 ^ 0x646f_7261_6e64_6f6d,
+            v2: key0 ^ 0x6c79_6765_6e65_7261,
+            v3: key1 ^ 0x7465_6462_7974_6573,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13);
+        self.v1 ^= self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16);
+        self.v3 ^= self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21);
+        self.v3 ^= self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17);
+        self.v1 ^= self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64, rounds: usize) {
+        self.v3 ^= word;
+        for _ in 0..rounds {
+            self.round();
+        }
+        self.v0 ^= word;
+    }
+
+    fn write(&mut self, bytes: &[u8], rounds: usize) {
+        let mut bytes = bytes;
+        let filled = self.length % 8;
+        self.length += bytes.len();
+        if filled != 0 {
+            // Complete the partial word from the previous write.
+            let needed = (8 - filled).min(bytes.len());
+            for (index, &byte) in bytes[..needed].iter().enumerate() {
+                self.tail |= u64::from(byte) << (8 * (filled + index));
+            }
+            bytes = &bytes[needed..];
+            if filled + needed < 8 {
+                return;
+            }
+            let word = self.tail;
+            self.compress(word, rounds);
+            self.tail = 0;
+        }
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0; 8];
+            word.copy_from_slice(chunk);
+            self.compress(u64::from_le_bytes(word), rounds);
+        }
+        for (index, &byte) in chunks.remainder().iter().enumerate() {
+            self.tail |= u64::from(byte) << (8 * index);
+        }
+    }
+
+    fn finish(&self, compression_rounds: usize, finalization_rounds: usize) -> u64 {
+        let mut state = *self;
+        let word = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(word, compression_rounds);
+        state.v2 ^= 0xff;
+        for _ in 0..finalization_rounds {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// SipHash-1-3 keyed hasher.
+#[derive(Clone, Copy, Debug)]
+pub struct SipHasher13 {
+    state: SipState,
+}
+
+impl SipHasher13 {
+    /// Creates a hasher with the specified 128-bit key.
+    pub fn new_with_keys(key0: u64, key1: u64) -> Self {
+        Self {
+            state: SipState::new(key0, key1),
+        }
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn finish(&self) -> u64 {
+        self.state.finish(1, 3)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.state.write(bytes, 1);
+    }
+}
+
+/// Builds `SipHasher13`s sharing the same key, for hash maps.
+#[derive(Clone, Copy, Debug)]
+pub struct SipBuildHasher {
+    key0: u64,
+    key1: u64,
+}
+
+impl SipBuildHasher {
+    /// Creates a builder with the specified 128-bit key.
+    pub fn new(key0: u64, key1: u64) -> Self {
+        Self {
+            key0,
+            key1,
+        }
+    }
+
+    /// Creates a builder with a key read from `/dev/urandom`.
+    pub fn random() -> io::Result<Self> {
+        let mut bytes = [0; 16];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        let mut key0 = [0; 8];
+        let mut key1 = [0; 8];
+        key0.copy_from_slice(&bytes[..8]);
+        key1.copy_from_slice(&bytes[8..]);
+        Ok(Self::new(u64::from_le_bytes(key0), u64::from_le_bytes(key1)))
+    }
+}
+
+impl BuildHasher for SipBuildHasher {
+    type Hasher = SipHasher13;
+
+    fn build_hasher(&self) -> SipHasher13 {
+        SipHasher13::new_with_keys(self.key0, self.key1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, Hasher};
+
+    use super::{FnvHashMap, SipBuildHasher, SipHasher13, SipState, fnv1a};
+
+    #[test]
+    fn fnv() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+
+        let mut map = FnvHashMap::default();
+        map.insert("key", 1);
+        assert_eq!(map.get("key"), Some(&1));
+    }
+
+    fn siphash24(key0: u64, key1: u64, chunks: &[&[u8]]) -> u64 {
+        let mut state = SipState::new(key0, key1);
+        for chunk in chunks {
+            state.write(chunk, 2);
+        }
+        state.finish(2, 4)
+    }
+
+    #[test]
+    fn siphash_reference_vectors() {
+        // Vectors from the SipHash-2-4 reference implementation, with the key 00 01 .. 0f.
+        let key0 = 0x0706_0504_0302_0100;
+        let key1 = 0x0f0e_0d0c_0b0a_0908;
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(key0, key1, &[]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(siphash24(key0, key1, &[&message[..1]]), 0x74f8_39c5_93dc_67fd);
+        assert_eq!(siphash24(key0, key1, &[&message]), 0xa129_ca61_49be_45e5);
+        // Incremental writes give the same result.
+        assert_eq!(siphash24(key0, key1, &[&message[..3], &message[3..4], &message[4..13], &message[13..]]),
+            0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn siphash13() {
+        let mut hasher = SipHasher13::new_with_keys(1, 2);
+        hasher.write(b"hello world");
+        let hash = hasher.finish();
+        let mut other = SipBuildHasher::new(1, 2).build_hasher();
+        other.write(b"hello ");
+        other.write(b"world");
+        assert_eq!(other.finish(), hash);
+        let mut other = SipHasher13::new_with_keys(1, 3);
+        other.write(b"hello world");
+        assert_ne!(other.finish(), hash);
+        assert!(SipBuildHasher::random().is_ok());
+    }
+}
@@ -0,0 +1,756 @@
+//! HTTP/1.x message head types and a zero-copy, incremental head parser.
+//!
+//! Data usually arrives in arbitrary chunks from `TcpConnectionNotify::received()`: feed them to a
+//! `HeadReader` until it reports a complete head, then parse the head without copying it.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::str;
+use std::sync::OnceLock;
+
+use intern::Interner;
+use parse::Cursor;
+
+/// Default maximum size of a message head accepted by `HeadReader`.
+pub const DEFAULT_MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Maximum number of headers accepted in a message head.
+const MAX_HEADERS: usize = 128;
+
+/// An HTTP request method.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Method {
+    Connect,
+    Delete,
+    Get,
+    Head,
+    Options,
+    Patch,
+    Post,
+    Put,
+    Trace,
+}
+
+impl Method {
+    /// Parses a method from its case-sensitive name.
+    pub fn from_bytes(method: &[u8]) -> Option<Method> {
+        let method =
+            match method {
+                b"CONNECT" => Method::Connect,
+                b"DELETE" => Method::Delete,
+                b"GET" => Method::Get,
+                b"HEAD" => Method::Head,
+                b"OPTIONS" => Method::Options,
+                b"PATCH" => Method::Patch,
+                b"POST" => Method::Post,
+                b"PUT" => Method::Put,
+                b"TRACE" => Method::Trace,
+                _ => return None,
+            };
+        Some(method)
+    }
+
+    /// Returns the name of the method.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Method::Connect => "CONNECT",
+            Method::Delete => "DELETE",
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Trace => "TRACE",
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str(self.as_str())
+    }
+}
+
+/// An HTTP protocol version.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    /// Parses a version like `HTTP/1.1`.
+    pub fn from_bytes(version: &[u8]) -> Option<Version> {
+        match version {
+            b"HTTP/1.0" => Some(Version::Http10),
+            b"HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+
+    /// Returns the version as it appears on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str(self.as_str())
+    }
+}
+
+/// An HTTP status code.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StatusCode(pub u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const ACCEPTED: StatusCode = StatusCode(202);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const CONFLICT: StatusCode = StatusCode(409);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+
+    /// Returns the numeric value of the status code.
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns the standard reason phrase of the status code, if known.
+    pub fn canonical_reason(&self) -> Option<&'static str> {
+        let reason =
+            match self.0 {
+                100 => "Continue",
+                101 => "Switching Protocols",
+                200 => "OK",
+                201 => "Created",
+                202 => "Accepted",
+                204 => "No Content",
+                206 => "Partial Content",
+                301 => "Moved Permanently",
+                302 => "Found",
+                303 => "See Other",
+                304 => "Not Modified",
+                307 => "Temporary Redirect",
+                308 => "Permanent Redirect",
+                400 => "Bad Request",
+                401 => "Unauthorized",
+                403 => "Forbidden",
+                404 => "Not Found",
+                405 => "Method Not Allowed",
+                408 => "Request Timeout",
+                409 => "Conflict",
+                413 => "Payload Too Large",
+                429 => "Too Many Requests",
+                431 => "Request Header Fields Too Large",
+                500 => "Internal Server Error",
+                501 => "Not Implemented",
+                502 => "Bad Gateway",
+                503 => "Service Unavailable",
+                504 => "Gateway Timeout",
+                _ => return None,
+            };
+        Some(reason)
+    }
+
+    /// Returns true for 1xx status codes.
+    pub fn is_informational(&self) -> bool {
+        self.0 >= 100 && self.0 < 200
+    }
+
+    /// Returns true for 2xx status codes.
+    pub fn is_success(&self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// Returns true for 3xx status codes.
+    pub fn is_redirection(&self) -> bool {
+        self.0 >= 300 && self.0 < 400
+    }
+
+    /// Returns true for 4xx status codes.
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
+
+    /// Returns true for 5xx status codes.
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500 && self.0 < 600
+    }
+}
+
+impl Display for StatusCode {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{} {}", self.0, self.canonical_reason().unwrap_or("Unknown"))
+    }
+}
+
+/// A header borrowed from the buffer it was parsed from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Header<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+/// Returns the value of the first header with the specified name, ignoring case.
+fn find_header<'a>(headers: &[Header<'a>], name: &str) -> Option<&'a [u8]> {
+    headers.iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value)
+}
+
+/// The request line and headers of a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RequestHead<'a> {
+    pub method: Method,
+    /// The request target, e.g. `/index.html?page=2`.
+    pub target: &'a str,
+    pub version: Version,
+    pub headers: Vec<Header<'a>>,
+}
+
+impl<'a> RequestHead<'a> {
+    /// Returns the value of the first header with the specified name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a [u8]> {
+        find_header(&self.headers, name)
+    }
+
+    /// Returns the path of the target, without the query string.
+    pub fn path(&self) -> &'a str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    /// Returns the query string of the target, if any.
+    pub fn query(&self) -> Option<&'a str> {
+        self.target.splitn(2, '?').nth(1)
+    }
+}
+
+/// The status line and headers of a response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResponseHead<'a> {
+    pub version: Version,
+    pub status: StatusCode,
+    pub reason: &'a str,
+    pub headers: Vec<Header<'a>>,
+}
+
+impl<'a> ResponseHead<'a> {
+    /// Returns the value of the first header with the specified name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a [u8]> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// Common header names, in their usual and lowercase spellings, which `HeaderMap` does not copy.
+const COMMON_HEADERS: [&str; 24] = [
+    "Accept", "Accept-Encoding", "Accept-Language", "Authorization", "Cache-Control", "Connection",
+    "Content-Encoding", "Content-Length", "Content-Type", "Cookie", "Date", "ETag", "Expires", "Host",
+    "If-Modified-Since", "If-None-Match", "Last-Modified", "Location", "Referer", "Server",
+    "Set-Cookie", "Transfer-Encoding", "User-Agent", "Vary",
+];
+
+fn common_headers() -> &'static Interner {
+    static NAMES: OnceLock<Interner> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let names = Interner::new();
+        for name in COMMON_HEADERS.iter() {
+            names.intern(name);
+            names.intern(&name.to_ascii_lowercase());
+        }
+        names
+    })
+}
+
+/// A header name, borrowed from the interned common names when possible.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum HeaderName {
+    Common(&'static str),
+    Other(String),
+}
+
+impl HeaderName {
+    fn new(name: &str) -> Self {
+        let names = common_headers();
+        match names.get(name).and_then(|symbol| names.resolve(symbol)) {
+            Some(name) => HeaderName::Common(name),
+            None => HeaderName::Other(name.to_string()),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match *self {
+            HeaderName::Common(name) => name,
+            HeaderName::Other(ref name) => name,
+        }
+    }
+}
+
+/// An owned collection of headers with case-insensitive names, preserving insertion order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(HeaderName, String)>,
+}
+
+impl HeaderMap {
+    /// Creates an empty header map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value to the header, keeping the existing values.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((HeaderName::new(name), value.to_string()));
+    }
+
+    /// Sets the value of the header, replacing all existing values.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Returns the first value of the header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|entry| entry.0.as_str().eq_ignore_ascii_case(name))
+            .map(|entry| entry.1.as_str())
+    }
+
+    /// Returns all the values of the header.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.entries.iter()
+            .filter(move |entry| entry.0.as_str().eq_ignore_ascii_case(name))
+            .map(|entry| entry.1.as_str())
+    }
+
+    /// Returns true if the header is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Removes all the values of the header, returning whether any was present.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|&(ref entry_name, _)| !entry_name.as_str().eq_ignore_ascii_case(name));
+        self.entries.len() != len
+    }
+
+    /// Returns an iterator over the names and values, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.entries.iter().map(|entry| (entry.0.as_str(), entry.1.as_str()))
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> From<&'a [Header<'a>]> for HeaderMap {
+    /// Copies parsed headers, replacing invalid UTF-8 in the values.
+    fn from(headers: &'a [Header<'a>]) -> Self {
+        let mut map = HeaderMap::new();
+        for header in headers {
+            map.append(header.name, &String::from_utf8_lossy(header.value));
+        }
+        map
+    }
+}
+
+/// The reason why a message head could not be parsed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    HeadTooLarge,
+    InvalidHeader,
+    InvalidMethod,
+    InvalidStatus,
+    InvalidTarget,
+    InvalidVersion,
+    TooManyHeaders,
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                Error::HeadTooLarge => "message head too large",
+                Error::InvalidHeader => "invalid header",
+                Error::InvalidMethod => "invalid method",
+                Error::InvalidStatus => "invalid status",
+                Error::InvalidTarget => "invalid request target",
+                Error::InvalidVersion => "invalid HTTP version",
+                Error::TooManyHeaders => "too many headers",
+            };
+        formatter.write_str(message)
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// Result of parsing a buffer which may not contain the whole message head yet.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Status<T> {
+    /// The head was parsed and spans the specified number of bytes, including the empty line.
+    Complete(T, usize),
+    /// More data is needed.
+    Partial,
+}
+
+/// Returns the length of the head if `buffer` contains the empty line ending it.
+pub fn head_len(buffer: &[u8]) -> Option<usize> {
+    find_head_end(buffer, 0)
+}
+
+fn find_head_end(buffer: &[u8], start: usize) -> Option<usize> {
+    let mut index = start;
+    while index < buffer.len() {
+        if buffer[index] == b'\n' {
+            if buffer[index + 1..].starts_with(b"\n") {
+                return Some(index + 2);
+            }
+            if buffer[index + 1..].starts_with(b"\r\n") {
+                return Some(index + 3);
+            }
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Parses a request head at the start of `buffer`.
+pub fn parse_request(buffer: &[u8]) -> Result<Status<RequestHead<'_>>, Error> {
+    let len =
+        match head_len(buffer) {
+            Some(len) => len,
+            None => return Ok(Status::Partial),
+        };
+    let mut lines = Lines::new(&buffer[..len]);
+    let request_line = lines.next().unwrap_or(b"");
+    let mut parts = request_line.split(|&byte| byte == b' ');
+    let method = parts.next().and_then(Method::from_bytes).ok_or(Error::InvalidMethod)?;
+    let target = parts.next()
+        .filter(|target| !target.is_empty() && target.iter().all(|&byte| byte > b' ' && byte < 0x7F))
+        .and_then(|target| str::from_utf8(target).ok())
+        .ok_or(Error::InvalidTarget)?;
+    let version = parts.next().and_then(Version::from_bytes).ok_or(Error::InvalidVersion)?;
+    if parts.next().is_some() {
+        return Err(Error::InvalidVersion);
+    }
+    let headers = parse_headers(lines)?;
+    Ok(Status::Complete(RequestHead {
+        method,
+        target,
+        version,
+        headers,
+    }, len))
+}
+
+/// Parses a response head at the start of `buffer`.
+pub fn parse_response(buffer: &[u8]) -> Result<Status<ResponseHead<'_>>, Error> {
+    let len =
+        match head_len(buffer) {
+            Some(len) => len,
+            None => return Ok(Status::Partial),
+        };
+    let mut lines = Lines::new(&buffer[..len]);
+    let status_line = lines.next().unwrap_or(b"");
+    let mut parts = status_line.splitn(3, |&byte| byte == b' ');
+    let version = parts.next().and_then(Version::from_bytes).ok_or(Error::InvalidVersion)?;
+    let status = parts.next()
+        .filter(|status| status.len() == 3 && status.iter().all(u8::is_ascii_digit))
+        .and_then(|status| str::from_utf8(status).ok())
+        .and_then(|status| status.parse().ok())
+        .map(StatusCode)
+        .ok_or(Error::InvalidStatus)?;
+    let reason = str::from_utf8(parts.next().unwrap_or(b"")).map_err(|_| Error::InvalidStatus)?;
+    let headers = parse_headers(lines)?;
+    Ok(Status::Complete(ResponseHead {
+        version,
+        status,
+        reason,
+        headers,
+    }, len))
+}
+
+fn parse_headers(lines: Lines<'_>) -> Result<Vec<Header<'_>>, Error> {
+    let mut headers = vec![];
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(Error::TooManyHeaders);
+        }
+        let colon = line.iter().position(|&byte| byte == b':').ok_or(Error::InvalidHeader)?;
+        let name = &line[..colon];
+        if name.is_empty() || !name.iter().all(|&byte| is_token(byte)) {
+            return Err(Error::InvalidHeader);
+        }
+        let name = str::from_utf8(name).map_err(|_| Error::InvalidHeader)?;
+        headers.push(Header {
+            name,
+            value: trim(&line[colon + 1..]),
+        });
+    }
+    Ok(headers)
+}
+
+fn is_token(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+fn trim(mut value: &[u8]) -> &[u8] {
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = value.split_first() {
+        value = rest;
+    }
+    while let Some((&b' ', rest)) | Some((&b'\t', rest)) = value.split_last() {
+        value = rest;
+    }
+    value
+}
+
+/// Iterator over lines terminated by `\n` or `\r\n`, without the terminator.
+struct Lines<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            cursor: Cursor::new(buffer),
+        }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.cursor.is_empty() {
+            return None;
+        }
+        let line = self.cursor.take_until(b"\n")
+            .unwrap_or_else(|_| self.cursor.take_while(|_| true));
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+}
+
+/// Accumulates chunks of data until a complete message head is received.
+///
+/// The search for the end of the head resumes where the previous chunk left off, so feeding a head
+/// byte by byte stays linear.
+pub struct HeadReader {
+    buffer: Vec<u8>,
+    head_len: Option<usize>,
+    max_len: usize,
+    scanned: usize,
+}
+
+impl HeadReader {
+    /// Creates a reader accepting heads up to `DEFAULT_MAX_HEAD_LEN` bytes.
+    pub fn new() -> Self {
+        Self::with_max_len(DEFAULT_MAX_HEAD_LEN)
+    }
+
+    /// Creates a reader accepting heads up to `max_len` bytes.
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            buffer: vec![],
+            head_len: None,
+            max_len,
+            scanned: 0,
+        }
+    }
+
+    /// Appends a chunk of data, returning true once the whole head is buffered.
+    pub fn feed(&mut self, data: &[u8]) -> Result<bool, Error> {
+        self.buffer.extend_from_slice(data);
+        if self.head_len.is_none() {
+            // The terminator may straddle the previous chunk, so back up a little.
+            let start = self.scanned.saturating_sub(2);
+            self.head_len = find_head_end(&self.buffer, start);
+            self.scanned = self.buffer.len();
+            if self.head_len.is_none() && self.buffer.len() > self.max_len {
+                return Err(Error::HeadTooLarge);
+            }
+        }
+        Ok(self.head_len.is_some())
+    }
+
+    /// Returns true if the whole head is buffered.
+    pub fn is_complete(&self) -> bool {
+        self.head_len.is_some()
+    }
+
+    /// Parses the buffered head as a request.
+    pub fn request(&self) -> Result<Status<RequestHead<'_>>, Error> {
+        match self.head_len {
+            Some(len) => parse_request(&self.buffer[..len]),
+            None => Ok(Status::Partial),
+        }
+    }
+
+    /// Parses the buffered head as a response.
+    pub fn response(&self) -> Result<Status<ResponseHead<'_>>, Error> {
+        match self.head_len {
+            Some(len) => parse_response(&self.buffer[..len]),
+            None => Ok(Status::Partial),
+        }
+    }
+
+    /// Returns the bytes received after the head, i.e. the start of the body.
+    pub fn remaining(&self) -> &[u8] {
+        match self.head_len {
+            Some(len) => &self.buffer[len..],
+            None => &[],
+        }
+    }
+
+    /// Clears the reader for the next message, returning the bytes received after the head.
+    pub fn reset(&mut self) -> Vec<u8> {
+        let remaining = self.remaining().to_vec();
+        self.buffer.clear();
+        self.head_len = None;
+        self.scanned = 0;
+        remaining
+    }
+}
+
+impl Default for HeadReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Error,
+        HeadReader,
+        HeaderMap,
+        Method,
+        StatusCode,
+        Status,
+        Version,
+        parse_request,
+        parse_response,
+    };
+
+    #[test]
+    fn request() {
+        let data = b"GET /search?q=mini HTTP/1.1\r\nHost: example.com\r\nX-Empty:\r\nAccept:  */* \r\n\r\nbody";
+        match parse_request(data) {
+            Ok(Status::Complete(head, len)) => {
+                assert_eq!(len, data.len() - 4);
+                assert_eq!(head.method, Method::Get);
+                assert_eq!(head.path(), "/search");
+                assert_eq!(head.query(), Some("q=mini"));
+                assert_eq!(head.version, Version::Http11);
+                assert_eq!(head.header("host"), Some(&b"example.com"[..]));
+                assert_eq!(head.header("ACCEPT"), Some(&b"*/*"[..]));
+                assert_eq!(head.header("x-empty"), Some(&b""[..]));
+                assert_eq!(head.header("missing"), None);
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(parse_request(b"GET / HTTP/1.1\r\nHost: a\r\n"), Ok(Status::Partial));
+        assert_eq!(parse_request(b"BREW / HTTP/1.1\r\n\r\n"), Err(Error::InvalidMethod));
+        assert_eq!(parse_request(b"GET / HTTP/2\r\n\r\n"), Err(Error::InvalidVersion));
+        assert_eq!(parse_request(b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\n"), Err(Error::InvalidHeader));
+    }
+
+    #[test]
+    fn response() {
+        match parse_response(b"HTTP/1.0 404 Not Found\nContent-Length: 0\n\n") {
+            Ok(Status::Complete(head, _)) => {
+                assert_eq!(head.version, Version::Http10);
+                assert_eq!(head.status, StatusCode::NOT_FOUND);
+                assert!(head.status.is_client_error());
+                assert_eq!(head.reason, "Not Found");
+                assert_eq!(head.header("content-length"), Some(&b"0"[..]));
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(parse_response(b"HTTP/1.1 20 OK\r\n\r\n"), Err(Error::InvalidStatus));
+        assert_eq!(StatusCode::OK.to_string(), "200 OK");
+    }
+
+    #[test]
+    fn incremental() {
+        let data = b"POST /submit HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = HeadReader::new();
+        let mut complete = false;
+        for chunk in data.chunks(3) {
+            complete = reader.feed(chunk).expect("feed");
+            if complete {
+                break;
+            }
+        }
+        assert!(complete);
+        match reader.request() {
+            Ok(Status::Complete(head, _)) => {
+                assert_eq!(head.method, Method::Post);
+                assert_eq!(head.header("Content-Length"), Some(&b"5"[..]));
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let remaining = reader.reset();
+        assert!(b"hello".starts_with(&remaining));
+        assert!(!reader.is_complete());
+
+        let mut reader = HeadReader::with_max_len(8);
+        assert_eq!(reader.feed(b"GET / HTTP/1.1\r\n"), Err(Error::HeadTooLarge));
+    }
+
+    #[test]
+    fn header_map() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.insert("Content-Type", "text/html");
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+        headers.insert("content-type", "text/plain");
+        assert_eq!(headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(headers.len(), 3);
+        assert!(headers.remove("set-cookie"));
+        assert!(!headers.contains("Set-Cookie"));
+        assert_eq!(headers.iter().collect::<Vec<_>>(), vec![("content-type", "text/plain")]);
+        headers.append("X-Request-Id", "42");
+        assert_eq!(headers.get("x-request-id"), Some("42"));
+        assert_eq!(headers.iter().last(), Some(("X-Request-Id", "42")));
+    }
+}
@@ -0,0 +1,453 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! A vector storing its first elements inline, for collections that usually hold few items.
+
+use std::borrow::{Borrow, BorrowMut};
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem::{self, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::slice;
+use std::vec;
+
+enum Data<T, const N: usize> {
+    Inline {
+        items: [MaybeUninit<T>; N],
+        len: usize,
+    },
+    Heap(Vec<T>),
+}
+
+/// A vector storing up to `N` elements inline and moving them to the heap when more are pushed.
+pub struct InlineVec<T, const N: usize> {
+    data: Data<T, N>,
+}
+
+impl<T, const N: usize> InlineVec<T, N> {
+    pub fn new() -> Self {
+        Self {
+            data: Data::Inline {
+                // An array of MaybeUninit does not need initialization.
+                items: unsafe { MaybeUninit::uninit().assume_init() },
+                len: 0,
+            },
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        match self.data {
+            Data::Inline { ref items, len } => unsafe { slice::from_raw_parts(items.as_ptr() as *const T, len) },
+            Data::Heap(ref vec) => vec,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        match self.data {
+            Data::Inline { ref mut items, len } => unsafe { slice::from_raw_parts_mut(items.as_mut_ptr() as *mut T, len) },
+            Data::Heap(ref mut vec) => vec,
+        }
+    }
+
+    /// Returns the number of elements the vector can hold without allocating.
+    pub fn capacity(&self) -> usize {
+        match self.data {
+            Data::Inline { .. } => N,
+            Data::Heap(ref vec) => vec.capacity(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        if len == N {
+            self.spill(len + 1);
+        }
+        match self.data {
+            Data::Inline { ref mut items, ref mut len } => unsafe {
+                let ptr = items.as_mut_ptr().add(index);
+                ptr::copy(ptr, ptr.add(1), *len - index);
+                ptr.write(MaybeUninit::new(value));
+                *len += 1;
+            },
+            Data::Heap(ref mut vec) => vec.insert(index, value),
+        }
+    }
+
+    /// Converts to a `Vec`, which does not allocate if the elements are already on the heap.
+    pub fn into_vec(mut self) -> Vec<T> {
+        match mem::replace(&mut self.data, Data::Heap(vec![])) {
+            Data::Inline { items, len } => {
+                let mut vec = Vec::with_capacity(len);
+                for item in &items[..len] {
+                    vec.push(unsafe { item.as_ptr().read() });
+                }
+                vec
+            },
+            Data::Heap(vec) => vec,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        match self.data {
+            Data::Inline { len, .. } => len,
+            Data::Heap(ref vec) => vec.len(),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        match self.data {
+            Data::Inline { ref items, ref mut len } => {
+                if *len == 0 {
+                    return None;
+                }
+                *len -= 1;
+                Some(unsafe { items[*len].as_ptr().read() })
+            },
+            Data::Heap(ref mut vec) => vec.pop(),
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len() == N {
+            self.spill(N + 1);
+        }
+        match self.data {
+            Data::Inline { ref mut items, ref mut len } => {
+                items[*len] = MaybeUninit::new(value);
+                *len += 1;
+            },
+            Data::Heap(ref mut vec) => vec.push(value),
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.len();
+        assert!(index < len, "removal index (is {}) should be < len (is {})", index, len);
+        match self.data {
+            Data::Inline { ref mut items, ref mut len } => unsafe {
+                let ptr = items.as_mut_ptr().add(index);
+                let value = (*ptr).as_ptr().read();
+                ptr::copy(ptr.add(1), ptr, *len - index - 1);
+                *len -= 1;
+                value
+            },
+            Data::Heap(ref mut vec) => vec.remove(index),
+        }
+    }
+
+    /// Returns true if the elements were moved to the heap.
+    pub fn spilled(&self) -> bool {
+        match self.data {
+            Data::Inline { .. } => false,
+            Data::Heap(_) => true,
+        }
+    }
+
+    pub fn truncate(&mut self, new_len: usize) {
+        match self.data {
+            Data::Inline { ref mut items, ref mut len } => {
+                while *len > new_len {
+                    *len -= 1;
+                    unsafe {
+                        ptr::drop_in_place(items[*len].as_mut_ptr());
+                    }
+                }
+            },
+            Data::Heap(ref mut vec) => vec.truncate(new_len),
+        }
+    }
+
+    fn spill(&mut self, capacity: usize) {
+        if let Data::Inline { ref items, len } = self.data {
+            let mut vec = Vec::with_capacity(capacity.max(N * 2));
+            for item in &items[..len] {
+                vec.push(unsafe { item.as_ptr().read() });
+            }
+            // The elements were moved: do not drop them again.
+            self.data = Data::Heap(vec);
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for InlineVec<T, N> {
+    fn drop(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl<T, const N: usize> Deref for InlineVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for InlineVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T, const N: usize> AsRef<[T]> for InlineVec<T, N> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T, const N: usize> Borrow<[T]> for InlineVec<T, N> {
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T, const N: usize> BorrowMut<[T]> for InlineVec<T, N> {
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for InlineVec<T, N> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: Debug, const N: usize> Debug for InlineVec<T, N> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, const N: usize> Default for InlineVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for InlineVec<T, N> {
+}
+
+impl<T, const N: usize> Extend<T> for InlineVec<T, N> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, const N: usize> From<Vec<T>> for InlineVec<T, N> {
+    fn from(vec: Vec<T>) -> Self {
+        if vec.len() <= N {
+            vec.into_iter().collect()
+        }
+        else {
+            Self {
+                data: Data::Heap(vec),
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for InlineVec<T, N> {
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: Hash, const N: usize> Hash for InlineVec<T, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for InlineVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq<[T]> for InlineVec<T, N> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a InlineVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut InlineVec<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for InlineVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        match mem::replace(&mut self.data, Data::Heap(vec![])) {
+            Data::Inline { items, len } => IntoIter::Inline {
+                items,
+                start: 0,
+                end: len,
+            },
+            Data::Heap(vec) => IntoIter::Heap(vec.into_iter()),
+        }
+    }
+}
+
+/// Iterator returned by `InlineVec::into_iter`.
+pub enum IntoIter<T, const N: usize> {
+    #[doc(hidden)]
+    Inline {
+        items: [MaybeUninit<T>; N],
+        start: usize,
+        end: usize,
+    },
+    #[doc(hidden)]
+    Heap(vec::IntoIter<T>),
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match *self {
+            IntoIter::Inline { ref items, ref mut start, end } => {
+                if *start == end {
+                    return None;
+                }
+                *start += 1;
+                Some(unsafe { items[*start - 1].as_ptr().read() })
+            },
+            IntoIter::Heap(ref mut iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            IntoIter::Inline { start, end, .. } => (end - start, Some(end - start)),
+            IntoIter::Heap(ref iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for IntoIter<T, N> {
+    fn drop(&mut self) {
+        for _ in self {
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::InlineVec;
+
+    #[test]
+    fn inline_and_spilled() {
+        let mut vec: InlineVec<u32, 2> = InlineVec::new();
+        assert!(vec.is_empty());
+        vec.push(1);
+        vec.push(2);
+        assert!(!vec.spilled());
+        assert_eq!(vec.capacity(), 2);
+        vec.insert(0, 0);
+        assert!(vec.spilled());
+        assert_eq!(vec.as_slice(), &[0, 1, 2]);
+        assert_eq!(vec.remove(1), 1);
+        assert_eq!(vec.pop(), Some(2));
+        assert_eq!(vec.into_vec(), vec![0]);
+
+        let mut vec: InlineVec<String, 4> = vec!["b".to_string(), "c".to_string()].into();
+        assert!(!vec.spilled());
+        vec.insert(0, "a".to_string());
+        assert_eq!(vec.remove(2), "c");
+        vec[1].push('!');
+        assert_eq!(vec.as_slice(), ["a", "b!"]);
+        assert_eq!(vec.clone().into_iter().collect::<Vec<_>>(), ["a", "b!"]);
+        assert_eq!(format!("{:?}", vec), r#"["a", "b!"]"#);
+        vec.extend(vec!["x".to_string(); 3]);
+        assert!(vec.spilled());
+        assert_eq!(vec.len(), 5);
+        vec.truncate(1);
+        assert_eq!(vec.into_iter().collect::<Vec<_>>(), ["a"]);
+    }
+
+    struct Counted<'a>(&'a Cell<usize>);
+
+    impl<'a> Drop for Counted<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drops() {
+        let drops = Cell::new(0);
+        {
+            let mut vec: InlineVec<Counted, 3> = InlineVec::new();
+            vec.push(Counted(&drops));
+            vec.push(Counted(&drops));
+            drop(vec.pop());
+            assert_eq!(drops.get(), 1);
+        }
+        assert_eq!(drops.get(), 2);
+
+        let mut vec: InlineVec<Counted, 3> = (0..3).map(|_| Counted(&drops)).collect();
+        vec.push(Counted(&drops));
+        assert_eq!(drops.get(), 2);
+        drop(vec);
+        assert_eq!(drops.get(), 6);
+
+        let vec: InlineVec<Counted, 3> = (0..3).map(|_| Counted(&drops)).collect();
+        let mut iter = vec.into_iter();
+        drop(iter.next());
+        assert_eq!(drops.get(), 7);
+        drop(iter);
+        assert_eq!(drops.get(), 9);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! String interning: each distinct string is stored once and represented by a `Symbol`, a small
+//! copyable identifier that compares and hashes as an integer.
+//!
+//! Interned strings are never freed, so only strings from a bounded set (names, labels, keys known
+//! in advance) should be interned, not arbitrary input.
+
+use std::sync::{OnceLock, RwLock};
+
+use hash::FnvHashMap;
+
+/// Identifier of an interned string.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Interns the string in the global interner.
+    pub fn intern(string: &str) -> Self {
+        global().intern(string)
+    }
+
+    /// Returns the string of a symbol of the global interner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the symbol was created by another interner and is unknown to the global one.
+    pub fn as_str(self) -> &'static str {
+        global().resolve(self).expect("symbol should come from the global interner")
+    }
+
+    /// Returns the index of the symbol, which is the number of strings interned before it.
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+struct Strings {
+    /// The keys borrow the boxed strings of `strings`, which are never removed nor moved.
+    map: FnvHashMap<&'static str, Symbol>,
+    strings: Vec<Box<str>>,
+}
+
+/// A thread-safe string interner.
+pub struct Interner {
+    strings: RwLock<Strings>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self {
+            strings: RwLock::new(Strings {
+                map: FnvHashMap::default(),
+                strings: vec![],
+            }),
+        }
+    }
+
+    /// Returns the symbol of the string if it was interned, without interning it.
+    pub fn get(&self, string: &str) -> Option<Symbol> {
+        self.strings.read().expect("read lock").map.get(string).cloned()
+    }
+
+    /// Returns the symbol of the string, interning it if needed.
+    pub fn intern(&self, string: &str) -> Symbol {
+        if let Some(symbol) = self.get(string) {
+            return symbol;
+        }
+        let mut strings = self.strings.write().expect("write lock");
+        // Another thread may have interned it in the meantime.
+        if let Some(&symbol) = strings.map.get(string) {
+            return symbol;
+        }
+        let symbol = Symbol(strings.strings.len() as u32);
+        let boxed: Box<str> = string.into();
+        let key: &'static str = unsafe { &*(&*boxed as *const str) };
+        strings.strings.push(boxed);
+        strings.map.insert(key, symbol);
+        symbol
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of interned strings.
+    pub fn len(&self) -> usize {
+        self.strings.read().expect("read lock").strings.len()
+    }
+
+    /// Returns the string of the symbol, or `None` if it was not created by this interner.
+    pub fn resolve(&self, symbol: Symbol) -> Option<&str> {
+        let strings = self.strings.read().expect("read lock");
+        strings.strings.get(symbol.0 as usize)
+            // The string lives as long as the interner.
+            .map(|string| unsafe { &*(&**string as *const str) })
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the interner shared by the whole process.
+pub fn global() -> &'static Interner {
+    static GLOBAL: OnceLock<Interner> = OnceLock::new();
+    GLOBAL.get_or_init(Interner::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::{Interner, Symbol};
+
+    #[test]
+    fn intern() {
+        let interner = Interner::new();
+        assert!(interner.is_empty());
+        let hello = interner.intern("hello");
+        let world = interner.intern("world");
+        assert_ne!(hello, world);
+        assert_eq!(interner.intern("hello"), hello);
+        assert_eq!(interner.get("world"), Some(world));
+        assert_eq!(interner.get("missing"), None);
+        assert_eq!(interner.resolve(hello), Some("hello"));
+        assert_eq!(interner.len(), 2);
+
+        let symbol = Symbol::intern("intern::tests::intern");
+        assert_eq!(symbol.as_str(), "intern::tests::intern");
+        assert_eq!(Symbol::intern("intern::tests::intern"), symbol);
+    }
+
+    #[test]
+    fn threads() {
+        let interner = Arc::new(Interner::new());
+        let threads: Vec<_> = (0..4).map(|_| {
+            let interner = interner.clone();
+            thread::spawn(move || {
+                (0..100).map(|i| interner.intern(&i.to_string())).collect::<Vec<_>>()
+            })
+        }).collect();
+        let results: Vec<_> = threads.into_iter().map(|thread| thread.join().expect("join")).collect();
+        assert!(results.iter().all(|symbols| *symbols == results[0]));
+        assert_eq!(interner.len(), 100);
+        assert_eq!(interner.resolve(results[0][42]), Some("42"));
+    }
+}
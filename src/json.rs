@@ -0,0 +1,644 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! JSON parser and serializer (RFC 8259) working on a DOM-style `Value`.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt::{self, Display, Formatter, Write};
+use std::ops::Index;
+use std::str::FromStr;
+
+/// Maximum nesting of arrays and objects accepted by the parser, to avoid stack overflows.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+static NULL: Value = Value::Null;
+
+impl Value {
+    /// Returns the value of the key if this is an object containing it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean if this is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if this is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if this is a number without a fractional part that fits in an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Number(value) if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 =>
+                Some(value as i64),
+            _ => None,
+        }
+    }
+
+    /// Returns the string if this is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements if this is an array.
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match *self {
+            Value::Array(ref array) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Returns the members if this is an object.
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match *self {
+            Value::Object(ref map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this is `null`.
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// Serializes the value with newlines and an indentation of two spaces.
+    pub fn to_string_pretty(&self) -> String {
+        let mut result = String::new();
+        // Writing to a String cannot fail.
+        let _ = write_value(&mut result, self, Some(0));
+        result
+    }
+}
+
+impl<'a> Index<&'a str> for Value {
+    type Output = Value;
+
+    /// Returns the value of the key, or `Value::Null` if this is not an object or the key is absent.
+    fn index(&self, key: &'a str) -> &Value {
+        self.get(key).unwrap_or(&NULL)
+    }
+}
+
+impl Index<usize> for Value {
+    type Output = Value;
+
+    /// Returns the element at the index, or `Value::Null` if this is not an array or the index is out of bounds.
+    fn index(&self, index: usize) -> &Value {
+        match *self {
+            Value::Array(ref array) => array.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(value: &'a str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(value: BTreeMap<String, Value>) -> Self {
+        Value::Object(value)
+    }
+}
+
+impl Display for Value {
+    /// Serializes the value without any whitespace.
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write_value(formatter, self, None)
+    }
+}
+
+impl FromStr for Value {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Error> {
+        parse(input)
+    }
+}
+
+/// The kind of error that happened while parsing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// The input ended in the middle of a value.
+    UnexpectedEnd,
+    /// A character that cannot start or continue a value was found.
+    UnexpectedCharacter(char),
+    /// A string contains an invalid escape sequence.
+    InvalidEscape,
+    /// A number is malformed.
+    InvalidNumber,
+    /// A string contains a raw control character.
+    ControlCharacter,
+    /// Arrays and objects are nested too deeply.
+    TooDeep,
+    /// Non-whitespace characters follow the value.
+    TrailingCharacters,
+}
+
+/// A parsing error along with the position where it occurred.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    /// The kind of error.
+    pub kind: ErrorKind,
+    /// The line of the error, starting at 1.
+    pub line: usize,
+    /// The column of the error in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::UnexpectedEnd => write!(formatter, "unexpected end of input")?,
+            ErrorKind::UnexpectedCharacter(character) => write!(formatter, "unexpected character {:?}", character)?,
+            ErrorKind::InvalidEscape => write!(formatter, "invalid escape sequence")?,
+            ErrorKind::InvalidNumber => write!(formatter, "invalid number")?,
+            ErrorKind::ControlCharacter => write!(formatter, "control character in string")?,
+            ErrorKind::TooDeep => write!(formatter, "nesting too deep")?,
+            ErrorKind::TrailingCharacters => write!(formatter, "trailing characters")?,
+        }
+        write!(formatter, " at line {} column {}", self.line, self.column)
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// Parses a JSON document.
+pub fn parse(input: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        depth: 0,
+        input: input.as_bytes(),
+        position: 0,
+        source: input,
+    };
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.position < parser.input.len() {
+        return Err(parser.error(ErrorKind::TrailingCharacters));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    depth: usize,
+    input: &'a [u8],
+    position: usize,
+    source: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, kind: ErrorKind) -> Error {
+        let before = &self.source[..self.position.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        Error {
+            kind,
+            line,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    fn unexpected(&self) -> Error {
+        match self.source[self.position..].chars().next() {
+            Some(character) => self.error(ErrorKind::UnexpectedCharacter(character)),
+            None => self.error(ErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        }
+        else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &[u8]) -> Result<(), Error> {
+        for &byte in literal {
+            self.expect(byte)?;
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some(b'n') => self.expect_literal(b"null").map(|()| Value::Null),
+            Some(b't') => self.expect_literal(b"true").map(|()| Value::Bool(true)),
+            Some(b'f') => self.expect_literal(b"false").map(|()| Value::Bool(false)),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'[') => self.parse_array(),
+            Some(b'{') => self.parse_object(),
+            Some(b'-') | Some(b'0' ..= b'9') => self.parse_number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error(ErrorKind::TooDeep));
+        }
+        Ok(())
+    }
+
+    fn parse_array(&mut self) -> Result<Value, Error> {
+        self.enter()?;
+        self.expect(b'[')?;
+        let mut array = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            self.depth -= 1;
+            return Ok(Value::Array(array));
+        }
+        loop {
+            self.skip_whitespace();
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    break;
+                },
+                _ => return Err(self.unexpected()),
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Array(array))
+    }
+
+    fn parse_object(&mut self) -> Result<Value, Error> {
+        self.enter()?;
+        self.expect(b'{')?;
+        let mut map = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            self.depth -= 1;
+            return Ok(Value::Object(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+            map.insert(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    break;
+                },
+                _ => return Err(self.unexpected()),
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Object(map))
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1' ..= b'9') => self.skip_digits(),
+            _ => return Err(self.error(ErrorKind::InvalidNumber)),
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if !self.peek().map_or(false, |byte| byte.is_ascii_digit()) {
+                return Err(self.error(ErrorKind::InvalidNumber));
+            }
+            self.skip_digits();
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.position += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.position += 1;
+            }
+            if !self.peek().map_or(false, |byte| byte.is_ascii_digit()) {
+                return Err(self.error(ErrorKind::InvalidNumber));
+            }
+            self.skip_digits();
+        }
+        match self.source[start..self.position].parse() {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => {
+                self.position = start;
+                Err(self.error(ErrorKind::InvalidNumber))
+            },
+        }
+    }
+
+    fn skip_digits(&mut self) {
+        while self.peek().map_or(false, |byte| byte.is_ascii_digit()) {
+            self.position += 1;
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, Error> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit =
+                match self.peek().and_then(|byte| (byte as char).to_digit(16)) {
+                    Some(digit) => digit,
+                    None => return Err(self.error(ErrorKind::InvalidEscape)),
+                };
+            value = value * 16 + digit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        loop {
+            let start = self.position;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.position += 1;
+            }
+            // Only ASCII bytes stop the scan, so the slice is on character boundaries.
+            string.push_str(&self.source[start..self.position]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
+                },
+                Some(b'\\') => {
+                    self.position += 1;
+                    let character =
+                        match self.peek() {
+                            Some(b'"') => '"',
+                            Some(b'\\') => '\\',
+                            Some(b'/') => '/',
+                            Some(b'b') => '\u{8}',
+                            Some(b'f') => '\u{c}',
+                            Some(b'n') => '\n',
+                            Some(b'r') => '\r',
+                            Some(b't') => '\t',
+                            Some(b'u') => {
+                                self.position += 1;
+                                let character = self.parse_unicode_escape()?;
+                                string.push(character);
+                                continue;
+                            },
+                            None => return Err(self.error(ErrorKind::UnexpectedEnd)),
+                            _ => return Err(self.error(ErrorKind::InvalidEscape)),
+                        };
+                    self.position += 1;
+                    string.push(character);
+                },
+                Some(_) => return Err(self.error(ErrorKind::ControlCharacter)),
+                None => return Err(self.error(ErrorKind::UnexpectedEnd)),
+            }
+        }
+    }
+
+    /// Parses the hexadecimal digits following `\u`, including a second escape for surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.parse_hex4()?;
+        let code_point =
+            if (0xD800..0xDC00).contains(&high) {
+                if self.input[self.position..].starts_with(b"\\u") {
+                    self.position += 2;
+                    let low = self.parse_hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.error(ErrorKind::InvalidEscape));
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                }
+                else {
+                    return Err(self.error(ErrorKind::InvalidEscape));
+                }
+            }
+            else {
+                high
+            };
+        ::std::char::from_u32(code_point).ok_or_else(|| self.error(ErrorKind::InvalidEscape))
+    }
+}
+
+fn write_value<W: Write>(writer: &mut W, value: &Value, indent: Option<usize>) -> fmt::Result {
+    match *value {
+        Value::Null => writer.write_str("null"),
+        Value::Bool(value) => write!(writer, "{}", value),
+        Value::Number(number) => {
+            if !number.is_finite() {
+                // JSON cannot represent NaN and infinity.
+                writer.write_str("null")
+            }
+            else {
+                write!(writer, "{}", number)
+            }
+        },
+        Value::String(ref string) => write_string(writer, string),
+        Value::Array(ref array) => {
+            if array.is_empty() {
+                return writer.write_str("[]");
+            }
+            writer.write_char('[')?;
+            for (index, element) in array.iter().enumerate() {
+                if index > 0 {
+                    writer.write_char(',')?;
+                }
+                write_newline(writer, indent.map(|indent| indent + 1))?;
+                write_value(writer, element, indent.map(|indent| indent + 1))?;
+            }
+            write_newline(writer, indent)?;
+            writer.write_char(']')
+        },
+        Value::Object(ref map) => {
+            if map.is_empty() {
+                return writer.write_str("{}");
+            }
+            writer.write_char('{')?;
+            for (index, (key, element)) in map.iter().enumerate() {
+                if index > 0 {
+                    writer.write_char(',')?;
+                }
+                write_newline(writer, indent.map(|indent| indent + 1))?;
+                write_string(writer, key)?;
+                writer.write_char(':')?;
+                if indent.is_some() {
+                    writer.write_char(' ')?;
+                }
+                write_value(writer, element, indent.map(|indent| indent + 1))?;
+            }
+            write_newline(writer, indent)?;
+            writer.write_char('}')
+        },
+    }
+}
+
+fn write_newline<W: Write>(writer: &mut W, indent: Option<usize>) -> fmt::Result {
+    if let Some(indent) = indent {
+        writer.write_char('\n')?;
+        for _ in 0..indent {
+            writer.write_str("  ")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    for character in string.chars() {
+        match character {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            character if (character as u32) < 0x20 => write!(writer, "\\u{:04x}", character as u32)?,
+            character => writer.write_char(character)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorKind, Value, parse};
+
+    #[test]
+    fn parse_values() {
+        let value = parse(r#" {"name": "mini", "version": [0, 0.5, -1e3], "ok": true, "none": null,
+            "escaped": "a\"b\\c\n\u00e9\ud83d\ude00"} "#).expect("parse");
+        assert_eq!(value["name"].as_str(), Some("mini"));
+        assert_eq!(value["version"][0].as_i64(), Some(0));
+        assert_eq!(value["version"][1].as_f64(), Some(0.5));
+        assert_eq!(value["version"][2].as_f64(), Some(-1000.0));
+        assert_eq!(value["ok"].as_bool(), Some(true));
+        assert!(value["none"].is_null());
+        assert!(value["missing"][3].is_null());
+        assert_eq!(value["escaped"].as_str(), Some("a\"b\\c\n\u{e9}\u{1f600}"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("{\n  \"key\": tru\n}").expect_err("parse");
+        assert_eq!(error.kind, ErrorKind::UnexpectedCharacter('\n'));
+        assert_eq!((error.line, error.column), (2, 13));
+        assert_eq!(error.to_string(), "unexpected character '\\n' at line 2 column 13");
+
+        assert_eq!(parse("[1, 2").expect_err("parse").kind, ErrorKind::UnexpectedEnd);
+        assert_eq!(parse("01").expect_err("parse").kind, ErrorKind::TrailingCharacters);
+        assert_eq!(parse("1.").expect_err("parse").kind, ErrorKind::InvalidNumber);
+        assert_eq!(parse("\"\\x\"").expect_err("parse").kind, ErrorKind::InvalidEscape);
+        assert_eq!(parse("\"a\tb\"").expect_err("parse").kind, ErrorKind::ControlCharacter);
+        let deep = "[".repeat(1000);
+        assert_eq!(parse(&deep).expect_err("parse").kind, ErrorKind::TooDeep);
+    }
+
+    #[test]
+    fn serialize() {
+        let value: Value = "{\"b\": [1, \"x\\ty\", {}], \"a\": null}".parse().expect("parse");
+        assert_eq!(value.to_string(), "{\"a\":null,\"b\":[1,\"x\\ty\",{}]}");
+        assert_eq!(value.to_string_pretty(), "{\n  \"a\": null,\n  \"b\": [\n    1,\n    \"x\\ty\",\n    {}\n  ]\n}");
+        assert_eq!(parse(&value.to_string_pretty()).expect("parse"), value);
+    }
+}
@@ -34,6 +34,38 @@
 // * metrics (probably trivial-statsd)
 
 pub mod aio;
+pub mod arena;
+pub mod bytes;
+pub mod cache;
+pub mod channel;
+pub mod checksum;
+pub mod compress;
+pub mod daemon;
+pub mod digest;
+pub mod dns;
+pub mod encoding;
+pub mod env;
 pub mod fs;
 pub mod getopts;
+pub mod glob;
+pub mod hash;
+pub mod http;
+pub mod inline_vec;
+pub mod intern;
+pub mod json;
+pub mod mmap;
+pub mod oneshot;
+pub mod parse;
+pub mod progress;
 pub mod rand;
+pub mod ratelimit;
+pub mod redis;
+pub mod retry;
+pub mod schedule;
+pub mod semver;
+pub mod shutdown;
+pub mod signal;
+pub mod term;
+pub mod testing;
+pub mod threadpool;
+pub mod time;
@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Memory-mapped files.
+//!
+//! `Mmap` maps a file read-only and `MmapMut` maps it copy-on-write: writes to the memory are
+//! private to the mapping and never reach the file. Both dereference to byte slices.
+//!
+//! The pages are read from the file lazily, on first access. The contents seen through a mapping
+//! change if another process modifies the file, and accessing the pages past the end of a file
+//! truncated by another process kills the process with SIGBUS: only map files that are not modified
+//! while mapped.
+
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+/// The expected access pattern, to tune the read-ahead of the kernel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Advice {
+    Normal,
+    /// The pages are accessed in random order: read-ahead is disabled.
+    Random,
+    /// The pages are accessed in order: read-ahead is aggressive and the read pages may be freed soon.
+    Sequential,
+    /// The pages will be accessed soon: they are read ahead.
+    WillNeed,
+    /// The pages will not be accessed soon: they can be freed.
+    DontNeed,
+}
+
+impl Advice {
+    fn as_raw(self) -> i32 {
+        match self {
+            Advice::Normal => ffi::MADV_NORMAL,
+            Advice::Random => ffi::MADV_RANDOM,
+            Advice::Sequential => ffi::MADV_SEQUENTIAL,
+            Advice::WillNeed => ffi::MADV_WILLNEED,
+            Advice::DontNeed => ffi::MADV_DONTNEED,
+        }
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { ffi::sysconf(ffi::_SC_PAGESIZE) as usize }
+}
+
+/// A mapped range of a file. The mapping starts at a page boundary, so it can start before the
+/// requested data.
+struct Mapping {
+    /// Null for an empty mapping, which mmap() does not support.
+    address: *mut u8,
+    /// Offset of the requested data in the mapping.
+    offset: usize,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(file: &File, offset: u64, len: Option<usize>, protection: i32, flags: i32) -> io::Result<Self> {
+        let len =
+            match len {
+                Some(len) => len,
+                None => {
+                    let file_len = file.metadata()?.len();
+                    let len = file_len.checked_sub(offset)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "offset past the end of the file"))?;
+                    if len > usize::MAX as u64 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"));
+                    }
+                    len as usize
+                },
+            };
+        if len == 0 {
+            return Ok(Self {
+                address: ptr::null_mut(),
+                offset: 0,
+                len: 0,
+            });
+        }
+
+        let alignment = (offset % page_size() as u64) as usize;
+        let map_len = len.checked_add(alignment)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "mapping too large"))?;
+        let address = unsafe {
+            ffi::mmap(ptr::null_mut(), map_len, protection, flags, file.as_raw_fd(), (offset - alignment as u64) as i64)
+        };
+        if address == ffi::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            address: address as *mut u8,
+            offset: alignment,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.address.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.address.add(self.offset), self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        if self.address.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.address.add(self.offset), self.len) }
+    }
+
+    fn advise(&self, advice: Advice) -> io::Result<()> {
+        self.call(|address, len| unsafe { ffi::madvise(address, len, advice.as_raw()) })
+    }
+
+    fn lock(&self) -> io::Result<()> {
+        self.call(|address, len| unsafe { ffi::mlock(address, len) })
+    }
+
+    fn unlock(&self) -> io::Result<()> {
+        self.call(|address, len| unsafe { ffi::munlock(address, len) })
+    }
+
+    /// Calls a function taking the whole mapping.
+    fn call<F: FnOnce(*mut ffi::c_void, usize) -> i32>(&self, function: F) -> io::Result<()> {
+        if self.address.is_null() {
+            return Ok(());
+        }
+        if function(self.address as *mut _, self.offset + self.len) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if !self.address.is_null() {
+            unsafe {
+                ffi::munmap(self.address as *mut _, self.offset + self.len);
+            }
+        }
+    }
+}
+
+/// A read-only memory map of a file.
+pub struct Mmap {
+    mapping: Mapping,
+}
+
+// The memory is never written, so it can be shared between threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the whole file.
+    pub fn map(file: &File) -> io::Result<Self> {
+        Ok(Self {
+            mapping: Mapping::new(file, 0, None, ffi::PROT_READ, ffi::MAP_SHARED)?,
+        })
+    }
+
+    /// Maps `len` bytes of the file starting at `offset`, which does not need to be aligned.
+    pub fn map_range(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        Ok(Self {
+            mapping: Mapping::new(file, offset, Some(len), ffi::PROT_READ, ffi::MAP_SHARED)?,
+        })
+    }
+
+    /// Opens and maps the whole file. The file can be closed once mapped, so it is not kept.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::map(&File::open(path)?)
+    }
+
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        self.mapping.advise(advice)
+    }
+
+    /// Locks the pages in memory, so that they are never swapped out nor freed. Limited by
+    /// `RLIMIT_MEMLOCK` for unprivileged processes.
+    pub fn lock(&self) -> io::Result<()> {
+        self.mapping.lock()
+    }
+
+    pub fn unlock(&self) -> io::Result<()> {
+        self.mapping.unlock()
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self.mapping.bytes()
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.mapping.bytes()
+    }
+}
+
+/// A copy-on-write memory map of a file: the memory can be modified, but the file is not.
+pub struct MmapMut {
+    mapping: Mapping,
+}
+
+unsafe impl Send for MmapMut {}
+unsafe impl Sync for MmapMut {}
+
+impl MmapMut {
+    /// Maps the whole file.
+    pub fn map(file: &File) -> io::Result<Self> {
+        Ok(Self {
+            mapping: Mapping::new(file, 0, None, ffi::PROT_READ | ffi::PROT_WRITE, ffi::MAP_PRIVATE)?,
+        })
+    }
+
+    /// Maps `len` bytes of the file starting at `offset`, which does not need to be aligned.
+    pub fn map_range(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        Ok(Self {
+            mapping: Mapping::new(file, offset, Some(len), ffi::PROT_READ | ffi::PROT_WRITE, ffi::MAP_PRIVATE)?,
+        })
+    }
+
+    /// Opens and maps the whole file, which only needs to be readable.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::map(&File::open(path)?)
+    }
+
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        self.mapping.advise(advice)
+    }
+
+    /// Locks the pages in memory. See `Mmap::lock()`.
+    pub fn lock(&self) -> io::Result<()> {
+        self.mapping.lock()
+    }
+
+    pub fn unlock(&self) -> io::Result<()> {
+        self.mapping.unlock()
+    }
+}
+
+impl AsMut<[u8]> for MmapMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.mapping.bytes_mut()
+    }
+}
+
+impl AsRef<[u8]> for MmapMut {
+    fn as_ref(&self) -> &[u8] {
+        self.mapping.bytes()
+    }
+}
+
+impl Deref for MmapMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.mapping.bytes()
+    }
+}
+
+impl DerefMut for MmapMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.mapping.bytes_mut()
+    }
+}
+
+mod ffi {
+    #![allow(non_camel_case_types)]
+
+    pub use std::os::raw::c_void;
+
+    pub const MADV_NORMAL: i32 = 0;
+    pub const MADV_RANDOM: i32 = 1;
+    pub const MADV_SEQUENTIAL: i32 = 2;
+    pub const MADV_WILLNEED: i32 = 3;
+    pub const MADV_DONTNEED: i32 = 4;
+
+    pub const MAP_SHARED: i32 = 0x01;
+    pub const MAP_PRIVATE: i32 = 0x02;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    pub const PROT_READ: i32 = 1;
+    pub const PROT_WRITE: i32 = 2;
+
+    pub const _SC_PAGESIZE: i32 = 30;
+
+    extern "C" {
+        pub fn madvise(addr: *mut c_void, length: usize, advice: i32) -> i32;
+        pub fn mlock(addr: *const c_void, len: usize) -> i32;
+        pub fn mmap(addr: *mut c_void, length: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+        pub fn munlock(addr: *const c_void, len: usize) -> i32;
+        pub fn munmap(addr: *mut c_void, length: usize) -> i32;
+        pub fn sysconf(name: i32) -> i64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use fs::TempFile;
+    use super::{Advice, Mmap, MmapMut, page_size};
+
+    fn temp_file(content: &[u8]) -> TempFile {
+        let file = TempFile::with_prefix("mmap").expect("temp file");
+        let mut handle = file.get();
+        handle.write_all(content).expect("write");
+        file
+    }
+
+    #[test]
+    fn read_only() {
+        let content: Vec<u8> = (0..3 * page_size()).map(|index| index as u8).collect();
+        let file = temp_file(&content);
+        let map = Mmap::map(file.get()).expect("map");
+        assert_eq!(&map[..], &content[..]);
+        map.advise(Advice::Sequential).expect("advise");
+
+        let offset = page_size() + 10;
+        let range = Mmap::map_range(file.get(), offset as u64, 100).expect("map range");
+        assert_eq!(&range[..], &content[offset..offset + 100]);
+        assert!(Mmap::map_range(file.get(), 0, 0).expect("map range").is_empty());
+
+        let empty = temp_file(b"");
+        assert!(Mmap::map(empty.get()).expect("map").is_empty());
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let file = temp_file(b"hello world");
+        let mut map = MmapMut::map(file.get()).expect("map");
+        map[..5].copy_from_slice(b"HELLO");
+        assert_eq!(&map[..], b"HELLO world");
+
+        let mut handle = file.get();
+        let mut content = String::new();
+        handle.seek(SeekFrom::Start(0)).expect("seek");
+        handle.read_to_string(&mut content).expect("read");
+        assert_eq!(content, "hello world");
+    }
+}
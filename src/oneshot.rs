@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Channel carrying a single value, typically the reply to a request sent to a handler or to a
+//! thread.
+//!
+//! The receiver can wait with a timeout, or be registered in an `EventLoop` through its eventfd so
+//! that the reply is awaited without blocking the loop.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use aio::async::ffi;
+use aio::net::close;
+pub use channel::{RecvError, SendError, TryRecvError};
+
+struct State<T> {
+    event_fd: Option<RawFd>,
+    receiver_alive: bool,
+    sender_alive: bool,
+    value: Option<T>,
+}
+
+impl<T> State<T> {
+    fn signal(&self) {
+        if let Some(event_fd) = self.event_fd {
+            unsafe {
+                ffi::eventfd_write(event_fd, 1);
+            }
+        }
+    }
+}
+
+struct Shared<T> {
+    condvar: Condvar,
+    state: Mutex<State<T>>,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if let Some(event_fd) = self.lock().event_fd.take() {
+            let _ = close(event_fd);
+        }
+    }
+}
+
+/// Creates a channel to send a single value.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        condvar: Condvar::new(),
+        state: Mutex::new(State {
+            event_fd: None,
+            receiver_alive: true,
+            sender_alive: true,
+            value: None,
+        }),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+/// The sending end of a oneshot channel.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends the value, or gives it back if the receiver was dropped.
+    pub fn send(self, value: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(SendError(value));
+        }
+        state.value = Some(value);
+        state.signal();
+        self.shared.condvar.notify_one();
+        Ok(())
+    }
+
+    /// Returns true if the receiver was dropped, meaning that nobody waits for the value anymore.
+    pub fn is_canceled(&self) -> bool {
+        !self.shared.lock().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_alive = false;
+        if state.value.is_none() {
+            // Wake up the receiver so that it notices the disconnection.
+            state.signal();
+            self.shared.condvar.notify_one();
+        }
+    }
+}
+
+/// The receiving end of a oneshot channel.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the value. Fails if the sender was dropped without sending.
+    pub fn recv(self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(value) = state.value.take() {
+                return Ok(value);
+            }
+            if !state.sender_alive {
+                return Err(RecvError);
+            }
+            state = self.shared.condvar.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+    }
+
+    /// Waits for the value for at most `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TryRecvError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(value) = state.value.take() {
+                return Ok(value);
+            }
+            if !state.sender_alive {
+                return Err(TryRecvError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TryRecvError::Empty);
+            }
+            state = self.shared.condvar.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|error| error.into_inner()).0;
+        }
+    }
+
+    /// Returns the value if it was sent.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if !state.sender_alive => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Returns an eventfd which becomes readable when the value is sent or the sender is dropped, to
+    /// register the receiver in an `EventLoop`.
+    pub fn event_fd(&self) -> io::Result<RawFd> {
+        let mut state = self.shared.lock();
+        if let Some(event_fd) = state.event_fd {
+            return Ok(event_fd);
+        }
+        let event_fd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK) };
+        if event_fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        state.event_fd = Some(event_fd);
+        if state.value.is_some() || !state.sender_alive {
+            state.signal();
+        }
+        Ok(event_fd)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.value.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use aio::handler::{Handler, Loop, Stream};
+    use super::{RecvError, TryRecvError, channel};
+
+    #[test]
+    fn send_recv() {
+        let (sender, receiver) = channel();
+        thread::spawn(move || sender.send(42).expect("send"));
+        assert_eq!(receiver.recv(), Ok(42));
+
+        let (sender, receiver) = channel::<()>();
+        drop(sender);
+        assert_eq!(receiver.recv(), Err(RecvError));
+
+        let (sender, receiver) = channel();
+        assert!(!sender.is_canceled());
+        drop(receiver);
+        assert!(sender.is_canceled());
+        assert!(sender.send(1).is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), Err(TryRecvError::Empty));
+        sender.send("reply").expect("send");
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), Ok("reply"));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    struct Waiter {
+        event_loop: Loop,
+        reply: Rc<RefCell<Option<Result<u32, RecvError>>>>,
+    }
+
+    impl Handler for Waiter {
+        type Msg = Result<u32, RecvError>;
+
+        fn update(&mut self, _stream: &Stream<Self::Msg>, msg: Self::Msg) {
+            *self.reply.borrow_mut() = Some(msg);
+            self.event_loop.stop();
+        }
+    }
+
+    #[test]
+    fn event_loop() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let reply = Rc::new(RefCell::new(None));
+        let stream = event_loop.spawn(Waiter {
+            event_loop: event_loop.clone(),
+            reply: reply.clone(),
+        });
+        let (sender, receiver) = channel();
+        event_loop.add_oneshot(receiver, &stream, |reply| reply).expect("add oneshot");
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(7).expect("send");
+        });
+        event_loop.run().expect("run");
+        assert_eq!(*reply.borrow(), Some(Ok(7)));
+    }
+}
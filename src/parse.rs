@@ -0,0 +1,363 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! A byte cursor for writing parsers of binary and text protocols.
+//!
+//! Input often arrives in chunks, e.g. from `TcpConnectionNotify::received()`, so a message can be
+//! incomplete. The cursor reports it with `ErrorKind::Incomplete`, and methods leave the cursor
+//! unchanged when they fail: a parser can save `position()`, try to parse a whole message, and on
+//! `Incomplete` keep the buffered bytes and retry once more data arrived.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+/// Maximum length of a LEB128-encoded 64-bit integer.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Why parsing failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// More input is needed.
+    Incomplete,
+    /// The input does not have the expected format.
+    Invalid,
+    /// A number does not fit in its type.
+    Overflow,
+}
+
+/// A parsing error at a position in the input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Offset in the input where the failing item starts.
+    pub position: usize,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, position: usize) -> Self {
+        Self {
+            kind,
+            position,
+        }
+    }
+
+    /// Returns true if more input could make parsing succeed.
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == ErrorKind::Incomplete
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Incomplete => write!(formatter, "incomplete input")?,
+            ErrorKind::Invalid => write!(formatter, "invalid input")?,
+            ErrorKind::Overflow => write!(formatter, "number too large")?,
+        }
+        write!(formatter, " at position {}", self.position)
+    }
+}
+
+impl error::Error for Error {
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// A position in a byte slice.
+#[derive(Clone, Copy, Debug)]
+pub struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            position: 0,
+        }
+    }
+
+    /// Returns the whole input, including the bytes already consumed.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns an error of the specified kind at the current position.
+    pub fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(kind, self.position)
+    }
+
+    /// Consumes `expected`, failing with `Invalid` if the input differs or with `Incomplete` if
+    /// the input is a prefix of it.
+    pub fn expect(&mut self, expected: &[u8]) -> Result<()> {
+        let remaining = self.remaining();
+        let len = remaining.len().min(expected.len());
+        if remaining[..len] != expected[..len] {
+            return Err(self.error(ErrorKind::Invalid));
+        }
+        if len < expected.len() {
+            return Err(self.error(ErrorKind::Incomplete));
+        }
+        self.position += len;
+        Ok(())
+    }
+
+    /// Returns true if all the input was consumed.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    /// Returns the next byte without consuming it.
+    pub fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).cloned()
+    }
+
+    /// Returns the next `len` bytes without consuming them.
+    pub fn peek_bytes(&self, len: usize) -> Option<&'a [u8]> {
+        self.remaining().get(..len)
+    }
+
+    /// Returns the number of bytes consumed.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the bytes not consumed yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    /// Moves to an absolute position, e.g. one saved before a failed attempt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is past the end of the input.
+    pub fn set_position(&mut self, position: usize) {
+        assert!(position <= self.bytes.len(), "position should be within the input");
+        self.position = position;
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.peek_bytes(len).ok_or_else(|| self.error(ErrorKind::Incomplete))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Consumes the bytes until `delimiter` and the delimiter, returning the bytes before it.
+    pub fn take_until(&mut self, delimiter: &[u8]) -> Result<&'a [u8]> {
+        let remaining = self.remaining();
+        let index =
+            if delimiter.is_empty() {
+                Some(0)
+            }
+            else {
+                remaining.windows(delimiter.len()).position(|window| window == delimiter)
+            };
+        match index {
+            Some(index) => {
+                self.position += index + delimiter.len();
+                Ok(&remaining[..index])
+            },
+            None => Err(self.error(ErrorKind::Incomplete)),
+        }
+    }
+
+    /// Consumes the bytes matching the predicate. This never fails, but the returned bytes may be
+    /// followed by more matching ones not received yet.
+    pub fn take_while<F: FnMut(u8) -> bool>(&mut self, mut predicate: F) -> &'a [u8] {
+        let remaining = self.remaining();
+        let len = remaining.iter().position(|&byte| !predicate(byte)).unwrap_or(remaining.len());
+        self.position += len;
+        &remaining[..len]
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16_be(&mut self) -> Result<u16> {
+        Ok(self.uint_be(2)? as u16)
+    }
+
+    pub fn u16_le(&mut self) -> Result<u16> {
+        Ok(self.uint_le(2)? as u16)
+    }
+
+    pub fn u32_be(&mut self) -> Result<u32> {
+        Ok(self.uint_be(4)? as u32)
+    }
+
+    pub fn u32_le(&mut self) -> Result<u32> {
+        Ok(self.uint_le(4)? as u32)
+    }
+
+    pub fn u64_be(&mut self) -> Result<u64> {
+        self.uint_be(8)
+    }
+
+    pub fn u64_le(&mut self) -> Result<u64> {
+        self.uint_le(8)
+    }
+
+    /// Reads an unsigned LEB128 integer, as used by Protocol Buffers.
+    pub fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for (index, &byte) in self.remaining().iter().enumerate() {
+            if index == MAX_VARINT_LEN || (index == MAX_VARINT_LEN - 1 && byte > 1) {
+                return Err(self.error(ErrorKind::Overflow));
+            }
+            value |= u64::from(byte & 0x7f) << (7 * index);
+            if byte & 0x80 == 0 {
+                self.position += index + 1;
+                return Ok(value);
+            }
+        }
+        Err(self.error(ErrorKind::Incomplete))
+    }
+
+    /// Reads an unsigned ASCII decimal number. Since the last digit may not be received yet, the
+    /// number must be followed by a non-digit byte, which is not consumed.
+    pub fn decimal(&mut self) -> Result<u64> {
+        let digits = self.remaining().iter().take_while(|byte| byte.is_ascii_digit()).count();
+        if digits == 0 {
+            return Err(self.error(if self.is_empty() { ErrorKind::Incomplete } else { ErrorKind::Invalid }));
+        }
+        if digits == self.remaining().len() {
+            return Err(self.error(ErrorKind::Incomplete));
+        }
+        let mut value = 0u64;
+        for &digit in &self.remaining()[..digits] {
+            value = value.checked_mul(10)
+                .and_then(|value| value.checked_add(u64::from(digit - b'0')))
+                .ok_or_else(|| self.error(ErrorKind::Overflow))?;
+        }
+        self.position += digits;
+        Ok(value)
+    }
+
+    /// Reads an ASCII decimal number with an optional `-` or `+` sign, followed by a non-digit
+    /// byte as for `decimal`.
+    pub fn signed_decimal(&mut self) -> Result<i64> {
+        let start = self.position;
+        let negative =
+            match self.peek() {
+                Some(b'-') => true,
+                Some(b'+') => false,
+                _ => return self.decimal().and_then(|value| to_i64(value, false, start)),
+            };
+        self.position += 1;
+        match self.decimal() {
+            Ok(value) => to_i64(value, negative, start).inspect_err(|_| self.position = start),
+            Err(error) => {
+                self.position = start;
+                Err(Error::new(error.kind, start))
+            },
+        }
+    }
+
+    fn uint_be(&mut self, len: usize) -> Result<u64> {
+        Ok(self.take(len)?.iter().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    fn uint_le(&mut self, len: usize) -> Result<u64> {
+        Ok(self.take(len)?.iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+}
+
+fn to_i64(value: u64, negative: bool, position: usize) -> Result<i64> {
+    if negative {
+        if value > i64::MAX as u64 + 1 {
+            return Err(Error::new(ErrorKind::Overflow, position));
+        }
+        Ok((value as i64).wrapping_neg())
+    }
+    else if value > i64::MAX as u64 {
+        Err(Error::new(ErrorKind::Overflow, position))
+    }
+    else {
+        Ok(value as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, Error, ErrorKind};
+
+    #[test]
+    fn bytes() {
+        let mut cursor = Cursor::new(b"\x01\x02\x03\x04\x05\x06\x07\x08\x09rest");
+        assert_eq!(cursor.peek(), Some(1));
+        assert_eq!(cursor.u8(), Ok(1));
+        assert_eq!(cursor.u16_be(), Ok(0x0203));
+        assert_eq!(cursor.u16_le(), Ok(0x0504));
+        assert_eq!(cursor.u32_be(), Ok(0x0607_0809));
+        assert_eq!(cursor.position(), 9);
+        assert_eq!(cursor.u64_le(), Err(Error::new(ErrorKind::Incomplete, 9)));
+        assert_eq!(cursor.position(), 9);
+        assert_eq!(cursor.peek_bytes(2), Some(&b"re"[..]));
+        assert_eq!(cursor.expect(b"rex"), Err(Error::new(ErrorKind::Invalid, 9)));
+        assert_eq!(cursor.expect(b"restful"), Err(Error::new(ErrorKind::Incomplete, 9)));
+        assert_eq!(cursor.expect(b"re"), Ok(()));
+        assert_eq!(cursor.take(2), Ok(&b"st"[..]));
+        assert!(cursor.is_empty());
+        cursor.set_position(1);
+        assert_eq!(cursor.u64_be(), Ok(0x0203_0405_0607_0809));
+    }
+
+    #[test]
+    fn text() {
+        let mut cursor = Cursor::new(b"GET / HTTP/1.1\r\nContent-Length: 42\r\n\r\n");
+        assert_eq!(cursor.take_while(|byte| byte.is_ascii_uppercase()), b"GET");
+        assert_eq!(cursor.take_until(b"\r\n"), Ok(&b" / HTTP/1.1"[..]));
+        assert_eq!(cursor.take_until(b": "), Ok(&b"Content-Length"[..]));
+        assert_eq!(cursor.decimal(), Ok(42));
+        assert_eq!(cursor.take_until(b"\r\n\r\n"), Ok(&b""[..]));
+        assert_eq!(cursor.take_until(b"\r\n"), Err(Error::new(ErrorKind::Incomplete, 38)));
+
+        assert_eq!(Cursor::new(b"123").decimal(), Err(Error::new(ErrorKind::Incomplete, 0)));
+        assert_eq!(Cursor::new(b"x").decimal(), Err(Error::new(ErrorKind::Invalid, 0)));
+        assert_eq!(Cursor::new(b"18446744073709551616\r").decimal(), Err(Error::new(ErrorKind::Overflow, 0)));
+        assert_eq!(Cursor::new(b"-9223372036854775808\r").signed_decimal(), Ok(i64::MIN));
+        assert_eq!(Cursor::new(b"+12\r").signed_decimal(), Ok(12));
+        let mut cursor = Cursor::new(b"-\r");
+        assert_eq!(cursor.signed_decimal(), Err(Error::new(ErrorKind::Invalid, 0)));
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(Cursor::new(b"9223372036854775808\r").signed_decimal(), Err(Error::new(ErrorKind::Overflow, 0)));
+    }
+
+    #[test]
+    fn varint() {
+        assert_eq!(Cursor::new(&[0x00]).varint(), Ok(0));
+        assert_eq!(Cursor::new(&[0xac, 0x02]).varint(), Ok(300));
+        assert_eq!(Cursor::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).varint(), Ok(u64::MAX));
+        assert_eq!(Cursor::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]).varint(),
+            Err(Error::new(ErrorKind::Overflow, 0)));
+        let mut cursor = Cursor::new(&[0x01, 0xac]);
+        assert_eq!(cursor.varint(), Ok(1));
+        assert_eq!(cursor.varint(), Err(Error::new(ErrorKind::Incomplete, 1)));
+        assert_eq!(cursor.position(), 1);
+    }
+}
@@ -0,0 +1,655 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Progress bars and spinners for command-line tools.
+//!
+//! On a terminal, the bars are redrawn in place at most 10 times per second. Otherwise, e.g. when
+//! stderr is redirected to a file, a plain line is logged at each 10% step (or every 5 seconds for
+//! a spinner) and when the work finishes. Bars can be updated from several threads.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use term::{self, Color, StdStream, Style, cursor, visible_width};
+
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+/// Minimum time between two samples of the rate.
+const RATE_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Time constant of the moving average of the rate, in seconds.
+const RATE_SMOOTHING: f64 = 3.0;
+const MAX_BAR_WIDTH: usize = 40;
+const MIN_BAR_WIDTH: usize = 10;
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panic while drawing must not prevent the other threads from reporting their progress.
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Where the progress is drawn.
+pub struct Target {
+    colors: bool,
+    hidden: bool,
+    tty: bool,
+    width: usize,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Target {
+    /// Draws on stderr, in place if it is a terminal.
+    pub fn stderr() -> Self {
+        Self::std_stream(StdStream::Stderr, Box::new(io::stderr()))
+    }
+
+    /// Draws on stdout, in place if it is a terminal.
+    pub fn stdout() -> Self {
+        Self::std_stream(StdStream::Stdout, Box::new(io::stdout()))
+    }
+
+    fn std_stream(stream: StdStream, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            colors: term::colors_enabled(stream),
+            hidden: false,
+            tty: term::is_tty(stream),
+            width: term::size(stream).map_or(80, |(columns, _)| usize::from(columns)),
+            writer,
+        }
+    }
+
+    /// Draws nothing, e.g. for a `--quiet` option.
+    pub fn hidden() -> Self {
+        Self {
+            colors: false,
+            hidden: true,
+            tty: false,
+            width: 80,
+            writer: Box::new(io::sink()),
+        }
+    }
+
+    /// Draws to `writer`, in place if `tty` is true, assuming a width of 80 columns.
+    pub fn writer<W: Write + Send + 'static>(writer: W, tty: bool) -> Self {
+        Self {
+            colors: tty,
+            hidden: false,
+            tty,
+            width: 80,
+            writer: Box::new(writer),
+        }
+    }
+}
+
+/// Smoothed rate of progress.
+struct Rate {
+    last_position: u64,
+    last_sample: Instant,
+    value: Option<f64>,
+}
+
+impl Rate {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_position: 0,
+            last_sample: now,
+            value: None,
+        }
+    }
+
+    fn update(&mut self, position: u64, now: Instant) {
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed < RATE_SAMPLE_INTERVAL {
+            return;
+        }
+        let seconds = elapsed.as_secs_f64();
+        let sample = position.saturating_sub(self.last_position) as f64 / seconds;
+        self.value = Some(match self.value {
+            Some(value) => {
+                let weight = 1.0 - (-seconds / RATE_SMOOTHING).exp();
+                value + weight * (sample - value)
+            },
+            None => sample,
+        });
+        self.last_position = position;
+        self.last_sample = now;
+    }
+}
+
+struct Bar {
+    finished: bool,
+    /// Step of 10% (or of 5 seconds for a spinner) last logged when not drawing on a terminal.
+    last_log: Option<u64>,
+    logged_finish: bool,
+    message: String,
+    position: u64,
+    rate: Rate,
+    spinner_frame: usize,
+    start: Instant,
+    style: Style,
+    total: Option<u64>,
+}
+
+impl Bar {
+    fn new(total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            finished: false,
+            last_log: None,
+            logged_finish: false,
+            message: String::new(),
+            position: 0,
+            rate: Rate::new(now),
+            spinner_frame: 0,
+            start: now,
+            style: Style::new().fg(Color::Cyan),
+            total,
+        }
+    }
+
+    fn eta(&self, now: Instant) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.position);
+        if remaining == 0 {
+            return Some(Duration::from_secs(0));
+        }
+        let rate = self.rate(now);
+        if rate > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / rate))
+        }
+        else {
+            None
+        }
+    }
+
+    fn percent(&self) -> Option<u64> {
+        self.total.map(|total| {
+            (u128::from(self.position.min(total)) * 100).checked_div(u128::from(total)).map_or(100, |percent| percent as u64)
+        })
+    }
+
+    fn rate(&self, now: Instant) -> f64 {
+        self.rate.value.unwrap_or_else(|| {
+            let elapsed = now.duration_since(self.start).as_secs_f64();
+            if elapsed > 0.0 {
+                self.position as f64 / elapsed
+            }
+            else {
+                0.0
+            }
+        })
+    }
+
+    /// Returns the line to log when not drawing on a terminal, if it is time to.
+    fn log_line(&mut self, now: Instant) -> Option<String> {
+        if self.finished {
+            if self.logged_finish {
+                return None;
+            }
+            self.logged_finish = true;
+        }
+        else {
+            let step =
+                match self.percent() {
+                    // Completion is logged by finish().
+                    Some(100) => return None,
+                    Some(percent) => percent / 10,
+                    None => now.duration_since(self.start).as_secs() / LOG_INTERVAL.as_secs(),
+                };
+            if self.last_log == Some(step) {
+                return None;
+            }
+            self.last_log = Some(step);
+        }
+
+        let mut line = String::new();
+        if !self.message.is_empty() {
+            let _ = write!(line, "{}: ", self.message);
+        }
+        match self.total {
+            Some(total) => {
+                let _ = write!(line, "{}% ({}/{})", self.percent().unwrap_or(0), self.position, total);
+            },
+            None => {
+                let _ = write!(line, "{}", self.position);
+            },
+        }
+        let elapsed = now.duration_since(self.start);
+        if self.finished {
+            let _ = write!(line, " done in {}", format_duration(elapsed));
+        }
+        else {
+            let _ = write!(line, ", {}", format_rate(self.rate(now)));
+            if let Some(eta) = self.eta(now) {
+                let _ = write!(line, ", ETA {}", format_duration(eta));
+            }
+        }
+        Some(line)
+    }
+
+    /// Renders the bar on a line of `width` columns.
+    fn render(&self, now: Instant, width: usize, colors: bool) -> String {
+        let elapsed = now.duration_since(self.start);
+        let mut suffix = String::new();
+        match self.total {
+            Some(total) => {
+                let _ = write!(suffix, " {:>3}% {}/{}", self.percent().unwrap_or(0), self.position, total);
+                if self.finished {
+                    let _ = write!(suffix, " in {}", format_duration(elapsed));
+                }
+                else {
+                    let _ = write!(suffix, " {}", format_rate(self.rate(now)));
+                    if let Some(eta) = self.eta(now) {
+                        let _ = write!(suffix, " ETA {}", format_duration(eta));
+                    }
+                }
+            },
+            None => {
+                let _ = write!(suffix, " {} {} {}", self.position, format_rate(self.rate(now)), format_duration(elapsed));
+            },
+        }
+
+        let mut line = String::new();
+        match self.total {
+            Some(total) => {
+                let prefix_width = if self.message.is_empty() { 0 } else { visible_width(&self.message) + 1 };
+                let available = width.saturating_sub(prefix_width + visible_width(&suffix) + 2);
+                let bar_width = available.clamp(MIN_BAR_WIDTH, MAX_BAR_WIDTH);
+                let filled =
+                    if total == 0 {
+                        bar_width
+                    }
+                    else {
+                        (self.position.min(total) as u128 * bar_width as u128 / u128::from(total)) as usize
+                    };
+                if !self.message.is_empty() {
+                    let _ = write!(line, "{} ", self.message);
+                }
+                let mut bar = "=".repeat(filled);
+                if filled < bar_width {
+                    bar.push('>');
+                    bar.push_str(&" ".repeat(bar_width - filled - 1));
+                }
+                let style = if colors { self.style } else { Style::new() };
+                let _ = write!(line, "[{}]", style.paint(bar));
+            },
+            None => {
+                let frame = if self.finished { "✓" } else { SPINNER_FRAMES[self.spinner_frame % SPINNER_FRAMES.len()] };
+                let _ = write!(line, "{}", frame);
+                if !self.message.is_empty() {
+                    let _ = write!(line, " {}", self.message);
+                }
+            },
+        }
+        line.push_str(&suffix);
+        line
+    }
+}
+
+/// Draws a group of bars on a target.
+struct Drawer {
+    bars: Vec<Arc<Mutex<Bar>>>,
+    last_draw: Option<Instant>,
+    /// Number of lines drawn by the last draw, to overwrite them.
+    lines: usize,
+    target: Target,
+}
+
+impl Drawer {
+    fn new(target: Target) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            bars: vec![],
+            last_draw: None,
+            lines: 0,
+            target,
+        }))
+    }
+
+    fn draw(&mut self, force: bool) {
+        if self.target.hidden {
+            return;
+        }
+        let now = Instant::now();
+        if !self.target.tty {
+            for bar in &self.bars {
+                if let Some(line) = lock(bar).log_line(now) {
+                    let _ = writeln!(self.target.writer, "{}", line);
+                }
+            }
+            return;
+        }
+
+        if !force && self.last_draw.is_some_and(|last_draw| now.duration_since(last_draw) < DRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(now);
+        let mut output = self.clear();
+        for bar in &self.bars {
+            let mut bar = lock(bar);
+            bar.spinner_frame += 1;
+            let _ = writeln!(output, "{}", bar.render(now, self.target.width.saturating_sub(1), self.target.colors));
+        }
+        self.lines = self.bars.len();
+        let _ = self.target.writer.write_all(output.as_bytes());
+        let _ = self.target.writer.flush();
+    }
+
+    /// Returns the sequence erasing the lines drawn previously.
+    fn clear(&self) -> String {
+        let mut output = String::new();
+        if self.lines > 0 {
+            output.push_str(&cursor::up(self.lines as u16));
+        }
+        for _ in 0..self.lines {
+            let _ = writeln!(output, "{}", cursor::CLEAR_LINE);
+        }
+        if self.lines > 0 {
+            output.push_str(&cursor::up(self.lines as u16));
+        }
+        output
+    }
+
+    fn println(&mut self, text: &str) {
+        if self.target.hidden {
+            return;
+        }
+        if self.target.tty {
+            let mut output = self.clear();
+            output.push_str(text);
+            output.push('\n');
+            self.lines = 0;
+            let _ = self.target.writer.write_all(output.as_bytes());
+            self.draw(true);
+        }
+        else {
+            let _ = writeln!(self.target.writer, "{}", text);
+        }
+    }
+}
+
+/// A progress bar, or a spinner when the total is unknown. Clones refer to the same bar.
+#[derive(Clone)]
+pub struct ProgressBar {
+    bar: Arc<Mutex<Bar>>,
+    drawer: Arc<Mutex<Drawer>>,
+}
+
+impl ProgressBar {
+    /// Creates a bar reaching completion at `total`, drawn on stderr.
+    pub fn new(total: u64) -> Self {
+        Self::with_total(Some(total))
+    }
+
+    /// Creates a spinner, for work of unknown size, drawn on stderr.
+    pub fn spinner() -> Self {
+        Self::with_total(None)
+    }
+
+    fn with_total(total: Option<u64>) -> Self {
+        let bar = Arc::new(Mutex::new(Bar::new(total)));
+        let drawer = Drawer::new(Target::stderr());
+        lock(&drawer).bars.push(bar.clone());
+        Self {
+            bar,
+            drawer,
+        }
+    }
+
+    /// Sets the text displayed before the bar.
+    pub fn message(self, message: &str) -> Self {
+        lock(&self.bar).message = message.to_string();
+        self
+    }
+
+    /// Sets the style of the filled part of the bar, on a terminal.
+    pub fn style(self, style: Style) -> Self {
+        lock(&self.bar).style = style;
+        self
+    }
+
+    /// Draws the bar on another target.
+    pub fn target(self, target: Target) -> Self {
+        lock(&self.drawer).target = target;
+        self
+    }
+
+    /// Returns the time elapsed since the bar was created.
+    pub fn elapsed(&self) -> Duration {
+        lock(&self.bar).start.elapsed()
+    }
+
+    /// Returns the estimated time until completion, if the total and the rate are known.
+    pub fn eta(&self) -> Option<Duration> {
+        lock(&self.bar).eta(Instant::now())
+    }
+
+    /// Marks the work as completed and draws the final state.
+    pub fn finish(&self) {
+        {
+            let mut bar = lock(&self.bar);
+            if let Some(total) = bar.total {
+                bar.position = bar.position.max(total);
+            }
+            bar.finished = true;
+        }
+        lock(&self.drawer).draw(true);
+    }
+
+    pub fn finish_with_message(&self, message: &str) {
+        lock(&self.bar).message = message.to_string();
+        self.finish();
+    }
+
+    /// Advances the position by `delta`.
+    pub fn inc(&self, delta: u64) {
+        {
+            let mut bar = lock(&self.bar);
+            let position = bar.position.saturating_add(delta);
+            bar.position = position;
+            bar.rate.update(position, Instant::now());
+        }
+        self.tick();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        lock(&self.bar).finished
+    }
+
+    pub fn position(&self) -> u64 {
+        lock(&self.bar).position
+    }
+
+    /// Prints a line above the bars, without breaking them.
+    pub fn println(&self, text: &str) {
+        lock(&self.drawer).println(text);
+    }
+
+    /// Returns the smoothed number of units per second.
+    pub fn rate(&self) -> f64 {
+        lock(&self.bar).rate(Instant::now())
+    }
+
+    pub fn set_message(&self, message: &str) {
+        lock(&self.bar).message = message.to_string();
+        self.tick();
+    }
+
+    pub fn set_position(&self, position: u64) {
+        {
+            let mut bar = lock(&self.bar);
+            bar.position = position;
+            bar.rate.update(position, Instant::now());
+        }
+        self.tick();
+    }
+
+    /// Redraws the bar if it is time to, e.g. to animate a spinner while waiting.
+    pub fn tick(&self) {
+        lock(&self.drawer).draw(false);
+    }
+}
+
+/// Several bars drawn together, one per line.
+pub struct MultiProgress {
+    drawer: Arc<Mutex<Drawer>>,
+}
+
+impl MultiProgress {
+    /// Creates a group drawn on stderr.
+    pub fn new() -> Self {
+        Self::with_target(Target::stderr())
+    }
+
+    pub fn with_target(target: Target) -> Self {
+        Self {
+            drawer: Drawer::new(target),
+        }
+    }
+
+    /// Adds a bar below the others and returns it, now drawn by the group.
+    pub fn add(&self, bar: ProgressBar) -> ProgressBar {
+        lock(&bar.drawer).bars.retain(|other| !Arc::ptr_eq(other, &bar.bar));
+        lock(&self.drawer).bars.push(bar.bar.clone());
+        ProgressBar {
+            bar: bar.bar,
+            drawer: self.drawer.clone(),
+        }
+    }
+
+    /// Prints a line above the bars, without breaking them.
+    pub fn println(&self, text: &str) {
+        lock(&self.drawer).println(text);
+    }
+}
+
+impl Default for MultiProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats a duration as `mm:ss`, or `h:mm:ss` from one hour.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+    else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn format_rate(rate: f64) -> String {
+    if rate >= 100.0 {
+        format!("{:.0}/s", rate)
+    }
+    else {
+        format!("{:.1}/s", rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Bar, MultiProgress, ProgressBar, Target, format_duration};
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().expect("lock").clone()).expect("utf-8")
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.lock().expect("lock").extend_from_slice(buffer);
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn render() {
+        let mut bar = Bar::new(Some(200));
+        bar.message = "copy".to_string();
+        bar.position = 50;
+        let now = bar.start + Duration::from_secs(5);
+        assert_eq!(bar.render(now, 60, false), "copy [======>                 ]  25% 50/200 10.0/s ETA 00:15");
+        assert_eq!(bar.eta(now), Some(Duration::from_secs(15)));
+        bar.finished = true;
+        bar.position = 200;
+        assert!(bar.render(now, 60, false).ends_with("] 100% 200/200 in 00:05"));
+
+        let mut spinner = Bar::new(None);
+        spinner.position = 1500;
+        assert_eq!(spinner.render(spinner.start + Duration::from_secs(3), 80, false), "⠋ 1500 500/s 00:03");
+
+        assert_eq!(format_duration(Duration::from_secs(75)), "01:15");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+
+    #[test]
+    fn plain_logs() {
+        let output = Output::default();
+        let bar = ProgressBar::new(100).message("files").target(Target::writer(output.clone(), false));
+        for _ in 0..100 {
+            bar.inc(1);
+        }
+        bar.finish();
+        let text = output.text();
+        let lines: Vec<_> = text.lines().collect();
+        // One line per 10% step, and the final line.
+        assert_eq!(lines.len(), 11);
+        assert!(lines[0].starts_with("files: 1% (1/100)"));
+        assert!(lines[10].starts_with("files: 100% (100/100) done in "));
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    fn terminal() {
+        let output = Output::default();
+        let multi = MultiProgress::with_target(Target::writer(output.clone(), true));
+        let first = multi.add(ProgressBar::new(10).message("a"));
+        let second = multi.add(ProgressBar::spinner().message("b"));
+        first.inc(5);
+        // Throttled.
+        second.inc(1);
+        multi.println("log line");
+        first.finish();
+        let text = output.text();
+        assert_eq!(text.matches("a [").count(), 3);
+        assert!(text.contains("\x1b[2A\x1b[2K\n\x1b[2K\n\x1b[2Alog line\n"));
+        assert!(text.contains("] 100% 10/10 in 00:00\n⠸ b 1 "));
+        assert!(first.is_finished() && !second.is_finished());
+        assert_eq!(second.position(), 1);
+        let start = Instant::now();
+        ProgressBar::spinner().target(Target::hidden()).finish();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -21,9 +21,16 @@
 
 //! Random number generator based of the PCG paper (http://www.pcg-random.org/paper.html).
 
+use std::marker::PhantomData;
 use std::u32;
 use std::time::*;
 
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// Stream used by `seed_with`, chosen so that its increment is the historical 12345.
+const DEFAULT_STREAM: u64 = 6172;
+
+#[derive(Clone, Debug)]
 pub struct Rng {
     state: u64,
     inc: u64,
@@ -42,7 +49,7 @@ impl Rng {
     fn pcg32(&mut self) -> u32 {
         let oldstate = self.state;
         // Advance internal state
-        self.state = u64::wrapping_add(u64::wrapping_mul(oldstate, 6_364_136_223_846_793_005u64), self.inc | 1);
+        self.state = u64::wrapping_add(u64::wrapping_mul(oldstate, MULTIPLIER), self.inc | 1);
         // Calculate output function (XSH RR), uses old state for max ILP
         let xorshifted = (((oldstate >> 18) ^ oldstate) >> 27) & 0xFFFF_FFFF;
         let rot = (oldstate >> 59) & 0xFFFF_FFFF;
@@ -52,14 +59,43 @@ impl Rng {
 
     /// Creates a new pseudo-random with a custom seed.
     pub fn seed_with(seed: u64) -> Self {
+        Self::seed_with_stream(seed, DEFAULT_STREAM)
+    }
+
+    /// Creates a new pseudo-random number generator with a custom seed on the specified stream.
+    ///
+    /// Generators created with the same seed but different streams produce independent sequences,
+    /// which is useful to give each worker thread its own reproducible generator.
+    pub fn seed_with_stream(seed: u64, stream: u64) -> Self {
         // We xor the seed with a randomly chosen number to avoid ending up with
         // a 0 state which would be bad.
         Self {
             state: seed ^ 0xedef_335f_00e1_70b3,
-            inc: 12345,
+            // The increment must be odd.
+            inc: (stream << 1) | 1,
         }
     }
 
+    /// Advances the generator by `delta` steps in O(log(delta)), as if `gen_int` was called `delta` times.
+    pub fn advance(&mut self, delta: u64) {
+        // Jump-ahead algorithm from Brown, "Random Number Generation with Arbitrary Stride".
+        let mut delta = delta;
+        let mut cur_mult = MULTIPLIER;
+        let mut cur_plus = self.inc | 1;
+        let mut acc_mult = 1u64;
+        let mut acc_plus = 0u64;
+        while delta > 0 {
+            if delta & 1 != 0 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta /= 2;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+
     /// Creates a new pseudo-random number generator with default seed.
     pub fn new() -> Self {
         Self::default()
@@ -81,13 +117,104 @@ impl Rng {
         let n = f64::from(self.gen_int());
         n / max
     }
+
+    /// Returns an infinite iterator of integers, as generated by `gen_int`.
+    pub fn iter(&mut self) -> Iter<'_> {
+        Iter {
+            rng: self,
+        }
+    }
+
+    /// Returns an infinite iterator of values sampled from `distribution`.
+    pub fn sample_iter<D, T>(&mut self, distribution: D) -> SampleIter<'_, D, T>
+    where D: Distribution<T>,
+    {
+        SampleIter {
+            distribution,
+            rng: self,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// A way to produce values of type `T` from a random number generator.
+pub trait Distribution<T> {
+    /// Generates a value using `rng`.
+    fn sample(&self, rng: &mut Rng) -> T;
+}
+
+impl<F, T> Distribution<T> for F
+where F: Fn(&mut Rng) -> T,
+{
+    fn sample(&self, rng: &mut Rng) -> T {
+        self(rng)
+    }
+}
+
+/// Integers between `min` (included) and `max` (excluded), i.e. [min, max).
+pub struct Interval {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Distribution<u32> for Interval {
+    fn sample(&self, rng: &mut Rng) -> u32 {
+        rng.gen_int_interval(self.min, self.max)
+    }
+}
+
+/// Floating-point numbers between 0.0 and 1.0, both included.
+pub struct UnitInterval;
+
+impl Distribution<f64> for UnitInterval {
+    fn sample(&self, rng: &mut Rng) -> f64 {
+        rng.gen_double_interval_unit()
+    }
+}
+
+/// Infinite iterator of integers created by `Rng::iter`.
+pub struct Iter<'a> {
+    rng: &'a mut Rng,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        Some(self.rng.gen_int())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// Infinite iterator of sampled values created by `Rng::sample_iter`.
+pub struct SampleIter<'a, D, T> {
+    distribution: D,
+    rng: &'a mut Rng,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, D, T> Iterator for SampleIter<'a, D, T>
+where D: Distribution<T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        Some(self.distribution.sample(self.rng))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
-    use super::Rng;
+    use super::{Interval, Rng, UnitInterval};
 
     #[test]
     fn avg_median() {
@@ -131,6 +258,57 @@ mod tests {
         assert!(!values.iter().any(|&v| v == 0));
     }
 
+    #[test]
+    fn streams() {
+        let mut rng1 = Rng::seed_with(42);
+        let mut rng2 = Rng::seed_with_stream(42, 6172);
+        for _ in 0..100 {
+            assert_eq!(rng1.gen_int(), rng2.gen_int());
+        }
+
+        let first: Vec<_> = {
+            let mut rng = Rng::seed_with_stream(42, 1);
+            (0..100).map(|_| rng.gen_int()).collect()
+        };
+        let second: Vec<_> = {
+            let mut rng = Rng::seed_with_stream(42, 2);
+            (0..100).map(|_| rng.gen_int()).collect()
+        };
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn advance() {
+        let mut rng1 = Rng::seed_with_stream(1234, 7);
+        let mut rng2 = Rng::seed_with_stream(1234, 7);
+        for _ in 0..1000 {
+            rng1.gen_int();
+        }
+        rng2.advance(1000);
+        for _ in 0..100 {
+            assert_eq!(rng1.gen_int(), rng2.gen_int());
+        }
+
+        let mut rng3 = Rng::seed_with(1234);
+        let state = rng3.state;
+        rng3.advance(0);
+        assert_eq!(rng3.state, state);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut rng1 = Rng::seed_with(99);
+        let mut rng2 = Rng::seed_with(99);
+        let values: Vec<_> = rng1.iter().take(10).collect();
+        let expected: Vec<_> = (0..10).map(|_| rng2.gen_int()).collect();
+        assert_eq!(values, expected);
+
+        assert!(rng1.sample_iter(Interval { min: 10, max: 20 }).take(1000).all(|n| n >= 10 && n < 20));
+        assert!(rng1.sample_iter(UnitInterval).take(1000).all(|n| n >= 0.0 && n <= 1.0));
+        let dice: Vec<u32> = rng1.sample_iter(|rng: &mut Rng| rng.gen_int_interval(1, 7)).take(5).collect();
+        assert_eq!(dice.len(), 5);
+    }
+
     #[test]
     fn distribution_small() {
         distribution_with_capacity(400_000);
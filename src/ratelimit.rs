@@ -0,0 +1,266 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Token-bucket rate limiting.
+//!
+//! A bucket holds up to `capacity` tokens and is refilled at `rate` tokens per second; every
+//! operation takes tokens and is refused when there are not enough of them. This allows bursts of
+//! `capacity` operations while limiting the average to `rate` operations per second.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    last_refill: Instant,
+    tokens: f64,
+}
+
+impl Bucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        Self {
+            last_refill: now,
+            tokens: capacity,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, rate: f64, now: Instant) {
+        if now > self.last_refill {
+            let elapsed = now - self.last_refill;
+            let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+            self.tokens = (self.tokens + elapsed * rate).min(capacity);
+            self.last_refill = now;
+        }
+    }
+
+    fn try_acquire(&mut self, tokens: f64, capacity: f64, rate: f64, now: Instant) -> bool {
+        self.refill(capacity, rate, now);
+        if self.tokens >= tokens {
+            self.tokens -= tokens;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn wait_time(&mut self, tokens: f64, capacity: f64, rate: f64, now: Instant) -> Option<Duration> {
+        if tokens > capacity {
+            return None;
+        }
+        self.refill(capacity, rate, now);
+        let missing = tokens - self.tokens;
+        if missing <= 0.0 {
+            return Some(Duration::from_secs(0));
+        }
+        if rate <= 0.0 {
+            return None;
+        }
+        let secs = missing / rate;
+        Some(Duration::new(secs as u64, ((secs - secs.trunc()) * 1_000_000_000.0) as u32))
+    }
+}
+
+/// A thread-safe token bucket.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bucket: Mutex<Bucket>,
+    capacity: f64,
+    rate: f64,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket holding at most `capacity` tokens and refilled at `rate` tokens per
+    /// second.
+    pub fn new(capacity: u32, rate: f64) -> Self {
+        let capacity = f64::from(capacity);
+        Self {
+            bucket: Mutex::new(Bucket::full(capacity, Instant::now())),
+            capacity,
+            rate,
+        }
+    }
+
+    /// Returns the number of tokens currently available.
+    pub fn available(&self) -> u32 {
+        let mut bucket = self.bucket.lock().expect("lock");
+        bucket.refill(self.capacity, self.rate, Instant::now());
+        bucket.tokens as u32
+    }
+
+    /// Takes one token, returning false if none is available.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_n(1)
+    }
+
+    /// Takes `tokens` tokens, returning false without taking any if not enough are available.
+    pub fn try_acquire_n(&self, tokens: u32) -> bool {
+        self.try_acquire_at(tokens, Instant::now())
+    }
+
+    fn try_acquire_at(&self, tokens: u32, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().expect("lock");
+        bucket.try_acquire(f64::from(tokens), self.capacity, self.rate, now)
+    }
+
+    /// Returns how long to wait until `tokens` tokens are available, or `None` if they never will
+    /// be because `tokens` exceeds the capacity.
+    pub fn wait_time(&self, tokens: u32) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().expect("lock");
+        bucket.wait_time(f64::from(tokens), self.capacity, self.rate, Instant::now())
+    }
+}
+
+/// Number of acquisitions between two purges of the idle keys.
+const PURGE_INTERVAL: usize = 1024;
+
+struct Keys<K> {
+    buckets: HashMap<K, Bucket>,
+    operations: usize,
+}
+
+/// A thread-safe set of token buckets, one per key (e.g. per client IP address).
+///
+/// A key whose bucket has been refilled completely is forgotten, since a new bucket would behave
+/// the same: memory use is thus bounded by the number of keys active during the refill time.
+pub struct KeyedLimiter<K> {
+    capacity: f64,
+    keys: Mutex<Keys<K>>,
+    rate: f64,
+}
+
+impl<K: Eq + Hash> KeyedLimiter<K> {
+    /// Creates a limiter whose buckets hold at most `capacity` tokens and are refilled at `rate`
+    /// tokens per second.
+    pub fn new(capacity: u32, rate: f64) -> Self {
+        Self {
+            capacity: f64::from(capacity),
+            keys: Mutex::new(Keys {
+                buckets: HashMap::new(),
+                operations: 0,
+            }),
+            rate,
+        }
+    }
+
+    /// Returns the number of keys being tracked.
+    pub fn len(&self) -> usize {
+        self.keys.lock().expect("lock").buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the keys whose bucket is full.
+    pub fn purge(&self) {
+        let mut keys = self.keys.lock().expect("lock");
+        self.purge_at(&mut keys, Instant::now());
+    }
+
+    fn purge_at(&self, keys: &mut Keys<K>, now: Instant) {
+        let capacity = self.capacity;
+        let rate = self.rate;
+        keys.buckets.retain(|_, bucket| {
+            bucket.refill(capacity, rate, now);
+            bucket.tokens < capacity
+        });
+        keys.operations = 0;
+    }
+
+    /// Takes one token from the bucket of `key`, returning false if none is available.
+    pub fn try_acquire(&self, key: K) -> bool {
+        self.try_acquire_n(key, 1)
+    }
+
+    /// Takes `tokens` tokens from the bucket of `key`, returning false without taking any if not
+    /// enough are available.
+    pub fn try_acquire_n(&self, key: K, tokens: u32) -> bool {
+        self.try_acquire_at(key, tokens, Instant::now())
+    }
+
+    fn try_acquire_at(&self, key: K, tokens: u32, now: Instant) -> bool {
+        let mut keys = self.keys.lock().expect("lock");
+        keys.operations += 1;
+        if keys.operations >= PURGE_INTERVAL {
+            self.purge_at(&mut keys, now);
+        }
+        let capacity = self.capacity;
+        keys.buckets.entry(key)
+            .or_insert_with(|| Bucket::full(capacity, now))
+            .try_acquire(f64::from(tokens), capacity, self.rate, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{KeyedLimiter, TokenBucket};
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket::new(3, 10.0);
+        let now = Instant::now();
+        assert!(bucket.try_acquire_at(2, now));
+        assert!(bucket.try_acquire_at(1, now));
+        assert!(!bucket.try_acquire_at(1, now));
+        assert!(bucket.try_acquire_at(1, now + Duration::from_millis(100)));
+        assert!(!bucket.try_acquire_at(1, now + Duration::from_millis(150)));
+        // Refilling stops at the capacity.
+        assert!(bucket.try_acquire_at(3, now + Duration::from_secs(10)));
+        assert!(!bucket.try_acquire_at(1, now + Duration::from_secs(10)));
+        assert!(!bucket.try_acquire_n(4));
+        assert_eq!(bucket.wait_time(4), None);
+
+        let bucket = TokenBucket::new(1, 2.0);
+        assert_eq!(bucket.wait_time(1), Some(Duration::from_secs(0)));
+        assert!(bucket.try_acquire());
+        let wait = bucket.wait_time(1).expect("wait time");
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn keyed_limiter() {
+        let limiter = KeyedLimiter::new(2, 1.0);
+        let now = Instant::now();
+        assert!(limiter.try_acquire_at("a", 1, now));
+        assert!(limiter.try_acquire_at("a", 1, now));
+        assert!(!limiter.try_acquire_at("a", 1, now));
+        assert!(limiter.try_acquire_at("b", 2, now));
+        assert_eq!(limiter.len(), 2);
+
+        assert!(limiter.try_acquire_at("a", 1, now + Duration::from_secs(1)));
+        {
+            let mut keys = limiter.keys.lock().expect("lock");
+            limiter.purge_at(&mut keys, now + Duration::from_secs(2));
+        }
+        // "b" is full again, but "a" is not.
+        assert_eq!(limiter.len(), 1);
+        {
+            let mut keys = limiter.keys.lock().expect("lock");
+            limiter.purge_at(&mut keys, now + Duration::from_secs(3));
+        }
+        assert!(limiter.is_empty());
+    }
+}
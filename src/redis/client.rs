@@ -0,0 +1,540 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::time::Duration;
+
+use aio::handler::{Handler, Loop, Stream};
+use aio::net::{TcpConnection, TcpConnectionNotify};
+use bytes::Bytes;
+use parse;
+use retry::{Exponential, Retry};
+use super::{Decoder, Value, encode_command};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The server answered with an error reply.
+    Server(String),
+    /// The connection was lost before the reply arrived. The command may have been executed.
+    ConnectionLost,
+    /// The server sent data that is not valid RESP.
+    Protocol(parse::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Server(ref message) => write!(formatter, "server error: {}", message),
+            Error::ConnectionLost => write!(formatter, "connection lost"),
+            Error::Protocol(ref error) => write!(formatter, "protocol error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// A message published on a channel the subscriber listens to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PubSubMessage {
+    pub channel: String,
+    /// The pattern that matched the channel, for pattern subscriptions.
+    pub pattern: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+type Completion = Box<dyn FnOnce(Result<Value, Error>)>;
+
+enum Subscription {
+    PSubscribe(String),
+    PUnsubscribe(String),
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+enum Msg {
+    Closed(u32),
+    Command(Vec<u8>, Completion),
+    Connect,
+    Connected(u32, TcpConnection),
+    Received(u32, Bytes),
+    Subscription(Subscription),
+}
+
+/// Forwards the events of one connection attempt, tagged so that the events of an abandoned
+/// connection can be ignored.
+struct Notify {
+    generation: u32,
+    stream: Stream<Msg>,
+}
+
+impl TcpConnectionNotify for Notify {
+    fn closed(&mut self, _connection: &mut TcpConnection) {
+        self.stream.send(Msg::Closed(self.generation));
+    }
+
+    fn connect_failed(&mut self) {
+        self.stream.send(Msg::Closed(self.generation));
+    }
+
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        self.stream.send(Msg::Connected(self.generation, connection.clone()));
+    }
+
+    fn error(&mut self, _error: ::std::io::Error) {
+        self.stream.send(Msg::Closed(self.generation));
+    }
+
+    fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
+        self.stream.send(Msg::Received(self.generation, data));
+    }
+}
+
+struct PubSub {
+    callback: Box<dyn Fn(PubSubMessage)>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+struct Connection {
+    connection: Option<TcpConnection>,
+    decoder: Decoder,
+    event_loop: Loop,
+    generation: u32,
+    host: String,
+    /// The completions of the commands sent, in order.
+    pending: VecDeque<Completion>,
+    port: u16,
+    pubsub: Option<PubSub>,
+    /// The commands waiting for the connection.
+    queued: Vec<(Vec<u8>, Completion)>,
+    retry: Option<Retry<Msg, Exponential>>,
+}
+
+impl Connection {
+    fn new(event_loop: &Loop, host: &str, port: u16, pubsub: Option<PubSub>) -> Self {
+        Self {
+            connection: None,
+            decoder: Decoder::new(),
+            event_loop: event_loop.clone(),
+            generation: 0,
+            host: host.to_string(),
+            pending: VecDeque::new(),
+            port,
+            pubsub,
+            queued: vec![],
+            retry: None,
+        }
+    }
+
+    fn connect(&mut self, stream: &Stream<Msg>) {
+        self.generation = self.generation.wrapping_add(1);
+        let notify = Notify {
+            generation: self.generation,
+            stream: stream.clone(),
+        };
+        if TcpConnection::ip4(&mut self.event_loop, &self.host, self.port, notify).is_none() {
+            self.reconnect(stream);
+        }
+    }
+
+    fn connected(&mut self, connection: TcpConnection) {
+        if let Some(ref mut retry) = self.retry {
+            let _ = retry.reset();
+        }
+        if let Some(ref pubsub) = self.pubsub {
+            if !pubsub.channels.is_empty() {
+                send(&connection, "SUBSCRIBE", &pubsub.channels);
+            }
+            if !pubsub.patterns.is_empty() {
+                send(&connection, "PSUBSCRIBE", &pubsub.patterns);
+            }
+        }
+        for (command, completion) in mem::take(&mut self.queued) {
+            self.write(&connection, command, completion);
+        }
+        self.connection = Some(connection);
+    }
+
+    /// Abandons the current connection, fails the commands waiting for a reply and schedules a
+    /// new connection.
+    fn disconnect(&mut self, stream: &Stream<Msg>, error: Error) {
+        if let Some(connection) = self.connection.take() {
+            connection.dispose();
+        }
+        // Ignore the remaining events of the abandoned connection.
+        self.generation = self.generation.wrapping_add(1);
+        self.decoder.clear();
+        for completion in self.pending.drain(..) {
+            completion(Err(error.clone()));
+        }
+        self.reconnect(stream);
+    }
+
+    fn dispatch(&mut self, value: Value) {
+        if let Some(ref pubsub) = self.pubsub {
+            if let Some(message) = pubsub_message(value) {
+                (pubsub.callback)(message);
+            }
+            // Other replies confirm subscriptions and need no completion.
+            return;
+        }
+        if let Some(completion) = self.pending.pop_front() {
+            let result =
+                match value {
+                    Value::Error(message) => Err(Error::Server(message)),
+                    value => Ok(value),
+                };
+            completion(result);
+        }
+    }
+
+    fn reconnect(&mut self, stream: &Stream<Msg>) {
+        if self.retry.is_none() {
+            let policy = Exponential::new(Duration::from_millis(100))
+                .max(Duration::from_secs(10));
+            match Retry::new(&self.event_loop, stream, policy) {
+                Ok(retry) => self.retry = Some(retry),
+                Err(_) => return,
+            }
+        }
+        if let Some(ref mut retry) = self.retry {
+            let _ = retry.schedule(Msg::Connect);
+        }
+    }
+
+    fn write(&mut self, connection: &TcpConnection, command: Vec<u8>, completion: Completion) {
+        match connection.write(command) {
+            Ok(()) => self.pending.push_back(completion),
+            Err(_) => completion(Err(Error::ConnectionLost)),
+        }
+    }
+}
+
+impl Handler for Connection {
+    type Msg = Msg;
+
+    fn update(&mut self, stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Msg::Closed(generation) =>
+                if generation == self.generation {
+                    self.disconnect(stream, Error::ConnectionLost);
+                },
+            Msg::Command(command, completion) => {
+                match self.connection.clone() {
+                    Some(connection) => self.write(&connection, command, completion),
+                    None => self.queued.push((command, completion)),
+                }
+            },
+            Msg::Connect => self.connect(stream),
+            Msg::Connected(generation, connection) =>
+                if generation == self.generation {
+                    self.connected(connection);
+                },
+            Msg::Received(generation, data) => {
+                if generation != self.generation {
+                    return;
+                }
+                self.decoder.feed(&data);
+                loop {
+                    match self.decoder.decode() {
+                        Ok(Some(value)) => self.dispatch(value),
+                        Ok(None) => break,
+                        Err(error) => {
+                            self.disconnect(stream, Error::Protocol(error));
+                            break;
+                        },
+                    }
+                }
+            },
+            Msg::Subscription(subscription) => {
+                let pubsub =
+                    match self.pubsub {
+                        Some(ref mut pubsub) => pubsub,
+                        None => return,
+                    };
+                let (command, name) =
+                    match subscription {
+                        Subscription::PSubscribe(pattern) => {
+                            pubsub.patterns.insert(pattern.clone());
+                            ("PSUBSCRIBE", pattern)
+                        },
+                        Subscription::PUnsubscribe(pattern) => {
+                            pubsub.patterns.remove(&pattern);
+                            ("PUNSUBSCRIBE", pattern)
+                        },
+                        Subscription::Subscribe(channel) => {
+                            pubsub.channels.insert(channel.clone());
+                            ("SUBSCRIBE", channel)
+                        },
+                        Subscription::Unsubscribe(channel) => {
+                            pubsub.channels.remove(&channel);
+                            ("UNSUBSCRIBE", channel)
+                        },
+                    };
+                // Otherwise, the subscriptions are sent once connected.
+                if let Some(ref connection) = self.connection {
+                    send(connection, command, &[name]);
+                }
+            },
+        }
+    }
+}
+
+fn send<'a, I: IntoIterator<Item=&'a String>>(connection: &TcpConnection, command: &str, names: I) {
+    let mut args = vec![command];
+    args.extend(names.into_iter().map(|name| name.as_str()));
+    let _ = connection.write(encode_command(&args));
+}
+
+fn pubsub_message(value: Value) -> Option<PubSubMessage> {
+    let mut values =
+        match value {
+            Value::Array(values) => values.into_iter(),
+            _ => return None,
+        };
+    let string = |value: Option<Value>| value.as_ref().and_then(Value::as_str).map(str::to_string);
+    let kind = string(values.next())?;
+    let pattern =
+        match kind.as_str() {
+            "message" => None,
+            "pmessage" => Some(string(values.next())?),
+            _ => return None,
+        };
+    let channel = string(values.next())?;
+    let payload =
+        match values.next()? {
+            Value::Bulk(payload) => payload,
+            _ => return None,
+        };
+    Some(PubSubMessage {
+        channel,
+        pattern,
+        payload,
+    })
+}
+
+/// A Redis client whose commands are pipelined on a single connection. The replies are matched
+/// to the commands in order. When the connection is lost, the commands waiting for a reply fail
+/// with `Error::ConnectionLost` and the client reconnects with an exponential backoff; the
+/// commands issued meanwhile are sent once connected.
+#[derive(Clone)]
+pub struct Client {
+    stream: Stream<Msg>,
+}
+
+impl Client {
+    pub fn connect(event_loop: &mut Loop, host: &str, port: u16) -> Self {
+        let stream = event_loop.spawn(Connection::new(event_loop, host, port, None));
+        stream.send(Msg::Connect);
+        Self {
+            stream,
+        }
+    }
+
+    /// Sends a command and sends its reply to the stream, converted by the callback. Error replies
+    /// are converted to `Error::Server`.
+    pub fn command<A, CALLBACK, MSG>(&self, args: &[A], stream: &Stream<MSG>, callback: CALLBACK)
+    where A: AsRef<[u8]>,
+          CALLBACK: FnOnce(Result<Value, Error>) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        let completion = Box::new(move |result| stream.send(callback(result)));
+        self.stream.send(Msg::Command(encode_command(args), completion));
+    }
+}
+
+/// A connection in subscriber mode, which delivers the messages published on its channels to a
+/// stream. The subscriptions are restored after a reconnection.
+#[derive(Clone)]
+pub struct Subscriber {
+    stream: Stream<Msg>,
+}
+
+impl Subscriber {
+    pub fn connect<CALLBACK, MSG>(event_loop: &mut Loop, host: &str, port: u16, stream: &Stream<MSG>, callback: CALLBACK)
+        -> Self
+    where CALLBACK: Fn(PubSubMessage) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        let pubsub = PubSub {
+            callback: Box::new(move |message| stream.send(callback(message))),
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        };
+        let stream = event_loop.spawn(Connection::new(event_loop, host, port, Some(pubsub)));
+        stream.send(Msg::Connect);
+        Self {
+            stream,
+        }
+    }
+
+    pub fn psubscribe(&self, pattern: &str) {
+        self.stream.send(Msg::Subscription(Subscription::PSubscribe(pattern.to_string())));
+    }
+
+    pub fn punsubscribe(&self, pattern: &str) {
+        self.stream.send(Msg::Subscription(Subscription::PUnsubscribe(pattern.to_string())));
+    }
+
+    pub fn subscribe(&self, channel: &str) {
+        self.stream.send(Msg::Subscription(Subscription::Subscribe(channel.to_string())));
+    }
+
+    pub fn unsubscribe(&self, channel: &str) {
+        self.stream.send(Msg::Subscription(Subscription::Unsubscribe(channel.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use aio::handler::{Handler, Loop, Stream};
+    use redis::{Value, decode};
+    use super::{Client, Error, PubSubMessage, Subscriber};
+
+    /// Reads one command from the socket.
+    fn read_command<R: Read>(reader: &mut R, buffer: &mut Vec<u8>) -> Option<Value> {
+        loop {
+            if let Some((value, len)) = decode(buffer).expect("decode") {
+                buffer.drain(..len);
+                return Some(value);
+            }
+            let mut chunk = [0; 1024];
+            match reader.read(&mut chunk) {
+                Ok(0) | Err(_) => return None,
+                Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+            }
+        }
+    }
+
+    fn args(value: Value) -> Vec<String> {
+        match value {
+            Value::Array(values) => values.iter().map(|value| value.as_str().expect("arg").to_string()).collect(),
+            _ => panic!("not an array"),
+        }
+    }
+
+    struct Collector<T> {
+        event_loop: Loop,
+        expected: usize,
+        results: Vec<T>,
+        sender: ::std::sync::mpsc::Sender<Vec<T>>,
+    }
+
+    impl<T> Handler for Collector<T> {
+        type Msg = T;
+
+        fn update(&mut self, _stream: &Stream<T>, msg: T) {
+            self.results.push(msg);
+            if self.results.len() == self.expected {
+                let _ = self.sender.send(::std::mem::take(&mut self.results));
+                self.event_loop.stop();
+            }
+        }
+    }
+
+    fn collect<T: 'static, F: FnOnce(&mut Loop, &Stream<T>)>(expected: usize, start: F) -> Vec<T> {
+        let mut event_loop = Loop::new().expect("event loop");
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let stream = event_loop.spawn(Collector {
+            event_loop: event_loop.clone(),
+            expected,
+            results: vec![],
+            sender,
+        });
+        start(&mut event_loop, &stream);
+        event_loop.run().expect("run");
+        receiver.recv().expect("results")
+    }
+
+    #[test]
+    fn pipelining_and_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("address").port();
+        thread::spawn(move || {
+            // The first connection answers one of the two commands, then drops the connection.
+            let (mut socket, _) = listener.accept().expect("accept");
+            let mut buffer = vec![];
+            assert_eq!(args(read_command(&mut socket, &mut buffer).expect("command")), vec!["SET", "key", "1"]);
+            assert_eq!(args(read_command(&mut socket, &mut buffer).expect("command")), vec!["GET", "key"]);
+            socket.write_all(b"+OK\r\n").expect("write");
+            drop(socket);
+
+            let (mut socket, _) = listener.accept().expect("accept");
+            let mut buffer = vec![];
+            while let Some(command) = read_command(&mut socket, &mut buffer) {
+                let reply: &[u8] =
+                    match args(command)[0].as_str() {
+                        "GET" => b"$1\r\n1\r\n",
+                        "INCR" => b":2\r\n",
+                        _ => b"-ERR unknown command\r\n",
+                    };
+                socket.write_all(reply).expect("write");
+            }
+        });
+
+        let results = collect(5, |event_loop, stream| {
+            let client = Client::connect(event_loop, "127.0.0.1", port);
+            client.command(&["SET", "key", "1"], stream, |result| (1, result));
+            client.command(&["GET", "key"], stream, |result| (2, result));
+            let retry_client = client.clone();
+            let retry_stream = stream.clone();
+            client.command(&["PING"], stream, move |_| {
+                // Sent after the reconnection.
+                retry_client.command(&["GET", "key"], &retry_stream, |result| (3, result));
+                retry_client.command(&["INCR", "key"], &retry_stream, |result| (4, result));
+                retry_client.command(&["NOPE"], &retry_stream, |result| (5, result));
+                (0, Ok(Value::Nil))
+            });
+        });
+        assert_eq!(results, vec![
+            (1, Ok(Value::SimpleString("OK".to_string()))),
+            (2, Err(Error::ConnectionLost)),
+            (0, Ok(Value::Nil)),
+            (3, Ok(Value::Bulk(b"1".to_vec()))),
+            (4, Ok(Value::Integer(2))),
+        ]);
+    }
+
+    #[test]
+    fn pubsub() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("address").port();
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().expect("accept");
+            let mut buffer = vec![];
+            assert_eq!(args(read_command(&mut socket, &mut buffer).expect("command")), vec!["SUBSCRIBE", "news"]);
+            assert_eq!(args(read_command(&mut socket, &mut buffer).expect("command")), vec!["PSUBSCRIBE", "log.*"]);
+            socket.write_all(b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n\
+                               *3\r\n$10\r\npsubscribe\r\n$5\r\nlog.*\r\n:2\r\n\
+                               *3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n\
+                               *4\r\n$8\r\npmessage\r\n$5\r\nlog.*\r\n$8\r\nlog.warn\r\n$4\r\ndisk\r\n")
+                .expect("write");
+            let _ = read_command(&mut socket, &mut buffer);
+        });
+
+        let messages = collect(2, |event_loop, stream| {
+            let subscriber = Subscriber::connect(event_loop, "127.0.0.1", port, stream, |message| message);
+            subscriber.subscribe("news");
+            subscriber.psubscribe("log.*");
+        });
+        assert_eq!(messages, vec![
+            PubSubMessage {
+                channel: "news".to_string(),
+                pattern: None,
+                payload: b"hello".to_vec(),
+            },
+            PubSubMessage {
+                channel: "log.warn".to_string(),
+                pattern: Some("log.*".to_string()),
+                payload: b"disk".to_vec(),
+            },
+        ]);
+    }
+}
@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Redis serialization protocol (RESP2) and a pipelined client running on the event loop.
+
+mod client;
+
+use std::str;
+
+use parse::{self, Cursor, ErrorKind};
+
+pub use self::client::{Client, Error, PubSubMessage, Subscriber};
+
+/// Maximum length of a bulk string, as enforced by Redis.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// A RESP2 value.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Value {
+    SimpleString(String),
+    /// An error reply.
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    /// The null bulk string or the null array.
+    Nil,
+}
+
+impl Value {
+    /// Returns the content of a simple or bulk string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::SimpleString(ref string) => Some(string.as_bytes()),
+            Value::Bulk(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(integer) => Some(integer),
+            _ => None,
+        }
+    }
+
+    /// Returns the content of a simple string or of a UTF-8 bulk string.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|bytes| str::from_utf8(bytes).ok())
+    }
+
+    /// Appends the encoding of the value to `buffer`.
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Value::SimpleString(ref string) => {
+                buffer.push(b'+');
+                buffer.extend_from_slice(string.as_bytes());
+            },
+            Value::Error(ref message) => {
+                buffer.push(b'-');
+                buffer.extend_from_slice(message.as_bytes());
+            },
+            Value::Integer(integer) => buffer.extend_from_slice(format!(":{}", integer).as_bytes()),
+            Value::Bulk(ref bytes) => {
+                put_bulk(buffer, bytes);
+                return;
+            },
+            Value::Array(ref values) => {
+                buffer.extend_from_slice(format!("*{}\r\n", values.len()).as_bytes());
+                for value in values {
+                    value.encode(buffer);
+                }
+                return;
+            },
+            Value::Nil => buffer.extend_from_slice(b"$-1"),
+        }
+        buffer.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Encodes a command, which is sent as an array of bulk strings.
+pub fn encode_command<A: AsRef<[u8]>>(args: &[A]) -> Vec<u8> {
+    let mut buffer = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        put_bulk(&mut buffer, arg.as_ref());
+    }
+    buffer
+}
+
+fn put_bulk(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+    buffer.extend_from_slice(bytes);
+    buffer.extend_from_slice(b"\r\n");
+}
+
+/// Decodes the value at the start of `bytes`, returning it with its encoded length, or `None` if
+/// the value is not complete yet.
+pub fn decode(bytes: &[u8]) -> Result<Option<(Value, usize)>, parse::Error> {
+    let mut cursor = Cursor::new(bytes);
+    match decode_value(&mut cursor) {
+        Ok(value) => Ok(Some((value, cursor.position()))),
+        Err(ref error) if error.is_incomplete() => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn decode_value(cursor: &mut Cursor) -> parse::Result<Value> {
+    let start = cursor.position();
+    let value =
+        match cursor.u8()? {
+            b'+' => Value::SimpleString(line(cursor)?),
+            b'-' => Value::Error(line(cursor)?),
+            b':' => {
+                let integer = cursor.signed_decimal()?;
+                cursor.expect(b"\r\n")?;
+                Value::Integer(integer)
+            },
+            b'$' => {
+                let len = length(cursor)?;
+                if len < 0 {
+                    return Ok(Value::Nil);
+                }
+                if len > MAX_BULK_LEN {
+                    return Err(parse::Error::new(ErrorKind::Overflow, start));
+                }
+                let bytes = cursor.take(len as usize)?.to_vec();
+                cursor.expect(b"\r\n")?;
+                Value::Bulk(bytes)
+            },
+            b'*' => {
+                let count = length(cursor)?;
+                if count < 0 {
+                    return Ok(Value::Nil);
+                }
+                // Do not trust the count for the allocation: the elements may never arrive.
+                let mut values = vec![];
+                for _ in 0..count {
+                    values.push(decode_value(cursor)?);
+                }
+                Value::Array(values)
+            },
+            _ => return Err(parse::Error::new(ErrorKind::Invalid, start)),
+        };
+    Ok(value)
+}
+
+fn length(cursor: &mut Cursor) -> parse::Result<i64> {
+    let len = cursor.signed_decimal()?;
+    cursor.expect(b"\r\n")?;
+    if len < -1 {
+        return Err(cursor.error(ErrorKind::Invalid));
+    }
+    Ok(len)
+}
+
+fn line(cursor: &mut Cursor) -> parse::Result<String> {
+    cursor.take_until(b"\r\n").map(|line| String::from_utf8_lossy(line).into_owned())
+}
+
+/// Accumulates received chunks and decodes the values they contain.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the next complete value, if any.
+    pub fn decode(&mut self) -> Result<Option<Value>, parse::Error> {
+        match decode(&self.buffer)? {
+            Some((value, len)) => {
+                self.buffer.drain(..len);
+                Ok(Some(value))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Discards the buffered data, e.g. after a reconnection.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parse::{Error, ErrorKind};
+    use super::{Decoder, Value, decode, encode_command};
+
+    #[test]
+    fn encode() {
+        assert_eq!(encode_command(&["SET", "key", "value"]), b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n".to_vec());
+        let value = Value::Array(vec![
+            Value::SimpleString("OK".to_string()),
+            Value::Error("ERR unknown".to_string()),
+            Value::Integer(-42),
+            Value::Bulk(b"a\r\nb".to_vec()),
+            Value::Nil,
+            Value::Array(vec![]),
+        ]);
+        let mut buffer = vec![];
+        value.encode(&mut buffer);
+        assert_eq!(buffer, b"*6\r\n+OK\r\n-ERR unknown\r\n:-42\r\n$4\r\na\r\nb\r\n$-1\r\n*0\r\n".to_vec());
+        assert_eq!(decode(&buffer), Ok(Some((value, buffer.len()))));
+    }
+
+    #[test]
+    fn incremental() {
+        let data = b"$5\r\nhello\r\n*-1\r\n:7\r\n";
+        for len in 0..11 {
+            assert_eq!(decode(&data[..len]), Ok(None));
+        }
+        let mut decoder = Decoder::new();
+        for byte in data.iter() {
+            decoder.feed(&[*byte]);
+            if let Some(value) = decoder.decode().expect("decode") {
+                assert_eq!(value, Value::Bulk(b"hello".to_vec()));
+                break;
+            }
+        }
+        decoder.feed(&data[11..]);
+        assert_eq!(decoder.decode(), Ok(Some(Value::Nil)));
+        assert_eq!(decoder.decode(), Ok(Some(Value::Integer(7))));
+        assert_eq!(decoder.decode(), Ok(None));
+
+        assert_eq!(decode(b"?\r\n"), Err(Error::new(ErrorKind::Invalid, 0)));
+        assert_eq!(decode(b"$-2\r\n"), Err(Error::new(ErrorKind::Invalid, 5)));
+        assert_eq!(decode(b"$1\r\nab\r\n"), Err(Error::new(ErrorKind::Invalid, 5)));
+        assert_eq!(Value::Bulk(b"1".to_vec()).as_str(), Some("1"));
+        assert_eq!(Value::Integer(1).as_integer(), Some(1));
+    }
+}
@@ -0,0 +1,472 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Retry policies with backoff, usable synchronously or from event loop handlers.
+//!
+//! A policy decides how long to wait after each failed attempt and when to give up. Policies
+//! compose: `Exponential::new(base).max_attempts(5).max_elapsed(timeout)`.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::{Rc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aio::async::{Action, Mode};
+use aio::handler::{Loop, Stream};
+use aio::timer::TimerFd;
+use rand::Rng;
+
+/// Decides the delay between attempts.
+pub trait Policy {
+    /// Returns the delay to wait before the next attempt, or `None` to give up.
+    /// `attempt` is the number of attempts that failed so far (starting at 1) and `elapsed` is the
+    /// time since the first attempt started.
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration>;
+
+    /// Gives up after `attempts` attempts, including the first one.
+    fn max_attempts(self, attempts: u32) -> MaxAttempts<Self>
+    where Self: Sized,
+    {
+        MaxAttempts {
+            attempts,
+            policy: self,
+        }
+    }
+
+    /// Caps each delay to `delay`.
+    fn max_delay(self, delay: Duration) -> MaxDelay<Self>
+    where Self: Sized,
+    {
+        MaxDelay {
+            delay,
+            policy: self,
+        }
+    }
+
+    /// Gives up when the next attempt would start after `elapsed` since the first attempt.
+    fn max_elapsed(self, elapsed: Duration) -> MaxElapsed<Self>
+    where Self: Sized,
+    {
+        MaxElapsed {
+            elapsed,
+            policy: self,
+        }
+    }
+}
+
+impl<P: Policy + ?Sized> Policy for &mut P {
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        (**self).next_delay(attempt, elapsed)
+    }
+}
+
+impl<P: Policy + ?Sized> Policy for Box<P> {
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        (**self).next_delay(attempt, elapsed)
+    }
+}
+
+/// Waits the same delay between every attempt and never gives up.
+#[derive(Clone, Debug)]
+pub struct Fixed {
+    delay: Duration,
+}
+
+impl Fixed {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+        }
+    }
+}
+
+impl Policy for Fixed {
+    fn next_delay(&mut self, _attempt: u32, _elapsed: Duration) -> Option<Duration> {
+        Some(self.delay)
+    }
+}
+
+/// How much randomness is applied to an exponential delay.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Jitter {
+    /// The delay is used as is.
+    None,
+    /// The delay is chosen uniformly between 0 and the computed delay.
+    Full,
+    /// The delay is chosen uniformly between half the computed delay and the computed delay.
+    Equal,
+}
+
+/// Multiplies the delay by a factor after every attempt and never gives up.
+/// The default factor is 2, the default maximum delay is 1 minute and the default jitter is
+/// `Jitter::Full`.
+#[derive(Clone, Debug)]
+pub struct Exponential {
+    factor: f64,
+    initial: Duration,
+    jitter: Jitter,
+    max: Duration,
+    rng: Rng,
+}
+
+impl Exponential {
+    pub fn new(initial: Duration) -> Self {
+        Self {
+            factor: 2.0,
+            initial,
+            jitter: Jitter::Full,
+            max: Duration::from_secs(60),
+            rng: Rng::new(),
+        }
+    }
+
+    pub fn factor(mut self, factor: f64) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the maximum delay before jitter is applied.
+    pub fn max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Uses the generator for the jitter, for reproducible delays.
+    pub fn rng(mut self, rng: Rng) -> Self {
+        self.rng = rng;
+        self
+    }
+}
+
+impl Policy for Exponential {
+    fn next_delay(&mut self, attempt: u32, _elapsed: Duration) -> Option<Duration> {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let max = duration_to_secs(self.max);
+        let delay = (duration_to_secs(self.initial) * self.factor.powi(exponent)).min(max);
+        let delay =
+            match self.jitter {
+                Jitter::None => delay,
+                Jitter::Full => delay * self.rng.gen_double_interval_unit(),
+                Jitter::Equal => delay / 2.0 + delay / 2.0 * self.rng.gen_double_interval_unit(),
+            };
+        Some(secs_to_duration(delay))
+    }
+}
+
+/// Policy returned by `Policy::max_attempts`.
+#[derive(Clone, Debug)]
+pub struct MaxAttempts<P> {
+    attempts: u32,
+    policy: P,
+}
+
+impl<P: Policy> Policy for MaxAttempts<P> {
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        self.policy.next_delay(attempt, elapsed)
+    }
+}
+
+/// Policy returned by `Policy::max_delay`.
+#[derive(Clone, Debug)]
+pub struct MaxDelay<P> {
+    delay: Duration,
+    policy: P,
+}
+
+impl<P: Policy> Policy for MaxDelay<P> {
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        self.policy.next_delay(attempt, elapsed)
+            .map(|delay| delay.min(self.delay))
+    }
+}
+
+/// Policy returned by `Policy::max_elapsed`.
+#[derive(Clone, Debug)]
+pub struct MaxElapsed<P> {
+    elapsed: Duration,
+    policy: P,
+}
+
+impl<P: Policy> Policy for MaxElapsed<P> {
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        self.policy.next_delay(attempt, elapsed)
+            .and_then(|delay| {
+                if elapsed + delay > self.elapsed {
+                    None
+                }
+                else {
+                    Some(delay)
+                }
+            })
+    }
+}
+
+/// Calls `operation` until it succeeds or the policy gives up, sleeping between attempts. The
+/// operation receives the attempt number, starting at 1. Returns the last error on failure.
+pub fn retry<F, P, T, E>(policy: P, operation: F) -> Result<T, E>
+where F: FnMut(u32) -> Result<T, E>,
+      P: Policy,
+{
+    retry_if(policy, operation, |_| true)
+}
+
+/// Like `retry`, but stops at the first error for which `should_retry` returns false.
+pub fn retry_if<C, F, P, T, E>(mut policy: P, mut operation: F, mut should_retry: C) -> Result<T, E>
+where C: FnMut(&E) -> bool,
+      F: FnMut(u32) -> Result<T, E>,
+      P: Policy,
+{
+    let start = Instant::now();
+    let mut attempt = 1;
+    loop {
+        match operation(attempt) {
+            Ok(value) => return Ok(value),
+            Err(error) => {
+                if !should_retry(&error) {
+                    return Err(error);
+                }
+                match policy.next_delay(attempt, start.elapsed()) {
+                    Some(delay) => thread::sleep(delay),
+                    None => return Err(error),
+                }
+            },
+        }
+        attempt += 1;
+    }
+}
+
+/// Schedules retries from an event loop handler: after a failure, `schedule` sends a message to
+/// the stream once the delay chosen by the policy has elapsed. Call `reset` after a success so
+/// that the next failure starts over with the initial delay.
+pub struct Retry<MSG, P> {
+    attempt: u32,
+    event_loop: Loop,
+    pending: Rc<RefCell<Option<MSG>>>,
+    policy: P,
+    start: Option<Instant>,
+    timer: Rc<TimerFd>,
+}
+
+impl<MSG: 'static, P: Policy> Retry<MSG, P> {
+    pub fn new(event_loop: &Loop, stream: &Stream<MSG>, policy: P) -> io::Result<Self> {
+        let timer = Rc::new(TimerFd::new()?);
+        let pending = Rc::new(RefCell::new(None));
+        {
+            let pending = pending.clone();
+            let stream = stream.clone();
+            let weak_timer: Weak<TimerFd> = Rc::downgrade(&timer);
+            event_loop.event_loop().add_fd(&*timer, Mode::Read, move |_event| {
+                if let Some(timer) = weak_timer.upgrade() {
+                    timer.acknowledge();
+                }
+                if let Some(msg) = pending.borrow_mut().take() {
+                    stream.send(msg);
+                }
+                Action::Continue
+            })?;
+        }
+        Ok(Self {
+            attempt: 0,
+            event_loop: event_loop.clone(),
+            pending,
+            policy,
+            start: None,
+            timer,
+        })
+    }
+
+    /// Returns the number of failed attempts since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Cancels the pending retry, if any.
+    pub fn cancel(&mut self) -> io::Result<()> {
+        *self.pending.borrow_mut() = None;
+        self.timer.disarm()
+    }
+
+    /// Cancels the pending retry and forgets the previous failures.
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.attempt = 0;
+        self.start = None;
+        self.cancel()
+    }
+
+    /// Records a failed attempt and sends `msg` to the stream after the next delay. Returns the
+    /// delay, or `None` if the policy gave up, in which case nothing is sent. A pending retry is
+    /// replaced.
+    pub fn schedule(&mut self, msg: MSG) -> io::Result<Option<Duration>> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.attempt += 1;
+        match self.policy.next_delay(self.attempt, start.elapsed()) {
+            Some(delay) => {
+                *self.pending.borrow_mut() = Some(msg);
+                self.timer.set(delay)?;
+                Ok(Some(delay))
+            },
+            None => {
+                self.cancel()?;
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl<MSG, P> Drop for Retry<MSG, P> {
+    fn drop(&mut self) {
+        *self.pending.borrow_mut() = None;
+        let _ = self.event_loop.remove_fd(&*self.timer);
+    }
+}
+
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn secs_to_duration(secs: f64) -> Duration {
+    if secs >= u64::MAX as f64 {
+        return Duration::from_secs(u64::MAX);
+    }
+    let secs = secs.max(0.0);
+    Duration::new(secs as u64, ((secs - secs.trunc()) * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use aio::handler::{Handler, Loop, Stream};
+    use rand::Rng;
+    use super::{Exponential, Fixed, Jitter, Policy, Retry, retry, retry_if};
+
+    #[test]
+    fn policies() {
+        let mut policy = Exponential::new(Duration::from_millis(100))
+            .jitter(Jitter::None)
+            .max(Duration::from_secs(1));
+        let delays: Vec<_> = (1..6).map(|attempt| policy.next_delay(attempt, Duration::from_secs(0))).collect();
+        assert_eq!(delays, vec![
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(400)),
+            Some(Duration::from_millis(800)),
+            Some(Duration::from_secs(1)),
+        ]);
+
+        let mut policy = Exponential::new(Duration::from_secs(1)).rng(Rng::seed_with(42));
+        for attempt in 1..20 {
+            let delay = policy.next_delay(attempt, Duration::from_secs(0)).expect("delay");
+            assert!(delay <= Duration::from_secs(60));
+        }
+        let mut policy = Exponential::new(Duration::from_secs(4)).jitter(Jitter::Equal);
+        let delay = policy.next_delay(1, Duration::from_secs(0)).expect("delay");
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+
+        let mut policy = Fixed::new(Duration::from_secs(1)).max_attempts(3);
+        assert!(policy.next_delay(1, Duration::from_secs(0)).is_some());
+        assert!(policy.next_delay(2, Duration::from_secs(0)).is_some());
+        assert_eq!(policy.next_delay(3, Duration::from_secs(0)), None);
+
+        let mut policy = Fixed::new(Duration::from_secs(1)).max_elapsed(Duration::from_secs(10));
+        assert!(policy.next_delay(1, Duration::from_secs(9)).is_some());
+        assert_eq!(policy.next_delay(2, Duration::from_millis(9500)), None);
+
+        let mut policy = Fixed::new(Duration::from_secs(5)).max_delay(Duration::from_secs(2));
+        assert_eq!(policy.next_delay(1, Duration::from_secs(0)), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn sync_retry() {
+        let policy = Fixed::new(Duration::from_millis(1)).max_attempts(5);
+        let result: Result<u32, u32> = retry(policy, |attempt| if attempt < 3 { Err(attempt) } else { Ok(attempt) });
+        assert_eq!(result, Ok(3));
+
+        let policy = Fixed::new(Duration::from_millis(1)).max_attempts(4);
+        let result: Result<(), u32> = retry(policy, Err);
+        assert_eq!(result, Err(4));
+
+        let calls = Cell::new(0);
+        let result: Result<(), &str> = retry_if(Fixed::new(Duration::from_millis(1)), |_| {
+            calls.set(calls.get() + 1);
+            Err("fatal")
+        }, |error| *error != "fatal");
+        assert_eq!(result, Err("fatal"));
+        assert_eq!(calls.get(), 1);
+    }
+
+    enum Msg {
+        Attempt,
+    }
+
+    struct Connector {
+        attempts: Rc<Cell<u32>>,
+        event_loop: Loop,
+        retry: Option<Retry<Msg, super::MaxAttempts<Fixed>>>,
+    }
+
+    impl Handler for Connector {
+        type Msg = Msg;
+
+        fn update(&mut self, stream: &Stream<Msg>, msg: Msg) {
+            match msg {
+                Msg::Attempt => {
+                    self.attempts.set(self.attempts.get() + 1);
+                    if self.retry.is_none() {
+                        let policy = Fixed::new(Duration::from_millis(10)).max_attempts(3);
+                        self.retry = Some(Retry::new(&self.event_loop, stream, policy).expect("retry"));
+                    }
+                    let retry = self.retry.as_mut().expect("retry");
+                    if retry.schedule(Msg::Attempt).expect("schedule").is_none() {
+                        self.event_loop.stop();
+                    }
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn event_loop_retry() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let attempts = Rc::new(Cell::new(0));
+        let stream = event_loop.spawn(Connector {
+            attempts: attempts.clone(),
+            event_loop: event_loop.clone(),
+            retry: None,
+        });
+        let start = Instant::now();
+        stream.send(Msg::Attempt);
+        event_loop.run().expect("run");
+        assert_eq!(attempts.get(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
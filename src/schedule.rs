@@ -0,0 +1,483 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Cron-like job scheduling.
+//!
+//! A `Schedule` is parsed from a cron expression with 5 fields (minute, hour, day of month, month,
+//! day of week) or 6 fields (with the second first). Fields accept `*`, numbers, ranges (`1-5`),
+//! steps (`*/15`, `0-30/10`), lists (`1,15`) and, for months and days of week, English
+//! abbreviations (`jan`, `mon`). As in Vixie cron, when both the day of month and the day of week
+//! are restricted, a day matching either field matches. The macros `@yearly`, `@monthly`, `@weekly`,
+//! `@daily` and `@hourly` are also accepted.
+//!
+//! Times are evaluated in UTC.
+//!
+//! A `Scheduler` fires jobs on the event loop, either by sending messages to a stream or by calling
+//! callbacks.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aio::async::{Action, Mode};
+use aio::handler::{Loop, Stream};
+use aio::timer::TimerFd;
+use time::{Instant, TimerWheel};
+use time::format::{civil_from_days, days_from_civil};
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAYS_OF_WEEK: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Stop searching for the next occurrence after this number of years, for expressions like
+/// `0 0 30 2 *` that never match.
+const MAX_YEARS: i64 = 5;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Error returned when parsing an invalid cron expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The expression does not have 5 or 6 fields.
+    FieldCount(usize),
+    /// The field is not valid.
+    InvalidField(String),
+    /// The macro is not known.
+    UnknownMacro(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            ParseError::FieldCount(count) => write!(formatter, "expected 5 or 6 fields, found {}", count),
+            ParseError::InvalidField(ref field) => write!(formatter, "invalid field `{}`", field),
+            ParseError::UnknownMacro(ref name) => write!(formatter, "unknown macro `{}`", name),
+        }
+    }
+}
+
+impl error::Error for ParseError {
+}
+
+/// A parsed cron expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schedule {
+    days_of_month: u64,
+    days_of_month_restricted: bool,
+    days_of_week: u64,
+    days_of_week_restricted: bool,
+    hours: u64,
+    minutes: u64,
+    months: u64,
+    seconds: u64,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Self, ParseError> {
+        let expression = expression.trim();
+        if expression.starts_with('@') {
+            let fields =
+                match expression {
+                    "@yearly" | "@annually" => "0 0 1 1 *",
+                    "@monthly" => "0 0 1 * *",
+                    "@weekly" => "0 0 * * 0",
+                    "@daily" | "@midnight" => "0 0 * * *",
+                    "@hourly" => "0 * * * *",
+                    _ => return Err(ParseError::UnknownMacro(expression.to_string())),
+                };
+            return Self::parse(fields);
+        }
+        let mut fields: Vec<_> = expression.split_whitespace().collect();
+        match fields.len() {
+            5 => fields.insert(0, "0"),
+            6 => (),
+            count => return Err(ParseError::FieldCount(count)),
+        }
+        let mut days_of_week = parse_field(fields[5], 0, 7, &DAYS_OF_WEEK, 0)?;
+        // Both 0 and 7 are Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            days_of_month: parse_field(fields[3], 1, 31, &[], 0)?,
+            days_of_month_restricted: !fields[3].starts_with('*'),
+            days_of_week,
+            days_of_week_restricted: !fields[5].starts_with('*'),
+            hours: parse_field(fields[2], 0, 23, &[], 0)?,
+            minutes: parse_field(fields[1], 0, 59, &[], 0)?,
+            months: parse_field(fields[4], 1, 12, &MONTHS, 1)?,
+            seconds: parse_field(fields[0], 0, 59, &[], 0)?,
+        })
+    }
+
+    /// Returns the first time matching the schedule strictly after `time`, truncated to the second,
+    /// or `None` if there is none in the next years.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let start = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() as i64).unwrap_or(0);
+        let (start_year, _, _) = civil_from_days(start.div_euclid(SECONDS_PER_DAY));
+        let mut time = start + 1;
+        loop {
+            let days = time.div_euclid(SECONDS_PER_DAY);
+            let (year, month, day) = civil_from_days(days);
+            if year > start_year + MAX_YEARS {
+                return None;
+            }
+            let second_of_day = time.rem_euclid(SECONDS_PER_DAY);
+            if !contains(self.months, month) {
+                let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                time = days_from_civil(year, month, 1) * SECONDS_PER_DAY;
+            }
+            else if !self.matches_day(day, (days + 4).rem_euclid(7) as u32) {
+                time = (days + 1) * SECONDS_PER_DAY;
+            }
+            else if !contains(self.hours, (second_of_day / 3600) as u32) {
+                time = (time / 3600 + 1) * 3600;
+            }
+            else if !contains(self.minutes, (second_of_day / 60 % 60) as u32) {
+                time = (time / 60 + 1) * 60;
+            }
+            else if !contains(self.seconds, (second_of_day % 60) as u32) {
+                time += 1;
+            }
+            else {
+                return Some(UNIX_EPOCH + Duration::from_secs(time as u64));
+            }
+        }
+    }
+
+    fn matches_day(&self, day_of_month: u32, day_of_week: u32) -> bool {
+        let day_of_month = contains(self.days_of_month, day_of_month);
+        let day_of_week = contains(self.days_of_week, day_of_week);
+        if self.days_of_month_restricted && self.days_of_week_restricted {
+            day_of_month || day_of_week
+        }
+        else {
+            day_of_month && day_of_week
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Self::parse(expression)
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parses a field into a bit set of the values between `min` and `max`. `names[i]` is an alias of
+/// `i + names_start`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], names_start: u32) -> Result<u64, ParseError> {
+    let error = || ParseError::InvalidField(field.to_string());
+    let value = |text: &str| -> Result<u32, ParseError> {
+        let lowercase = text.to_lowercase();
+        let value =
+            match names.iter().position(|name| *name == lowercase) {
+                Some(index) => index as u32 + names_start,
+                None => text.parse().map_err(|_| error())?,
+            };
+        if value < min || value > max {
+            return Err(error());
+        }
+        Ok(value)
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let mut parts = part.splitn(2, '/');
+        let range = parts.next().unwrap_or("");
+        let step =
+            match parts.next() {
+                Some(step) => step.parse::<u32>().ok().filter(|&step| step > 0).ok_or_else(error)?,
+                None => 1,
+            };
+        let (start, end) =
+            if range == "*" {
+                (min, max)
+            }
+            else if let Some(index) = range.find('-') {
+                (value(&range[..index])?, value(&range[index + 1..])?)
+            }
+            else {
+                let start = value(range)?;
+                // `5/15` means from 5 to the maximum by 15.
+                (start, if step > 1 { max } else { start })
+            };
+        if start > end {
+            return Err(error());
+        }
+        let mut value = start;
+        while value <= end {
+            set |= 1 << value;
+            value += step;
+        }
+    }
+    Ok(set)
+}
+
+/// Identifier of a job added to a `Scheduler`, used to remove it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct JobId(u64);
+
+struct Job {
+    callback: Box<dyn FnMut()>,
+    next: SystemTime,
+    schedule: Schedule,
+}
+
+struct Inner {
+    jobs: HashMap<JobId, Job>,
+    next_id: u64,
+    wheel: TimerWheel<JobId>,
+}
+
+/// Fires jobs on the event loop according to their schedule.
+///
+/// The jobs are kept in a timer wheel with a resolution of one second, and a single timerfd is
+/// armed for the earliest one.
+pub struct Scheduler {
+    event_loop: Loop,
+    inner: Rc<RefCell<Inner>>,
+    timer: Rc<TimerFd>,
+}
+
+impl Scheduler {
+    pub fn new(event_loop: &Loop) -> io::Result<Self> {
+        let timer = Rc::new(TimerFd::new()?);
+        let inner = Rc::new(RefCell::new(Inner {
+            jobs: HashMap::new(),
+            next_id: 0,
+            wheel: TimerWheel::new(Duration::from_secs(1)),
+        }));
+        {
+            let weak_inner: Weak<RefCell<Inner>> = Rc::downgrade(&inner);
+            let weak_timer: Weak<TimerFd> = Rc::downgrade(&timer);
+            event_loop.event_loop().add_fd(&*timer, Mode::Read, move |_event| {
+                if let (Some(inner), Some(timer)) = (weak_inner.upgrade(), weak_timer.upgrade()) {
+                    timer.acknowledge();
+                    fire(&inner, &timer);
+                }
+                Action::Continue
+            })?;
+        }
+        Ok(Self {
+            event_loop: event_loop.clone(),
+            inner,
+            timer,
+        })
+    }
+
+    /// Sends the message created by `msg` to the stream at every occurrence of the schedule.
+    pub fn add<F, MSG>(&self, schedule: Schedule, stream: &Stream<MSG>, msg: F) -> io::Result<JobId>
+    where F: Fn() -> MSG + 'static,
+          MSG: 'static,
+    {
+        let stream = stream.clone();
+        self.add_callback(schedule, move || stream.send(msg()))
+    }
+
+    /// Calls the callback at every occurrence of the schedule.
+    pub fn add_callback<F>(&self, schedule: Schedule, callback: F) -> io::Result<JobId>
+    where F: FnMut() + 'static,
+    {
+        let id = {
+            let mut inner = self.inner.borrow_mut();
+            let id = JobId(inner.next_id);
+            inner.next_id += 1;
+            let job = Job {
+                callback: Box::new(callback),
+                next: SystemTime::now(),
+                schedule,
+            };
+            inner.jobs.insert(id, job);
+            if !inner.schedule(id) {
+                inner.jobs.remove(&id);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the schedule never fires"));
+            }
+            id
+        };
+        arm(&self.inner, &self.timer)?;
+        Ok(id)
+    }
+
+    /// Returns the next time the job fires.
+    pub fn next_run(&self, id: JobId) -> Option<SystemTime> {
+        self.inner.borrow().jobs.get(&id).map(|job| job.next)
+    }
+
+    /// Removes a job, returning false if it does not exist.
+    pub fn remove(&self, id: JobId) -> bool {
+        // The job stays in the wheel and is ignored when it expires.
+        self.inner.borrow_mut().jobs.remove(&id).is_some()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let _ = self.event_loop.remove_fd(&*self.timer);
+    }
+}
+
+impl Inner {
+    /// Inserts the next occurrence of the job in the wheel, returning false if there is none.
+    fn schedule(&mut self, id: JobId) -> bool {
+        let now = SystemTime::now();
+        let next =
+            match self.jobs.get_mut(&id) {
+                Some(job) => {
+                    // Never fire the same occurrence twice if the timer expired a bit early.
+                    let after = if job.next > now { job.next } else { now };
+                    match job.schedule.next_after(after) {
+                        Some(next) => {
+                            job.next = next;
+                            next
+                        },
+                        None => return false,
+                    }
+                },
+                None => return false,
+            };
+        let delay = next.duration_since(now).unwrap_or(Duration::from_secs(0));
+        self.wheel.insert(Instant::now() + delay, id);
+        true
+    }
+}
+
+fn arm(inner: &Rc<RefCell<Inner>>, timer: &TimerFd) -> io::Result<()> {
+    match inner.borrow().wheel.next_expiration() {
+        Some(expiration) => timer.set(expiration.saturating_duration_since(Instant::now())),
+        None => timer.disarm(),
+    }
+}
+
+fn fire(inner: &Rc<RefCell<Inner>>, timer: &TimerFd) {
+    let expired = inner.borrow_mut().wheel.poll();
+    for id in expired {
+        // Take the callback out so that it can add or remove jobs.
+        let callback = inner.borrow_mut().jobs.get_mut(&id).map(|job| {
+            let noop: Box<dyn FnMut()> = Box::new(|| ());
+            mem::replace(&mut job.callback, noop)
+        });
+        if let Some(mut callback) = callback {
+            callback();
+            let mut inner = inner.borrow_mut();
+            if let Some(job) = inner.jobs.get_mut(&id) {
+                job.callback = callback;
+            }
+            if !inner.schedule(id) {
+                inner.jobs.remove(&id);
+            }
+        }
+    }
+    let _ = arm(inner, timer);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use aio::handler::Loop;
+    use super::{ParseError, Schedule, Scheduler, civil_from_days, days_from_civil};
+
+    /// Returns the time at the specified UTC date.
+    fn at(year: i64, month: u32, day: u32, hour: u64, minute: u64, second: u64) -> SystemTime {
+        let days = days_from_civil(year, month, day) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second)
+    }
+
+    fn next(expression: &str, time: SystemTime) -> Option<SystemTime> {
+        Schedule::parse(expression).expect("parse").next_after(time)
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        for days in -1000..100_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Schedule::parse("* * *"), Err(ParseError::FieldCount(3)));
+        assert_eq!(Schedule::parse("60 * * * *"), Err(ParseError::InvalidField("60".to_string())));
+        assert_eq!(Schedule::parse("*/0 * * * *"), Err(ParseError::InvalidField("*/0".to_string())));
+        assert_eq!(Schedule::parse("5-1 * * * *"), Err(ParseError::InvalidField("5-1".to_string())));
+        assert_eq!(Schedule::parse("@often"), Err(ParseError::UnknownMacro("@often".to_string())));
+        assert_eq!(Schedule::parse("@daily"), Schedule::parse("0 0 * * *"));
+        assert_eq!("0 0 * * 7".parse::<Schedule>(), Schedule::parse("0 0 * * sun"));
+        assert!(Schedule::parse("0,30 9-17/2 1,15 JAN-jun mon-fri").is_ok());
+    }
+
+    #[test]
+    fn next_after() {
+        let time = at(2024, 2, 28, 23, 59, 30);
+        assert_eq!(next("* * * * *", time), Some(at(2024, 2, 29, 0, 0, 0)));
+        assert_eq!(next("*/15 * * * * *", time), Some(at(2024, 2, 28, 23, 59, 45)));
+        assert_eq!(next("30 8 * * *", time), Some(at(2024, 2, 29, 8, 30, 0)));
+        assert_eq!(next("0 0 1 * *", time), Some(at(2024, 3, 1, 0, 0, 0)));
+        assert_eq!(next("@yearly", time), Some(at(2025, 1, 1, 0, 0, 0)));
+        assert_eq!(next("0 12 29 2 *", at(2024, 3, 1, 0, 0, 0)), Some(at(2028, 2, 29, 12, 0, 0)));
+        // 2024-02-28 is a Wednesday.
+        assert_eq!(next("0 9 * * mon", time), Some(at(2024, 3, 4, 9, 0, 0)));
+        assert_eq!(next("0 9 * * 1-5", time), Some(at(2024, 2, 29, 9, 0, 0)));
+        // Either the 10th or a Monday.
+        assert_eq!(next("0 0 10 * mon", time), Some(at(2024, 3, 4, 0, 0, 0)));
+        assert_eq!(next("0 0 */10 * *", time), Some(at(2024, 3, 1, 0, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", time), None);
+        // Strictly after.
+        assert_eq!(next("0 0 * * *", at(2024, 1, 1, 0, 0, 0)), Some(at(2024, 1, 2, 0, 0, 0)));
+    }
+
+    #[test]
+    fn scheduler() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let scheduler = Scheduler::new(&event_loop).expect("scheduler");
+        let count = Rc::new(Cell::new(0));
+        let job = {
+            let count = count.clone();
+            let mut event_loop = event_loop.clone();
+            scheduler.add_callback(Schedule::parse("* * * * * *").expect("parse"), move || {
+                count.set(count.get() + 1);
+                if count.get() == 2 {
+                    event_loop.stop();
+                }
+            }).expect("add")
+        };
+        assert!(scheduler.next_run(job).expect("next run") > SystemTime::now());
+        let never = Schedule::parse("0 0 30 2 *").expect("parse");
+        assert!(scheduler.add_callback(never, || ()).is_err());
+        event_loop.run().expect("run");
+        assert_eq!(count.get(), 2);
+        assert!(scheduler.remove(job));
+        assert!(!scheduler.remove(job));
+    }
+}
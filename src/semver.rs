@@ -0,0 +1,566 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Semantic versions (https://semver.org) and version requirements.
+//!
+//! Requirements follow the syntax of Cargo, with npm-style hyphen ranges and alternatives:
+//!
+//! * `^1.2.3` (or just `1.2.3`) allows the changes that do not modify the leftmost non-zero
+//!   component: `>=1.2.3, <2.0.0`, and `^0.2.3` means `>=0.2.3, <0.3.0`.
+//! * `~1.2.3` allows patch changes: `>=1.2.3, <1.3.0`.
+//! * `=`, `>`, `>=`, `<` and `<=` compare with a version whose missing components are wildcards:
+//!   `>1.2` means `>=1.3.0` and `<=1.2` means `<1.3.0`.
+//! * `1.2.*`, `1.*` and `*` are wildcards.
+//! * `1.2 - 1.4` is an inclusive range: `>=1.2.0, <1.5.0`.
+//! * Comparators separated by commas or spaces must all match, and `||` separates alternatives.
+//!
+//! A pre-release version only matches a requirement having a comparator with a pre-release on the
+//! same major, minor and patch numbers: `>=1.2.3-alpha` matches `1.2.3-beta` but not `1.3.0-beta`.
+
+use std::cmp::Ordering;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// Error returned when parsing an invalid version or requirement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    Empty,
+    /// A number is not valid, e.g. it has a leading zero.
+    InvalidNumber(String),
+    /// A pre-release or build identifier is empty or has invalid characters.
+    InvalidIdentifier(String),
+    /// The version does not have 3 numbers, or the requirement has a comparator that cannot be
+    /// parsed.
+    InvalidVersion(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Empty => write!(formatter, "empty version"),
+            ParseError::InvalidNumber(ref number) => write!(formatter, "invalid number `{}`", number),
+            ParseError::InvalidIdentifier(ref identifier) => write!(formatter, "invalid identifier `{}`", identifier),
+            ParseError::InvalidVersion(ref version) => write!(formatter, "invalid version `{}`", version),
+        }
+    }
+}
+
+impl error::Error for ParseError {
+}
+
+/// A pre-release identifier. Numeric identifiers have lower precedence than alphanumeric ones.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Identifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+impl Display for Identifier {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Identifier::Numeric(number) => write!(formatter, "{}", number),
+            Identifier::AlphaNumeric(ref text) => write!(formatter, "{}", text),
+        }
+    }
+}
+
+/// A version `MAJOR.MINOR.PATCH[-PRE][+BUILD]`.
+///
+/// Versions are ordered by precedence, then by build metadata, so that the order is consistent
+/// with equality. Use `cmp_precedence()` to ignore the build metadata as the specification says.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// The pre-release identifiers, empty for a release.
+    pub pre: Vec<Identifier>,
+    pub build: Vec<String>,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            pre: vec![],
+            build: vec![],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let (text, build) =
+            match text.find('+') {
+                Some(index) => (&text[..index], parse_build(&text[index + 1..])?),
+                None => (text, vec![]),
+            };
+        let (text, pre) =
+            match text.find('-') {
+                Some(index) => (&text[..index], parse_pre(&text[index + 1..])?),
+                None => (text, vec![]),
+            };
+        let numbers = text.split('.').map(parse_number).collect::<Result<Vec<_>, _>>()?;
+        if numbers.len() != 3 {
+            return Err(ParseError::InvalidVersion(text.to_string()));
+        }
+        Ok(Self {
+            major: numbers[0],
+            minor: numbers[1],
+            patch: numbers[2],
+            pre,
+            build,
+        })
+    }
+
+    /// Compares the versions as specified by semver, ignoring the build metadata.
+    pub fn cmp_precedence(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| {
+                // A release has a higher precedence than its pre-releases.
+                match (self.pre.is_empty(), other.pre.is_empty()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => self.pre.cmp(&other.pre),
+                }
+            })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    fn same_release(&self, other: &Self) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_precedence(other).then_with(|| self.build.cmp(&other.build))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (index, identifier) in self.pre.iter().enumerate() {
+            write!(formatter, "{}{}", if index == 0 { '-' } else { '.' }, identifier)?;
+        }
+        for (index, identifier) in self.build.iter().enumerate() {
+            write!(formatter, "{}{}", if index == 0 { '+' } else { '.' }, identifier)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        Self::parse(text)
+    }
+}
+
+fn parse_number(text: &str) -> Result<u64, ParseError> {
+    let valid = !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit()) && (text == "0" || !text.starts_with('0'));
+    if !valid {
+        return Err(ParseError::InvalidNumber(text.to_string()));
+    }
+    text.parse().map_err(|_| ParseError::InvalidNumber(text.to_string()))
+}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+fn parse_pre(text: &str) -> Result<Vec<Identifier>, ParseError> {
+    text.split('.')
+        .map(|identifier| {
+            if !is_identifier(identifier) {
+                Err(ParseError::InvalidIdentifier(identifier.to_string()))
+            }
+            else if identifier.bytes().all(|byte| byte.is_ascii_digit()) {
+                parse_number(identifier).map(Identifier::Numeric)
+            }
+            else {
+                Ok(Identifier::AlphaNumeric(identifier.to_string()))
+            }
+        })
+        .collect()
+}
+
+fn parse_build(text: &str) -> Result<Vec<String>, ParseError> {
+    text.split('.')
+        .map(|identifier| {
+            if is_identifier(identifier) {
+                Ok(identifier.to_string())
+            }
+            else {
+                Err(ParseError::InvalidIdentifier(identifier.to_string()))
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Caret,
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    /// `*`, `1.*`, `1.2.*`.
+    Wildcard,
+}
+
+/// One comparison of a requirement, with the missing components of its version as `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Comparator {
+    op: Op,
+    major: Option<u64>,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<Identifier>,
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Result<Self, ParseError> {
+        let operators = [(">=", Op::GreaterEq), ("<=", Op::LessEq), (">", Op::Greater), ("<", Op::Less), ("=", Op::Exact),
+            ("^", Op::Caret), ("~", Op::Tilde)];
+        let (op, version) = operators.iter()
+            .find(|&&(prefix, _)| text.starts_with(prefix))
+            .map_or((None, text), |&(prefix, op)| (Some(op), &text[prefix.len()..]));
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        if version.is_empty() {
+            return Err(ParseError::InvalidVersion(text.to_string()));
+        }
+
+        let (version, pre) =
+            match version.find('-') {
+                Some(index) => (&version[..index], parse_pre(&version[index + 1..])?),
+                None => (version, vec![]),
+            };
+        let mut numbers = [None; 3];
+        let mut wildcard = false;
+        for (index, part) in version.split('.').enumerate() {
+            if index == 3 {
+                return Err(ParseError::InvalidVersion(text.to_string()));
+            }
+            if part == "*" || part == "x" || part == "X" {
+                wildcard = true;
+            }
+            else if wildcard {
+                // Like `1.*.3`.
+                return Err(ParseError::InvalidVersion(text.to_string()));
+            }
+            else {
+                numbers[index] = Some(parse_number(part)?);
+            }
+        }
+        let complete = numbers.iter().all(Option::is_some);
+        if !pre.is_empty() && !complete {
+            return Err(ParseError::InvalidVersion(text.to_string()));
+        }
+        let op =
+            match op {
+                Some(op) => op,
+                None if wildcard => Op::Wildcard,
+                None => Op::Caret,
+            };
+        if wildcard && numbers[0].is_none() && op != Op::Wildcard && op != Op::Exact {
+            // Like `>*`.
+            return Err(ParseError::InvalidVersion(text.to_string()));
+        }
+        Ok(Self {
+            op,
+            major: numbers[0],
+            minor: numbers[1],
+            patch: numbers[2],
+            pre,
+        })
+    }
+
+    /// Returns the lowest version matching, if any, and the lowest version above the matching ones,
+    /// if any.
+    fn bounds(&self) -> (Option<Version>, Option<Version>) {
+        let lowest = Version {
+            major: self.major.unwrap_or(0),
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+            build: vec![],
+        };
+        // The lowest version above the ones equal to the partial version, like 1.3.0 for 1.2.
+        let above =
+            match (self.major, self.minor, self.patch) {
+                (Some(major), Some(minor), Some(patch)) => {
+                    if self.pre.is_empty() {
+                        Some(Version::new(major, minor, patch + 1))
+                    }
+                    else {
+                        // The versions equal to 1.2.3-alpha are below 1.2.3-alpha.0.
+                        let mut version = lowest.clone();
+                        version.pre.push(Identifier::Numeric(0));
+                        Some(version)
+                    }
+                },
+                (Some(major), Some(minor), None) => Some(Version::new(major, minor + 1, 0)),
+                (Some(major), None, _) => Some(Version::new(major + 1, 0, 0)),
+                (None, _, _) => None,
+            };
+        match self.op {
+            Op::Exact | Op::Wildcard => (Some(lowest), above),
+            Op::Greater => (above, None),
+            Op::GreaterEq => (Some(lowest), None),
+            Op::Less => (None, Some(lowest)),
+            Op::LessEq => (None, above),
+            Op::Tilde => {
+                let upper =
+                    match (self.major, self.minor) {
+                        (Some(major), Some(minor)) => Version::new(major, minor + 1, 0),
+                        (major, _) => Version::new(major.unwrap_or(0) + 1, 0, 0),
+                    };
+                (Some(lowest), Some(upper))
+            },
+            Op::Caret => {
+                let major = self.major.unwrap_or(0);
+                let upper =
+                    match (major, self.minor, self.patch) {
+                        (0, Some(0), Some(patch)) => Version::new(0, 0, patch + 1),
+                        (0, Some(minor), _) => Version::new(0, minor + 1, 0),
+                        _ => Version::new(major + 1, 0, 0),
+                    };
+                (Some(lowest), Some(upper))
+            },
+        }
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let (lower, upper) = self.bounds();
+        lower.is_none_or(|lower| version.cmp_precedence(&lower) != Ordering::Less)
+            && upper.is_none_or(|upper| {
+                // Exclude the pre-releases of the upper bound, as in `<2.0.0`, unless the comparator
+                // itself allows pre-releases of that version.
+                version.cmp_precedence(&upper) == Ordering::Less
+                    && !(version.is_prerelease() && upper.pre.is_empty() && version.same_release(&upper) && self.pre.is_empty())
+            })
+    }
+}
+
+/// A version requirement, like `>=1.2, <1.5 || ^2`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VersionReq {
+    /// Alternatives of comparators that must all match.
+    alternatives: Vec<Vec<Comparator>>,
+    source: String,
+}
+
+impl VersionReq {
+    /// Returns the requirement matching every release.
+    pub fn any() -> Self {
+        Self {
+            alternatives: vec![vec![]],
+            source: "*".to_string(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let alternatives = text.split("||").map(parse_alternative).collect::<Result<_, _>>()?;
+        Ok(Self {
+            alternatives,
+            source: text.to_string(),
+        })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|comparators| {
+            let allows_prerelease = !version.is_prerelease() || comparators.iter().any(|comparator| {
+                !comparator.pre.is_empty() && Some(version.major) == comparator.major
+                    && Some(version.minor) == comparator.minor && Some(version.patch) == comparator.patch
+            });
+            allows_prerelease && comparators.iter().all(|comparator| comparator.matches(version))
+        })
+    }
+
+    /// Returns the highest of `versions` matching the requirement.
+    pub fn best_match<'a, I: IntoIterator<Item=&'a Version>>(&self, versions: I) -> Option<&'a Version> {
+        versions.into_iter().filter(|version| self.matches(version)).max()
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.source)
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        Self::parse(text)
+    }
+}
+
+fn parse_alternative(text: &str) -> Result<Vec<Comparator>, ParseError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    if let Some(index) = text.find(" - ") {
+        let mut lower = Comparator::parse(text[..index].trim())?;
+        let mut upper = Comparator::parse(text[index + 3..].trim())?;
+        if lower.op != Op::Caret || upper.op != Op::Caret {
+            return Err(ParseError::InvalidVersion(text.to_string()));
+        }
+        lower.op = Op::GreaterEq;
+        upper.op = Op::LessEq;
+        return Ok(vec![lower, upper]);
+    }
+
+    let mut comparators = vec![];
+    for part in text.split(',') {
+        // Join the operators separated from their version, like in `>= 1.2`.
+        let mut pending = String::new();
+        for word in part.split_whitespace() {
+            pending.push_str(word);
+            if !word.trim_start_matches(|c| "<>=^~".contains(c)).is_empty() {
+                comparators.push(Comparator::parse(&pending)?);
+                pending.clear();
+            }
+        }
+        if !pending.is_empty() || part.trim().is_empty() {
+            return Err(ParseError::InvalidVersion(part.trim().to_string()));
+        }
+    }
+    Ok(comparators)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{Identifier, ParseError, Version, VersionReq};
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).expect("version")
+    }
+
+    fn matches(requirement: &str, text: &str) -> bool {
+        VersionReq::parse(requirement).expect("requirement").matches(&version(text))
+    }
+
+    #[test]
+    fn parse() {
+        let parsed = version("1.2.3-alpha.10+build.5");
+        assert_eq!((parsed.major, parsed.minor, parsed.patch), (1, 2, 3));
+        assert_eq!(parsed.pre, vec![Identifier::AlphaNumeric("alpha".to_string()), Identifier::Numeric(10)]);
+        assert_eq!(parsed.build, vec!["build".to_string(), "5".to_string()]);
+        assert_eq!(parsed.to_string(), "1.2.3-alpha.10+build.5");
+        assert_eq!(version("1.0.0-x-y").to_string(), "1.0.0-x-y");
+
+        assert_eq!(Version::parse(""), Err(ParseError::Empty));
+        assert_eq!(Version::parse("1.2"), Err(ParseError::InvalidVersion("1.2".to_string())));
+        assert_eq!(Version::parse("01.2.3"), Err(ParseError::InvalidNumber("01".to_string())));
+        assert_eq!(Version::parse("1.2.3-"), Err(ParseError::InvalidIdentifier(String::new())));
+        assert_eq!(Version::parse("1.2.3-a_b"), Err(ParseError::InvalidIdentifier("a_b".to_string())));
+        assert!(Version::parse("1.2.3-01").is_err());
+        assert!("1.2.3+01".parse::<Version>().is_ok());
+    }
+
+    #[test]
+    fn precedence() {
+        let ordered = ["1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2", "1.0.0-beta.11",
+            "1.0.0-rc.1", "1.0.0", "1.0.1", "1.1.0", "2.0.0"];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(version("1.0.0+a").cmp_precedence(&version("1.0.0+b")), Ordering::Equal);
+        assert!(version("1.0.0+a") < version("1.0.0+b"));
+    }
+
+    #[test]
+    fn requirements() {
+        assert!(matches("1.2.3", "1.9.0"));
+        assert!(!matches("^1.2.3", "2.0.0"));
+        assert!(!matches("^1.2.3", "1.2.2"));
+        assert!(matches("^0.2.3", "0.2.9"));
+        assert!(!matches("^0.2.3", "0.3.0"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+        assert!(matches("^0", "0.9.0"));
+        assert!(matches("~1.2.3", "1.2.9"));
+        assert!(!matches("~1.2.3", "1.3.0"));
+        assert!(matches("~1", "1.9.0"));
+        assert!(matches("=1.2", "1.2.7"));
+        assert!(!matches("=1.2.3", "1.2.4"));
+        assert!(matches(">1.2", "1.3.0"));
+        assert!(!matches(">1.2", "1.2.9"));
+        assert!(matches("<=1.2", "1.2.9"));
+        assert!(!matches("<1.2", "1.2.0"));
+        assert!(matches(">= 1.2, < 1.5", "1.4.9"));
+        assert!(!matches(">=1.2 <1.5", "1.5.0"));
+        assert!(matches("1.2.*", "1.2.5"));
+        assert!(!matches("1.*", "2.0.0"));
+        assert!(matches("*", "3.1.4"));
+        assert!(matches("1.2 - 1.4", "1.4.8"));
+        assert!(!matches("1.2 - 1.4", "1.5.0"));
+        assert!(matches("^1 || ^3", "3.0.1"));
+        assert!(!matches("^1 || ^3", "2.0.0"));
+
+        assert!(!matches("<2.0.0", "2.0.0-rc.1"));
+        assert!(!matches("^1.2", "1.3.0-beta"));
+        assert!(matches(">=1.2.3-alpha", "1.2.3-beta"));
+        assert!(!matches(">=1.2.3-alpha", "1.3.0-beta"));
+        assert!(matches("=1.2.3-alpha", "1.2.3-alpha"));
+        assert!(!matches("=1.2.3-alpha", "1.2.3-alpha.1"));
+        assert!(!matches("*", "1.0.0-alpha"));
+        assert!(VersionReq::any().matches(&version("1.0.0")));
+
+        for invalid in &["", ">", "1.*.3", "1.2.3.4", "^1.2-alpha", "1,", "a.b", ">= 1 ||"] {
+            assert!(VersionReq::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let versions = vec![version("1.2.0"), version("1.4.1"), version("2.0.0")];
+        let requirement: VersionReq = "~1".parse().expect("requirement");
+        assert_eq!(requirement.best_match(&versions), Some(&versions[1]));
+        assert_eq!(requirement.to_string(), "~1");
+    }
+}
@@ -0,0 +1,428 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Graceful shutdown of a server running on the event loop.
+//!
+//! A `ShutdownController` knows the listeners, connections, timers and other components of the
+//! server. When the shutdown starts, on a signal or by calling `shutdown()`, it:
+//!
+//! 1. stops the listeners, so that no new connection is accepted;
+//! 2. waits for the components to drain: the connections to send what is buffered and the handlers
+//!    to process their pending messages, until the deadline;
+//! 3. closes the components, forcibly for those that did not drain in time;
+//! 4. disarms the timers and runs the `on_stop()` hooks;
+//! 5. stops the event loop, so that `Loop::run()` returns.
+//!
+//! `report()` then tells which components drained and which were force-closed.
+
+use std::cell::{RefCell, RefMut};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use aio::async::{LoopError, Mode};
+use aio::handler::{Handler, Loop, Stream};
+use aio::net::{ListenerMsg, TcpConnection};
+use aio::timer::TimerFd;
+use signal::{Signal, SignalSet};
+
+/// How often the components are checked while draining.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Name reported when the handlers still have messages to process at the deadline.
+const MAILBOXES: &str = "handler mailboxes";
+
+/// A component that finishes its work before being closed.
+pub trait Drain {
+    /// Called when the shutdown starts: stop taking new work.
+    fn start_drain(&mut self) {
+    }
+
+    /// Returns true once the component can be closed without losing work.
+    fn is_drained(&self) -> bool;
+
+    /// Returns true if the component closed by itself, so that it can be forgotten.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Closes the component, after it drained or when the deadline is reached.
+    fn close(&mut self);
+}
+
+/// A connection is drained once everything written to it has been sent.
+impl Drain for TcpConnection {
+    fn is_drained(&self) -> bool {
+        TcpConnection::is_closed(self) || self.pending_writes() == 0
+    }
+
+    fn is_closed(&self) -> bool {
+        TcpConnection::is_closed(self)
+    }
+
+    fn close(&mut self) {
+        self.dispose();
+    }
+}
+
+/// What happened during the shutdown.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ShutdownReport {
+    /// The components closed after draining.
+    pub drained: Vec<String>,
+    /// The components closed at the deadline without having drained.
+    pub forced: Vec<String>,
+    /// The time from the start of the shutdown to the stop of the loop.
+    pub elapsed: Duration,
+    /// The signal that started the shutdown, if any.
+    pub signal: Option<Signal>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Running,
+    Draining,
+    Stopped,
+}
+
+pub enum Msg {
+    Poll,
+    Start(Option<Signal>),
+}
+
+struct Inner {
+    deadline: Duration,
+    drains: Vec<(String, Box<dyn Drain>)>,
+    event_loop: Loop,
+    hooks: Vec<Box<dyn FnOnce()>>,
+    listeners: Vec<Stream<ListenerMsg>>,
+    phase: Phase,
+    poll_timer: TimerFd,
+    report: ShutdownReport,
+    started: Option<Instant>,
+    timers: Vec<Weak<TimerFd>>,
+}
+
+/// Coordinates the graceful shutdown. Clones refer to the same controller.
+#[derive(Clone)]
+pub struct ShutdownController {
+    inner: Rc<RefCell<Inner>>,
+    stream: Stream<Msg>,
+}
+
+impl ShutdownController {
+    /// Creates a controller giving `deadline` to the components to drain.
+    pub fn new(event_loop: &mut Loop, deadline: Duration) -> io::Result<Self> {
+        let poll_timer = TimerFd::new()?;
+        let inner = Rc::new(RefCell::new(Inner {
+            deadline,
+            drains: vec![],
+            event_loop: event_loop.clone(),
+            hooks: vec![],
+            listeners: vec![],
+            phase: Phase::Running,
+            poll_timer,
+            report: ShutdownReport::default(),
+            started: None,
+            timers: vec![],
+        }));
+        let stream = event_loop.spawn(Controller {
+            inner: inner.clone(),
+        });
+        event_loop.add_fd(&inner.borrow().poll_timer, Mode::Read, &stream, |_| Msg::Poll)?;
+        Ok(Self {
+            inner,
+            stream,
+        })
+    }
+
+    /// Adds a connection, named after its peer address. Add the connections when they are accepted.
+    pub fn add_connection(&self, connection: &TcpConnection) {
+        let name =
+            match connection.peer_addr() {
+                Ok(address) => format!("connection {}", address),
+                Err(_) => "connection".to_string(),
+            };
+        self.add_drain(&name, connection.clone());
+    }
+
+    /// Adds a component to drain. If the shutdown already started, its draining starts now, and if
+    /// the shutdown is over, it is closed right away.
+    pub fn add_drain<D: Drain + 'static>(&self, name: &str, mut drain: D) {
+        let mut inner = self.inner.borrow_mut();
+        match inner.phase {
+            Phase::Running => {
+                // Forget the connections closed since they were added.
+                inner.drains.retain(|(_, drain)| !drain.is_closed());
+                inner.drains.push((name.to_string(), Box::new(drain)));
+            },
+            Phase::Draining => {
+                drop(inner);
+                drain.start_drain();
+                self.inner.borrow_mut().drains.push((name.to_string(), Box::new(drain)));
+            },
+            Phase::Stopped => {
+                drop(inner);
+                drain.close();
+            },
+        }
+    }
+
+    /// Adds a listener, which is disposed as soon as the shutdown starts.
+    pub fn add_listener(&self, listener: &Stream<ListenerMsg>) {
+        self.inner.borrow_mut().listeners.push(listener.clone());
+    }
+
+    /// Adds a timer to disarm when the loop stops. The controller does not keep it alive.
+    pub fn add_timer(&self, timer: &Rc<TimerFd>) {
+        self.inner.borrow_mut().timers.push(Rc::downgrade(timer));
+    }
+
+    /// Starts the shutdown when one of the signals is received. See `Loop::add_signals()`.
+    pub fn handle_signals(&self, event_loop: &Loop, signals: &SignalSet) -> io::Result<()> {
+        event_loop.add_signals(signals, &self.stream, |signal| Msg::Start(Some(signal)))
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.borrow().phase != Phase::Running
+    }
+
+    /// Calls `hook` after the components are closed, just before the loop stops.
+    pub fn on_stop<F: FnOnce() + 'static>(&self, hook: F) {
+        self.inner.borrow_mut().hooks.push(Box::new(hook));
+    }
+
+    /// Returns the report once the shutdown is over.
+    pub fn report(&self) -> Option<ShutdownReport> {
+        let inner = self.inner.borrow();
+        if inner.phase == Phase::Stopped {
+            Some(inner.report.clone())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Starts the shutdown. Does nothing if it already started.
+    pub fn shutdown(&self) {
+        self.stream.send(Msg::Start(None));
+    }
+}
+
+struct Controller {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Controller {
+    fn start(&mut self, signal: Option<Signal>) {
+        let mut drains = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.phase != Phase::Running {
+                return;
+            }
+            inner.phase = Phase::Draining;
+            inner.started = Some(Instant::now());
+            inner.report.signal = signal;
+            for listener in inner.listeners.drain(..) {
+                listener.send(ListenerMsg::Dispose);
+            }
+            mem::take(&mut inner.drains)
+        };
+        // The borrow is released since the drains could call the controller.
+        for (_, drain) in &mut drains {
+            drain.start_drain();
+        }
+        self.inner.borrow_mut().drains.extend(drains);
+        self.check(false);
+    }
+
+    /// Closes the drained components and finishes the shutdown once all are closed or the deadline
+    /// is reached, or right away if `force` is true.
+    fn check(&mut self, force: bool) {
+        let (drains, expired, idle) = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.phase != Phase::Draining {
+                return;
+            }
+            let expired = force || inner.started.is_some_and(|started| started.elapsed() >= inner.deadline);
+            (mem::take(&mut inner.drains), expired, inner.event_loop.is_idle())
+        };
+
+        let mut remaining = vec![];
+        let mut drained = vec![];
+        let mut forced = vec![];
+        for (name, mut drain) in drains {
+            if drain.is_drained() {
+                drain.close();
+                drained.push(name);
+            }
+            else if expired {
+                drain.close();
+                forced.push(name);
+            }
+            else {
+                remaining.push((name, drain));
+            }
+        }
+
+        let mut inner = self.inner.borrow_mut();
+        inner.report.drained.extend(drained);
+        inner.report.forced.extend(forced);
+        // Components added while closing the others.
+        remaining.append(&mut inner.drains);
+        inner.drains = remaining;
+        let done = expired || (inner.drains.is_empty() && idle);
+        if !done {
+            let error =
+                match inner.poll_timer.set(POLL_INTERVAL) {
+                    Ok(()) => return,
+                    Err(error) => LoopError::Timer(inner.poll_timer.as_raw_fd(), error),
+                };
+            let reported = inner.event_loop.event_loop().report_error(error);
+            drop(inner);
+            if let Err(error) = reported {
+                panic!("{}", error);
+            }
+            // The components would not be checked again: close them now.
+            self.check(true);
+            return;
+        }
+        if !idle {
+            inner.report.forced.push(MAILBOXES.to_string());
+        }
+        self.stop(inner);
+    }
+
+    fn stop(&self, mut inner: RefMut<Inner>) {
+        inner.phase = Phase::Stopped;
+        let _ = inner.poll_timer.disarm();
+        for timer in inner.timers.drain(..) {
+            if let Some(timer) = timer.upgrade() {
+                let _ = timer.disarm();
+            }
+        }
+        inner.report.elapsed = inner.started.map_or(Duration::from_secs(0), |started| started.elapsed());
+        let hooks = mem::take(&mut inner.hooks);
+        let mut event_loop = inner.event_loop.clone();
+        drop(inner);
+        for hook in hooks {
+            hook();
+        }
+        event_loop.stop();
+    }
+}
+
+impl Handler for Controller {
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Msg::Poll => {
+                let _ = self.inner.borrow().poll_timer.acknowledge();
+                self.check(false);
+            },
+            Msg::Start(signal) => self.start(signal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use aio::handler::Loop;
+    use aio::timer::TimerFd;
+    use super::{Drain, MAILBOXES, ShutdownController};
+    use testing::Harness;
+
+    #[derive(Clone, Default)]
+    struct Job {
+        closed: Rc<Cell<bool>>,
+        done: Rc<Cell<bool>>,
+        started: Rc<Cell<bool>>,
+    }
+
+    impl Drain for Job {
+        fn start_drain(&mut self) {
+            self.started.set(true);
+        }
+
+        fn is_drained(&self) -> bool {
+            self.done.get()
+        }
+
+        fn close(&mut self) {
+            self.closed.set(true);
+        }
+    }
+
+    #[test]
+    fn drained() {
+        let mut harness = Harness::new().expect("harness");
+        let controller = ShutdownController::new(harness.event_loop(), Duration::from_secs(60)).expect("controller");
+        let job = Job::default();
+        job.done.set(true);
+        controller.add_drain("job", job.clone());
+        let stopped = Rc::new(Cell::new(false));
+        {
+            let stopped = stopped.clone();
+            controller.on_stop(move || stopped.set(true));
+        }
+        let timer = Rc::new(TimerFd::new().expect("timer"));
+        timer.set(Duration::from_secs(60)).expect("set");
+        controller.add_timer(&timer);
+
+        assert!(!controller.is_shutting_down());
+        controller.shutdown();
+        harness.run_until_idle();
+        assert!(job.started.get() && job.closed.get() && stopped.get());
+        let report = controller.report().expect("report");
+        assert_eq!(report.drained, vec!["job".to_string()]);
+        assert!(report.forced.is_empty());
+        assert_eq!(report.signal, None);
+
+        let late = Job::default();
+        controller.add_drain("late", late.clone());
+        assert!(late.closed.get() && !late.started.get());
+    }
+
+    #[test]
+    fn deadline() {
+        let mut event_loop = Loop::new().expect("loop");
+        let controller = ShutdownController::new(&mut event_loop, Duration::from_millis(50)).expect("controller");
+        let (quick, slow) = (Job::default(), Job::default());
+        controller.add_drain("quick", quick.clone());
+        controller.add_drain("slow", slow.clone());
+        controller.shutdown();
+        quick.done.set(true);
+        // Returns once the controller stops the loop.
+        event_loop.run().expect("run");
+        let report = controller.report().expect("report");
+        assert_eq!(report.drained, vec!["quick".to_string()]);
+        assert_eq!(report.forced, vec!["slow".to_string()]);
+        assert!(report.elapsed >= Duration::from_millis(50));
+        assert!(slow.closed.get());
+        assert!(!report.forced.contains(&MAILBOXES.to_string()));
+    }
+}
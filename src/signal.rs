@@ -0,0 +1,388 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Signal masks, dispositions and delivery through a file descriptor.
+//!
+//! Signals are received synchronously with a `SignalFd`: the signals are first blocked with
+//! `SignalSet::block()` so that their default action does not run, then read from the fd when it
+//! becomes readable. `Loop::add_signals()` does both and turns each signal into a message, which is
+//! the usual way to reload the configuration on SIGHUP or to shut down gracefully on SIGTERM (see
+//! `examples/signals.rs`).
+//!
+//! The signal mask is per thread and inherited by the threads spawned afterwards: block the signals
+//! before spawning any thread, otherwise a thread with the signals unblocked may receive them.
+
+use std::io;
+use std::iter::FromIterator;
+use std::mem;
+use std::os::raw::c_void;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+use aio::net::close;
+
+/// A signal that can be handled.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Signal {
+    /// SIGALRM: timer expired.
+    Alarm,
+    /// SIGCHLD: a child process stopped or terminated.
+    Child,
+    /// SIGHUP: the controlling terminal hung up; daemons use it to reload their configuration.
+    Hangup,
+    /// SIGINT: interrupted from the keyboard (Ctrl-C).
+    Interrupt,
+    /// SIGPIPE: write to a pipe or socket with no reader.
+    Pipe,
+    /// SIGQUIT: quit from the keyboard (Ctrl-\).
+    Quit,
+    /// SIGTERM: termination request.
+    Terminate,
+    /// SIGUSR1: user-defined signal 1.
+    User1,
+    /// SIGUSR2: user-defined signal 2.
+    User2,
+    /// SIGWINCH: the terminal window was resized.
+    WindowChange,
+}
+
+impl Signal {
+    /// Returns the signal number.
+    pub fn as_raw(self) -> i32 {
+        match self {
+            Signal::Alarm => ffi::SIGALRM,
+            Signal::Child => ffi::SIGCHLD,
+            Signal::Hangup => ffi::SIGHUP,
+            Signal::Interrupt => ffi::SIGINT,
+            Signal::Pipe => ffi::SIGPIPE,
+            Signal::Quit => ffi::SIGQUIT,
+            Signal::Terminate => ffi::SIGTERM,
+            Signal::User1 => ffi::SIGUSR1,
+            Signal::User2 => ffi::SIGUSR2,
+            Signal::WindowChange => ffi::SIGWINCH,
+        }
+    }
+
+    /// Returns the signal with the specified number, if supported.
+    pub fn from_raw(number: i32) -> Option<Self> {
+        let signal =
+            match number {
+                ffi::SIGALRM => Signal::Alarm,
+                ffi::SIGCHLD => Signal::Child,
+                ffi::SIGHUP => Signal::Hangup,
+                ffi::SIGINT => Signal::Interrupt,
+                ffi::SIGPIPE => Signal::Pipe,
+                ffi::SIGQUIT => Signal::Quit,
+                ffi::SIGTERM => Signal::Terminate,
+                ffi::SIGUSR1 => Signal::User1,
+                ffi::SIGUSR2 => Signal::User2,
+                ffi::SIGWINCH => Signal::WindowChange,
+                _ => return None,
+            };
+        Some(signal)
+    }
+
+    /// Sends the signal to the current process.
+    pub fn raise(self) -> io::Result<()> {
+        if unsafe { ffi::raise(self.as_raw()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// A set of signals, used as a signal mask.
+#[derive(Clone, Copy)]
+pub struct SignalSet {
+    set: ffi::sigset_t,
+}
+
+impl SignalSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        let mut set = ffi::sigset_t {
+            bits: [0; 16],
+        };
+        unsafe {
+            ffi::sigemptyset(&mut set);
+        }
+        Self {
+            set,
+        }
+    }
+
+    /// Adds a signal to the set.
+    pub fn add(&mut self, signal: Signal) -> &mut Self {
+        unsafe {
+            ffi::sigaddset(&mut self.set, signal.as_raw());
+        }
+        self
+    }
+
+    /// Returns true if the signal is in the set.
+    pub fn contains(&self, signal: Signal) -> bool {
+        unsafe { ffi::sigismember(&self.set, signal.as_raw()) == 1 }
+    }
+
+    /// Removes a signal from the set.
+    pub fn remove(&mut self, signal: Signal) -> &mut Self {
+        unsafe {
+            ffi::sigdelset(&mut self.set, signal.as_raw());
+        }
+        self
+    }
+
+    /// Adds the signals of the set to the signal mask of the current thread, returning the previous
+    /// mask.
+    pub fn block(&self) -> io::Result<SignalSet> {
+        self.change_mask(ffi::SIG_BLOCK)
+    }
+
+    /// Replaces the signal mask of the current thread with this set, returning the previous mask.
+    pub fn set_mask(&self) -> io::Result<SignalSet> {
+        self.change_mask(ffi::SIG_SETMASK)
+    }
+
+    /// Removes the signals of the set from the signal mask of the current thread, returning the
+    /// previous mask.
+    pub fn unblock(&self) -> io::Result<SignalSet> {
+        self.change_mask(ffi::SIG_UNBLOCK)
+    }
+
+    /// Returns a pointer to the `sigset_t` of the set, for system calls taking a signal mask like
+    /// `epoll_pwait`.
+    pub fn as_ptr(&self) -> *const c_void {
+        &self.set as *const ffi::sigset_t as *const c_void
+    }
+
+    /// Returns the signal mask of the current thread.
+    pub fn current_mask() -> io::Result<SignalSet> {
+        let mut old = SignalSet::new();
+        if unsafe { ffi::sigprocmask(ffi::SIG_BLOCK, ptr::null(), &mut old.set) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(old)
+    }
+
+    fn change_mask(&self, how: i32) -> io::Result<SignalSet> {
+        let mut old = SignalSet::new();
+        if unsafe { ffi::sigprocmask(how, &self.set, &mut old.set) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(old)
+    }
+}
+
+impl Default for SignalSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> From<&'a [Signal]> for SignalSet {
+    fn from(signals: &'a [Signal]) -> Self {
+        signals.iter().cloned().collect()
+    }
+}
+
+impl FromIterator<Signal> for SignalSet {
+    fn from_iter<I: IntoIterator<Item=Signal>>(iter: I) -> Self {
+        let mut set = SignalSet::new();
+        for signal in iter {
+            set.add(signal);
+        }
+        set
+    }
+}
+
+/// Ignores the signal, like `Signal::Pipe` in a network server.
+pub fn ignore(signal: Signal) -> io::Result<()> {
+    set_handler(signal, ffi::SIG_IGN)
+}
+
+/// Restores the default action of the signal.
+pub fn reset_default(signal: Signal) -> io::Result<()> {
+    set_handler(signal, ffi::SIG_DFL)
+}
+
+fn set_handler(signal: Signal, handler: usize) -> io::Result<()> {
+    let action = ffi::sigaction {
+        sa_sigaction: handler,
+        sa_mask: SignalSet::new().set,
+        sa_flags: 0,
+        sa_restorer: None,
+    };
+    if unsafe { ffi::sigaction(signal.as_raw(), &action, ptr::null_mut()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A non-blocking file descriptor from which the pending signals of a set are read.
+///
+/// The signals must be blocked, otherwise their default action runs instead.
+pub struct SignalFd {
+    fd: RawFd,
+}
+
+impl SignalFd {
+    /// Creates a file descriptor receiving the signals of the set.
+    pub fn new(signals: &SignalSet) -> io::Result<Self> {
+        let fd = unsafe { ffi::signalfd(-1, &signals.set, ffi::SFD_NONBLOCK | ffi::SFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd,
+        })
+    }
+
+    /// Reads the next pending signal, returning `None` when no signal is pending.
+    pub fn read(&self) -> io::Result<Option<Signal>> {
+        loop {
+            let mut info: ffi::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of::<ffi::signalfd_siginfo>();
+            let result = unsafe { ffi::read(self.fd, &mut info as *mut _ as *mut _, size) };
+            if result == -1 {
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(None),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(error),
+                }
+            }
+            // Skip signals not supported by Signal: they can only come from a mask modified elsewhere.
+            if let Some(signal) = Signal::from_raw(info.ssi_signo as i32) {
+                return Ok(Some(signal));
+            }
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+mod ffi {
+    #![allow(non_camel_case_types)]
+
+    use std::os::raw::c_void;
+
+    pub const SIGHUP: i32 = 1;
+    pub const SIGINT: i32 = 2;
+    pub const SIGQUIT: i32 = 3;
+    pub const SIGUSR1: i32 = 10;
+    pub const SIGUSR2: i32 = 12;
+    pub const SIGPIPE: i32 = 13;
+    pub const SIGALRM: i32 = 14;
+    pub const SIGTERM: i32 = 15;
+    pub const SIGCHLD: i32 = 17;
+    pub const SIGWINCH: i32 = 28;
+
+    pub const SIG_BLOCK: i32 = 0;
+    pub const SIG_UNBLOCK: i32 = 1;
+    pub const SIG_SETMASK: i32 = 2;
+
+    pub const SIG_DFL: usize = 0;
+    pub const SIG_IGN: usize = 1;
+
+    pub const SFD_CLOEXEC: i32 = 0o2000000;
+    pub const SFD_NONBLOCK: i32 = 0o4000;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct sigset_t {
+        pub bits: [u64; 16],
+    }
+
+    #[repr(C)]
+    pub struct sigaction {
+        pub sa_sigaction: usize,
+        pub sa_mask: sigset_t,
+        pub sa_flags: i32,
+        pub sa_restorer: Option<extern "C" fn()>,
+    }
+
+    #[repr(C)]
+    pub struct signalfd_siginfo {
+        pub ssi_signo: u32,
+        pub ssi_errno: i32,
+        pub ssi_code: i32,
+        pub ssi_pid: u32,
+        pub ssi_uid: u32,
+        _pad: [u8; 108],
+    }
+
+    extern "C" {
+        pub fn raise(sig: i32) -> i32;
+        pub fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
+        pub fn sigaction(signum: i32, act: *const sigaction, oldact: *mut sigaction) -> i32;
+        pub fn sigaddset(set: *mut sigset_t, signum: i32) -> i32;
+        pub fn sigdelset(set: *mut sigset_t, signum: i32) -> i32;
+        pub fn sigemptyset(set: *mut sigset_t) -> i32;
+        pub fn sigismember(set: *const sigset_t, signum: i32) -> i32;
+        pub fn signalfd(fd: i32, mask: *const sigset_t, flags: i32) -> i32;
+        pub fn sigprocmask(how: i32, set: *const sigset_t, oldset: *mut sigset_t) -> i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{Signal, SignalFd, SignalSet};
+
+    #[test]
+    fn signal_set() {
+        let mut set = SignalSet::from(&[Signal::Hangup, Signal::Terminate][..]);
+        assert!(set.contains(Signal::Hangup));
+        assert!(!set.contains(Signal::Interrupt));
+        set.remove(Signal::Hangup);
+        assert!(!set.contains(Signal::Hangup));
+        assert_eq!(Signal::from_raw(Signal::User2.as_raw()), Some(Signal::User2));
+        assert_eq!(Signal::from_raw(64), None);
+    }
+
+    #[test]
+    fn signal_fd() {
+        // Run in a new thread to leave the mask of the test thread untouched.
+        thread::spawn(|| {
+            let set: SignalSet = vec![Signal::User1].into_iter().collect();
+            let previous = set.block().expect("block");
+            assert!(SignalSet::current_mask().expect("mask").contains(Signal::User1));
+            let signal_fd = SignalFd::new(&set).expect("signalfd");
+            assert_eq!(signal_fd.read().expect("read"), None);
+            // raise() sends the signal to the calling thread.
+            Signal::User1.raise().expect("raise");
+            assert_eq!(signal_fd.read().expect("read"), Some(Signal::User1));
+            assert_eq!(signal_fd.read().expect("read"), None);
+            previous.set_mask().expect("restore mask");
+        }).join().expect("join");
+    }
+}
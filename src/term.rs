@@ -0,0 +1,410 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Terminal helpers: TTY detection, size query, ANSI styles and cursor control.
+
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+/// A standard stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StdStream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl StdStream {
+    fn fd(self) -> i32 {
+        match self {
+            StdStream::Stdin => 0,
+            StdStream::Stdout => 1,
+            StdStream::Stderr => 2,
+        }
+    }
+}
+
+/// Returns true if the stream is connected to a terminal.
+pub fn is_tty(stream: StdStream) -> bool {
+    unsafe { ffi::isatty(stream.fd()) == 1 }
+}
+
+/// Returns true if colors should be written to the stream: it is a terminal, `TERM` is not `dumb`
+/// and `NO_COLOR` is not set.
+pub fn colors_enabled(stream: StdStream) -> bool {
+    is_tty(stream) &&
+        env::var_os("NO_COLOR").map_or(true, |value| value.is_empty()) &&
+        env::var("TERM").map(|term| term != "dumb").unwrap_or(true)
+}
+
+/// Returns the size of the terminal connected to the stream as (columns, rows).
+pub fn size(stream: StdStream) -> Option<(u16, u16)> {
+    let mut size: ffi::winsize = unsafe { mem::zeroed() };
+    if unsafe { ffi::ioctl(stream.fd(), ffi::TIOCGWINSZ, &mut size) } == -1 || size.ws_col == 0 {
+        return None;
+    }
+    Some((size.ws_col, size.ws_row))
+}
+
+/// Returns the width of the terminal, looking at stdout, then stderr, then the `COLUMNS`
+/// environment variable.
+pub fn width() -> Option<usize> {
+    size(StdStream::Stdout)
+        .or_else(|| size(StdStream::Stderr))
+        .map(|(columns, _)| usize::from(columns))
+        .or_else(|| {
+            env::var("COLUMNS").ok()
+                .and_then(|columns| columns.trim().parse().ok())
+                .filter(|&columns| columns > 0)
+        })
+}
+
+/// A terminal color.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// A color of the 256-color palette.
+    Fixed(u8),
+    /// A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Writes the SGR parameters of the color; `base` is 30 for the foreground and 40 for the
+    /// background.
+    fn write_parameters(self, formatter: &mut Formatter, base: u8) -> fmt::Result {
+        let basic = |index: u8| index + base;
+        let bright = |index: u8| index + base + 60;
+        match self {
+            Color::Black => write!(formatter, "{}", basic(0)),
+            Color::Red => write!(formatter, "{}", basic(1)),
+            Color::Green => write!(formatter, "{}", basic(2)),
+            Color::Yellow => write!(formatter, "{}", basic(3)),
+            Color::Blue => write!(formatter, "{}", basic(4)),
+            Color::Magenta => write!(formatter, "{}", basic(5)),
+            Color::Cyan => write!(formatter, "{}", basic(6)),
+            Color::White => write!(formatter, "{}", basic(7)),
+            Color::BrightBlack => write!(formatter, "{}", bright(0)),
+            Color::BrightRed => write!(formatter, "{}", bright(1)),
+            Color::BrightGreen => write!(formatter, "{}", bright(2)),
+            Color::BrightYellow => write!(formatter, "{}", bright(3)),
+            Color::BrightBlue => write!(formatter, "{}", bright(4)),
+            Color::BrightMagenta => write!(formatter, "{}", bright(5)),
+            Color::BrightCyan => write!(formatter, "{}", bright(6)),
+            Color::BrightWhite => write!(formatter, "{}", bright(7)),
+            Color::Fixed(index) => write!(formatter, "{};5;{}", base + 8, index),
+            Color::Rgb(red, green, blue) => write!(formatter, "{};2;{};{};{}", base + 8, red, green, blue),
+        }
+    }
+}
+
+/// The sequence resetting all the attributes.
+pub const RESET: &str = "\x1b[0m";
+
+/// A set of text attributes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Style {
+    background: Option<Color>,
+    bold: bool,
+    dimmed: bool,
+    foreground: Option<Color>,
+    italic: bool,
+    reverse: bool,
+    underline: bool,
+}
+
+impl Style {
+    /// Creates a style without any attribute.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the background color.
+    pub fn bg(mut self, color: Color) -> Self {
+        self.background = Some(color);
+        self
+    }
+
+    /// Makes the text bold.
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    /// Makes the text dim.
+    pub fn dimmed(mut self) -> Self {
+        self.dimmed = true;
+        self
+    }
+
+    /// Sets the foreground color.
+    pub fn fg(mut self, color: Color) -> Self {
+        self.foreground = Some(color);
+        self
+    }
+
+    /// Makes the text italic.
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    /// Swaps the foreground and background colors.
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Underlines the text.
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    /// Returns true if the style has no attribute.
+    pub fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Wraps the text so that it is displayed with this style.
+    pub fn paint<T: Display>(self, text: T) -> Painted<T> {
+        Painted {
+            style: self,
+            text,
+        }
+    }
+
+    /// Returns the sequence enabling this style, empty for a plain style.
+    pub fn prefix(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for Style {
+    /// Writes the sequence enabling this style.
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        if self.is_plain() {
+            return Ok(());
+        }
+        write!(formatter, "\x1b[")?;
+        let mut separator = "";
+        let flags = [(self.bold, "1"), (self.dimmed, "2"), (self.italic, "3"), (self.underline, "4"), (self.reverse, "7")];
+        for &(enabled, code) in &flags {
+            if enabled {
+                write!(formatter, "{}{}", separator, code)?;
+                separator = ";";
+            }
+        }
+        if let Some(color) = self.foreground {
+            write!(formatter, "{}", separator)?;
+            color.write_parameters(formatter, 30)?;
+            separator = ";";
+        }
+        if let Some(color) = self.background {
+            write!(formatter, "{}", separator)?;
+            color.write_parameters(formatter, 40)?;
+        }
+        write!(formatter, "m")
+    }
+}
+
+/// Text displayed with a style, followed by a reset.
+#[derive(Clone, Copy, Debug)]
+pub struct Painted<T> {
+    style: Style,
+    text: T,
+}
+
+impl<T: Display> Display for Painted<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        if self.style.is_plain() {
+            return self.text.fmt(formatter);
+        }
+        write!(formatter, "{}", self.style)?;
+        self.text.fmt(formatter)?;
+        write!(formatter, "{}", RESET)
+    }
+}
+
+/// Characters occupying no column: combining marks, zero-width spaces and joiners, and variation
+/// selectors.
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036f), (0x0483, 0x0489), (0x0591, 0x05bd), (0x0610, 0x061a), (0x064b, 0x065f),
+    (0x0e31, 0x0e31), (0x0e34, 0x0e3a), (0x0e47, 0x0e4e), (0x1ab0, 0x1aff), (0x1dc0, 0x1dff),
+    (0x200b, 0x200f), (0x20d0, 0x20ff), (0xfe00, 0xfe0f), (0xfe20, 0xfe2f), (0xe0100, 0xe01ef),
+];
+
+/// Characters occupying two columns: East Asian wide and fullwidth characters, and emojis.
+const DOUBLE_WIDTH: &[(u32, u32)] = &[
+    (0x1100, 0x115f), (0x2e80, 0x303e), (0x3041, 0x33ff), (0x3400, 0x4dbf), (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf), (0xac00, 0xd7a3), (0xf900, 0xfaff), (0xfe30, 0xfe4f), (0xff00, 0xff60),
+    (0xffe0, 0xffe6), (0x1f300, 0x1f64f), (0x1f900, 0x1f9ff), (0x20000, 0x2fffd), (0x30000, 0x3fffd),
+];
+
+/// Returns the number of columns a character occupies in a terminal: 0 for control characters
+/// and combining marks, 2 for wide characters, 1 otherwise.
+pub fn char_width(character: char) -> usize {
+    let code = character as u32;
+    let within = |ranges: &[(u32, u32)]| ranges.iter().any(|&(start, end)| start <= code && code <= end);
+    if character.is_control() || within(ZERO_WIDTH) {
+        0
+    }
+    else if within(DOUBLE_WIDTH) {
+        2
+    }
+    else {
+        1
+    }
+}
+
+/// Returns the number of columns the text occupies, ignoring the ANSI escape sequences.
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(character) = chars.next() {
+        if character == '\x1b' {
+            // Skip a CSI sequence up to its final byte.
+            if chars.next() == Some('[') {
+                while let Some(character) = chars.next() {
+                    if ('@'..='~').contains(&character) {
+                        break;
+                    }
+                }
+            }
+        }
+        else {
+            width += char_width(character);
+        }
+    }
+    width
+}
+
+/// Cursor movement and screen clearing sequences.
+pub mod cursor {
+    /// Hides the cursor.
+    pub const HIDE: &str = "\x1b[?25l";
+    /// Shows the cursor.
+    pub const SHOW: &str = "\x1b[?25h";
+    /// Saves the cursor position.
+    pub const SAVE: &str = "\x1b7";
+    /// Restores the cursor position saved with `SAVE`.
+    pub const RESTORE: &str = "\x1b8";
+    /// Clears the whole line of the cursor.
+    pub const CLEAR_LINE: &str = "\x1b[2K";
+    /// Clears from the cursor to the end of the line.
+    pub const CLEAR_TO_END_OF_LINE: &str = "\x1b[0K";
+    /// Clears the whole screen.
+    pub const CLEAR_SCREEN: &str = "\x1b[2J";
+
+    /// Moves the cursor up.
+    pub fn up(lines: u16) -> String {
+        format!("\x1b[{}A", lines)
+    }
+
+    /// Moves the cursor down.
+    pub fn down(lines: u16) -> String {
+        format!("\x1b[{}B", lines)
+    }
+
+    /// Moves the cursor right.
+    pub fn forward(columns: u16) -> String {
+        format!("\x1b[{}C", columns)
+    }
+
+    /// Moves the cursor left.
+    pub fn back(columns: u16) -> String {
+        format!("\x1b[{}D", columns)
+    }
+
+    /// Moves the cursor to a column of the current line, starting at 1.
+    pub fn column(column: u16) -> String {
+        format!("\x1b[{}G", column)
+    }
+
+    /// Moves the cursor to a position, starting at (1, 1).
+    pub fn move_to(row: u16, column: u16) -> String {
+        format!("\x1b[{};{}H", row, column)
+    }
+}
+
+mod ffi {
+    #![allow(non_camel_case_types)]
+
+    pub const TIOCGWINSZ: u64 = 0x5413;
+
+    #[repr(C)]
+    pub struct winsize {
+        pub ws_row: u16,
+        pub ws_col: u16,
+        pub ws_xpixel: u16,
+        pub ws_ypixel: u16,
+    }
+
+    extern "C" {
+        pub fn ioctl(fd: i32, request: u64, ...) -> i32;
+        pub fn isatty(fd: i32) -> i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, RESET, Style, char_width, cursor, visible_width};
+
+    #[test]
+    fn styles() {
+        assert_eq!(Style::new().paint("plain").to_string(), "plain");
+        assert_eq!(Style::new().fg(Color::Red).paint("error").to_string(), "\x1b[31merror\x1b[0m");
+        assert_eq!(Style::new().bold().underline().fg(Color::BrightBlue).bg(Color::Black).prefix(),
+            "\x1b[1;4;94;40m");
+        assert_eq!(Style::new().fg(Color::Fixed(208)).bg(Color::Rgb(1, 2, 3)).prefix(), "\x1b[38;5;208;48;2;1;2;3m");
+        assert_eq!(format!("{:>5}", Style::new().paint(1)), "    1");
+        assert_eq!(RESET, "\x1b[0m");
+    }
+
+    #[test]
+    fn cursor_and_width() {
+        assert_eq!(cursor::up(2), "\x1b[2A");
+        assert_eq!(cursor::move_to(3, 4), "\x1b[3;4H");
+        let painted = Style::new().bold().fg(Color::Green).paint("héllo").to_string();
+        assert_eq!(visible_width(&painted), 5);
+        assert_eq!(visible_width(&format!("{}{}ab", cursor::CLEAR_LINE, cursor::column(1))), 2);
+        assert_eq!(visible_width("日本語"), 6);
+        assert_eq!(visible_width("e\u{301}t\u{e9}"), 3);
+        assert_eq!(char_width('\t'), 0);
+        assert_eq!(char_width('\u{ff21}'), 2);
+    }
+}
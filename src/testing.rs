@@ -0,0 +1,566 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Helpers to unit-test handlers and `TcpConnectionNotify` implementations without real sockets or
+//! sleeps.
+//!
+//! `Harness` drives a `Loop` until its handlers have processed all their messages, with timers on
+//! a virtual clock that advance instantly. `MockConnection` feeds a notify as if it were connected
+//! to a peer, and `Pair` connects two notifies, e.g. a client and a server, to each other.
+//! `Recorder` collects the messages sent to a stream.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::{self, ErrorKind, Read};
+use std::net::TcpStream;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use aio::async::{EpollResult, EventLoop};
+use aio::handler::{Handler, Loop, Stream};
+use aio::net::{TcpConnection, TcpConnectionNotify};
+use bytes::Bytes;
+
+/// Maximum number of loop iterations before `Harness::run_until_idle()` concludes that the
+/// handlers keep sending messages to each other forever.
+const MAX_ITERATIONS: usize = 100_000;
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct Clock {
+    elapsed: Rc<Cell<Duration>>,
+    start: Instant,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            elapsed: Rc::new(Cell::new(Duration::from_secs(0))),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+
+    /// Returns the virtual time elapsed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    /// Returns the virtual current time.
+    pub fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identifies a timer, to cancel it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TimerId(u64);
+
+struct TimerQueue {
+    next_id: u64,
+    /// Keyed by deadline, then by id to keep the insertion order of timers with the same deadline.
+    timers: BTreeMap<(Duration, TimerId), Box<dyn FnOnce()>>,
+}
+
+/// Timers on the virtual clock of a `Harness`. Clones share the same timers.
+#[derive(Clone)]
+pub struct Timers {
+    clock: Clock,
+    queue: Rc<RefCell<TimerQueue>>,
+}
+
+impl Timers {
+    /// Sends `msg` to `stream` after `delay` of virtual time.
+    pub fn after<MSG: 'static>(&self, delay: Duration, stream: &Stream<MSG>, msg: MSG) -> TimerId {
+        let stream = stream.clone();
+        let mut queue = self.queue.borrow_mut();
+        let id = TimerId(queue.next_id);
+        queue.next_id += 1;
+        queue.timers.insert((self.clock.elapsed() + delay, id), Box::new(move || stream.send(msg)));
+        id
+    }
+
+    /// Cancels the timer. Returns false if it already fired or was cancelled.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        let mut queue = self.queue.borrow_mut();
+        let key = queue.timers.keys().find(|&&(_, timer_id)| timer_id == id).cloned();
+        key.and_then(|key| queue.timers.remove(&key)).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().timers.is_empty()
+    }
+
+    /// Returns the virtual time at which the next timer fires.
+    fn next_deadline(&self) -> Option<Duration> {
+        self.queue.borrow().timers.keys().next().map(|&(deadline, _)| deadline)
+    }
+
+    /// Removes the next timer if it fires at or before `deadline`.
+    fn pop(&self, deadline: Duration) -> Option<(Duration, Box<dyn FnOnce()>)> {
+        let mut queue = self.queue.borrow_mut();
+        let key = *queue.timers.keys().next()?;
+        if key.0 > deadline {
+            return None;
+        }
+        queue.timers.remove(&key).map(|callback| (key.0, callback))
+    }
+}
+
+/// Deterministic single-threaded driver of a `Loop`, with a virtual clock.
+pub struct Harness {
+    clock: Clock,
+    event_loop: Loop,
+    timers: Timers,
+}
+
+impl Harness {
+    pub fn new() -> io::Result<Self> {
+        let clock = Clock::new();
+        Ok(Self {
+            event_loop: Loop::new()?,
+            timers: Timers {
+                clock: clock.clone(),
+                queue: Rc::new(RefCell::new(TimerQueue {
+                    next_id: 0,
+                    timers: BTreeMap::new(),
+                })),
+            },
+            clock,
+        })
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn event_loop(&mut self) -> &mut Loop {
+        &mut self.event_loop
+    }
+
+    pub fn spawn<HANDLER, MSG>(&mut self, handler: HANDLER) -> Stream<MSG>
+    where HANDLER: Handler<Msg=MSG> + 'static,
+          MSG: 'static,
+    {
+        self.event_loop.spawn(handler)
+    }
+
+    /// Returns the timers on the virtual clock, to give to the handlers under test.
+    pub fn timers(&self) -> &Timers {
+        &self.timers
+    }
+
+    /// Advances the virtual clock by `duration`, firing the timers that expire in order, each at its
+    /// own deadline, and processing the resulting messages.
+    pub fn advance(&mut self, duration: Duration) {
+        let target = self.clock.elapsed() + duration;
+        self.run_until_idle();
+        while let Some((deadline, callback)) = self.timers.pop(target) {
+            self.clock.advance(deadline.saturating_sub(self.clock.elapsed()));
+            callback();
+            self.run_until_idle();
+        }
+        self.clock.advance(target - self.clock.elapsed());
+    }
+
+    /// Processes messages and fires timers, advancing the virtual clock as needed, until nothing is
+    /// left to do. Does not return if the handlers keep rearming timers: use `advance()` or
+    /// `run_until()` for those.
+    pub fn run(&mut self) {
+        self.run_until_idle();
+        while let Some(deadline) = self.timers.next_deadline() {
+            let delay = deadline.saturating_sub(self.clock.elapsed());
+            self.advance(delay);
+        }
+    }
+
+    /// Like `run()`, but stops as soon as `condition` returns true, which is then returned.
+    pub fn run_until<F: FnMut() -> bool>(&mut self, mut condition: F) -> bool {
+        self.run_until_idle();
+        while !condition() {
+            match self.timers.next_deadline() {
+                Some(deadline) => {
+                    let delay = deadline.saturating_sub(self.clock.elapsed());
+                    self.advance(delay);
+                },
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Processes the messages sent to the handlers, and the ones they send in turn, without
+    /// advancing the virtual clock. Events on file descriptors that are already ready are
+    /// processed too, but this never waits for one.
+    ///
+    /// Panics if the handlers are still busy after many iterations.
+    pub fn run_until_idle(&mut self) {
+        for _ in 0..MAX_ITERATIONS {
+            if self.event_loop.is_idle() {
+                return;
+            }
+            // Makes epoll_wait() return right away.
+            EventLoop::wakeup();
+            match self.event_loop.iterate() {
+                EpollResult::Ok | EpollResult::Interrupted => (),
+                EpollResult::Error(error) => panic!("event loop error: {}", error),
+            }
+        }
+        panic!("the handlers are still busy after {} iterations", MAX_ITERATIONS);
+    }
+}
+
+/// Collects the messages sent to its stream.
+pub struct Recorder<MSG> {
+    messages: Rc<RefCell<Vec<MSG>>>,
+}
+
+impl<MSG: 'static> Recorder<MSG> {
+    /// Spawns a handler recording the messages sent to the returned stream.
+    pub fn spawn(harness: &mut Harness) -> (Self, Stream<MSG>) {
+        let messages = Rc::new(RefCell::new(vec![]));
+        let stream = harness.spawn(Recording {
+            messages: messages.clone(),
+        });
+        (Self {
+            messages,
+        }, stream)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.borrow().is_empty()
+    }
+
+    /// Removes and returns the messages recorded so far.
+    pub fn take(&self) -> Vec<MSG> {
+        self.messages.borrow_mut().drain(..).collect()
+    }
+
+    /// Panics unless the messages recorded since the last check are `expected`.
+    pub fn assert_messages(&self, expected: &[MSG])
+    where MSG: Debug + PartialEq,
+    {
+        let messages = self.take();
+        assert_eq!(messages.as_slice(), expected, "unexpected messages");
+    }
+}
+
+struct Recording<MSG> {
+    messages: Rc<RefCell<Vec<MSG>>>,
+}
+
+impl<MSG> Handler for Recording<MSG> {
+    type Msg = MSG;
+
+    fn update(&mut self, _stream: &Stream<MSG>, msg: MSG) {
+        self.messages.borrow_mut().push(msg);
+    }
+}
+
+/// Panics with both values escaped, which is more readable than the lists of numbers of
+/// `assert_eq!`, unless `actual` equals `expected`.
+pub fn assert_bytes_eq(actual: &[u8], expected: &[u8]) {
+    if actual != expected {
+        panic!("bytes differ:\n  actual: b\"{}\"\nexpected: b\"{}\"", escape(actual), escape(expected));
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| ::std::ascii::escape_default(byte)).map(char::from).collect()
+}
+
+/// A `TcpConnection` over an in-memory socket, not registered on any event loop, whose notify is
+/// driven by the test. `peer_addr()` returns an error on it.
+pub struct MockConnection<NOTIFY> {
+    closed: bool,
+    connection: TcpConnection,
+    notify: NOTIFY,
+    /// The other end of the socket, which receives what the notify writes.
+    peer: UnixStream,
+}
+
+impl<NOTIFY: TcpConnectionNotify> MockConnection<NOTIFY> {
+    /// Creates the connection and calls `notify.connected()`.
+    pub fn new(notify: NOTIFY) -> io::Result<Self> {
+        let (local, peer) = UnixStream::pair()?;
+        local.set_nonblocking(true)?;
+        peer.set_nonblocking(true)?;
+        // The TcpStream methods used by TcpConnection are plain reads and writes, which work the same
+        // on a Unix socket.
+        let stream = unsafe { TcpStream::from_raw_fd(local.into_raw_fd()) };
+        let mut connection = Self {
+            closed: false,
+            connection: TcpConnection::new(stream),
+            notify,
+            peer,
+        };
+        connection.notify.connected(&mut connection.connection);
+        Ok(connection)
+    }
+
+    /// Calls `notify.closed()`, as if the peer closed the connection.
+    pub fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.notify.closed(&mut self.connection);
+        }
+    }
+
+    pub fn connection(&self) -> &TcpConnection {
+        &self.connection
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn notify(&self) -> &NOTIFY {
+        &self.notify
+    }
+
+    pub fn notify_mut(&mut self) -> &mut NOTIFY {
+        &mut self.notify
+    }
+
+    /// Calls `notify.received()` with `data`, as if the peer sent it. Ignored once closed.
+    pub fn receive<B: Into<Bytes>>(&mut self, data: B) {
+        if !self.closed {
+            self.notify.received(&mut self.connection, data.into());
+        }
+    }
+
+    /// Removes and returns what the notify wrote to the connection since the last call.
+    pub fn written(&mut self) -> Vec<u8> {
+        let mut data = vec![];
+        let mut buffer = [0; 4096];
+        loop {
+            match self.peer.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => data.extend_from_slice(&buffer[..size]),
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(_) => break,
+            }
+        }
+        data
+    }
+
+    /// Panics unless what the notify wrote since the last call is `expected`.
+    pub fn assert_written(&mut self, expected: &[u8]) {
+        let written = self.written();
+        assert_bytes_eq(&written, expected);
+    }
+}
+
+/// Two notifies connected to each other in memory, e.g. a client and a server.
+pub struct Pair<A, B> {
+    pub first: MockConnection<A>,
+    pub second: MockConnection<B>,
+}
+
+impl<A: TcpConnectionNotify, B: TcpConnectionNotify> Pair<A, B> {
+    /// Creates the connections and calls `connected()` on `first`, then on `second`. What they
+    /// write is only delivered by `transfer()`.
+    pub fn new(first: A, second: B) -> io::Result<Self> {
+        Ok(Self {
+            first: MockConnection::new(first)?,
+            second: MockConnection::new(second)?,
+        })
+    }
+
+    /// Closes both sides: `closed()` is called on `first`, then on `second`.
+    pub fn close(&mut self) {
+        self.first.close();
+        self.second.close();
+    }
+
+    /// Delivers what each side wrote to the other, as many times as needed for both to stop writing.
+    /// Returns the number of bytes delivered.
+    pub fn transfer(&mut self) -> usize {
+        let mut total = 0;
+        loop {
+            let data = self.first.written();
+            let mut delivered = data.len();
+            if !data.is_empty() {
+                self.second.receive(data);
+            }
+            let data = self.second.written();
+            delivered += data.len();
+            if !data.is_empty() {
+                self.first.receive(data);
+            }
+            if delivered == 0 {
+                return total;
+            }
+            total += delivered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use aio::handler::{Handler, Stream};
+    use aio::net::{TcpConnection, TcpConnectionNotify};
+    use bytes::Bytes;
+    use super::{Harness, MockConnection, Pair, Recorder, Timers};
+
+    #[derive(Debug, PartialEq)]
+    enum Msg {
+        Ping(u32),
+        Tick,
+    }
+
+    /// Forwards the pings and ticks every second until stopped.
+    struct Ticker {
+        output: Stream<Msg>,
+        ticks: u32,
+        timers: Timers,
+    }
+
+    impl Handler for Ticker {
+        type Msg = Msg;
+
+        fn update(&mut self, stream: &Stream<Msg>, msg: Msg) {
+            match msg {
+                Msg::Ping(value) => self.output.send(Msg::Ping(value)),
+                Msg::Tick => {
+                    self.output.send(Msg::Tick);
+                    self.ticks -= 1;
+                    if self.ticks > 0 {
+                        self.timers.after(Duration::from_secs(1), stream, Msg::Tick);
+                    }
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn virtual_time() {
+        let mut harness = Harness::new().expect("harness");
+        let (recorder, output) = Recorder::spawn(&mut harness);
+        let timers = harness.timers().clone();
+        let ticker = harness.spawn(Ticker {
+            output,
+            ticks: 3,
+            timers: timers.clone(),
+        });
+        ticker.send(Msg::Ping(1));
+        harness.run_until_idle();
+        recorder.assert_messages(&[Msg::Ping(1)]);
+
+        timers.after(Duration::from_secs(10), &ticker, Msg::Tick);
+        let cancelled = timers.after(Duration::from_secs(5), &ticker, Msg::Ping(2));
+        timers.after(Duration::from_secs(10), &ticker, Msg::Ping(3));
+        assert!(timers.cancel(cancelled));
+        assert!(!timers.cancel(cancelled));
+        harness.advance(Duration::from_secs(9));
+        assert!(recorder.is_empty());
+        harness.advance(Duration::from_secs(1));
+        recorder.assert_messages(&[Msg::Tick, Msg::Ping(3)]);
+
+        harness.run();
+        recorder.assert_messages(&[Msg::Tick, Msg::Tick]);
+        assert_eq!(harness.clock().elapsed(), Duration::from_secs(12));
+        assert!(timers.is_empty());
+    }
+
+    /// Answers each line with its length.
+    #[derive(Default)]
+    struct LineLength {
+        buffer: Vec<u8>,
+        closed: bool,
+    }
+
+    impl TcpConnectionNotify for LineLength {
+        fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
+            self.buffer.extend_from_slice(&data);
+            while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                self.buffer.drain(..=end);
+                connection.write(format!("{}\n", end)).expect("write");
+            }
+        }
+
+        fn closed(&mut self, _connection: &mut TcpConnection) {
+            self.closed = true;
+        }
+    }
+
+    #[test]
+    fn mock_connection() {
+        let mut connection = MockConnection::new(LineLength::default()).expect("connection");
+        connection.receive("hello\nwor");
+        connection.assert_written(b"5\n");
+        connection.receive(&b"ld!\n\n"[..]);
+        connection.assert_written(b"6\n0\n");
+        connection.assert_written(b"");
+        connection.close();
+        assert!(connection.notify().closed && connection.is_closed());
+        assert!(connection.connection().peer_addr().is_err());
+    }
+
+    /// Sends lines and collects the answers.
+    struct Client {
+        answers: Vec<u8>,
+        lines: Vec<&'static str>,
+    }
+
+    impl TcpConnectionNotify for Client {
+        fn connected(&mut self, connection: &mut TcpConnection) {
+            for line in &self.lines {
+                connection.write(*line).expect("write");
+            }
+        }
+
+        fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
+            self.answers.extend_from_slice(&data);
+        }
+    }
+
+    #[test]
+    fn pair() {
+        let client = Client {
+            answers: vec![],
+            lines: vec!["a\n", "bcd\n"],
+        };
+        let mut pair = Pair::new(client, LineLength::default()).expect("pair");
+        assert_eq!(pair.transfer(), 10);
+        assert_eq!(pair.first.notify().answers, b"1\n3\n");
+        pair.close();
+        assert!(pair.second.notify().closed);
+    }
+}
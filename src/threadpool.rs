@@ -0,0 +1,432 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Pool of threads to run blocking work, like name resolution or file I/O, outside of event-loop
+//! threads.
+//!
+//! The pool has a bounded job queue: `execute` blocks when it is full while `try_execute` gives the
+//! job back. Both fail if the pool needs one more thread for the job and cannot spawn it. A
+//! panicking job does not take its worker thread down. Dropping the pool (or calling `shutdown`)
+//! runs the jobs still in the queue and joins the threads.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Builder to configure a `ThreadPool`.
+pub struct Builder {
+    keep_alive: Duration,
+    max_threads: usize,
+    min_threads: usize,
+    name: String,
+    queue_capacity: usize,
+}
+
+impl Builder {
+    /// Creates a builder for a pool of 4 threads with a queue of 1024 jobs.
+    pub fn new() -> Self {
+        Self {
+            keep_alive: Duration::from_secs(60),
+            max_threads: 4,
+            min_threads: 4,
+            name: "mini-pool".to_string(),
+            queue_capacity: 1024,
+        }
+    }
+
+    /// Sets how long threads above the minimum stay idle before exiting.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets the maximum number of threads. Threads are started on demand above the minimum.
+    pub fn max_threads(mut self, max_threads: usize) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Sets the number of threads started with the pool and always kept alive.
+    pub fn min_threads(mut self, min_threads: usize) -> Self {
+        self.min_threads = min_threads;
+        self
+    }
+
+    /// Sets the prefix of the names of the threads.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets the number of jobs that can wait in the queue.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Creates the pool and starts the minimum number of threads.
+    pub fn build(self) -> io::Result<ThreadPool> {
+        assert!(self.max_threads > 0, "the pool needs at least one thread");
+        assert!(self.min_threads <= self.max_threads, "min_threads should not be greater than max_threads");
+        assert!(self.queue_capacity > 0, "the queue capacity should not be zero");
+        let pool = ThreadPool {
+            inner: Arc::new(Inner {
+                job_done: Condvar::new(),
+                job_queued: Condvar::new(),
+                keep_alive: self.keep_alive,
+                max_threads: self.max_threads,
+                min_threads: self.min_threads,
+                name: self.name,
+                queue_capacity: self.queue_capacity,
+                space_available: Condvar::new(),
+                state: Mutex::new(State {
+                    active: 0,
+                    handles: vec![],
+                    jobs: VecDeque::new(),
+                    next_thread_id: 0,
+                    panicked: 0,
+                    shutdown: false,
+                    threads: 0,
+                }),
+            }),
+        };
+        {
+            let mut state = pool.inner.lock();
+            for _ in 0..pool.inner.min_threads {
+                spawn_worker(&pool.inner, &mut state)?;
+            }
+        }
+        Ok(pool)
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct State {
+    active: usize,
+    handles: Vec<JoinHandle<()>>,
+    jobs: VecDeque<Job>,
+    next_thread_id: usize,
+    panicked: usize,
+    shutdown: bool,
+    threads: usize,
+}
+
+struct Inner {
+    job_done: Condvar,
+    job_queued: Condvar,
+    keep_alive: Duration,
+    max_threads: usize,
+    min_threads: usize,
+    name: String,
+    queue_capacity: usize,
+    space_available: Condvar,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Jobs run outside of the lock, so the mutex cannot be poisoned by them.
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// Error returned by `ThreadPool::try_execute`. Contains the job.
+pub enum TryExecuteError<F> {
+    /// The queue is full.
+    Full(F),
+    /// The pool needed one more thread for the job and could not spawn it.
+    Spawn(F, io::Error),
+}
+
+impl<F> Debug for TryExecuteError<F> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            TryExecuteError::Full(_) => formatter.write_str("Full(..)"),
+            TryExecuteError::Spawn(_, ref error) => write!(formatter, "Spawn(.., {:?})", error),
+        }
+    }
+}
+
+impl<F> Display for TryExecuteError<F> {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            TryExecuteError::Full(_) => formatter.write_str("executing on a full thread pool"),
+            TryExecuteError::Spawn(_, ref error) => write!(formatter, "cannot spawn thread pool worker: {}", error),
+        }
+    }
+}
+
+impl<F> Error for TryExecuteError<F> {
+}
+
+/// A pool of threads executing jobs from a bounded queue.
+pub struct ThreadPool {
+    inner: Arc<Inner>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with a fixed number of threads.
+    pub fn new(threads: usize) -> io::Result<Self> {
+        Builder::new()
+            .min_threads(threads)
+            .max_threads(threads)
+            .build()
+    }
+
+    /// Queues a job, blocking while the queue is full.
+    ///
+    /// Fails, without queuing the job, if the pool needs one more thread for it and cannot spawn
+    /// it.
+    pub fn execute<F>(&self, job: F) -> io::Result<()>
+    where F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.inner.lock();
+        while state.jobs.len() >= self.inner.queue_capacity {
+            state = self.inner.space_available.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+        self.grow(&mut state)?;
+        self.push(state, Box::new(job));
+        Ok(())
+    }
+
+    /// Queues a job, or gives it back if the queue is full or if the pool needs one more thread for
+    /// it and cannot spawn it.
+    pub fn try_execute<F>(&self, job: F) -> Result<(), TryExecuteError<F>>
+    where F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.inner.lock();
+        if state.jobs.len() >= self.inner.queue_capacity {
+            return Err(TryExecuteError::Full(job));
+        }
+        if let Err(error) = self.grow(&mut state) {
+            return Err(TryExecuteError::Spawn(job, error));
+        }
+        self.push(state, Box::new(job));
+        Ok(())
+    }
+
+    /// Spawns a thread if there are not enough idle ones to run one more job.
+    fn grow(&self, state: &mut State) -> io::Result<()> {
+        let idle = state.threads - state.active;
+        if state.jobs.len() >= idle && state.threads < self.inner.max_threads {
+            spawn_worker(&self.inner, state)?;
+        }
+        Ok(())
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, State>, job: Job) {
+        state.jobs.push_back(job);
+        self.inner.job_queued.notify_one();
+    }
+
+    /// Blocks until the queue is empty and no job is running.
+    pub fn join(&self) {
+        let mut state = self.inner.lock();
+        while !state.jobs.is_empty() || state.active > 0 {
+            state = self.inner.job_done.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+    }
+
+    /// Returns the number of jobs waiting in the queue.
+    pub fn queued_count(&self) -> usize {
+        self.inner.lock().jobs.len()
+    }
+
+    /// Returns the number of jobs currently running.
+    pub fn active_count(&self) -> usize {
+        self.inner.lock().active
+    }
+
+    /// Returns the number of jobs which panicked.
+    pub fn panic_count(&self) -> usize {
+        self.inner.lock().panicked
+    }
+
+    /// Returns the number of running threads.
+    pub fn thread_count(&self) -> usize {
+        self.inner.lock().threads
+    }
+
+    /// Runs the jobs remaining in the queue, then stops and joins the threads.
+    pub fn shutdown(self) {
+        // Done in drop().
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        let handles = {
+            let mut state = self.inner.lock();
+            state.shutdown = true;
+            self.inner.job_queued.notify_all();
+            state.handles.drain(..).collect::<Vec<_>>()
+        };
+        for handle in handles {
+            // Panics are caught in the worker, so join cannot fail.
+            let _ = handle.join();
+        }
+    }
+}
+
+fn spawn_worker(inner: &Arc<Inner>, state: &mut State) -> io::Result<()> {
+    let worker_inner = inner.clone();
+    let handle = thread::Builder::new()
+        .name(format!("{}-{}", inner.name, state.next_thread_id))
+        .spawn(move || worker(worker_inner))?;
+    state.next_thread_id += 1;
+    state.threads += 1;
+    // Forget about the threads which exited after being idle for too long.
+    state.handles.retain(|handle| !handle.is_finished());
+    state.handles.push(handle);
+    Ok(())
+}
+
+fn worker(inner: Arc<Inner>) {
+    let mut state = inner.lock();
+    loop {
+        if let Some(job) = state.jobs.pop_front() {
+            state.active += 1;
+            inner.space_available.notify_one();
+            drop(state);
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            state = inner.lock();
+            state.active -= 1;
+            if result.is_err() {
+                state.panicked += 1;
+            }
+            inner.job_done.notify_all();
+        }
+        else if state.shutdown {
+            break;
+        }
+        else if state.threads > inner.min_threads {
+            let (new_state, timeout) = inner.job_queued.wait_timeout(state, inner.keep_alive)
+                .unwrap_or_else(|error| error.into_inner());
+            state = new_state;
+            if timeout.timed_out() && state.jobs.is_empty() && state.threads > inner.min_threads {
+                break;
+            }
+        }
+        else {
+            state = inner.job_queued.wait(state).unwrap_or_else(|error| error.into_inner());
+        }
+    }
+    state.threads -= 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Builder, ThreadPool, TryExecuteError};
+
+    #[test]
+    fn execute() {
+        let pool = ThreadPool::new(4).expect("thread pool");
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }).expect("execute");
+        }
+        pool.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+        assert_eq!(pool.thread_count(), 4);
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let pool = Builder::new()
+            .min_threads(1)
+            .max_threads(1)
+            .queue_capacity(1)
+            .build()
+            .expect("thread pool");
+        let (sender, receiver) = mpsc::channel::<()>();
+        let (started_sender, started_receiver) = mpsc::channel();
+        pool.execute(move || {
+            started_sender.send(()).expect("send");
+            let _ = receiver.recv();
+        }).expect("execute");
+        started_receiver.recv().expect("recv");
+        assert!(pool.try_execute(|| ()).is_ok());
+        match pool.try_execute(|| ()) {
+            Err(TryExecuteError::Full(_)) => (),
+            result => panic!("expected a full queue, got {:?}", result),
+        }
+        drop(sender);
+        pool.join();
+        assert_eq!(pool.queued_count(), 0);
+    }
+
+    #[test]
+    fn panic_containment() {
+        let pool = ThreadPool::new(1).expect("thread pool");
+        pool.execute(|| panic!("job panic")).expect("execute");
+        let counter = Arc::new(AtomicUsize::new(0));
+        let job_counter = counter.clone();
+        pool.execute(move || {
+            job_counter.fetch_add(1, Ordering::SeqCst);
+        }).expect("execute");
+        pool.join();
+        assert_eq!(pool.panic_count(), 1);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn elastic_shutdown() {
+        let pool = Builder::new()
+            .min_threads(0)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(10))
+            .build()
+            .expect("thread pool");
+        assert_eq!(pool.thread_count(), 0);
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..6 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                counter.fetch_add(1, Ordering::SeqCst);
+            }).expect("execute");
+        }
+        assert_eq!(pool.thread_count(), 3);
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 6);
+    }
+}
@@ -0,0 +1,335 @@
+//! Formatting and parsing of wall-clock times as RFC 3339 timestamps (`2024-03-01T12:30:00Z`) and
+//! as HTTP dates (RFC 7231, `Fri, 01 Mar 2024 12:30:00 GMT`).
+//!
+//! Times are formatted in UTC. Years must be between 0 and 9999 to be formatted correctly.
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const DAYS_OF_WEEK: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const LONG_DAYS_OF_WEEK: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Error returned when parsing an invalid timestamp.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The text does not follow the format.
+    InvalidFormat,
+    /// A field is out of its range, like a 13th month or a February 30.
+    InvalidDate,
+    /// The time cannot be represented by `SystemTime`.
+    OutOfRange,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message =
+            match *self {
+                ParseError::InvalidFormat => "invalid timestamp format",
+                ParseError::InvalidDate => "invalid date or time",
+                ParseError::OutOfRange => "timestamp out of range",
+            };
+        write!(formatter, "{}", message)
+    }
+}
+
+impl error::Error for ParseError {
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) date.
+// Algorithm from Howard Hinnant, "chrono-Compatible Low-Level Date Algorithms".
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a date to a number of days since 1970-01-01.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the seconds since the Unix epoch, negative before it, and the nanoseconds.
+fn to_unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(error) => {
+            let duration = error.duration();
+            let seconds = -(duration.as_secs() as i64);
+            match duration.subsec_nanos() {
+                0 => (seconds, 0),
+                nanos => (seconds - 1, 1_000_000_000 - nanos),
+            }
+        },
+    }
+}
+
+fn from_unix(seconds: i64, nanos: u32) -> Result<SystemTime, ParseError> {
+    let time =
+        if seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(seconds as u64, nanos))
+        }
+        else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+                .and_then(|time| time.checked_add(Duration::new(0, nanos)))
+        };
+    time.ok_or(ParseError::OutOfRange)
+}
+
+/// A broken-down UTC time.
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    day_of_week: usize,
+}
+
+impl DateTime {
+    fn from_unix(seconds: i64) -> Self {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let seconds_of_day = seconds.rem_euclid(SECONDS_PER_DAY) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: seconds_of_day / 60 % 60,
+            second: seconds_of_day % 60,
+            // 1970-01-01 was a Thursday.
+            day_of_week: (days + 4).rem_euclid(7) as usize,
+        }
+    }
+
+    /// Returns the seconds since the Unix epoch, after validating the fields. A leap second (60) is
+    /// accepted and counted as the first second of the next minute.
+    fn to_unix(&self) -> Result<i64, ParseError> {
+        if self.month < 1 || self.month > 12 || self.day < 1 || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23 || self.minute > 59 || self.second > 60
+        {
+            return Err(ParseError::InvalidDate);
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        Ok(days * SECONDS_PER_DAY + i64::from(self.hour * 3600 + self.minute * 60 + self.second))
+    }
+}
+
+/// Formats the time as an RFC 3339 timestamp in UTC, without fractional seconds:
+/// `2024-03-01T12:30:00Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (seconds, _) = to_unix(time);
+    let date = DateTime::from_unix(seconds);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", date.year, date.month, date.day, date.hour, date.minute, date.second)
+}
+
+/// Formats the time as an RFC 3339 timestamp in UTC, with milliseconds: `2024-03-01T12:30:00.250Z`.
+pub fn format_rfc3339_millis(time: SystemTime) -> String {
+    let (seconds, nanos) = to_unix(time);
+    let date = DateTime::from_unix(seconds);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", date.year, date.month, date.day, date.hour, date.minute,
+        date.second, nanos / 1_000_000)
+}
+
+/// Parses an RFC 3339 timestamp, like `2024-03-01T12:30:00Z` or `2024-03-01 13:30:00.5+01:00`.
+pub fn parse_rfc3339(text: &str) -> Result<SystemTime, ParseError> {
+    let bytes = text.trim().as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+    {
+        return Err(ParseError::InvalidFormat);
+    }
+    let date = DateTime {
+        year: i64::from(digits(&bytes[0..4])?),
+        month: digits(&bytes[5..7])?,
+        day: digits(&bytes[8..10])?,
+        hour: digits(&bytes[11..13])?,
+        minute: digits(&bytes[14..16])?,
+        second: digits(&bytes[17..19])?,
+        day_of_week: 0,
+    };
+
+    let mut rest = &bytes[19..];
+    let mut nanos = 0;
+    if rest.first() == Some(&b'.') {
+        let count = rest[1..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+        if count == 0 {
+            return Err(ParseError::InvalidFormat);
+        }
+        // Only the nanoseconds are kept.
+        let fraction = &rest[1..1 + count.min(9)];
+        nanos = digits(fraction)? * 10u32.pow(9 - fraction.len() as u32);
+        rest = &rest[1 + count..];
+    }
+
+    let offset =
+        match rest {
+            b"Z" | b"z" => 0,
+            [sign @ (b'+' | b'-'), hours @ .., b':', minute_tens, minute_units] if hours.len() == 2 => {
+                let hours = digits(hours)?;
+                let minutes = digits(&[*minute_tens, *minute_units])?;
+                if hours > 23 || minutes > 59 {
+                    return Err(ParseError::InvalidDate);
+                }
+                let offset = i64::from(hours * 3600 + minutes * 60);
+                if *sign == b'+' { offset } else { -offset }
+            },
+            _ => return Err(ParseError::InvalidFormat),
+        };
+    from_unix(date.to_unix()? - offset, nanos)
+}
+
+/// Formats the time as an HTTP date (the IMF-fixdate format of RFC 7231), for headers like `Date`,
+/// `Last-Modified` or `Expires`: `Fri, 01 Mar 2024 12:30:00 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let (seconds, _) = to_unix(time);
+    let date = DateTime::from_unix(seconds);
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT", DAYS_OF_WEEK[date.day_of_week], date.day,
+        MONTHS[date.month as usize - 1], date.year, date.hour, date.minute, date.second)
+}
+
+/// Parses an HTTP date in any of the formats that RFC 7231 requires recipients to accept:
+/// IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`), the obsolete RFC 850 format
+/// (`Sunday, 06-Nov-94 08:49:37 GMT`) and the asctime format (`Sun Nov  6 08:49:37 1994`).
+/// The day of the week is checked to be a valid name but not to match the date.
+pub fn parse_http_date(text: &str) -> Result<SystemTime, ParseError> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let date =
+        match words.as_slice() {
+            [day_of_week, day, month, year, time, "GMT"] if DAYS_OF_WEEK.iter().any(|name| day_of_week.strip_suffix(',') == Some(name)) => {
+                if day.len() != 2 || year.len() != 4 {
+                    return Err(ParseError::InvalidFormat);
+                }
+                date_time(i64::from(digits(year.as_bytes())?), month, digits(day.as_bytes())?, time)?
+            },
+            [day_of_week, date, time, "GMT"] if LONG_DAYS_OF_WEEK.iter().any(|name| day_of_week.strip_suffix(',') == Some(name)) => {
+                let parts: Vec<&str> = date.split('-').collect();
+                if parts.len() != 3 || parts[0].len() != 2 || parts[2].len() != 2 {
+                    return Err(ParseError::InvalidFormat);
+                }
+                let year = full_year(i64::from(digits(parts[2].as_bytes())?));
+                date_time(year, parts[1], digits(parts[0].as_bytes())?, time)?
+            },
+            [day_of_week, month, day, time, year] if DAYS_OF_WEEK.contains(day_of_week) => {
+                if day.is_empty() || day.len() > 2 || year.len() != 4 {
+                    return Err(ParseError::InvalidFormat);
+                }
+                date_time(i64::from(digits(year.as_bytes())?), month, digits(day.as_bytes())?, time)?
+            },
+            _ => return Err(ParseError::InvalidFormat),
+        };
+    from_unix(date.to_unix()?, 0)
+}
+
+/// Interprets a two-digit year as the closest one not more than 50 years in the future.
+fn full_year(year: i64) -> i64 {
+    let (seconds, _) = to_unix(SystemTime::now());
+    let current = DateTime::from_unix(seconds).year;
+    let year = current - current.rem_euclid(100) + year;
+    if year > current + 50 { year - 100 } else { year }
+}
+
+fn date_time(year: i64, month: &str, day: u32, time: &str) -> Result<DateTime, ParseError> {
+    let month = MONTHS.iter().position(|&name| name == month).ok_or(ParseError::InvalidFormat)? as u32 + 1;
+    let time = time.as_bytes();
+    if time.len() != 8 || time[2] != b':' || time[5] != b':' {
+        return Err(ParseError::InvalidFormat);
+    }
+    Ok(DateTime {
+        year,
+        month,
+        day,
+        hour: digits(&time[0..2])?,
+        minute: digits(&time[3..5])?,
+        second: digits(&time[6..8])?,
+        day_of_week: 0,
+    })
+}
+
+fn digits(bytes: &[u8]) -> Result<u32, ParseError> {
+    if bytes.is_empty() || bytes.len() > 9 {
+        return Err(ParseError::InvalidFormat);
+    }
+    bytes.iter().try_fold(0, |value, &byte| {
+        if byte.is_ascii_digit() {
+            Ok(value * 10 + u32::from(byte - b'0'))
+        }
+        else {
+            Err(ParseError::InvalidFormat)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        ParseError,
+        format_http_date,
+        format_rfc3339,
+        format_rfc3339_millis,
+        parse_http_date,
+        parse_rfc3339,
+    };
+
+    #[test]
+    fn rfc3339() {
+        let time = UNIX_EPOCH + Duration::new(1_709_296_200, 250_000_000);
+        assert_eq!(format_rfc3339(time), "2024-03-01T12:30:00Z");
+        assert_eq!(format_rfc3339_millis(time), "2024-03-01T12:30:00.250Z");
+        assert_eq!(format_rfc3339(UNIX_EPOCH - Duration::from_millis(500)), "1969-12-31T23:59:59Z");
+
+        assert_eq!(parse_rfc3339("2024-03-01T12:30:00.25Z"), Ok(time));
+        assert_eq!(parse_rfc3339("2024-03-01 14:30:00.250000000001+02:00"), Ok(time));
+        assert_eq!(parse_rfc3339("2024-03-01t07:00:00-05:30"), Ok(UNIX_EPOCH + Duration::from_secs(1_709_296_200)));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59.5Z"), Ok(UNIX_EPOCH - Duration::from_millis(500)));
+        assert_eq!(parse_rfc3339("2016-12-31T23:59:60Z"), parse_rfc3339("2017-01-01T00:00:00Z"));
+        assert_eq!(parse_rfc3339("2023-02-29T00:00:00Z"), Err(ParseError::InvalidDate));
+        assert_eq!(parse_rfc3339("2024-03-01T24:00:00Z"), Err(ParseError::InvalidDate));
+        assert_eq!(parse_rfc3339("2024-03-01T12:30:00"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse_rfc3339("2024-03-01T12:30:00.Z"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse_rfc3339("2024-3-01T12:30:00Z"), Err(ParseError::InvalidFormat));
+    }
+
+    #[test]
+    fn http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format_http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Ok(time));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Ok(time));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Ok(time));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse_http_date("Sun, 6 Nov 1994 08:49:37 GMT"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse_http_date("Xyz, 06 Nov 1994 08:49:37 GMT"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse_http_date("Sun, 31 Nov 1994 08:49:37 GMT"), Err(ParseError::InvalidDate));
+    }
+}
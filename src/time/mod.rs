@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2018 Adgear
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Monotonic clock helpers: instants, deadlines, timeouts and a timer wheel, and the formatting of
+//! wall-clock times.
+
+pub mod format;
+mod wheel;
+
+use std::ops::{Add, Sub};
+use std::time::{self, Duration};
+
+pub use self::wheel::{TimerId, TimerWheel};
+
+/// A point in time read from the monotonic clock.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Instant(time::Instant);
+
+impl Instant {
+    /// Returns the current instant.
+    pub fn now() -> Self {
+        Instant(time::Instant::now())
+    }
+
+    /// Returns the time elapsed since this instant, or zero if it is in the future.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    /// Returns the time elapsed from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_duration_since(earlier.0)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns the wrapped standard library instant.
+    pub fn as_std(&self) -> time::Instant {
+        self.0
+    }
+}
+
+impl From<time::Instant> for Instant {
+    fn from(instant: time::Instant) -> Self {
+        Instant(instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration)
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0 - duration)
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+/// A point in time after which an operation should give up.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Deadline {
+    instant: Instant,
+}
+
+impl Deadline {
+    /// Creates a deadline expiring `duration` from now.
+    pub fn after(duration: Duration) -> Self {
+        Self::at(Instant::now() + duration)
+    }
+
+    /// Creates a deadline expiring at `instant`.
+    pub fn at(instant: Instant) -> Self {
+        Self {
+            instant,
+        }
+    }
+
+    /// Returns the instant at which this deadline expires.
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// Returns true if the deadline is reached.
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.instant
+    }
+
+    /// Returns the time left before the deadline, or zero if it is reached.
+    pub fn remaining(&self) -> Duration {
+        self.instant.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns the time left before the deadline as a timeout.
+    pub fn timeout(&self) -> Timeout {
+        Timeout::After(self.remaining())
+    }
+}
+
+/// How long a blocking operation may wait.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Timeout {
+    /// Wait for as long as needed.
+    Never,
+    /// Wait for at most the specified duration.
+    After(Duration),
+}
+
+impl Timeout {
+    /// Returns the deadline corresponding to this timeout, starting now.
+    pub fn deadline(&self) -> Option<Deadline> {
+        match *self {
+            Timeout::Never => None,
+            Timeout::After(duration) => Some(Deadline::after(duration)),
+        }
+    }
+
+    /// Converts the timeout to milliseconds as expected by `poll()` and `epoll_wait()`: -1 means
+    /// no timeout, and durations are rounded up so that a non-zero timeout never becomes a busy loop.
+    pub fn as_millis(&self) -> i32 {
+        match *self {
+            Timeout::Never => -1,
+            Timeout::After(duration) => {
+                let mut millis = duration.as_millis();
+                if duration.subsec_nanos() % 1_000_000 != 0 {
+                    millis += 1;
+                }
+                if millis > i32::MAX as u128 {
+                    i32::MAX
+                }
+                else {
+                    millis as i32
+                }
+            },
+        }
+    }
+}
+
+impl From<Option<Duration>> for Timeout {
+    fn from(duration: Option<Duration>) -> Self {
+        match duration {
+            Some(duration) => Timeout::After(duration),
+            None => Timeout::Never,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Deadline, Instant, Timeout};
+
+    #[test]
+    fn deadline() {
+        let deadline = Deadline::after(Duration::from_secs(60));
+        assert!(!deadline.expired());
+        assert!(deadline.remaining() > Duration::from_secs(59));
+
+        let deadline = Deadline::at(Instant::now() - Duration::from_millis(1));
+        assert!(deadline.expired());
+        assert_eq!(deadline.remaining(), Duration::from_secs(0));
+        assert_eq!(deadline.timeout(), Timeout::After(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn timeout_millis() {
+        assert_eq!(Timeout::Never.as_millis(), -1);
+        assert_eq!(Timeout::After(Duration::from_millis(15)).as_millis(), 15);
+        assert_eq!(Timeout::After(Duration::from_micros(1)).as_millis(), 1);
+        assert_eq!(Timeout::After(Duration::from_secs(u64::MAX)).as_millis(), i32::MAX);
+        assert_eq!(Timeout::from(None), Timeout::Never);
+    }
+}
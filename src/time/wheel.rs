@@ -0,0 +1,250 @@
+//! Hierarchical timer wheel.
+//!
+//! Timers are bucketed by expiration tick in `LEVELS` wheels of `SLOTS` slots each: the first wheel
+//! has a granularity of one tick, the next one of `SLOTS` ticks, and so on. When the first wheel
+//! wraps around, the timers of the next slot of the upper wheel are cascaded down. Insertion and
+//! cancellation are O(1) and advancing is O(ticks + expired timers).
+//!
+//! The wheel does not read the clock by itself: the owner calls `advance_to()`, either from an event
+//! loop after `epoll_wait()` returns or from a plain thread, and gets back the expired values.
+
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+use super::Instant;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+const LEVELS: usize = 4;
+/// Timers further than this number of ticks are parked in the last wheel and reinserted when cascaded.
+const MAX_DELTA: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+/// Identifier of a timer inserted in a `TimerWheel`, used to cancel it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TimerId(u64);
+
+struct Timer<T> {
+    expiration: u64,
+    value: T,
+}
+
+/// A hierarchical timer wheel storing values of type `T` until their deadline.
+pub struct TimerWheel<T> {
+    current_tick: u64,
+    expired: Vec<TimerId>,
+    next_id: u64,
+    resolution: Duration,
+    slots: Vec<Vec<TimerId>>,
+    start: Instant,
+    timers: HashMap<TimerId, Timer<T>>,
+}
+
+impl<T> TimerWheel<T> {
+    /// Creates a new timer wheel with the specified tick duration, starting now.
+    pub fn new(resolution: Duration) -> Self {
+        Self::with_start(resolution, Instant::now())
+    }
+
+    /// Creates a new timer wheel with the specified tick duration, starting at `start`.
+    pub fn with_start(resolution: Duration, start: Instant) -> Self {
+        assert!(resolution > Duration::from_secs(0), "the resolution should not be zero");
+        Self {
+            current_tick: 0,
+            expired: vec![],
+            next_id: 0,
+            resolution,
+            slots: (0..LEVELS * SLOTS).map(|_| vec![]).collect(),
+            start,
+            timers: HashMap::new(),
+        }
+    }
+
+    /// Inserts a timer expiring at `deadline`. A deadline in the past expires on the next advance.
+    pub fn insert(&mut self, deadline: Instant, value: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        // Round up so that a timer never fires early.
+        let expiration = self.tick_ceil(deadline);
+        self.timers.insert(id, Timer {
+            expiration,
+            value,
+        });
+        self.schedule(id, expiration);
+        id
+    }
+
+    /// Inserts a timer expiring after `delay` from now.
+    pub fn insert_after(&mut self, delay: Duration, value: T) -> TimerId {
+        self.insert(Instant::now() + delay, value)
+    }
+
+    /// Cancels a timer, returning its value if it did not expire yet.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        // The id stays in its slot and is skipped when the slot is processed.
+        self.timers.remove(&id).map(|timer| timer.value)
+    }
+
+    /// Returns true if no timers are pending.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns the earliest instant at which a pending timer expires.
+    pub fn next_expiration(&self) -> Option<Instant> {
+        self.timers.values()
+            .map(|timer| timer.expiration)
+            .min()
+            .map(|tick| self.start + Duration::from_nanos((self.resolution.as_nanos() * u128::from(tick)) as u64))
+    }
+
+    /// Advances the wheel to `now`, returning the values of the expired timers in expiration order.
+    pub fn advance_to(&mut self, now: Instant) -> Vec<T> {
+        let target = self.tick_floor(now);
+        let mut result = vec![];
+        let expired = mem::take(&mut self.expired);
+        self.collect(expired, &mut result);
+        while self.current_tick < target {
+            if self.timers.is_empty() {
+                self.current_tick = target;
+                break;
+            }
+            self.current_tick += 1;
+            self.cascade();
+            let index = (self.current_tick & SLOT_MASK) as usize;
+            let ids = mem::take(&mut self.slots[index]);
+            self.collect(ids, &mut result);
+            // Cascaded timers expiring exactly on this tick.
+            let expired = mem::take(&mut self.expired);
+            self.collect(expired, &mut result);
+        }
+        result
+    }
+
+    /// Advances the wheel to the current instant, returning the values of the expired timers.
+    pub fn poll(&mut self) -> Vec<T> {
+        self.advance_to(Instant::now())
+    }
+
+    fn collect(&mut self, ids: Vec<TimerId>, result: &mut Vec<T>) {
+        for id in ids {
+            if let Some(timer) = self.timers.remove(&id) {
+                result.push(timer.value);
+            }
+        }
+    }
+
+    /// Moves the timers of the upper wheels down when the lower wheels wrap around.
+    fn cascade(&mut self) {
+        for level in 1..LEVELS {
+            let shift = SLOT_BITS * level as u32;
+            if self.current_tick & ((1 << shift) - 1) != 0 {
+                break;
+            }
+            let index = level * SLOTS + ((self.current_tick >> shift) & SLOT_MASK) as usize;
+            let ids = mem::take(&mut self.slots[index]);
+            for id in ids {
+                let expiration =
+                    match self.timers.get(&id) {
+                        Some(timer) => timer.expiration,
+                        None => continue,
+                    };
+                self.schedule(id, expiration);
+            }
+        }
+    }
+
+    fn schedule(&mut self, id: TimerId, expiration: u64) {
+        if expiration <= self.current_tick {
+            self.expired.push(id);
+            return;
+        }
+        let delta = (expiration - self.current_tick).min(MAX_DELTA);
+        let expiration = self.current_tick + delta;
+        let mut level = 0;
+        while level < LEVELS - 1 && delta >= 1 << (SLOT_BITS * (level as u32 + 1)) {
+            level += 1;
+        }
+        let index = ((expiration >> (SLOT_BITS * level as u32)) & SLOT_MASK) as usize;
+        self.slots[level * SLOTS + index].push(id);
+    }
+
+    fn tick_ceil(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.start);
+        let resolution = self.resolution.as_nanos();
+        elapsed.as_nanos().div_ceil(resolution) as u64
+    }
+
+    fn tick_floor(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.start);
+        (elapsed.as_nanos() / self.resolution.as_nanos()) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::Instant;
+    use super::TimerWheel;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn expiration_order() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_start(ms(1), start);
+        wheel.insert(start + ms(30), 3);
+        wheel.insert(start + ms(10), 1);
+        wheel.insert(start + ms(20), 2);
+        assert_eq!(wheel.len(), 3);
+        assert_eq!(wheel.next_expiration(), Some(start + ms(10)));
+        assert!(wheel.advance_to(start + ms(9)).is_empty());
+        assert_eq!(wheel.advance_to(start + ms(10)), vec![1]);
+        assert_eq!(wheel.advance_to(start + ms(100)), vec![2, 3]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn cancel() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_start(ms(1), start);
+        let id = wheel.insert(start + ms(5), "cancelled");
+        wheel.insert(start + ms(5), "kept");
+        assert_eq!(wheel.cancel(id), Some("cancelled"));
+        assert_eq!(wheel.cancel(id), None);
+        assert_eq!(wheel.advance_to(start + ms(5)), vec!["kept"]);
+    }
+
+    #[test]
+    fn cascade() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_start(ms(1), start);
+        let deadlines = [63, 64, 65, 4095, 4096, 300_000, 20_000_000];
+        for &deadline in &deadlines {
+            wheel.insert(start + ms(deadline), deadline);
+        }
+        for &deadline in &deadlines {
+            assert!(wheel.advance_to(start + ms(deadline - 1)).is_empty());
+            assert_eq!(wheel.advance_to(start + ms(deadline)), vec![deadline]);
+        }
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn past_deadline() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::with_start(ms(1), start);
+        wheel.advance_to(start + ms(50));
+        wheel.insert(start + ms(10), ());
+        assert_eq!(wheel.advance_to(start + ms(50)).len(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+extern crate mini;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+use mini::aio::async::{Action, EventLoop, Mode};
+use mini::aio::handler::{Handler, Loop, Stream};
+
+/// Counts the allocations of the current thread, so that tests running in parallel don't interfere.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const WARMUP_EVENTS: usize = 10;
+const EVENTS: usize = 10_000;
+/// Events allowed per allocation once warmed up, leaving room for an occasional buffer growth.
+const EVENTS_PER_ALLOCATION: usize = 1000;
+
+/// Returns the number of allocations done by `function`.
+fn allocations<F: FnMut()>(mut function: F) -> usize {
+    let before = ALLOCATIONS.with(|allocations| allocations.get());
+    function();
+    ALLOCATIONS.with(|allocations| allocations.get()) - before
+}
+
+#[test]
+fn event_loop_callback() {
+    let event_loop = EventLoop::new().expect("event loop");
+    let (mut reader, mut writer) = UnixStream::pair().expect("socket pair");
+    reader.set_nonblocking(true).expect("nonblocking");
+    let count = Rc::new(Cell::new(0));
+    let counter = count.clone();
+    event_loop.add_fd(&reader.try_clone().expect("clone"), Mode::Read, move |_event| {
+        let mut buffer = [0; 16];
+        while let Ok(size) = reader.read(&mut buffer) {
+            if size == 0 {
+                break;
+            }
+            counter.set(counter.get() + size);
+        }
+        Action::Continue
+    }).expect("add");
+
+    let mut send_events = |events| {
+        for _ in 0..events {
+            writer.write_all(&[1]).expect("write");
+            let expected = count.get() + 1;
+            while count.get() < expected {
+                event_loop.iterate();
+            }
+        }
+    };
+    send_events(WARMUP_EVENTS);
+    let allocations = allocations(|| send_events(EVENTS));
+    assert!(allocations * EVENTS_PER_ALLOCATION <= EVENTS, "{} allocations for {} events", allocations, EVENTS);
+}
+
+#[test]
+fn registration() {
+    let event_loop = EventLoop::new().expect("event loop");
+    let (reader, _writer) = UnixStream::pair().expect("socket pair");
+    let count = Rc::new(Cell::new(0));
+    let register = |registrations| {
+        for _ in 0..registrations {
+            // Captures two words, stored inline.
+            let counter = count.clone();
+            let size = 1;
+            event_loop.add_fd(&reader, Mode::Read, move |_event| {
+                counter.set(counter.get() + size);
+                Action::Continue
+            }).expect("add");
+            event_loop.remove_fd(&reader).expect("remove");
+        }
+    };
+    register(WARMUP_EVENTS);
+    let allocations = allocations(|| register(EVENTS));
+    assert!(allocations * EVENTS_PER_ALLOCATION <= EVENTS, "{} allocations for {} registrations", allocations, EVENTS);
+}
+
+#[test]
+fn deferred_callback() {
+    let event_loop = EventLoop::new().expect("event loop");
+    let count = Rc::new(Cell::new(0));
+    let defer = |callbacks| {
+        for _ in 0..callbacks {
+            let counter = count.clone();
+            event_loop.defer(move || counter.set(counter.get() + 1));
+            event_loop.iterate_timeout(Some(Duration::from_millis(0)));
+        }
+    };
+    defer(WARMUP_EVENTS);
+    let allocations = allocations(|| defer(EVENTS));
+    assert_eq!(count.get(), WARMUP_EVENTS + EVENTS);
+    assert!(allocations * EVENTS_PER_ALLOCATION <= EVENTS, "{} allocations for {} deferred callbacks", allocations,
+        EVENTS);
+}
+
+struct Counter {
+    count: Rc<Cell<usize>>,
+}
+
+impl Handler for Counter {
+    type Msg = usize;
+
+    fn update(&mut self, _stream: &Stream<usize>, size: usize) {
+        self.count.set(self.count.get() + size);
+    }
+}
+
+#[test]
+fn handler_message() {
+    let mut event_loop = Loop::new().expect("loop");
+    let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+    reader.set_nonblocking(true).expect("nonblocking");
+    let count = Rc::new(Cell::new(0));
+    let stream = event_loop.spawn(Counter {
+        count: count.clone(),
+    });
+    let fd = reader.try_clone().expect("clone");
+    event_loop.add_fd(&fd, Mode::Read, &stream, move |_event| {
+        let mut buffer = [0; 16];
+        (&reader).read(&mut buffer).unwrap_or(0)
+    }).expect("add");
+
+    let mut send_events = |events| {
+        for _ in 0..events {
+            writer.write_all(&[1]).expect("write");
+            let expected = count.get() + 1;
+            while count.get() < expected {
+                event_loop.iterate();
+            }
+        }
+    };
+    send_events(WARMUP_EVENTS);
+    let allocations = allocations(|| send_events(EVENTS));
+    assert!(allocations * EVENTS_PER_ALLOCATION <= EVENTS, "{} allocations for {} events", allocations, EVENTS);
+}
@@ -643,7 +643,7 @@ fn test_usage() {
 "Usage: fruits
 
 Options:
-    -b, --banana VAL    Desc
+    -b, --banana VAL    Desc (required)
     -a, --012345678901234567890123456789 VAL
                         Desc
     -k, --kiwi          Desc
@@ -807,3 +807,1064 @@ fn test_undefined_opt_present() {
         Err(e) => panic!("{}", e)
     }
 }
+
+#[test]
+fn test_undefined_try_opt() {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Description");
+    opts.optopt("o", "output", "Output", "FILE");
+    let matches = opts.parse(vec!["-h", "-o", "out"]).ok().unwrap();
+    let undefined = || UnrecognizedOption("undefined".to_string());
+    assert_eq!(matches.try_opt_present("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_count("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_strs("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_str("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_positions("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_prefix("undefined"), Err(undefined()));
+    assert_eq!(matches.opt_str_strict("undefined"), Err(undefined()));
+    assert_eq!(matches.opt_get::<u32>("undefined"), Err(undefined()));
+    assert_eq!(matches.try_opt_present("h"), Ok(true));
+    assert_eq!(matches.try_opt_str("output"), Ok(Some("out".to_string())));
+    assert_eq!(matches.try_opt_positions("o"), Ok(vec![1]));
+}
+
+#[test]
+fn test_opt_get() {
+    let mut opts = Options::new();
+    opts.optopt("p", "port", "Port", "PORT");
+    opts.optopt("r", "ratio", "Ratio", "RATIO");
+    opts.optopt("t", "timeout", "Timeout", "SECS");
+
+    let args = vec!["-p", "8080", "--ratio=0.5"];
+    let matches = opts.parse(args).unwrap();
+    assert_eq!(matches.opt_get::<u16>("port"), Ok(Some(8080)));
+    assert_eq!(matches.opt_get::<f64>("r"), Ok(Some(0.5)));
+    assert_eq!(matches.opt_get::<u32>("timeout"), Ok(None));
+    assert_eq!(matches.opt_get_default("timeout", 30u32), Ok(30));
+    assert_eq!(matches.opt_get_default("port", 80u16), Ok(8080));
+
+    let args = vec!["--port", "http"];
+    let matches = opts.parse(args).unwrap();
+    match matches.opt_get::<u16>("p") {
+        Err(InvalidArgument(ref nm, ref value)) => {
+            assert_eq!(nm, "p");
+            assert_eq!(value, "http");
+        },
+        result => panic!("unexpected {:?}", result),
+    }
+    let error = matches.opt_get_default("port", 80u16).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid argument 'http' to option 'port'");
+}
+
+#[test]
+fn test_optopt_default() {
+    let mut opts = Options::new();
+    opts.optopt_default("o", "out", "Output file", "FILE", "a.out");
+    opts.optopt_default("j", "jobs", "Parallel jobs", "N", "4");
+
+    let matches = opts.parse(Vec::<String>::new()).unwrap();
+    assert!(!matches.opt_present("o"));
+    assert_eq!(matches.opt_str("out").unwrap(), "a.out");
+    assert_eq!(matches.opt_get::<u32>("j"), Ok(Some(4)));
+
+    let matches = opts.parse(vec!["-o", "main", "--jobs=8"]).unwrap();
+    assert_eq!(matches.opt_str("o").unwrap(), "main");
+    assert_eq!(matches.opt_get_default("jobs", 1u32), Ok(8));
+
+    let expected =
+"Usage: fruits
+
+Options:
+    -o, --out FILE      Output file [default: a.out]
+    -j, --jobs N        Parallel jobs [default: 4]
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+fn test_requires() {
+    let mut opts = Options::new();
+    opts.optopt("k", "key-file", "Private key", "FILE");
+    opts.optopt("c", "cert-file", "Certificate", "FILE");
+    opts.optflag("v", "verbose", "Verbose");
+    opts.requires("key-file", "c");
+
+    assert!(opts.parse(vec!["-v"]).is_ok());
+    assert!(opts.parse(vec!["--cert-file", "cert.pem"]).is_ok());
+    assert!(opts.parse(vec!["-k", "key.pem", "-c", "cert.pem"]).is_ok());
+    match opts.parse(vec!["--key-file=key.pem", "-v"]) {
+        Err(e @ OptionRequires(..)) => {
+            assert_eq!(e, OptionRequires("key-file".to_string(), "c".to_string()));
+            assert_eq!(e.to_string(), "Option 'key-file' requires option 'c'");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_requires_undefined() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose");
+    opts.requires("verbose", "quiet");
+    assert_eq!(opts.parse(vec!["-v"]).err().unwrap(), UnrecognizedOption("quiet".to_string()));
+    // Even when the option having the rule is absent.
+    assert_eq!(opts.parse(Vec::<String>::new()).err().unwrap(), UnrecognizedOption("quiet".to_string()));
+}
+
+#[test]
+fn test_choices() {
+    let mut opts = Options::new();
+    opts.optopt("O", "opt-level", "Optimization profile", "PROFILE")
+        .choices(&["fast", "small", "debug"]);
+    opts.optmulti("f", "feature", "Enabled feature", "NAME")
+        .choices(&["tls", "gzip"]);
+
+    let matches = opts.parse(vec!["-O", "small", "-f", "tls", "--feature=gzip"]).unwrap();
+    assert_eq!(matches.opt_str("O").unwrap(), "small");
+    assert_eq!(matches.opt_strs("f"), vec!["tls", "gzip"]);
+
+    match opts.parse(vec!["-f", "tls", "-f", "zstd"]) {
+        Err(e @ InvalidChoice(..)) => {
+            assert_eq!(e, InvalidChoice("feature".to_string(), "zstd".to_string(),
+                                        vec!["tls".to_string(), "gzip".to_string()]));
+            assert_eq!(e.to_string(),
+                       "Invalid argument 'zstd' to option 'feature' (expected one of: tls, gzip)");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+
+    let expected =
+"Usage: fruits
+
+Options:
+    -O, --opt-level PROFILE
+                        Optimization profile (one of: fast, small, debug)
+    -f, --feature NAME  Enabled feature (one of: tls, gzip)
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+#[should_panic]
+fn test_choices_on_flag() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose").choices(&["yes"]);
+}
+
+#[test]
+fn test_abbreviations() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose");
+    opts.optflag("", "version", "Print version");
+    opts.optopt("o", "output", "Output file", "FILE");
+    opts.optflag("", "out", "Write to stdout");
+
+    // Disabled by default.
+    match opts.parse(vec!["--verb"]) {
+        Err(UnrecognizedOption(ref nm)) => assert_eq!(nm, "verb"),
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+
+    opts.abbreviations(true);
+    let matches = opts.parse(vec!["--verb", "--vers", "--outp=a.txt", "--out"]).unwrap();
+    assert!(matches.opt_present("verbose"));
+    assert!(matches.opt_present("version"));
+    assert_eq!(matches.opt_str("output").unwrap(), "a.txt");
+    // An exact match wins over a longer option sharing the prefix.
+    assert!(matches.opt_present("out"));
+
+    match opts.parse(vec!["--ver"]) {
+        Err(e @ AmbiguousOption(..)) => {
+            assert_eq!(e, AmbiguousOption("ver".to_string(),
+                                          vec!["verbose".to_string(), "version".to_string()]));
+            assert_eq!(e.to_string(), "Option 'ver' is ambiguous (could be: verbose, version)");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+    match opts.parse(vec!["--quiet"]) {
+        Err(UnrecognizedOption(ref nm)) => assert_eq!(nm, "quiet"),
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_usage_sections() {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Print this help");
+    opts.section("Output options");
+    opts.optopt("o", "output", "Output file", "FILE");
+    opts.optflag("q", "quiet", "No output");
+    opts.section("Network options");
+    opts.optopt("p", "port", "Port to listen on", "PORT");
+
+    let expected =
+"Usage: fruits
+
+Options:
+    -h, --help          Print this help
+
+Output options:
+    -o, --output FILE   Output file
+    -q, --quiet         No output
+
+Network options:
+    -p, --port PORT     Port to listen on
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+
+    let mut opts = Options::new();
+    opts.section("Network options");
+    opts.optopt("p", "port", "Port to listen on", "PORT");
+    let expected =
+"Usage: fruits
+
+Network options:
+    -p, --port PORT     Port to listen on
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+fn test_usage_config() {
+    use mini::getopts::UsageConfig;
+
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Write the generated report to this file", "FILE");
+    opts.optflag("", "quiet", "No output");
+    opts.usage_config(UsageConfig { indent: 2, desc_column: 20, wrap_width: 20 });
+
+    let expected =
+"Usage: fruits
+
+Options:
+  -o, --output FILE Write the generated
+                    report to this file
+      --quiet       No output
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+
+    assert_eq!(UsageConfig::for_width(80), UsageConfig::default());
+    assert_eq!(UsageConfig::for_width(120).wrap_width, 94);
+    assert_eq!(UsageConfig::for_width(30).wrap_width, 20);
+}
+
+#[test]
+fn test_positionals() {
+    use mini::getopts::Occur;
+
+    let mut opts = Options::new();
+    opts.optflag("r", "recursive", "Copy directories");
+    opts.positional("SRC", "Source file", Occur::Req);
+    opts.positional("DEST", "Destination", Occur::Req);
+    opts.positional("EXTRA", "Extra files", Occur::Multi);
+
+    let matches = opts.parse(vec!["a", "-r", "b"]).unwrap();
+    assert_eq!(matches.positional("SRC").unwrap(), "a");
+    assert_eq!(matches.positional("DEST").unwrap(), "b");
+    assert_eq!(matches.positional("EXTRA"), None);
+    assert_eq!(matches.free, vec!["a", "b"]);
+
+    let matches = opts.parse(vec!["a", "b", "c", "d"]).unwrap();
+    assert_eq!(matches.positionals("EXTRA"), vec!["c", "d"]);
+
+    match opts.parse(vec!["a"]) {
+        Err(e @ PositionalMissing(_)) => {
+            assert_eq!(e, PositionalMissing("DEST".to_string()));
+            assert_eq!(e.to_string(), "Required argument 'DEST' missing");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+
+    assert_eq!(opts.short_usage("cp"), "Usage: cp [-r] SRC DEST [EXTRA]..");
+    let expected =
+"Usage: cp
+
+Arguments:
+    SRC                 Source file
+    DEST                Destination
+    EXTRA               Extra files
+
+Options:
+    -r, --recursive     Copy directories
+";
+    assert_eq!(opts.usage("Usage: cp"), expected);
+}
+
+#[test]
+fn test_positionals_optional() {
+    use mini::getopts::Occur;
+
+    let mut opts = Options::new();
+    opts.positional("INPUT", "Input file", Occur::Optional);
+
+    let matches = opts.parse(Vec::<String>::new()).unwrap();
+    assert_eq!(matches.positional("INPUT"), None);
+    assert_eq!(opts.parse(vec!["in"]).unwrap().positional("INPUT").unwrap(), "in");
+    match opts.parse(vec!["in", "out"]) {
+        Err(UnexpectedPositional(ref value)) => assert_eq!(value, "out"),
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_optcount() {
+    let mut opts = Options::new();
+    opts.optcount("v", "verbose", "More output", Some(3));
+    opts.optcount("d", "debug", "Debug level", None);
+    opts.optflag("x", "", "Trace");
+
+    let matches = opts.parse(Vec::<String>::new()).unwrap();
+    assert_eq!(matches.opt_level("v"), 0);
+
+    let matches = opts.parse(vec!["-vv", "--verbose", "-xddvd"]).unwrap();
+    assert_eq!(matches.opt_count("v"), 4);
+    assert_eq!(matches.opt_level("verbose"), 3);
+    assert_eq!(matches.opt_level("d"), 3);
+    assert_eq!(matches.opt_level("x"), 1);
+}
+
+#[test]
+fn test_short_equals() {
+    let mut opts = Options::new();
+    opts.optopt("o", "", "Output", "FILE");
+    opts.optflagopt("c", "", "Color", "WHEN");
+    opts.optflag("v", "", "Verbose");
+
+    let matches = opts.parse(vec!["-o=file.txt", "-vc=never"]).unwrap();
+    assert_eq!(matches.opt_str("o").unwrap(), "file.txt");
+    assert_eq!(matches.opt_str("c").unwrap(), "never");
+    assert_eq!(opts.parse(vec!["-ofile.txt"]).unwrap().opt_str("o").unwrap(), "file.txt");
+    assert_eq!(opts.parse(vec!["-o==x"]).unwrap().opt_str("o").unwrap(), "=x");
+    assert_eq!(opts.parse(vec!["-o", "=x"]).unwrap().opt_str("o").unwrap(), "=x");
+}
+
+#[test]
+fn test_usage_style() {
+    use mini::getopts::{Occur, UsageStyle};
+
+    let mut opts = Options::new();
+    opts.optopt_default("o", "out", "Output file", "FILE", "a.out");
+    opts.positional("SRC", "Source", Occur::Req);
+    let plain = opts.usage("Usage: cc");
+
+    opts.usage_style(UsageStyle::plain());
+    assert_eq!(opts.usage("Usage: cc"), plain);
+
+    opts.usage_style(UsageStyle::colored());
+    let expected =
+"Usage: cc
+
+\x1b[1;33mArguments:\x1b[0m
+    \x1b[1mSRC\x1b[0m                 Source
+
+\x1b[1;33mOptions:\x1b[0m
+    \x1b[1m-o\x1b[0m, \x1b[1m--out\x1b[0m FILE      Output file \x1b[2m[default: a.out]\x1b[0m
+";
+    assert_eq!(opts.usage("Usage: cc"), expected);
+}
+
+#[test]
+fn test_deprecated() {
+    let mut opts = Options::new();
+    opts.optflag("", "old-flag", "Old behavior").deprecated("--new-flag");
+    opts.optflag("", "new-flag", "New behavior");
+    opts.optopt("x", "", "Legacy", "VAL").deprecated("");
+
+    let matches = opts.parse(vec!["--new-flag"]).unwrap();
+    assert!(matches.warnings().is_empty());
+
+    let matches = opts.parse(vec!["--old-flag", "-x", "1"]).unwrap();
+    assert!(matches.opt_present("old-flag"));
+    assert_eq!(matches.warnings(), ["--old-flag is deprecated, use --new-flag", "-x is deprecated"]);
+
+    let expected =
+"Usage: fruits
+
+Options:
+        --old-flag      Old behavior (deprecated, use --new-flag)
+        --new-flag      New behavior
+    -x VAL              Legacy (deprecated)
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+fn test_positions() {
+    let mut opts = Options::new();
+    opts.optmulti("I", "include", "Include path", "DIR");
+    opts.optflag("v", "", "Verbose");
+
+    let args = vec!["a.c", "-I", "inc", "b.c", "-vIlib", "--include=sys", "c.c", "--", "-d.c"];
+    let matches = opts.parse(args).unwrap();
+    assert_eq!(matches.opt_positions("I"), vec![1, 4, 5]);
+    assert_eq!(matches.opt_positions("v"), vec![4]);
+    assert_eq!(matches.free, vec!["a.c", "b.c", "c.c", "-d.c"]);
+    assert_eq!(matches.free_positions(), [0, 3, 6, 8]);
+}
+
+#[test]
+fn test_validate() {
+    let mut opts = Options::new();
+    opts.optopt("p", "port", "Port", "PORT")
+        .validate(|value| value.parse::<u16>());
+    opts.optmulti("t", "tag", "Tag", "TAG")
+        .validate(|value| if value.is_empty() { Err("empty tag") } else { Ok(()) });
+
+    let matches = opts.parse(vec!["-p", "8080", "-t", "a", "-t", "b"]).unwrap();
+    assert_eq!(matches.opt_str("port").unwrap(), "8080");
+
+    match opts.parse(vec!["--port=99999"]) {
+        Err(e @ InvalidValue { .. }) => {
+            assert_eq!(e.to_string(),
+                       "Invalid value '99999' for option 'port': number too large to fit in target type");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+    match opts.parse(vec!["-t", "a", "-t", ""]) {
+        Err(InvalidValue { option, value, message }) => {
+            assert_eq!(option, "tag");
+            assert_eq!(value, "");
+            assert_eq!(message, "empty tag");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_parse_detailed() {
+    let mut opts = Options::new();
+    opts.optopt("c", "color", "Colorize", "WHEN").choices(&["auto", "never"]);
+    opts.optflag("v", "verbose", "Verbose");
+
+    let failure = opts.parse_detailed(vec!["-v", "--colr=auto"]).err().unwrap();
+    assert_eq!(*failure.fail(), UnrecognizedOption("colr".to_string()));
+    assert_eq!(failure.context().index, Some(1));
+    assert_eq!(failure.context().token.as_deref(), Some("--colr=auto"));
+    assert_eq!(failure.context().long, Some(true));
+    assert_eq!(failure.context().suggestion.as_deref(), Some("color"));
+    assert_eq!(failure.to_string(), "Unrecognized option: 'colr' (did you mean '--color'?)");
+
+    let failure = opts.parse_detailed(vec!["a", "-vx"]).err().unwrap();
+    assert_eq!(*failure.fail(), UnrecognizedOption("x".to_string()));
+    assert_eq!(failure.context().index, Some(1));
+    assert_eq!(failure.context().long, Some(false));
+    assert_eq!(failure.context().suggestion, None);
+
+    let failure = opts.parse_detailed(vec!["-c", "auto", "x", "-c", "always"]).err().unwrap();
+    assert_eq!(*failure.fail(), OptionDuplicated("color".to_string()));
+    assert_eq!(failure.context().index, Some(3));
+    assert_eq!(failure.context().token.as_deref(), Some("-c"));
+
+    let failure = opts.parse_detailed(vec!["-v", "--color", "always"]).err().unwrap();
+    match *failure.fail() {
+        InvalidChoice(..) => {},
+        ref fail => panic!("unexpected {:?}", fail),
+    }
+    assert_eq!(failure.context().index, Some(1));
+
+    match opts.parse(vec!["--colr"]) {
+        Err(UnrecognizedOption(ref nm)) => assert_eq!(nm, "colr"),
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_required_unless_if() {
+    let mut opts = Options::new();
+    opts.optflag("", "stdin", "Read from stdin");
+    opts.optopt("i", "input", "Input file", "FILE");
+    opts.optopt("f", "format", "Output format", "FORMAT");
+    opts.optopt("s", "schema", "Schema", "FILE");
+    opts.required_unless("stdin", "input");
+    opts.required_if("format", "json", "schema");
+
+    assert!(opts.parse(vec!["--stdin"]).is_ok());
+    assert!(opts.parse(vec!["-i", "in.txt", "-f", "csv"]).is_ok());
+    assert!(opts.parse(vec!["-i", "in.txt", "-f", "json", "-s", "s.json"]).is_ok());
+
+    match opts.parse(vec!["-f", "csv"]) {
+        Err(e @ RequiredUnless(..)) => {
+            assert_eq!(e, RequiredUnless("stdin".to_string(), "input".to_string()));
+            assert_eq!(e.to_string(), "Required option 'stdin' missing (unless 'input' is given)");
+        },
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+    let failure = opts.parse_detailed(vec!["--stdin", "--format=json"]).err().unwrap();
+    assert_eq!(*failure.fail(), RequiredIf("schema".to_string(), "format".to_string(), "json".to_string()));
+    assert_eq!(failure.context().index, Some(1));
+    assert_eq!(failure.to_string(), "Option 'schema' is required when option 'format' is 'json'");
+}
+
+#[test]
+fn test_required_unless_if_undefined() {
+    let mut opts = Options::new();
+    opts.optflag("", "stdin", "Read from stdin");
+    opts.required_unless("stdin", "input");
+    assert_eq!(opts.parse(vec!["--stdin"]).err().unwrap(), UnrecognizedOption("input".to_string()));
+
+    let mut opts = Options::new();
+    opts.optopt("f", "format", "Output format", "FORMAT");
+    opts.required_if("format", "json", "schema");
+    assert_eq!(opts.parse(vec!["-f", "csv"]).err().unwrap(), UnrecognizedOption("schema".to_string()));
+}
+
+#[test]
+fn test_matches_iter() {
+    use mini::getopts::Item;
+
+    let mut opts = Options::new();
+    opts.optmulti("l", "", "Library", "LIB");
+    opts.optmulti("L", "library-path", "Library path", "DIR");
+    opts.optflag("s", "static", "Static linking");
+
+    let args = vec!["a.o", "-L", "/usr/lib", "-slm", "b.o", "--library-path=/opt", "--", "-c.o"];
+    let matches = opts.parse(args).unwrap();
+    let items: Vec<Item> = matches.iter().collect();
+    assert_eq!(items, vec![
+        Item::Free("a.o"),
+        Item::Opt("library-path", Some("/usr/lib")),
+        Item::Opt("static", None),
+        Item::Opt("l", Some("m")),
+        Item::Free("b.o"),
+        Item::Opt("library-path", Some("/opt")),
+        Item::Free("-c.o"),
+    ]);
+}
+
+#[test]
+fn test_parse_into() {
+    use mini::getopts::{Fail, FromMatches, Matches};
+
+    #[derive(Debug, PartialEq)]
+    struct Args {
+        jobs: u32,
+        output: Option<String>,
+    }
+
+    impl FromMatches for Args {
+        fn from_matches(matches: &Matches) -> Result<Self, Fail> {
+            Ok(Args {
+                jobs: matches.opt_get_default("jobs", 1)?,
+                output: matches.opt_str("output"),
+            })
+        }
+    }
+
+    let mut opts = Options::new();
+    opts.optopt("j", "jobs", "Jobs", "N");
+    opts.optopt("o", "output", "Output", "FILE");
+
+    let args: Args = opts.parse_into(vec!["-j4"]).unwrap();
+    assert_eq!(args, Args { jobs: 4, output: None });
+    assert_eq!(opts.parse_into::<Args, _>(vec!["-j", "x"]),
+               Err(InvalidArgument("jobs".to_string(), "x".to_string())));
+    assert_eq!(opts.parse_into::<Args, _>(vec!["-q"]), Err(UnrecognizedOption("q".to_string())));
+}
+
+#[test]
+fn test_canonical_name() {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output", "FILE");
+    opts.optflag("v", "", "Verbose");
+    opts.optflag("", "dry-run", "Dry run");
+
+    let matches = opts.parse(vec!["--output=x", "-v"]).unwrap();
+    assert_eq!(matches.canonical_name("o"), Ok("output"));
+    assert_eq!(matches.canonical_name("output"), Ok("output"));
+    assert_eq!(matches.canonical_name("v"), Ok("v"));
+    assert_eq!(matches.canonical_name("dry-run"), Ok("dry-run"));
+    assert_eq!(matches.canonical_name("x"), Err(UnrecognizedOption("x".to_string())));
+    assert_eq!(matches.canonical_name(""), Err(UnrecognizedOption("".to_string())));
+    assert_eq!(matches.opt_str("o"), matches.opt_str("output"));
+    assert!(matches.opt_present("v"));
+    assert!(!matches.opt_defined("verbose"));
+}
+
+#[test]
+fn test_usage_wide_characters() {
+    use mini::getopts::UsageConfig;
+
+    let mut opts = Options::new();
+    opts.optopt("o", "出力", "出力 ファイル を 指定 します", "ファイル");
+    opts.optflag("c", "", "cafe\u{301} cre\u{300}me bru\u{302}le\u{301}e");
+    opts.usage_config(UsageConfig { indent: 2, desc_column: 20, wrap_width: 12 });
+
+    let expected =
+"Usage: fruits
+
+Options:
+  -o, --出力 ファイル
+                    出力
+                    ファイル を
+                    指定 します
+  -c                cafe\u{301} cre\u{300}me
+                    bru\u{302}le\u{301}e
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+fn test_prefix_chars() {
+    let mut opts = Options::new();
+    opts.optopt("o", "out", "Output", "FILE");
+    opts.optflag("x", "trace", "Trace");
+    opts.optflagopt("c", "color", "Color", "WHEN");
+    opts.prefix_chars(&['/', '+']);
+
+    let matches = opts.parse(vec!["/out=a.txt", "+x", "/", "in", "/color", "b"]).unwrap();
+    assert_eq!(matches.opt_str("o").unwrap(), "a.txt");
+    assert_eq!(matches.opt_prefix("out"), Some('/'));
+    assert!(matches.opt_present("trace"));
+    assert_eq!(matches.opt_prefix("x"), Some('+'));
+    assert!(matches.opt_present("color"));
+    assert_eq!(matches.opt_str("color"), None);
+    assert_eq!(matches.free, vec!["/", "in", "b"]);
+
+    let matches = opts.parse(vec!["-x", "/out", "b.txt"]).unwrap();
+    assert_eq!(matches.opt_str("o").unwrap(), "b.txt");
+    assert_eq!(matches.opt_prefix("x"), Some('-'));
+    assert_eq!(matches.opt_prefix("c"), None);
+
+    let failure = opts.parse_detailed(vec!["/usr/lib"]).err().unwrap();
+    assert_eq!(*failure.fail(), UnrecognizedOption("usr/lib".to_string()));
+    assert_eq!(failure.context().long, Some(true));
+
+    // A dash after another prefix is part of the name.
+    for arg in &["/-x", "+-x"] {
+        let failure = opts.parse_detailed(vec![*arg]).err().unwrap();
+        assert_eq!(*failure.fail(), UnrecognizedOption("-x".to_string()));
+    }
+
+    // Without prefix characters, these are free arguments.
+    let mut opts = Options::new();
+    opts.optflag("x", "", "Trace");
+    assert_eq!(opts.parse(vec!["/x", "+x"]).unwrap().free, vec!["/x", "+x"]);
+}
+
+#[test]
+fn test_long_only() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose");
+    opts.optflag("e", "", "E");
+    opts.optflag("r", "", "R");
+    opts.optopt("o", "output", "Output", "FILE");
+
+    // Disabled by default: `-verbose` is a cluster of short options.
+    match opts.parse(vec!["-verbose"]) {
+        Err(UnrecognizedOption(ref nm)) => assert_eq!(nm, "b"),
+        result => panic!("unexpected {:?}", result.map(|_| ())),
+    }
+
+    opts.long_only(true);
+    let matches = opts.parse(vec!["-verbose", "-output=a.txt"]).unwrap();
+    assert!(matches.opt_present("verbose"));
+    assert!(!matches.opt_present("e"));
+    assert_eq!(matches.opt_str("o").unwrap(), "a.txt");
+
+    // No long option matches: short options.
+    let matches = opts.parse(vec!["-ver", "-ofile"]).unwrap();
+    assert!(matches.opt_present("v") && matches.opt_present("e") && matches.opt_present("r"));
+    assert_eq!(matches.opt_str("o").unwrap(), "file");
+
+    opts.abbreviations(true);
+    let matches = opts.parse(vec!["-verb", "-out", "b.txt"]).unwrap();
+    assert!(matches.opt_present("verbose"));
+    assert_eq!(matches.opt_str("o").unwrap(), "b.txt");
+}
+
+#[test]
+fn test_range() {
+    let mut opts = Options::new();
+    opts.optopt("p", "port", "Port", "PORT").range(1..=65535);
+    opts.optopt("j", "jobs", "Jobs", "N").range(1..);
+    opts.optopt("r", "ratio", "Ratio", "R").range(0.0..1.0);
+
+    let matches = opts.parse(vec!["-p", "443", "-j", "64", "-r", "0.5"]).unwrap();
+    assert_eq!(matches.opt_get::<u16>("p"), Ok(Some(443)));
+
+    assert_eq!(opts.parse(vec!["--port=0"]).err().unwrap().to_string(),
+               "Invalid value '0' for option 'port': must be between 1 and 65535");
+    assert_eq!(opts.parse(vec!["-j", "0"]).err().unwrap().to_string(),
+               "Invalid value '0' for option 'jobs': must be at least 1");
+    assert_eq!(opts.parse(vec!["-r", "1"]).err().unwrap().to_string(),
+               "Invalid value '1' for option 'ratio': must be at least 0 and less than 1");
+    assert_eq!(opts.parse(vec!["-p", "http"]).err().unwrap().to_string(),
+               "Invalid value 'http' for option 'port': invalid digit found in string");
+
+    let expected =
+"Usage: fruits
+
+Options:
+    -p, --port PORT     Port (between 1 and 65535)
+    -j, --jobs N        Jobs (at least 1)
+    -r, --ratio R       Ratio (at least 0 and less than 1)
+";
+    assert_eq!(opts.usage("Usage: fruits"), expected);
+}
+
+#[test]
+fn test_check_definitions() {
+    use mini::getopts::Occur;
+
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output", "FILE");
+    opts.optflag("v", "verbose", "Verbose");
+    opts.positional("SRC", "Source", Occur::Req);
+    assert_eq!(opts.check_definitions(), Ok(()));
+
+    opts.optflag("o", "overwrite", "Overwrite");
+    opts.optflag("", "verbose", "More output");
+    opts.optopt_default("m", "mode", "Mode", "MODE", "slow").choices(&["fast", "small"]);
+    opts.positional("SRC", "Other source", Occur::Optional);
+    opts.requires("output", "force");
+
+    let error = opts.check_definitions().unwrap_err();
+    assert_eq!(error.conflicts, vec![
+        "option '-o' is defined more than once",
+        "option '--verbose' is defined more than once",
+        "default 'slow' of option '--mode' is not one of its choices",
+        "argument 'SRC' is defined more than once",
+        "a rule names the undefined option 'force'",
+    ]);
+    assert!(error.to_string().starts_with("Conflicting option definitions: option '-o' is defined"));
+}
+
+#[test]
+fn test_opt_str_strict() {
+    let mut opts = Options::new();
+    opts.optopt_default("o", "output", "Output", "FILE", "a.out");
+    opts.optmulti("I", "include", "Include", "DIR");
+    opts.optflagopt("c", "color", "Color", "WHEN");
+    opts.optflag("v", "verbose", "Verbose");
+
+    let matches = opts.parse(vec!["-I", "a", "-I", "b", "-c", "-v"]).unwrap();
+    assert_eq!(matches.opt_str_strict("output"), Ok(Some("a.out".to_string())));
+    assert_eq!(matches.opt_str_strict("c"), Ok(None));
+    assert_eq!(matches.opt_str_strict("v"), Err(NoValue("v".to_string())));
+    assert_eq!(matches.opt_str_strict("include"), Err(OptionDuplicated("include".to_string())));
+    assert_eq!(matches.opt_str_strict("x"), Err(UnrecognizedOption("x".to_string())));
+    assert_eq!(matches.opt_str("include").unwrap(), "a");
+
+    let matches = opts.parse(vec!["-o", "b.out", "-Ic"]).unwrap();
+    assert_eq!(matches.opt_str_strict("o"), Ok(Some("b.out".to_string())));
+    assert_eq!(matches.opt_str_strict("I"), Ok(Some("c".to_string())));
+}
+
+#[test]
+fn test_with_standard_flags() {
+    use mini::getopts::Occur;
+
+    let mut opts = Options::new();
+    opts.with_standard_flags("1.0")
+        .reqopt("o", "output", "Output", "FILE")
+        .positional("SRC", "Source", Occur::Req);
+
+    let matches = opts.parse(vec!["--help"]).unwrap();
+    assert!(matches.opt_present("h"));
+    assert!(matches.handled_standard_flags());
+    let matches = opts.parse(vec!["-V"]).unwrap();
+    assert!(matches.handled_standard_flags());
+
+    let matches = opts.parse(vec!["-o", "a.out", "src"]).unwrap();
+    assert!(!matches.opt_present("version"));
+    assert!(!matches.handled_standard_flags());
+    assert_eq!(opts.parse(vec!["src"]).err().unwrap(), OptionMissing("output".to_string()));
+
+    let usage = opts.usage("Usage: prog");
+    assert!(usage.contains("-h, --help          Print this help and exit"), "{}", usage);
+    assert!(usage.contains("-V, --version       Print version information and exit"), "{}", usage);
+}
+
+#[test]
+fn test_usage_examples_and_footer() {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output file", "FILE")
+        .example("prog in.txt", "Convert in.txt to a.out")
+        .example("prog -o out.txt in.txt", "Convert in.txt to out.txt")
+        .footer("Environment:\n    PROG_HOME    Configuration directory\n");
+
+    let expected =
+"Usage: prog
+
+Options:
+    -o, --output FILE   Output file
+
+Examples:
+    prog in.txt         Convert in.txt to a.out
+    prog -o out.txt in.txt
+                        Convert in.txt to out.txt
+
+Environment:
+    PROG_HOME    Configuration directory
+";
+
+    let usage = opts.usage("Usage: prog");
+    assert_eq!(usage, expected, "\n{}", usage);
+}
+
+#[test]
+fn test_response_files() {
+    use std::{env, fs, process};
+
+    let dir = env::temp_dir();
+    let outer = dir.join(format!("mini-getopts-{}-outer.rsp", process::id()));
+    let inner = dir.join(format!("mini-getopts-{}-inner.rsp", process::id()));
+    fs::write(&inner, "-v 'with space.txt'\n").unwrap();
+    fs::write(&outer, format!("-o \"out \\\"1\\\".txt\" \\\n  @{}\nsrc.txt", inner.display())).unwrap();
+
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output", "FILE")
+        .optflag("v", "verbose", "Verbose")
+        .response_files(true);
+
+    let outer_arg = format!("@{}", outer.display());
+    let matches = opts.parse(vec![outer_arg.as_str(), "@", "--", outer_arg.as_str()]).unwrap();
+    assert_eq!(matches.opt_str("o").unwrap(), "out \"1\".txt");
+    assert!(matches.opt_present("v"));
+    assert_eq!(matches.free, vec!["with space.txt", "src.txt", "@", outer_arg.as_str()]);
+    assert_eq!(matches.opt_positions("v"), vec![2]);
+
+    fs::write(&inner, "-v 'unterminated\n").unwrap();
+    match opts.parse(vec![outer_arg.as_str()]) {
+        Err(ResponseFile(ref path, ref reason)) => {
+            assert_eq!(*path, inner.display().to_string());
+            assert_eq!(reason, "unterminated quote");
+        }
+        result => panic!("{:?}", result.map(|_| ())),
+    }
+    fs::write(&inner, format!("@{}", outer.display())).unwrap();
+    match opts.parse(vec![outer_arg.as_str()]) {
+        Err(ResponseFile(_, ref reason)) => assert_eq!(reason, "nested too deeply"),
+        result => panic!("{:?}", result.map(|_| ())),
+    }
+    fs::remove_file(&outer).unwrap();
+    fs::remove_file(&inner).unwrap();
+    match opts.parse(vec![outer_arg.as_str()]) {
+        Err(ResponseFile(ref path, _)) => assert_eq!(*path, outer.display().to_string()),
+        result => panic!("{:?}", result.map(|_| ())),
+    }
+
+    opts.response_files(false);
+    let matches = opts.parse(vec![outer_arg.as_str()]).unwrap();
+    assert_eq!(matches.free, vec![outer_arg]);
+}
+
+#[test]
+fn test_parse_str() {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output", "FILE")
+        .optmulti("D", "define", "Define", "VAR");
+
+    let matches = opts.parse_str(r#"  -o 'my file.txt' -D "msg=say \"hi\"" -Da\ b "" src\\dir  "#).unwrap();
+    assert_eq!(matches.opt_str("output").unwrap(), "my file.txt");
+    assert_eq!(matches.opt_strs("D"), vec!["msg=say \"hi\"", "a b"]);
+    assert_eq!(matches.free, vec!["", "src\\dir"]);
+
+    let matches = opts.parse_str("").unwrap();
+    assert!(matches.free.is_empty());
+
+    assert_eq!(opts.parse_str("-o 'a").err().unwrap(), InvalidCommandLine("unterminated quote".to_string()));
+    assert_eq!(opts.parse_str("-o a\\").err().unwrap(), InvalidCommandLine("trailing backslash".to_string()));
+    assert_eq!(opts.parse_str("-x").err().unwrap(), UnrecognizedOption("x".to_string()));
+}
+
+#[test]
+fn test_merge() {
+    let mut logging = Options::new();
+    logging.section("Logging options")
+        .optflag("q", "quiet", "Quiet")
+        .optopt("", "log-level", "Level", "LEVEL")
+        .validate(|value| value.parse::<u8>())
+        .requires("log-level", "quiet");
+
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "Output", "FILE");
+    opts.merge(&logging).unwrap().optflag("v", "verbose", "Verbose");
+    assert_eq!(opts.check_definitions(), Ok(()));
+
+    let matches = opts.parse(vec!["-q", "--log-level", "3", "-v"]).unwrap();
+    assert!(matches.opt_present("quiet"));
+    assert_eq!(matches.opt_str("log-level").unwrap(), "3");
+    match opts.parse(vec!["-q", "--log-level", "high"]) {
+        Err(InvalidValue { ref option, .. }) => assert_eq!(option, "log-level"),
+        result => panic!("{:?}", result.map(|_| ())),
+    }
+    assert_eq!(opts.parse(vec!["--log-level", "3"]).err().unwrap(),
+               OptionRequires("log-level".to_string(), "quiet".to_string()));
+    assert!(opts.usage("Usage: prog").contains("\nLogging options:\n    -q, --quiet"));
+
+    let mut other = Options::new();
+    other.optflag("v", "version", "Version")
+        .optflag("", "quiet", "Quiet")
+        .optflag("x", "", "Extra");
+    let error = opts.merge(&other).err().unwrap();
+    assert_eq!(error.conflicts, vec!["option '-v' is defined more than once",
+                                     "option '--quiet' is defined more than once"]);
+    assert_eq!(opts.parse(vec!["-x"]).err().unwrap(), UnrecognizedOption("x".to_string()));
+}
+
+#[test]
+fn test_lookup_precedence() {
+    let mut opts = Options::new();
+    opts.optflagmulti("v", "", "Version")
+        .optflag("x", "verbose", "Verbose")
+        // Its short name is shadowed by the name of the first option.
+        .optflag("v", "vv", "Very verbose")
+        // Shadowed by the earlier option of the same name.
+        .optflag("", "verbose", "Duplicate");
+
+    let matches = opts.parse(vec!["-v", "-v", "-x", "--vv"]).unwrap();
+    assert_eq!(matches.opt_count("v"), 2);
+    assert_eq!(matches.opt_count("vv"), 1);
+    assert_eq!(matches.opt_count("x"), 1);
+    assert_eq!(matches.opt_count("verbose"), 1);
+    assert_eq!(matches.canonical_name("x"), Ok("verbose"));
+    assert_eq!(matches.canonical_name("v"), Ok("v"));
+    assert_eq!(matches.try_opt_present("y"), Err(UnrecognizedOption("y".to_string())));
+    assert_eq!(matches.try_opt_present(""), Err(UnrecognizedOption("".to_string())));
+}
+
+#[test]
+fn test_merge_positional_order() {
+    use mini::getopts::Occur;
+
+    let mut files = Options::new();
+    files.positional("FILES", "Files", Occur::Multi);
+    let mut dest = Options::new();
+    dest.positional("DEST", "Destination", Occur::Req);
+    let mut extra = Options::new();
+    extra.positional("EXTRA", "Extra", Occur::Optional);
+
+    let mut opts = Options::new();
+    opts.positional("SRC", "Source", Occur::Optional);
+    let error = opts.merge(&dest).err().unwrap();
+    assert_eq!(error.conflicts, vec!["required argument 'DEST' cannot follow the optional argument 'SRC'"]);
+
+    let mut opts = Options::new();
+    opts.positional("SRC", "Source", Occur::Req);
+    opts.merge(&files).unwrap();
+    let error = opts.merge(&extra).err().unwrap();
+    assert_eq!(error.conflicts, vec!["argument 'EXTRA' cannot follow the variadic argument 'FILES'"]);
+    // Nothing was merged.
+    let matches = opts.parse(vec!["a", "b", "c"]).unwrap();
+    assert_eq!(matches.positionals("FILES"), vec!["b", "c"]);
+
+    let mut opts = Options::new();
+    opts.positional("SRC", "Source", Occur::Req);
+    opts.merge(&extra).unwrap();
+    opts.merge(&files).unwrap();
+}
+
+#[test]
+fn test_opt_map() {
+    let mut opts = Options::new();
+    opts.optmap("D", "define", "Define a variable", "NAME=VALUE")
+        .optmulti("I", "", "Include", "DIR");
+
+    let matches = opts.parse(vec!["-DDEBUG=1", "--define", "NAME=a=b", "-D", "EMPTY=", "-I", "inc"]).unwrap();
+    assert_eq!(matches.opt_map("define"), vec![
+        ("DEBUG".to_string(), "1".to_string()),
+        ("NAME".to_string(), "a=b".to_string()),
+        ("EMPTY".to_string(), "".to_string()),
+    ]);
+    assert_eq!(matches.opt_map("I"), vec![("inc".to_string(), "".to_string())]);
+    assert!(opts.parse(Vec::<String>::new()).unwrap().opt_map("D").is_empty());
+
+    match opts.parse(vec!["-D", "DEBUG"]) {
+        Err(InvalidValue { ref option, ref value, ref message }) => {
+            assert_eq!(option, "define");
+            assert_eq!(value, "DEBUG");
+            assert_eq!(message, "expected KEY=VALUE");
+        }
+        result => panic!("{:?}", result.map(|_| ())),
+    }
+    assert!(opts.usage("Usage: prog").contains("-D, --define NAME=VALUE"));
+}
+
+#[test]
+fn test_messages() {
+    use mini::getopts::Messages;
+
+    let mut messages = Messages::new();
+    messages.translate("Options:", "Optionen:")
+        .translate("Usage: {}", "Aufruf: {}")
+        .translate("(required)", "(erforderlich)")
+        .translate("[default: {}]", "[Standard: {}]")
+        .translate("between {} and {}", "zwischen {} und {}")
+        .translate("must be {}", "muss {} sein")
+        .translate("Print this help and exit", "Diese Hilfe anzeigen")
+        .translate("Unrecognized option: '{}'", "Unbekannte Option: '{}'")
+        .translate("unterminated quote", "offenes Anführungszeichen")
+        .translate("Invalid command line: {}", "Ungültige Befehlszeile: {}");
+
+    let mut opts = Options::new();
+    opts.messages(messages.clone())
+        .with_standard_flags("1.0")
+        .reqopt("o", "output", "Ausgabe", "DATEI")
+        .optopt_default("p", "port", "Port", "PORT", "80")
+        .range(1..=65535);
+
+    let expected =
+"Aufruf: prog [-h] [-V] -o DATEI [-p PORT]
+
+Optionen:
+    -h, --help          Diese Hilfe anzeigen
+    -V, --version       Print version information and exit
+    -o, --output DATEI  Ausgabe (erforderlich)
+    -p, --port PORT     Port (zwischen 1 und 65535) [Standard: 80]
+";
+    let usage = opts.usage(&opts.short_usage("prog"));
+    assert_eq!(usage, expected, "\n{}", usage);
+
+    let fail = opts.parse(vec!["-x"]).err().unwrap();
+    assert_eq!(fail.localized(&messages), "Unbekannte Option: 'x'");
+    assert_eq!(fail.to_string(), "Unrecognized option: 'x'");
+    let fail = opts.parse(vec!["-o", "a", "-p", "0"]).err().unwrap();
+    assert_eq!(fail.localized(&messages), "Invalid value '0' for option 'port': muss zwischen 1 und 65535 sein");
+    let fail = opts.parse_str("-o 'a").err().unwrap();
+    assert_eq!(fail.localized(&messages), "Ungültige Befehlszeile: offenes Anführungszeichen");
+    assert_eq!(fail.localized(&Messages::new()), fail.to_string());
+}
+
+#[test]
+fn test_separator() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose");
+
+    let matches = opts.parse(vec!["a", "-v", "b", "--", "cmd", "-v", "--"]).unwrap();
+    assert_eq!(matches.separator_position(), Some(3));
+    assert_eq!(matches.free_before_separator(), ["a", "b"]);
+    assert_eq!(matches.free_after_separator(), ["cmd", "-v", "--"]);
+
+    let matches = opts.parse(vec!["a", "--"]).unwrap();
+    assert_eq!(matches.separator_position(), Some(1));
+    assert_eq!(matches.free_before_separator(), ["a"]);
+    assert!(matches.free_after_separator().is_empty());
+
+    let matches = opts.parse(vec!["a", "-v"]).unwrap();
+    assert_eq!(matches.separator_position(), None);
+    assert_eq!(matches.free_before_separator(), ["a"]);
+    assert!(matches.free_after_separator().is_empty());
+}
+
+#[test]
+fn test_optrest() {
+    let mut opts = Options::new();
+    opts.optflag("v", "verbose", "Verbose")
+        .optrest("e", "exec", "Run a command", "CMD ARGS...");
+
+    let matches = opts.parse(vec!["in", "--exec", "ls", "-v", "--", "-l"]).unwrap();
+    assert!(!matches.opt_present("v"));
+    assert_eq!(matches.opt_strs("exec"), vec!["ls", "-v", "--", "-l"]);
+    assert_eq!(matches.opt_positions("e"), vec![1, 3, 4, 5]);
+    assert_eq!(matches.free, vec!["in"]);
+
+    let matches = opts.parse(vec!["-vels", "-a"]).unwrap();
+    assert!(matches.opt_present("v"));
+    assert_eq!(matches.opt_strs("e"), vec!["ls", "-a"]);
+
+    let matches = opts.parse(vec!["-e", "true"]).unwrap();
+    assert_eq!(matches.opt_strs("e"), vec!["true"]);
+    assert_eq!(opts.parse(vec!["-e"]).err().unwrap(), ArgumentMissing("e".to_string()));
+    assert!(opts.short_usage("prog").ends_with("[-v] [-e CMD ARGS...]"));
+}
@@ -1,15 +1,15 @@
 extern crate mini;
 
+use std::cell::{Cell, RefCell};
 use std::io::Write;
 use std::net;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::thread;
 
-use mini::aio::async::{
-    EpollResult,
-    event_list,
-};
+use mini::aio::async::EpollResult;
 use mini::aio::handler::Loop;
 use mini::aio::net::{
     TcpConnection,
@@ -17,6 +17,8 @@ use mini::aio::net::{
     TcpListenNotify,
 };
 use mini::aio::net::TcpListener;
+use mini::aio::net::tcp;
+use mini::bytes::Bytes;
 
 struct Listener {
 }
@@ -48,7 +50,7 @@ impl TcpConnectionNotify for Server {
         println!("Connection accepted.");
     }
 
-    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
+    fn received(&mut self, connection: &mut TcpConnection, data: Bytes) {
         println!("Data of size {} received, looping it back.", data.len());
         let _ = connection.write(b"server says: ".to_vec());
         let _ = connection.write(data); // TODO: handle errors.
@@ -94,10 +96,8 @@ fn test_blocked_write() {
         thread_done.store(true, Ordering::SeqCst);
     });
 
-    let mut event_list = event_list();
-
     while !done.load(Ordering::SeqCst) {
-        match event_loop.iterate(&mut event_list) {
+        match event_loop.iterate() {
             // Restart if interrupted by signal.
             EpollResult::Interrupted => continue,
             EpollResult::Error(error) => panic!("{}", error),
@@ -105,3 +105,78 @@ fn test_blocked_write() {
         }
     }
 }
+
+struct Client {
+    closed: Rc<Cell<bool>>,
+    connected: Rc<Cell<bool>>,
+    received: Rc<RefCell<Vec<u8>>>,
+    request: &'static [u8],
+}
+
+impl TcpConnectionNotify for Client {
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        if !self.request.is_empty() {
+            let _ = connection.write(self.request.to_vec());
+        }
+        self.connected.set(true);
+    }
+
+    fn received(&mut self, _connection: &mut TcpConnection, data: Bytes) {
+        self.received.borrow_mut().extend_from_slice(&data);
+    }
+
+    fn closed(&mut self, _connection: &mut TcpConnection) {
+        self.closed.set(true);
+    }
+}
+
+/// Returns the data received by a client sending `request` to a server replying then closing the
+/// connection, the reply and the close being received in the same event.
+fn reply_then_close(request: &'static [u8]) -> Vec<u8> {
+    let listener = net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("address").port();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().expect("accept");
+        if !request.is_empty() {
+            // Wait for the request without reading it, so that closing resets the connection.
+            let mut buffer = [0; 1];
+            socket.peek(&mut buffer).expect("peek");
+        }
+        socket.write_all(b"reply").expect("write");
+        drop(socket);
+        sender.send(()).expect("send");
+    });
+
+    let mut event_loop = Loop::new().expect("event loop");
+    let client = Client {
+        closed: Rc::new(Cell::new(false)),
+        connected: Rc::new(Cell::new(false)),
+        received: Rc::new(RefCell::new(vec![])),
+        request,
+    };
+    let (closed, connected, received) = (client.closed.clone(), client.connected.clone(), client.received.clone());
+    tcp::connect_to_host("127.0.0.1", &port.to_string(), &mut event_loop, client).expect("connect");
+    while !connected.get() {
+        event_loop.iterate();
+    }
+    receiver.recv().expect("server closed");
+    while !closed.get() {
+        match event_loop.iterate() {
+            EpollResult::Interrupted | EpollResult::Ok => (),
+            EpollResult::Error(error) => panic!("{}", error),
+        }
+    }
+    let data = received.borrow().clone();
+    data
+}
+
+#[test]
+fn test_data_before_fin() {
+    assert_eq!(reply_then_close(b""), b"reply");
+}
+
+#[test]
+fn test_data_before_reset() {
+    assert_eq!(reply_then_close(b"unread request"), b"reply");
+}